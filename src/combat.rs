@@ -0,0 +1,2596 @@
+use colored::*;
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use rig::completion::{Chat, Message};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, fs::OpenOptions, io::Write, path::Path};
+
+use crate::{
+    ability_modifier, dm_chat, print_dice_roll, print_fancy_message, save_game, save_success_chance,
+    find_weapon, roll_d20, theme, choose_roll_mode, describe_d20, print_d20_roll, Ability, DiceExpr, GameState,
+    FavoriteAction, Roller, RollMode, SceneType, Weapon,
+};
+
+// Monster stat blocks
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MonsterAttack {
+    pub name: String,
+    pub attack_bonus: i32,
+    pub damage: DiceExpr,
+    pub damage_type: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MonsterStatBlock {
+    pub name: String,
+    pub armor_class: u32,
+    pub hit_points: u32,
+    pub hit_dice: DiceExpr,
+    // STR, DEX, CON, INT, WIS, CHA
+    pub abilities: [u32; 6],
+    pub attacks: Vec<MonsterAttack>,
+    pub xp: u32,
+}
+
+impl MonsterAttack {
+    // Bows, crossbows and the like, recognized by the weapon table
+    fn is_ranged(&self) -> bool {
+        find_weapon(&self.name).is_some_and(|w| w.ranged)
+    }
+}
+
+fn attack(name: &str, attack_bonus: i32, damage: &str, damage_type: &str) -> MonsterAttack {
+    MonsterAttack {
+        name: name.to_string(),
+        attack_bonus,
+        damage: DiceExpr::parse(damage).expect("bestiary damage notation is valid"),
+        damage_type: damage_type.to_string(),
+    }
+}
+
+fn monster(
+    name: &str,
+    armor_class: u32,
+    hit_points: u32,
+    hit_dice: &str,
+    abilities: [u32; 6],
+    attacks: Vec<MonsterAttack>,
+    xp: u32,
+) -> MonsterStatBlock {
+    MonsterStatBlock {
+        name: name.to_string(),
+        armor_class,
+        hit_points,
+        hit_dice: DiceExpr::parse(hit_dice).expect("bestiary hit dice notation is valid"),
+        abilities,
+        attacks,
+        xp,
+    }
+}
+
+// A handful of common SRD creatures for quick encounters
+pub fn bestiary() -> Vec<MonsterStatBlock> {
+    vec![
+        monster("Bandit", 12, 11, "2d8+2", [11, 12, 12, 10, 10, 10],
+                vec![attack("Scimitar", 3, "1d6+1", "slashing"), attack("Light crossbow", 3, "1d8+1", "piercing")], 25),
+        monster("Cultist", 12, 9, "2d8", [11, 12, 10, 10, 11, 10],
+                vec![attack("Ritual sickle", 3, "1d4+1", "slashing")], 25),
+        monster("Giant Rat", 12, 7, "2d6", [7, 15, 11, 2, 10, 4],
+                vec![attack("Bite", 4, "1d4+2", "piercing")], 25),
+        monster("Guard", 16, 11, "2d8+2", [13, 12, 12, 10, 11, 10],
+                vec![attack("Spear", 3, "1d6+1", "piercing")], 25),
+        monster("Goblin", 15, 10, "3d6", [8, 15, 10, 10, 8, 8],
+                vec![attack("Scimitar", 4, "1d6+2", "slashing"), attack("Shortbow", 4, "1d6+2", "piercing")], 50),
+        monster("Skeleton", 14, 13, "2d8+4", [10, 16, 15, 6, 8, 5],
+                vec![attack("Shortsword", 5, "1d6+3", "piercing"), attack("Shortbow", 5, "1d6+3", "piercing")], 50),
+        monster("Wolf", 12, 11, "2d8+2", [14, 15, 12, 3, 12, 6],
+                vec![attack("Bite", 4, "1d6+2", "piercing")], 50),
+        monster("Zombie", 8, 15, "2d8+6", [13, 6, 16, 3, 6, 5],
+                vec![attack("Slam", 3, "1d8+1", "bludgeoning")], 50),
+        monster("Orc", 13, 15, "2d8+6", [16, 12, 16, 7, 11, 10],
+                vec![attack("Greataxe", 5, "1d12+3", "slashing")], 100),
+        monster("Bugbear", 14, 33, "6d8+6", [15, 14, 13, 8, 11, 9],
+                vec![attack("Light hammer", 4, "3d4+2", "bludgeoning")], 200),
+        monster("Brown Bear", 11, 22, "3d10+6", [17, 12, 15, 2, 13, 7],
+                vec![attack("Bite", 5, "1d8+3", "piercing"), attack("Claw", 5, "1d4+3", "slashing")], 200),
+        monster("Ogre", 11, 68, "8d10+24", [19, 8, 16, 5, 7, 7],
+                vec![attack("Greatclub", 6, "2d8+4", "bludgeoning")], 450),
+    ]
+}
+
+// Encounter state
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CombatantKind {
+    Player,
+    Monster(MonsterStatBlock),
+    // One of the character's companions fighting alongside them
+    Companion,
+}
+
+// Encounter difficulty bands from the DMG XP thresholds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncounterDifficulty {
+    Easy,
+    Medium,
+    Hard,
+    Deadly,
+}
+
+impl EncounterDifficulty {
+    pub const ALL: [EncounterDifficulty; 4] =
+        [EncounterDifficulty::Easy, EncounterDifficulty::Medium, EncounterDifficulty::Hard, EncounterDifficulty::Deadly];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EncounterDifficulty::Easy => "Easy",
+            EncounterDifficulty::Medium => "Medium",
+            EncounterDifficulty::Hard => "Hard",
+            EncounterDifficulty::Deadly => "Deadly",
+        }
+    }
+}
+
+// XP thresholds per character for levels 1-20: Easy, Medium, Hard, Deadly
+const XP_THRESHOLDS: [[u32; 4]; 20] = [
+    [25, 50, 75, 100], [50, 100, 150, 200], [75, 150, 225, 400], [125, 250, 375, 500],
+    [250, 500, 750, 1100], [300, 600, 900, 1400], [350, 750, 1100, 1700], [450, 900, 1400, 2100],
+    [550, 1100, 1600, 2400], [600, 1200, 1900, 2800], [800, 1600, 2400, 3600], [1000, 2000, 3000, 4500],
+    [1100, 2200, 3400, 5100], [1250, 2500, 3800, 5700], [1400, 2800, 4300, 6400], [1600, 3200, 4800, 7200],
+    [2000, 3900, 5900, 8800], [2100, 4200, 6300, 9500], [2400, 4900, 7300, 10900], [2800, 5700, 8500, 12700],
+];
+
+// The party's XP budget for a fight of the given difficulty: the sum of each member's threshold
+pub fn encounter_budget(levels: &[u32], difficulty: EncounterDifficulty) -> u32 {
+    let column = EncounterDifficulty::ALL.iter().position(|&d| d == difficulty).unwrap_or(0);
+    levels.iter().map(|&level| XP_THRESHOLDS[level.clamp(1, 20) as usize - 1][column]).sum()
+}
+
+// Monster XP scaled up for their numbers, the figure compared against the budget
+fn adjusted_xp(monster_xp: &[u32]) -> u32 {
+    let multiplier = match monster_xp.len() {
+        0 | 1 => 1.0,
+        2 => 1.5,
+        3..=6 => 2.0,
+        7..=10 => 2.5,
+        11..=14 => 3.0,
+        _ => 4.0,
+    };
+    (monster_xp.iter().sum::<u32>() as f64 * multiplier) as u32
+}
+
+// The hardest band the adjusted XP reaches, or None if it falls short of Easy
+fn rate_encounter(levels: &[u32], xp: u32) -> Option<EncounterDifficulty> {
+    EncounterDifficulty::ALL.into_iter().rev().find(|&d| xp >= encounter_budget(levels, d))
+}
+
+// Coarse distance between an enemy and the player instead of a battle map.
+// Melee needs Engaged; one move closes or opens one step.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Zone {
+    Engaged,
+    #[default]
+    Near,
+    Far,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 3] = [Zone::Engaged, Zone::Near, Zone::Far];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Zone::Engaged => "engaged",
+            Zone::Near => "near",
+            Zone::Far => "far",
+        }
+    }
+
+    pub fn closer(self) -> Zone {
+        match self {
+            Zone::Far => Zone::Near,
+            _ => Zone::Engaged,
+        }
+    }
+
+    pub fn farther(self) -> Zone {
+        match self {
+            Zone::Engaged => Zone::Near,
+            _ => Zone::Far,
+        }
+    }
+}
+
+// What a combatant has spent this turn; reset when their turn starts
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TurnEconomy {
+    pub action_used: bool,
+    pub bonus_action_used: bool,
+    pub reaction_used: bool,
+    // In zone steps; Dash adds one
+    pub movement_remaining: u32,
+    pub disengaged: bool,
+    // What each spent slot went on, e.g. "action: weapon attack", so the DM narrates only that
+    #[serde(default)]
+    pub spent_on: Vec<String>,
+}
+
+impl TurnEconomy {
+    pub fn fresh() -> Self {
+        TurnEconomy { movement_remaining: 1, ..Default::default() }
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.action_used && self.bonus_action_used && self.movement_remaining == 0
+    }
+
+    pub fn summary(&self) -> String {
+        let mark = |used: bool| if used { "✗" } else { "✓" };
+        format!(
+            "Action {} | Bonus action {} | Reaction {} | Movement {}",
+            mark(self.action_used), mark(self.bonus_action_used), mark(self.reaction_used),
+            if self.movement_remaining > 0 { "✓" } else { "✗" }
+        )
+    }
+
+    pub fn record(&mut self, slot: &str, what: &str) {
+        self.spent_on.push(format!("{}: {}", slot, what));
+    }
+
+    // Plain-language spent/available lists for the DM prompt
+    pub fn describe(&self) -> String {
+        let slots = [
+            ("action", self.action_used),
+            ("bonus action", self.bonus_action_used),
+            ("reaction", self.reaction_used),
+            ("movement", self.movement_remaining == 0),
+        ];
+        let list = |used: bool| {
+            let names: Vec<&str> = slots.iter().filter(|(_, u)| *u == used).map(|(n, _)| *n).collect();
+            if names.is_empty() { "nothing".to_string() } else { names.join(", ") }
+        };
+        let used_on = if self.spent_on.is_empty() { String::new() } else { format!(" ({})", self.spent_on.join("; ")) };
+        format!(
+            "spent {}{}; still has {}. Narrate only what those slots allow: no second action, bonus action, or reaction once it is spent",
+            list(true), used_on, list(false)
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Combatant {
+    pub name: String,
+    pub kind: CombatantKind,
+    pub initiative: i32,
+    pub dexterity: u32,
+    // Only tracked for monsters and companions; the player's HP lives on their Character
+    pub hit_points: u32,
+    pub max_hit_points: u32,
+    pub armor_class: u32,
+    // Distance from the player; unused for the player themselves
+    #[serde(default)]
+    pub zone: Zone,
+    #[serde(default)]
+    pub economy: TurnEconomy,
+    // Caught off guard; loses their first turn of the fight
+    #[serde(default)]
+    pub surprised: bool,
+    // Free-form conditions such as "prone" or "frightened", shown in the order and told to the DM
+    #[serde(default)]
+    pub conditions: Vec<String>,
+}
+
+impl Combatant {
+    pub fn is_player(&self) -> bool {
+        matches!(self.kind, CombatantKind::Player)
+    }
+
+    pub fn is_enemy(&self) -> bool {
+        matches!(self.kind, CombatantKind::Monster(_))
+    }
+
+    pub fn is_defeated(&self) -> bool {
+        !self.is_player() && self.hit_points == 0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CombatEvent {
+    Attack {
+        attacker: String,
+        target: String,
+        roll: u32,
+        total: i32,
+        target_ac: u32,
+        hit: bool,
+        critical: bool,
+        damage: u32,
+    },
+    DeathSave {
+        roll: u32,
+        success: bool,
+    },
+    Defeated {
+        name: String,
+    },
+    Note(String),
+}
+
+impl fmt::Display for CombatEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombatEvent::Attack { attacker, target, roll, total, target_ac, hit, critical, damage } => {
+                write!(f, "{} attacks {}: {} ({} on the die) vs AC {} - ", attacker, target, total, roll, target_ac)?;
+                match (hit, critical) {
+                    (true, true) => write!(f, "critical hit for {} damage", damage),
+                    (true, false) => write!(f, "hit for {} damage", damage),
+                    _ => write!(f, "miss"),
+                }
+            }
+            CombatEvent::DeathSave { roll, success } => {
+                write!(f, "Death save: {} ({})", roll, if *success { "success" } else { "failure" })
+            }
+            CombatEvent::Defeated { name } => write!(f, "{} is defeated", name),
+            CombatEvent::Note(note) => write!(f, "{}", note),
+        }
+    }
+}
+
+impl CombatEvent {
+    // One short line for batched summaries, e.g. "Goblin hits for 5"
+    fn brief(&self) -> String {
+        match self {
+            CombatEvent::Attack { attacker, hit: false, .. } => format!("{} misses", attacker),
+            CombatEvent::Attack { attacker, critical: true, damage, .. } => format!("{} crits for {}", attacker, damage),
+            CombatEvent::Attack { attacker, damage, .. } => format!("{} hits for {}", attacker, damage),
+            other => other.to_string(),
+        }
+    }
+}
+
+// Something scheduled to happen when a round begins, e.g. reinforcements arriving
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoundTrigger {
+    pub at_round: u32,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Encounter {
+    pub combatants: Vec<Combatant>,
+    pub turn: usize,
+    pub log: Vec<CombatEvent>,
+    // Starts at 1 and goes up each time the turn order wraps back to the top
+    #[serde(default)]
+    pub round: u32,
+    #[serde(default)]
+    pub triggers: Vec<RoundTrigger>,
+    // Show enemies as "unhurt", "bloodied", ... instead of exact HP, for DMs who keep monster HP secret
+    #[serde(default)]
+    pub hide_enemy_hp: bool,
+}
+
+impl Encounter {
+    pub fn living_enemies(&self) -> Vec<usize> {
+        self.combatants
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.is_enemy() && !c.is_defeated())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.living_enemies().is_empty()
+    }
+
+    pub fn player_mut(&mut self) -> Option<&mut Combatant> {
+        self.combatants.iter_mut().find(|c| c.is_player())
+    }
+
+    // Where every enemy stands relative to the player, for the DM prompt
+    pub fn positions(&self) -> String {
+        self.living_enemies()
+            .iter()
+            .map(|&i| {
+                let enemy = &self.combatants[i];
+                if enemy.conditions.is_empty() {
+                    format!("{} is {}", enemy.name, enemy.zone.name())
+                } else {
+                    format!("{} is {} ({})", enemy.name, enemy.zone.name(), enemy.conditions.join(", "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Moves to the next combatant who can still act
+    pub fn advance(&mut self) {
+        let next = self.next_turn();
+        if next <= self.turn {
+            self.round += 1;
+        }
+        self.turn = next;
+    }
+
+    // Removes and returns the triggers due by the current round
+    pub fn take_due_triggers(&mut self) -> Vec<RoundTrigger> {
+        let (due, pending): (Vec<_>, Vec<_>) = self.triggers.drain(..).partition(|t| t.at_round <= self.round);
+        self.triggers = pending;
+        due
+    }
+
+    // Index of whoever acts after the current combatant, skipping the defeated
+    pub fn next_turn(&self) -> usize {
+        let mut turn = self.turn;
+        for _ in 0..self.combatants.len() {
+            turn = (turn + 1) % self.combatants.len();
+            if !self.combatants[turn].is_defeated() {
+                break;
+            }
+        }
+        turn
+    }
+
+    // Sorts by initiative, breaking ties on DEX; full ties keep their current (chosen) order
+    pub fn sort_by_initiative(&mut self) {
+        self.combatants.sort_by(|a, b| {
+            b.initiative
+                .cmp(&a.initiative)
+                .then(b.dexterity.cmp(&a.dexterity))
+        });
+        self.turn = 0;
+    }
+}
+
+// A ten-cell health bar, e.g. "[██████░░░░]"
+fn hp_bar(hp: u32, max_hp: u32) -> String {
+    let filled = if max_hp == 0 { 0 } else { ((hp.min(max_hp) * 10).div_ceil(max_hp)) as usize };
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(10 - filled))
+}
+
+// Green above half, yellow down to a quarter, red below that
+fn hp_color(hp: u32, max_hp: u32) -> Color {
+    let t = crate::theme();
+    if hp * 2 > max_hp {
+        t.good
+    } else if hp * 4 > max_hp {
+        t.warning
+    } else {
+        t.bad
+    }
+}
+
+// What the party can tell by looking when exact HP is hidden
+fn health_label(hp: u32, max_hp: u32) -> &'static str {
+    if hp == 0 {
+        "down"
+    } else if hp >= max_hp {
+        "unhurt"
+    } else if hp * 2 > max_hp {
+        "wounded"
+    } else if hp * 4 > max_hp {
+        "bloodied"
+    } else {
+        "near death"
+    }
+}
+
+// How a target appears in the pick lists: "Goblin [███████░░░] 5/7 HP, AC 15", or "Goblin (bloodied), AC 15" when HP is hidden
+fn target_label(combatant: &Combatant, hide_hp: bool) -> String {
+    if hide_hp && combatant.is_enemy() {
+        return format!("{} ({}), AC {}", combatant.name, health_label(combatant.hit_points, combatant.max_hit_points),
+                       combatant.armor_class);
+    }
+    format!("{} {} {}/{} HP, AC {}", combatant.name, hp_bar(combatant.hit_points, combatant.max_hit_points),
+            combatant.hit_points, combatant.max_hit_points, combatant.armor_class)
+}
+
+fn print_initiative_order(encounter: &Encounter, state: &GameState) {
+    let t = crate::theme();
+    let heading = if encounter.combatants.iter().any(|c| c.surprised) {
+        format!("INITIATIVE ORDER - ROUND {} (SURPRISE ROUND)", encounter.round)
+    } else {
+        format!("INITIATIVE ORDER - ROUND {}", encounter.round)
+    };
+    println!("\n{}", heading.color(t.heading).bold());
+    println!("{}", "-".repeat(60).color(t.border));
+    for (i, combatant) in encounter.combatants.iter().enumerate() {
+        let marker = if i == encounter.turn { "▶" } else { " " };
+        let (hp, max_hp, ac) = if combatant.is_player() {
+            (state.character.hit_points, state.character.effective_max_hp(), state.character.armor_class)
+        } else {
+            (combatant.hit_points, combatant.max_hit_points, combatant.armor_class)
+        };
+        let zone = if combatant.is_enemy() { combatant.zone.name() } else { "" };
+        let prefix = format!("{} {:>3}  {:<20} ", marker, combatant.initiative, combatant.name);
+        let hidden = encounter.hide_enemy_hp && combatant.is_enemy();
+        let health = if hidden {
+            format!("{:<23}", health_label(hp, max_hp))
+        } else {
+            format!("{:>3}/{:<3} HP", hp, max_hp)
+        };
+        let mut suffix = format!("  AC {:<3} {}", ac, zone);
+        if combatant.surprised {
+            suffix.push_str(" (surprised)");
+        }
+        if !combatant.conditions.is_empty() {
+            suffix.push_str(&format!(" [{}]", combatant.conditions.join(", ")));
+        }
+        if combatant.is_defeated() {
+            let bar = if hidden { String::new() } else { format!("{} ", hp_bar(hp, max_hp)) };
+            println!("{}", format!("{}{}{}{}", prefix, bar, health, suffix).dimmed().strikethrough());
+            continue;
+        }
+        let color = if combatant.is_enemy() { t.bad } else { t.good };
+        let bar = if hidden { String::new() } else { format!("{} ", hp_bar(hp, max_hp).color(hp_color(hp, max_hp))) };
+        println!("{}{}{}{}", prefix.color(color), bar, health.color(color), suffix.color(color));
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+}
+
+// Lets the player pick the enemies for a new fight and rolls initiative
+pub fn setup_encounter(state: &GameState, roller: &mut impl Roller) -> Result<Option<Encounter>, Box<dyn Error>> {
+    let t = theme();
+    let bestiary = bestiary();
+    let mut encounter = Encounter { round: 1, ..Default::default() };
+
+    loop {
+        let mut options: Vec<String> = bestiary
+            .iter()
+            .map(|m| format!("{} (AC {}, {} HP)", m.name, m.armor_class, m.hit_points))
+            .collect();
+        options.push("Custom monster...".to_string());
+        options.push(if encounter.hide_enemy_hp {
+            "Enemy HP: hidden (shown as unhurt / bloodied)".to_string()
+        } else {
+            "Enemy HP: exact numbers".to_string()
+        });
+        options.push("Begin combat".to_string());
+        options.push("Cancel".to_string());
+
+        let enemies = encounter.combatants.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ");
+        if !enemies.is_empty() {
+            println!("{}: {}", "Enemies".color(t.bad), enemies);
+        }
+        let levels = state.party_levels();
+        let monster_xp: Vec<u32> = encounter.combatants.iter()
+            .filter_map(|c| match &c.kind {
+                CombatantKind::Monster(stat_block) => Some(stat_block.xp),
+                _ => None,
+            })
+            .collect();
+        let xp = adjusted_xp(&monster_xp);
+        println!("{}: {} adjusted XP ({}) | Budget: {}", "Difficulty".color(t.heading), xp,
+                 rate_encounter(&levels, xp).map_or("Trivial", |d| d.name()),
+                 EncounterDifficulty::ALL.iter()
+                     .map(|&d| format!("{} {}", d.name(), encounter_budget(&levels, d)))
+                     .collect::<Vec<_>>()
+                     .join(", "));
+
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Add an enemy to the encounter")
+            .default(0)
+            .items(&options)
+            .interact()?;
+
+        let stat_block = if choice < bestiary.len() {
+            bestiary[choice].clone()
+        } else if choice == bestiary.len() {
+            custom_monster()?
+        } else if choice == bestiary.len() + 1 {
+            encounter.hide_enemy_hp = !encounter.hide_enemy_hp;
+            continue;
+        } else if choice == bestiary.len() + 2 {
+            if encounter.combatants.is_empty() {
+                print_fancy_message("Add at least one enemy first.", "yellow");
+                continue;
+            }
+            break;
+        } else {
+            return Ok(None);
+        };
+
+        let count: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("How many?")
+            .default("1".to_string())
+            .interact_text()?;
+        let count = count.parse::<usize>().unwrap_or(1).clamp(1, 12);
+
+        let zones: Vec<&str> = Zone::ALL.iter().map(|z| z.name()).collect();
+        let zone = Zone::ALL[Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Starting distance from you")
+            .default(1)
+            .items(&zones)
+            .interact()?];
+
+        // Average HP by default; rolling the hit dice gives each monster its own total
+        let rolled = stat_block.hit_dice.count > 0 && Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Hit points")
+            .default(0)
+            .items(&[format!("Average ({})", stat_block.hit_points), format!("Rolled ({})", stat_block.hit_dice)])
+            .interact()? == 1;
+
+        for _ in 0..count {
+            let hit_points = if rolled {
+                let (dice, total) = stat_block.hit_dice.roll(roller);
+                let hit_points = total.max(1) as u32;
+                println!("{} rolls {} HP ({} → {:?})", stat_block.name, hit_points.to_string().color(t.value),
+                         stat_block.hit_dice, dice);
+                hit_points
+            } else {
+                stat_block.hit_points
+            };
+            encounter.combatants.push(Combatant {
+                name: stat_block.name.clone(),
+                initiative: 0,
+                dexterity: stat_block.abilities[1],
+                hit_points,
+                max_hit_points: hit_points,
+                armor_class: stat_block.armor_class,
+                kind: CombatantKind::Monster(stat_block.clone()),
+                zone,
+                economy: TurnEconomy::fresh(),
+                surprised: false,
+                conditions: Vec::new(),
+            });
+        }
+    }
+
+    // Label duplicates "Goblin A", "Goblin B", ...
+    let names: Vec<String> = encounter.combatants.iter().map(|c| c.name.clone()).collect();
+    for (i, combatant) in encounter.combatants.iter_mut().enumerate() {
+        let same: Vec<usize> = names.iter().enumerate().filter(|(_, n)| **n == names[i]).map(|(j, _)| j).collect();
+        if same.len() > 1 {
+            let letter = (b'A' + same.iter().position(|&j| j == i).unwrap_or(0) as u8) as char;
+            combatant.name = format!("{} {}", names[i], letter);
+        }
+    }
+
+    encounter.combatants.push(Combatant {
+        name: state.character.name.clone(),
+        kind: CombatantKind::Player,
+        initiative: 0,
+        dexterity: state.character.effective_score(Ability::Dexterity),
+        hit_points: 0,
+        max_hit_points: 0,
+        armor_class: state.character.armor_class,
+        zone: Zone::Engaged,
+        economy: TurnEconomy::fresh(),
+        surprised: false,
+        conditions: Vec::new(),
+    });
+
+    // Companions still on their feet fight alongside the player
+    for companion in state.character.companions.iter().filter(|c| c.hp > 0) {
+        encounter.combatants.push(Combatant {
+            name: companion.name.clone(),
+            kind: CombatantKind::Companion,
+            initiative: 0,
+            dexterity: companion.dexterity,
+            hit_points: companion.hp,
+            max_hit_points: companion.max_hp,
+            armor_class: companion.ac,
+            zone: Zone::Engaged,
+            economy: TurnEconomy::fresh(),
+            surprised: false,
+            conditions: Vec::new(),
+        });
+    }
+
+    print_fancy_message("Roll for initiative!", "yellow");
+    roll_initiative(&mut encounter, roller);
+    break_initiative_ties(&mut encounter, None)?;
+    confirm_initiative(&mut encounter, state)?;
+    choose_surprise(&mut encounter, state, roller)?;
+
+    Ok(Some(encounter))
+}
+
+// Rolls d20 + DEX for everyone in one pass and sorts the order
+fn roll_initiative(encounter: &mut Encounter, roller: &mut impl Roller) {
+    for combatant in encounter.combatants.iter_mut() {
+        combatant.initiative = roller.roll(20) as i32 + ability_modifier(combatant.dexterity);
+    }
+    encounter.sort_by_initiative();
+}
+
+// Shows the rolled order and lets the player overwrite any roll, e.g. one made at the table
+fn confirm_initiative(encounter: &mut Encounter, state: &GameState) -> Result<(), Box<dyn Error>> {
+    loop {
+        print_initiative_order(encounter, state);
+        let mut options: Vec<String> = encounter.combatants.iter()
+            .map(|c| format!("Override {} ({})", c.name, c.initiative))
+            .collect();
+        options.insert(0, "Start the fight".to_string());
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Initiative")
+            .default(0)
+            .items(&options)
+            .interact()?;
+        if choice == 0 {
+            return Ok(());
+        }
+
+        let combatant = &mut encounter.combatants[choice - 1];
+        let initiative: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Initiative total for {} (DEX {})", combatant.name,
+                                 crate::format_modifier(ability_modifier(combatant.dexterity))))
+            .default(combatant.initiative)
+            .interact_text()?;
+        combatant.initiative = initiative;
+        let name = combatant.name.clone();
+        encounter.sort_by_initiative();
+        break_initiative_ties(encounter, Some(&name))?;
+    }
+}
+
+// Combatants tied on both initiative and DEX go in the order the player picks. The sort is
+// stable, so the chosen order is kept in the combatant list through any later re-sorts.
+// With `involving` set, only ties that include that combatant are asked about again.
+fn break_initiative_ties(encounter: &mut Encounter, involving: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    let mut start = 0;
+    while start < encounter.combatants.len() {
+        let first = &encounter.combatants[start];
+        let end = start + encounter.combatants[start..]
+            .iter()
+            .take_while(|c| c.initiative == first.initiative && c.dexterity == first.dexterity)
+            .count();
+        let tied = &encounter.combatants[start..end];
+        if tied.len() > 1 && involving.is_none_or(|name| tied.iter().any(|c| c.name == name)) {
+            println!("{} {} are tied at {} with the same DEX", "Tie:".color(t.heading),
+                     tied.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", "), first.initiative);
+            let mut remaining: Vec<Combatant> = tied.to_vec();
+            let mut ordered = Vec::new();
+            while remaining.len() > 1 {
+                let names: Vec<&str> = remaining.iter().map(|c| c.name.as_str()).collect();
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Who acts {}?", if ordered.is_empty() { "first" } else { "next" }))
+                    .default(0)
+                    .items(&names)
+                    .interact()?;
+                ordered.push(remaining.remove(choice));
+            }
+            ordered.append(&mut remaining);
+            encounter.combatants.splice(start..end, ordered);
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+// Asks whether either side was caught off guard. Stealth is rolled against passive Perception;
+// companions have no Perception of their own and share the player's fate.
+fn choose_surprise(encounter: &mut Encounter, state: &GameState, roller: &mut impl Roller) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    let options = [
+        "No one is surprised",
+        "We ambush them (your Stealth vs their passive Perception)",
+        "They ambush us (their Stealth vs your passive Perception)",
+        "Choose who is surprised...",
+    ];
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Is anyone surprised?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+
+    match choice {
+        1 => {
+            let roll = roller.roll(20);
+            let stealth = roll as i32 + state.character.skill_modifier("Stealth");
+            println!("Stealth: {} ({} on the die)", stealth.to_string().color(t.value), roll);
+            for combatant in encounter.combatants.iter_mut() {
+                if let CombatantKind::Monster(stat_block) = &combatant.kind {
+                    combatant.surprised = stealth > 10 + ability_modifier(stat_block.abilities[4]);
+                }
+            }
+        }
+        2 => {
+            // The party only notices the ambush if they spot at least one of the hidden enemies
+            let passive = state.character.passive_perception();
+            let mut noticed = false;
+            for combatant in encounter.combatants.iter().filter(|c| c.is_enemy()) {
+                let roll = roller.roll(20);
+                let stealth = roll as i32 + ability_modifier(combatant.dexterity);
+                println!("{} Stealth: {} ({} on the die) vs passive Perception {}", combatant.name,
+                         stealth.to_string().color(t.value), roll, passive);
+                noticed |= stealth <= passive;
+            }
+            for combatant in encounter.combatants.iter_mut().filter(|c| !c.is_enemy()) {
+                combatant.surprised = !noticed;
+            }
+        }
+        3 => {
+            let names: Vec<&str> = encounter.combatants.iter().map(|c| c.name.as_str()).collect();
+            let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("Who is surprised? (space to select)")
+                .items(&names)
+                .interact()?;
+            for index in picked {
+                encounter.combatants[index].surprised = true;
+            }
+        }
+        _ => {}
+    }
+
+    let surprised: Vec<&str> = encounter.combatants.iter().filter(|c| c.surprised).map(|c| c.name.as_str()).collect();
+    if surprised.is_empty() {
+        if choice != 0 {
+            print_fancy_message("No one is caught off guard.", "yellow");
+        }
+    } else {
+        print_fancy_message(&format!("Surprise round! {} can't act on their first turn.", surprised.join(", ")), "yellow");
+        encounter.log.push(CombatEvent::Note(format!("Surprise round: {} surprised", surprised.join(", "))));
+    }
+    Ok(())
+}
+
+// A surprised combatant's first turn passes without them acting
+fn skip_surprised_turn(encounter: &mut Encounter) -> bool {
+    let t = theme();
+    let combatant = &mut encounter.combatants[encounter.turn];
+    if !combatant.surprised {
+        return false;
+    }
+    combatant.surprised = false;
+    let note = format!("{} is surprised and loses their turn", combatant.name);
+    println!("\n{}", note.color(t.warning));
+    encounter.log.push(CombatEvent::Note(note));
+    true
+}
+
+// Lets the player schedule an event for the start of a later round
+fn schedule_round_trigger(encounter: &mut Encounter) -> Result<(), Box<dyn Error>> {
+    let at_round: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("At the start of which round? (now round {})", encounter.round))
+        .default(encounter.round + 1)
+        .validate_with(|round: &u32| -> Result<(), String> {
+            if *round > encounter.round { Ok(()) } else { Err(format!("Pick a round after {}", encounter.round)) }
+        })
+        .interact_text()?;
+    let description: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("What happens? (e.g. reinforcements arrive, the ceiling collapses)")
+        .interact_text()?;
+    print_fancy_message(&format!("Scheduled for round {}: {}", at_round, description.trim()), "blue");
+    encounter.triggers.push(RoundTrigger { at_round, description: description.trim().to_string() });
+    Ok(())
+}
+
+// Debug-mode override for improvising: change an enemy's or companion's HP, AC, or conditions mid-fight
+fn adjust_combatant(encounter: &mut Encounter) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    // The player's own numbers live on the character and have their own menus
+    let others: Vec<usize> = (0..encounter.combatants.len()).filter(|&i| !encounter.combatants[i].is_player()).collect();
+    let names: Vec<String> = others.iter().map(|&i| {
+        let c = &encounter.combatants[i];
+        format!("{} ({}/{} HP, AC {})", c.name, c.hit_points, c.max_hit_points, c.armor_class)
+    }).collect();
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Adjust which combatant?")
+        .default(0)
+        .items(&names)
+        .interact()?;
+    let combatant = &mut encounter.combatants[others[choice]];
+
+    let mut options = vec![
+        format!("Hit points ({})", combatant.hit_points),
+        format!("Maximum hit points ({})", combatant.max_hit_points),
+        format!("Armor class ({})", combatant.armor_class),
+        "Add a condition".to_string(),
+    ];
+    options.extend(combatant.conditions.iter().map(|c| format!("Remove: {}", c)));
+    options.push("Back".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Adjust {}", combatant.name))
+        .default(0)
+        .items(&options)
+        .interact()?;
+
+    let change = match choice {
+        0 => {
+            let hp: u32 = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("New hit points")
+                .default(combatant.hit_points)
+                .interact_text()?;
+            let change = format!("HP {} → {}", combatant.hit_points, hp);
+            combatant.hit_points = hp;
+            combatant.max_hit_points = combatant.max_hit_points.max(hp);
+            change
+        }
+        1 => {
+            let max_hp: u32 = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("New maximum hit points")
+                .default(combatant.max_hit_points)
+                .interact_text()?;
+            let change = format!("max HP {} → {}", combatant.max_hit_points, max_hp.max(1));
+            combatant.max_hit_points = max_hp.max(1);
+            combatant.hit_points = combatant.hit_points.min(combatant.max_hit_points);
+            change
+        }
+        2 => {
+            let ac: u32 = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("New armor class")
+                .default(combatant.armor_class)
+                .interact_text()?;
+            let change = format!("AC {} → {}", combatant.armor_class, ac);
+            combatant.armor_class = ac;
+            change
+        }
+        3 => {
+            let condition: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Condition (e.g. prone, frightened, restrained)")
+                .interact_text()?;
+            let condition = condition.trim().to_lowercase();
+            combatant.conditions.push(condition.clone());
+            format!("now {}", condition)
+        }
+        i if i < options.len() - 1 => {
+            let condition = combatant.conditions.remove(i - 4);
+            format!("no longer {}", condition)
+        }
+        _ => return Ok(()),
+    };
+
+    let note = format!("DM adjusts {}: {}", combatant.name, change);
+    println!("{}", note.color(t.accent));
+    encounter.log.push(CombatEvent::Note(note));
+    Ok(())
+}
+
+fn custom_monster() -> Result<MonsterStatBlock, Box<dyn Error>> {
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Monster name")
+        .interact_text()?;
+    let armor_class: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Armor class")
+        .default(12)
+        .interact_text()?;
+    let hit_points: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Hit points")
+        .default(10)
+        .interact_text()?;
+    let attack_bonus: i32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Attack bonus")
+        .default(3)
+        .interact_text()?;
+    let damage: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Attack damage (e.g. 1d6+2)")
+        .default("1d6+1".to_string())
+        .validate_with(|input: &String| -> Result<(), &str> {
+            DiceExpr::parse(input).map(|_| ()).ok_or("Use dice notation like 1d6+2")
+        })
+        .interact_text()?;
+
+    Ok(MonsterStatBlock {
+        name,
+        armor_class,
+        hit_points: hit_points.max(1),
+        hit_dice: DiceExpr { count: 0, sides: 0, modifier: hit_points as i32 },
+        abilities: [10; 6],
+        attacks: vec![attack("Attack", attack_bonus, &damage, "bludgeoning")],
+        xp: 0,
+    })
+}
+
+// Sends a mechanical combat result to the DM and records the narration
+async fn narrate(
+    dm: &impl Chat,
+    state: &mut GameState,
+    encounter: &Encounter,
+    prompt: String,
+) -> Result<(), Box<dyn Error>> {
+    let mut prompt = prompt;
+    let positions = encounter.positions();
+    if !positions.is_empty() {
+        prompt.push_str(&format!("\n\nPositions relative to {}: {}.", state.character.name, positions));
+    }
+    if let Some(player) = encounter.combatants.iter().find(|c| c.is_player()) {
+        prompt.push_str(&format!("\nIt is round {}; this round {} has {}.", encounter.round, state.character.name, player.economy.describe()));
+    }
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to narrate combat",
+        "The Dungeon Master is narrating...",
+    )
+    .await?;
+
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+
+    print_fancy_message("Dungeon Master:", "cyan");
+    crate::print_dm_text(&response);
+    Ok(())
+}
+
+fn is_ranged(attack: &MonsterAttack) -> bool {
+    find_weapon(&attack.name).is_some_and(|w| w.ranged)
+}
+
+// A monster attacks from range if it can, otherwise closes in and attacks once engaged
+// With `auto` set the player is never asked to react, so a whole round of enemies can roll unattended
+fn resolve_monster_turn(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    auto: bool,
+    roller: &mut impl Roller,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let t = theme();
+    let index = encounter.turn;
+    let attacker = encounter.combatants[index].clone();
+    let CombatantKind::Monster(stat_block) = &attacker.kind else {
+        return Ok(None);
+    };
+    let Some(melee) = stat_block.attacks.iter().find(|a| !is_ranged(a)).or(stat_block.attacks.first()) else {
+        return Ok(None);
+    };
+    let ranged = stat_block.attacks.iter().find(|a| is_ranged(a));
+
+    // Badly hurt enemies with a ranged attack break away from melee, which gives the player an opportunity attack
+    if attacker.zone == Zone::Engaged && ranged.is_some() && attacker.hit_points * 4 <= attacker.max_hit_points {
+        return resolve_monster_retreat(state, encounter, auto, roller).map(Some);
+    }
+
+    match (attacker.zone, ranged) {
+        (Zone::Engaged, _) => Ok(Some(resolve_monster_attack(state, encounter, &attacker, melee, "", auto, roller)?)),
+        (_, Some(ranged)) => Ok(Some(resolve_monster_attack(state, encounter, &attacker, ranged, "", auto, roller)?)),
+        (zone, None) => {
+            let zone = zone.closer();
+            encounter.combatants[index].zone = zone;
+            let movement = format!("{} moves to {} with {}.", attacker.name, zone.name(), state.character.name);
+            println!("\n{}", movement.color(t.bad));
+            encounter.log.push(CombatEvent::Note(movement.clone()));
+            if zone == Zone::Engaged {
+                Ok(Some(resolve_monster_attack(state, encounter, &attacker, melee, &movement, auto, roller)?))
+            } else {
+                Ok(Some(format!(
+                    "COMBAT - enemy turn. {}
+
+                    As the DM, narrate the enemy closing the distance in one or two sentences.",
+                    movement
+                )))
+            }
+        }
+    }
+}
+
+// The monster whose turn it is falls back from melee; the player may spend their reaction on an opportunity attack
+fn resolve_monster_retreat(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    auto: bool,
+    roller: &mut impl Roller,
+) -> Result<String, Box<dyn Error>> {
+    let t = theme();
+    let index = encounter.turn;
+    let name = encounter.combatants[index].name.clone();
+    println!("\n{}", format!("{} tries to pull away from {}!", name, state.character.name).color(t.bad));
+
+    let reaction_ready = encounter.player_mut().is_some_and(|p| !p.economy.reaction_used);
+    let mut attack_text = String::new();
+    if reaction_ready && !auto && state.character.hit_points > 0
+        && Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Make an opportunity attack? (uses your reaction)")
+            .default(true)
+            .interact()?
+        && let Some(prompt) = resolve_player_attack(state, encounter, false, roller, None, Some(index))?
+    {
+        if let Some(player) = encounter.player_mut() {
+            player.economy.reaction_used = true;
+            player.economy.record("reaction", &format!("opportunity attack on {}", name));
+        }
+        attack_text = format!(" {} takes an opportunity attack as it goes: {}", state.character.name, prompt);
+    }
+
+    if encounter.combatants[index].is_defeated() {
+        return Ok(format!(
+            "COMBAT - enemy turn. {} tried to break away from {}, but was cut down.{}",
+            name, state.character.name, attack_text
+        ));
+    }
+    encounter.combatants[index].zone = Zone::Near;
+    let movement = format!("{} falls back to near {}.", name, state.character.name);
+    println!("{}", movement.color(t.bad));
+    encounter.log.push(CombatEvent::Note(movement.clone()));
+    Ok(format!(
+        "COMBAT - enemy turn. {}{}
+
+        As the DM, narrate the wounded enemy retreating in one or two sentences.",
+        movement, attack_text
+    ))
+}
+
+// Resolves a monster's attack against the player's AC and applies any damage
+fn resolve_monster_attack(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    attacker: &Combatant,
+    monster_attack: &MonsterAttack,
+    lead_in: &str,
+    auto: bool,
+    roller: &mut impl Roller,
+) -> Result<String, Box<dyn Error>> {
+    let t = theme();
+    println!("\n{} attacks with {}!", attacker.name.color(t.bad).bold(), monster_attack.name);
+    let mode = if monster_attack.is_ranged() {
+        crate::with_environment(RollMode::Normal, state.environment.ranged_attack_penalty(), "ranged attacks")
+    } else {
+        RollMode::Normal
+    };
+    let (d20s, roll) = roll_d20(roller, mode);
+    let total = roll as i32 + monster_attack.attack_bonus;
+    let mut target_ac = state.character.armor_class;
+    let critical = roll == 20;
+    let mut hit = critical || (roll != 1 && total >= target_ac as i32);
+    state.record_roll(&crate::d20_expr(monster_attack.attack_bonus, mode), &d20s, total,
+                      &format!("{}'s {} attack", attacker.name, monster_attack.name));
+
+    println!("Attack roll: {} {} = {} vs AC {} — {}",
+             describe_d20(&d20s, roll, mode), crate::format_modifier(monster_attack.attack_bonus), total, target_ac,
+             if hit { "HIT".color(t.bad).bold() } else { "MISS".color(t.good).bold() });
+
+    // A hit is the moment for reactions like Shield or Parry
+    let mut reaction_line = String::new();
+    let reaction_ready = encounter.player_mut().is_some_and(|p| !p.economy.reaction_used);
+    if hit && reaction_ready && !auto && state.character.hit_points > 0
+        && Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Use your reaction against this attack?")
+            .default(false)
+            .interact()?
+    {
+        let reaction: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Your reaction (e.g. Shield, Parry)")
+            .interact_text()?;
+        let ac_bonus: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("AC bonus it grants against this attack (0 if none)")
+            .default(0)
+            .interact_text()?;
+        if let Some(player) = encounter.player_mut() {
+            player.economy.reaction_used = true;
+            player.economy.record("reaction", &reaction);
+        }
+        target_ac += ac_bonus;
+        hit = critical || total >= target_ac as i32;
+        println!("{} — {} vs AC {} — {}", reaction.color(t.info), total, target_ac,
+                 if hit { "still a HIT".color(t.bad).bold() } else { "now a MISS".color(t.good).bold() });
+        encounter.log.push(CombatEvent::Note(format!("{} reacts: {}", state.character.name, reaction)));
+        reaction_line = format!(" {} uses their reaction: {}.", state.character.name, reaction);
+    }
+
+    let mut damage_taken = 0;
+    let mut damage_line = String::new();
+    if hit {
+        let damage_expr = if critical {
+            let doubled = monster_attack.damage.critical();
+            println!("{} {} → {}", "CRIT!".color(t.bad).bold(), monster_attack.damage, doubled);
+            doubled
+        } else {
+            monster_attack.damage
+        };
+        let (dice, rolled) = damage_expr.roll(roller);
+        let rolled = rolled.max(0) as u32;
+        print_dice_roll(&damage_expr.to_string(), &dice);
+        let turn = state.turn();
+        let source = format!("{}'s {}", attacker.name, monster_attack.name);
+        state.record_roll(&damage_expr.to_string(), &dice, rolled as i32, &format!("{} damage", source));
+        damage_taken = state.character.take_damage(rolled, &monster_attack.damage_type, &source, turn);
+        println!("{} takes {} {} damage ({}/{} HP)",
+                 state.character.name.color(t.heading), damage_taken.to_string().color(t.bad).bold(),
+                 monster_attack.damage_type, state.character.hit_points, state.character.effective_max_hp());
+        damage_line = format!(
+            "Damage: {} = {} {} ({} lost after resistances and temporary HP). {} now has {}/{} HP.",
+            damage_expr, rolled, monster_attack.damage_type, damage_taken,
+            state.character.name, state.character.hit_points, state.character.effective_max_hp()
+        );
+        if state.character.hit_points == 0 {
+            damage_line.push_str(" They are down and unconscious.");
+        }
+    }
+
+    encounter.log.push(CombatEvent::Attack {
+        attacker: attacker.name.clone(),
+        target: state.character.name.clone(),
+        roll,
+        total,
+        target_ac,
+        hit,
+        critical,
+        damage: damage_taken,
+    });
+
+    Ok(format!(
+        "COMBAT - enemy turn. {} {} attacks {} with {}.
+        Attack roll: {} + {} = {} vs AC {} - {}.{}
+        {}
+
+        As the DM, narrate this attack in two or three vivid sentences. Do not change the mechanical outcome.",
+        lead_in, attacker.name, state.character.name, monster_attack.name,
+        roll, monster_attack.attack_bonus, total, target_ac,
+        if critical { "CRITICAL HIT" } else if hit { "HIT" } else { "MISS" },
+        reaction_line,
+        damage_line
+    ))
+}
+
+// Rolls a death saving throw for a downed player
+fn resolve_death_save(state: &mut GameState, encounter: &mut Encounter, roller: &mut impl Roller) -> String {
+    let t = theme();
+    let roll = roller.roll(20);
+    let turn = state.turn();
+    state.record_roll("1d20", &[roll], roll as i32, "Death saving throw");
+    let character = &mut state.character;
+    print_fancy_message("Death Saving Throw", "magenta");
+    print_dice_roll("d20", &[roll]);
+
+    let success = roll >= 10;
+    match roll {
+        20 => {
+            character.heal(1, "Natural 20 on a death save", turn);
+            println!("{}", "A natural 20! You regain 1 hit point and rise.".color(t.good).bold());
+        }
+        1 => character.death_save_failures = (character.death_save_failures + 2).min(3),
+        r if r >= 10 => character.death_save_successes += 1,
+        _ => character.death_save_failures += 1,
+    }
+    character.update_status();
+    println!("Successes: {} | Failures: {}",
+             character.death_save_successes.to_string().color(t.good),
+             character.death_save_failures.to_string().color(t.bad));
+
+    encounter.log.push(CombatEvent::DeathSave { roll, success });
+
+    format!(
+        "COMBAT - {} is unconscious and rolls a death saving throw: {} ({}). Successes: {}, failures: {}.{}
+
+        As the DM, briefly narrate this moment between life and death.",
+        character.name, roll, if success { "success" } else { "failure" },
+        character.death_save_successes, character.death_save_failures,
+        if character.is_dead() { " They have died." } else if character.is_stable() { " They are now stable." } else { "" }
+    )
+}
+
+fn pin_weapon(state: &mut GameState, weapons: &[&'static Weapon]) -> Result<(), Box<dyn Error>> {
+    let names: Vec<&str> = weapons.iter().map(|w| w.name).collect();
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Weapon to pin or unpin")
+        .default(0)
+        .items(&names)
+        .interact()?;
+    let pinned = state.character.toggle_favorite(FavoriteAction::WeaponAttack(names[index].to_string()));
+    print_fancy_message(&format!("{} {} favorites", names[index], if pinned { "pinned to" } else { "removed from" }), "green");
+    Ok(())
+}
+
+// Resolves one of the player's weapon attacks against a chosen enemy
+// Off-hand attacks (a bonus action) don't add a positive ability modifier to damage;
+// `favorite` names a pinned weapon to attack with directly instead of asking;
+// `target` fixes the enemy for an opportunity attack, which only melee weapons can make
+fn resolve_player_attack(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    off_hand: bool,
+    roller: &mut impl Roller,
+    favorite: Option<&str>,
+    target: Option<usize>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let weapons: Vec<&'static Weapon> = state.character.weapons()
+        .into_iter()
+        .filter(|w| target.is_none() || !w.ranged)
+        .collect();
+    let weapon = match favorite.and_then(|name| weapons.iter().find(|w| w.name == name)) {
+        Some(weapon) => Some(*weapon),
+        None => {
+            let character = &state.character;
+            let mut weapon_names: Vec<String> = weapons
+                .iter()
+                .map(|w| format!("{} ({} to hit, {})", w.name, crate::format_modifier(character.attack_bonus(w)), w.damage))
+                .collect();
+            weapon_names.push("Unarmed strike".to_string());
+            if !weapons.is_empty() {
+                weapon_names.push("Pin / unpin a weapon in favorites...".to_string());
+            }
+
+            let weapon_index = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Attack with")
+                .default(0)
+                .items(&weapon_names)
+                .interact()?;
+            if weapon_index == weapons.len() + 1 {
+                pin_weapon(state, &weapons)?;
+                return Ok(None);
+            }
+            weapons.get(weapon_index).copied()
+        }
+    };
+
+    // An empty quiver blocks the shot, though the weapon can still be swung
+    let weapon = match weapon {
+        Some(w) if state.character.ammunition_left(w) == Some(0) => {
+            let kind = crate::ammunition_kind(w).unwrap_or("ammunition");
+            print_fancy_message(&format!("Your {} is out of {}.", w.name, kind), "red");
+            let improvise = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Use it as an improvised melee weapon instead?")
+                .default(false)
+                .interact()?;
+            if !improvise {
+                return Ok(None);
+            }
+            Some(&crate::IMPROVISED_WEAPON)
+        }
+        other => other,
+    };
+
+    let target_index = match target {
+        Some(index) => index,
+        None => {
+            // Melee only reaches engaged enemies
+            let reach_all = weapon.is_some_and(|w| w.ranged);
+            let targets: Vec<usize> = encounter
+                .living_enemies()
+                .into_iter()
+                .filter(|&i| reach_all || encounter.combatants[i].zone == Zone::Engaged)
+                .collect();
+            if targets.is_empty() {
+                print_fancy_message("No enemies in reach. Move to engage them or use a ranged weapon.", "yellow");
+                return Ok(None);
+            }
+            let target_names: Vec<String> = targets
+                .iter()
+                .map(|&i| {
+                    let c = &encounter.combatants[i];
+                    format!("{}, {}", target_label(c, encounter.hide_enemy_hp), c.zone.name())
+                })
+                .collect();
+            let target_choice = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Target")
+                .default(0)
+                .items(&target_names)
+                .interact()?;
+            targets[target_choice]
+        }
+    };
+
+    spend_ammunition(state, weapon);
+    let mode = choose_roll_mode()?;
+    Ok(Some(roll_player_attack(state, encounter, weapon, off_hand, mode, target_index, roller)))
+}
+
+fn spend_ammunition(state: &mut GameState, weapon: Option<&Weapon>) {
+    if let Some(w) = weapon
+        && let Some(left) = state.character.spend_ammunition(w)
+        && left <= crate::LOW_AMMO_WARNING
+    {
+        let kind = crate::ammunition_kind(w).unwrap_or("ammunition");
+        print_fancy_message(&format!("Only {} {} left for your {}", left, kind, w.name), "yellow");
+    }
+}
+
+// Rolls the player's attack against a chosen target and applies the damage; None for the weapon is an unarmed strike
+fn roll_player_attack(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    weapon: Option<&Weapon>,
+    off_hand: bool,
+    mode: RollMode,
+    target_index: usize,
+    roller: &mut impl Roller,
+) -> String {
+    let t = theme();
+    let mode = match weapon {
+        Some(w) if w.ranged => crate::with_environment(mode, state.environment.ranged_attack_penalty(), "ranged attacks"),
+        _ => mode,
+    };
+    let character = &state.character;
+    let (attack_name, attack_bonus, damage_mod) = match weapon {
+        Some(w) => (w.name.to_string(), character.attack_bonus(w), character.weapon_ability_modifier(w)),
+        None => {
+            let str_mod = character.effective_modifier(Ability::Strength);
+            ("Unarmed strike".to_string(), str_mod + crate::proficiency_bonus(character.level), str_mod)
+        }
+    };
+    let attack_parts = match weapon {
+        Some(w) => character.attack_bonus_parts(w),
+        None => vec![
+            ("for your STR".to_string(), damage_mod),
+            ("proficiency (everyone is proficient with unarmed strikes)".to_string(), crate::proficiency_bonus(character.level)),
+        ],
+    };
+    let (attack_name, damage_mod) = if off_hand {
+        (format!("off-hand {}", attack_name), damage_mod.min(0))
+    } else {
+        (attack_name, damage_mod)
+    };
+
+    // With advantage a 20 on either die is kept, so it crits; with disadvantage both must be 20
+    let (d20s, roll) = roll_d20(roller, mode);
+    let total = roll as i32 + attack_bonus;
+    let target_ac = encounter.combatants[target_index].armor_class;
+    let critical = roll == 20;
+    let hit = critical || (roll != 1 && total >= target_ac as i32);
+    state.record_roll(&crate::d20_expr(attack_bonus, mode), &d20s, total,
+                      &format!("{} attack vs {}", attack_name, encounter.combatants[target_index].name));
+
+    print_fancy_message(&format!("{} Attack", attack_name), "yellow");
+    print_d20_roll(&d20s, roll, mode);
+    println!("{} vs AC {} — {}", total.to_string().color(t.value).bold(), target_ac,
+             if hit { "HIT!".color(t.good).bold() } else { "MISS".color(t.bad).bold() });
+    let d20 = crate::D20Roll { dice: d20s.clone(), kept: roll, mode, bonus: None };
+    crate::print_roll_explanation(&d20, &format!("{} attack", attack_name), &attack_parts, total);
+
+    // Damage is only rolled once the hit against this target's AC is settled
+    let mut damage = 0;
+    if hit {
+        let mut damage_expr = match weapon {
+            Some(w) => DiceExpr::parse(w.damage).unwrap_or(DiceExpr { count: 0, sides: 0, modifier: 1 }),
+            None => DiceExpr { count: 0, sides: 0, modifier: 1 },
+        };
+        if critical {
+            let doubled = damage_expr.critical();
+            println!("{} {} → {}", "CRIT!".color(t.good).bold(), damage_expr, doubled);
+            damage_expr = doubled;
+        }
+        let (dice, rolled) = damage_expr.roll(roller);
+        damage = (rolled + damage_mod).max(0) as u32;
+        print_dice_roll(&format!("{}{}", damage_expr, crate::format_modifier(damage_mod)), &dice);
+        state.record_roll(&format!("{}{}", damage_expr, crate::format_modifier(damage_mod)), &dice, damage as i32,
+                          &format!("{} damage", attack_name));
+
+        let hide_hp = encounter.hide_enemy_hp;
+        let target = &mut encounter.combatants[target_index];
+        target.hit_points = target.hit_points.saturating_sub(damage);
+        let health = if hide_hp {
+            health_label(target.hit_points, target.max_hit_points).to_string()
+        } else {
+            format!("{}/{} HP", target.hit_points, target.max_hit_points)
+        };
+        println!("{} takes {} damage ({})", target.name.color(t.bad), damage.to_string().color(t.value).bold(), health);
+    }
+
+    let target = encounter.combatants[target_index].clone();
+    encounter.log.push(CombatEvent::Attack {
+        attacker: state.character.name.clone(),
+        target: target.name.clone(),
+        roll,
+        total,
+        target_ac,
+        hit,
+        critical,
+        damage,
+    });
+    if target.is_defeated() {
+        encounter.log.push(CombatEvent::Defeated { name: target.name.clone() });
+        print_fancy_message(&format!("{} is defeated!", target.name), "green");
+    }
+
+    format!(
+        "COMBAT - player turn. {} attacks {} with {}{}.
+        Attack roll: {} + {} = {} vs AC {} - {}.{}
+
+        As the DM, narrate this attack in two or three vivid sentences. Do not change the mechanical outcome.",
+        state.character.name, target.name, attack_name,
+        if mode == RollMode::Normal { String::new() } else { format!(" ({})", describe_d20(&d20s, roll, mode)) },
+        roll, attack_bonus, total, target_ac,
+        if critical { "CRITICAL HIT" } else if hit { "HIT" } else { "MISS" },
+        if hit {
+            format!(" Damage: {}. {} has {}/{} HP left{}.", damage, target.name, target.hit_points, target.max_hit_points,
+                    if target.is_defeated() { " and is defeated" } else { "" })
+        } else {
+            String::new()
+        }
+    )
+}
+
+enum MoveOutcome {
+    Cancelled,
+    Moved,
+    // Falling back from an engaged enemy drew an opportunity attack; holds the narration prompt
+    Provoked(String),
+}
+
+// Moves the player one zone toward or away from an enemy
+fn resolve_move(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    disengaged: bool,
+    roller: &mut impl Roller,
+) -> Result<MoveOutcome, Box<dyn Error>> {
+    let t = theme();
+    let enemies = encounter.living_enemies();
+    let mut names: Vec<String> = enemies
+        .iter()
+        .map(|&i| format!("{} ({})", encounter.combatants[i].name, encounter.combatants[i].zone.name()))
+        .collect();
+    names.push("Back".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Move relative to")
+        .default(0)
+        .items(&names)
+        .interact()?;
+    let Some(&index) = enemies.get(choice) else {
+        return Ok(MoveOutcome::Cancelled);
+    };
+
+    let zone = encounter.combatants[index].zone;
+    let mut directions = Vec::new();
+    if zone != Zone::Engaged {
+        directions.push("Approach");
+    }
+    if zone != Zone::Far {
+        directions.push("Fall back");
+    }
+    directions.push("Back");
+    let direction = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Direction")
+        .default(0)
+        .items(&directions)
+        .interact()?;
+
+    let enemy = encounter.combatants[index].clone();
+    let new_zone = match directions[direction] {
+        "Approach" => zone.closer(),
+        "Fall back" => zone.farther(),
+        _ => return Ok(MoveOutcome::Cancelled),
+    };
+    encounter.combatants[index].zone = new_zone;
+    let movement = format!("{} moves to {} with {}.", state.character.name, new_zone.name(), enemy.name);
+    println!("{}", movement.color(t.info));
+    encounter.log.push(CombatEvent::Note(movement.clone()));
+
+    // Opportunity attacks spend the enemy's reaction
+    if zone == Zone::Engaged && !disengaged && !enemy.economy.reaction_used
+        && let CombatantKind::Monster(stat_block) = &enemy.kind
+        && let Some(melee) = stat_block.attacks.iter().find(|a| !is_ranged(a))
+    {
+        encounter.combatants[index].economy.reaction_used = true;
+        print_fancy_message(&format!("{} gets an opportunity attack!", enemy.name), "red");
+        let lead_in = format!("{} As {} pulls away, {} takes an opportunity attack.", movement, state.character.name, enemy.name);
+        return Ok(MoveOutcome::Provoked(resolve_monster_attack(state, encounter, &enemy, melee, &lead_in, false, roller)?));
+    }
+    Ok(MoveOutcome::Moved)
+}
+
+const SAVE_ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+
+// A damage spell as the player described it, kept so it can be pinned to favorites
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DamageSpell {
+    pub name: String,
+    pub damage: DiceExpr,
+    pub damage_type: String,
+    // Index into SAVE_ABILITIES and the DC, if the spell allows a save
+    pub save: Option<(usize, u32)>,
+    // Hits on a spell attack roll against AC instead
+    #[serde(default)]
+    pub attack: bool,
+}
+
+impl fmt::Display for DamageSpell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} {}", self.name, self.damage, self.damage_type)?;
+        if let Some((ability, dc)) = self.save {
+            write!(f, ", DC {} {}", dc, SAVE_ABILITIES[ability])?;
+        }
+        if self.attack {
+            write!(f, ", spell attack")?;
+        }
+        write!(f, ")")
+    }
+}
+
+// The save DC defaults to the caster's own
+fn prompt_damage_spell(default_dc: u32) -> Result<DamageSpell, Box<dyn Error>> {
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Spell name")
+        .interact_text()?;
+    let damage: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Damage dice (e.g. 8d6)")
+        .validate_with(|input: &String| -> Result<(), &str> {
+            DiceExpr::parse(input).map(|_| ()).ok_or("Use dice notation like 8d6 or 3d4+3")
+        })
+        .interact_text()?;
+    let damage = DiceExpr::parse(&damage).unwrap_or(DiceExpr { count: 1, sides: 6, modifier: 0 });
+    let damage_type: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Damage type")
+        .default("force".to_string())
+        .interact_text()?;
+
+    let mut save_options = vec!["No saving throw".to_string(), "Spell attack roll".to_string()];
+    save_options.extend(SAVE_ABILITIES.iter().map(|a| format!("{} save", a)));
+    let save_choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Saving throw or attack roll")
+        .default(0)
+        .items(&save_options)
+        .interact()?;
+    let save = if save_choice < 2 {
+        None
+    } else {
+        let dc: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Save DC")
+            .default(default_dc)
+            .interact_text()?;
+        Some((save_choice - 2, dc))
+    };
+
+    Ok(DamageSpell {
+        name: name.trim().to_string(),
+        damage,
+        damage_type: damage_type.trim().to_string(),
+        save,
+        attack: save_choice == 1,
+    })
+}
+
+// One target's spell attack roll, returning (hit, critical, damage taken); a natural 20 rolls the damage dice again
+fn roll_spell_attack(
+    state: &mut GameState,
+    target: &Combatant,
+    spell: &str,
+    bonus: i32,
+    damage: DiceExpr,
+    total: u32,
+    roller: &mut impl Roller,
+) -> (bool, bool, u32) {
+    let t = theme();
+    let roll = roller.roll(20);
+    state.record_roll(&format!("1d20{}", crate::format_modifier(bonus)), &[roll], roll as i32 + bonus,
+                      &format!("{} spell attack vs {}", spell, target.name));
+    let critical = roll == 20;
+    let hit = critical || (roll != 1 && roll as i32 + bonus >= target.armor_class as i32);
+    println!("{}: spell attack {} ({} on the die) vs AC {} — {}", target.name, roll as i32 + bonus, roll,
+             target.armor_class, if hit { "HIT".color(t.good).bold() } else { "MISS".color(t.bad).bold() });
+    if !hit {
+        return (false, false, 0);
+    }
+    let mut taken = total;
+    if critical {
+        let extra_dice = DiceExpr { modifier: 0, ..damage };
+        let (dice, extra) = extra_dice.roll(roller);
+        println!("{} {} → {}", "CRIT!".color(t.good).bold(), damage, damage.critical());
+        print_dice_roll(&extra_dice.to_string(), &dice);
+        state.record_roll(&extra_dice.to_string(), &dice, extra, &format!("{} critical damage vs {}", spell, target.name));
+        taken += extra.max(0) as u32;
+    }
+    (true, critical, taken)
+}
+
+// Rolls a damage spell, a favorite or one described on the spot, with a save for half or an attack roll per target
+pub fn resolve_damage_spell(
+    state: &mut GameState,
+    encounter: Option<&mut Encounter>,
+    roller: &mut impl Roller,
+    favorite: Option<&DamageSpell>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let t = theme();
+    let mut class = None;
+    let spell = match favorite {
+        Some(spell) => spell.clone(),
+        None => {
+            class = crate::choose_casting_class(&state.character)?;
+            let default_dc = class.as_deref().and_then(|c| state.character.spell_save_dc(c)).unwrap_or(13);
+            let spell = prompt_damage_spell(default_dc)?;
+            let favorite = FavoriteAction::Spell(spell.clone());
+            if !state.character.favorites.contains(&favorite)
+                && Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Pin {} to favorites?", spell.name))
+                    .default(false)
+                    .interact()?
+                && state.character.toggle_favorite(favorite)
+            {
+                print_fancy_message(&format!("{} pinned to favorites", spell.name), "green");
+            }
+            spell
+        }
+    };
+    let DamageSpell { name: spell, damage, damage_type, save, attack } = spell;
+    // Warlock spells above cantrip level burn a pact slot, always cast at the slot's level
+    let warlock = match class.as_deref() {
+        Some(class) => class == "Warlock",
+        None => state.character.casting_classes().first() == Some(&"Warlock"),
+    };
+    let spell = match state.character.pact_slots() {
+        Some((_, slot_level)) if warlock => {
+            let left = state.character.pact_slots_left();
+            let options = [
+                "Cantrip (no slot)".to_string(),
+                format!("Pact slot, level {} ({} left)", slot_level, left),
+            ];
+            let use_slot = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Cast as")
+                .default(if left > 0 { 1 } else { 0 })
+                .items(&options)
+                .interact()? == 1;
+            if !use_slot {
+                spell
+            } else if let Some(level) = state.character.spend_pact_slot() {
+                format!("{} (level {} pact slot)", spell, level)
+            } else {
+                print_fancy_message("No pact slots left; take a short rest to recover them.", "red");
+                return Ok(None);
+            }
+        }
+        _ => spell,
+    };
+    // Without a casting class (a scroll or wand, say) only proficiency applies
+    let attack_bonus = if attack {
+        let class = match class {
+            Some(class) => Some(class),
+            None => crate::choose_casting_class(&state.character)?,
+        };
+        Some(class.as_deref()
+            .and_then(|c| state.character.spell_attack_bonus(c))
+            .unwrap_or(crate::proficiency_bonus(state.character.level)))
+    } else {
+        None
+    };
+
+    // Expected damage, so the caster can weigh the spell before rolling
+    match save {
+        Some((ability, dc)) => println!(
+            "Average damage {:.1} ({:.1} on a save); a +0 {} save succeeds {:.0}% of the time against DC {}",
+            damage.average(), (damage.average() / 2.0).floor(), SAVE_ABILITIES[ability],
+            save_success_chance(dc, 0) * 100.0, dc
+        ),
+        None => println!("Average damage {:.1}", damage.average()),
+    }
+
+    let (dice, total) = damage.roll(roller);
+    let total = total.max(0) as u32;
+    state.record_roll(&damage.to_string(), &dice, total as i32, &format!("{} damage", spell));
+    print_fancy_message(&spell, "magenta");
+    print_dice_roll(&damage.to_string(), &dice);
+    println!("{} {} damage", total.to_string().color(t.value).bold(), damage_type);
+
+    let save_text = match (save, attack_bonus) {
+        (Some((ability, dc)), _) => format!("DC {} {} saving throw for half damage", dc, SAVE_ABILITIES[ability]),
+        (None, Some(bonus)) => format!("a {} spell attack roll against each target's AC", crate::format_modifier(bonus)),
+        (None, None) => "no saving throw".to_string(),
+    };
+
+    let Some(encounter) = encounter else {
+        return Ok(Some(format!(
+            "The player ({} the {} {}) casts {}, dealing {} {} damage ({} [{}]) with {}.
+
+            As the DM, decide which creatures are affected, resolve any saving throws (half damage on a success) or attack rolls, and narrate the result. Continue the scene afterwards.",
+            state.character.name, state.character.race, state.character.class,
+            spell, total, damage_type, damage,
+            dice.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(", "),
+            save_text
+        )));
+    };
+
+    let targets = encounter.living_enemies();
+    let chosen = choose_spell_targets(encounter, &targets)?;
+
+    let hide_hp = encounter.hide_enemy_hp;
+    let mut results = Vec::new();
+    for choice in chosen {
+        let target = &mut encounter.combatants[targets[choice]];
+        let taken = match (save, &target.kind) {
+            (None, _) if let Some(bonus) = attack_bonus => {
+                let (hit, critical, taken) = roll_spell_attack(state, target, &spell, bonus, damage, total, roller);
+                results.push(match (hit, critical) {
+                    (true, true) => format!("{} takes a critical hit for {}", target.name, taken),
+                    (true, false) => format!("{} is hit for {}", target.name, taken),
+                    _ => format!("the spell misses {}", target.name),
+                });
+                taken
+            }
+            (Some((ability, dc)), CombatantKind::Monster(stat_block)) => {
+                let modifier = ability_modifier(stat_block.abilities[ability]);
+                let roll = roller.roll(20);
+                state.record_roll(&format!("1d20{}", crate::format_modifier(modifier)), &[roll], roll as i32 + modifier,
+                                  &format!("{} {} save vs {}", target.name, SAVE_ABILITIES[ability], spell));
+                let saved = roll as i32 + modifier >= dc as i32;
+                let taken = if saved { total / 2 } else { total };
+                println!("{}: {} save {} vs DC {} — {} ({} damage)",
+                         target.name, SAVE_ABILITIES[ability], roll as i32 + modifier, dc,
+                         if saved { "SAVED".color(t.warning).bold() } else { "FAILED".color(t.bad).bold() },
+                         taken);
+                results.push(format!("{} {} its save and takes {}", target.name, if saved { "makes" } else { "fails" }, taken));
+                taken
+            }
+            _ => {
+                println!("{}: {} damage", target.name, total);
+                results.push(format!("{} takes {}", target.name, total));
+                total
+            }
+        };
+        target.hit_points = target.hit_points.saturating_sub(taken);
+        if taken > 0 {
+            println!("  {} is now {}", target.name, if hide_hp {
+                health_label(target.hit_points, target.max_hit_points).to_string()
+            } else {
+                format!("{}/{} HP", target.hit_points, target.max_hit_points)
+            });
+        }
+        encounter.log.push(CombatEvent::Note(format!("{}'s {} deals {} damage to {}", state.character.name, spell, taken, target.name)));
+        if target.is_defeated() {
+            let name = target.name.clone();
+            print_fancy_message(&format!("{} is defeated!", name), "green");
+            encounter.log.push(CombatEvent::Defeated { name: name.clone() });
+            results.push(format!("{} is defeated", name));
+        }
+    }
+
+    Ok(Some(format!(
+        "COMBAT - player turn. {} casts {} ({} {} damage, {}). Results: {}.
+
+        As the DM, narrate the spell's effect in two or three vivid sentences. Do not change the mechanical outcome.",
+        state.character.name, spell, total, damage_type, save_text,
+        if results.is_empty() { "no targets were caught".to_string() } else { results.join("; ") }
+    )))
+}
+
+// Who a spell catches, as indices into `targets`: hand-picked enemies, or every enemy at one
+// distance for an area like Fireball
+fn choose_spell_targets(encounter: &Encounter, targets: &[usize]) -> Result<Vec<usize>, Box<dyn Error>> {
+    let in_zone = |zone: Zone| -> Vec<usize> {
+        (0..targets.len()).filter(|&t| encounter.combatants[targets[t]].zone == zone).collect()
+    };
+    let zones: Vec<Zone> = Zone::ALL.into_iter().filter(|&z| !in_zone(z).is_empty()).collect();
+    let mut options = vec!["Pick targets".to_string(), format!("Every enemy ({})", targets.len())];
+    options.extend(zones.iter().map(|&z| format!("Every enemy {} ({})", z.name(), in_zone(z).len())));
+    let mode = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Who does the spell catch?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+
+    Ok(match mode {
+        0 => {
+            let target_names: Vec<String> = targets
+                .iter()
+                .map(|&i| target_label(&encounter.combatants[i], encounter.hide_enemy_hp))
+                .collect();
+            MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("Targets (space to select, enter to confirm)")
+                .items(&target_names)
+                .interact()?
+        }
+        1 => (0..targets.len()).collect(),
+        n => in_zone(zones[n - 2]),
+    })
+}
+
+// Round-based effects count down at the start of the player's turn
+fn start_player_turn(state: &mut GameState, encounter: &mut Encounter) {
+    let t = theme();
+    let turn = state.turn();
+    for expired in state.character.tick_ability_modifiers(turn) {
+        let note = format!("{} ({} {}) wears off", expired.source,
+                           expired.ability.abbreviation(), crate::format_modifier(expired.delta));
+        println!("{}", note.color(t.accent));
+        encounter.log.push(CombatEvent::Note(note));
+    }
+    for expired in state.character.tick_active_effects(1) {
+        let note = format!("{} ends in round {}", expired.name, encounter.round);
+        println!("{}", note.color(t.accent));
+        encounter.log.push(CombatEvent::Note(note));
+    }
+}
+
+// Quick-resolve gives up and hands the fight back after this many rounds
+const QUICK_RESOLVE_MAX_ROUNDS: u32 = 30;
+
+// Plays the rest of the fight without prompts: the player and companions attack, enemies take their
+// usual turns. Returns None when the player takes over again, which happens just before one of their
+// turns once the fight turns close (they're at half HP or less) or it drags on too long.
+fn quick_resolve(
+    state: &mut GameState,
+    encounter: &mut Encounter,
+    roller: &mut impl Roller,
+) -> Result<Option<CombatOutcome>, Box<dyn Error>> {
+    let t = theme();
+    print_fancy_message("Quick-resolving the fight...", "cyan");
+    let mut offered_control = false;
+    let mut first = true;
+    loop {
+        let current = encounter.combatants[encounter.turn].clone();
+        if !skip_surprised_turn(encounter) {
+            match current.kind {
+                CombatantKind::Player => {
+                    if !first {
+                        start_player_turn(state, encounter);
+                    }
+                    auto_player_turn(state, encounter, roller);
+                }
+                CombatantKind::Companion => auto_companion_turn(encounter, roller),
+                CombatantKind::Monster(_) => {
+                    resolve_monster_turn(state, encounter, true, roller)?;
+                }
+            }
+        }
+        first = false;
+
+        if encounter.is_over() {
+            print_fancy_message("Victory! All enemies are defeated.", "green");
+            return Ok(Some(CombatOutcome::Victory));
+        }
+        if state.character.is_dead() {
+            print_fancy_message(&format!("{} has fallen...", state.character.name), "red");
+            return Ok(Some(CombatOutcome::Fallen));
+        }
+
+        let next = encounter.next_turn();
+        if encounter.combatants[next].is_player() {
+            let close = state.character.hit_points * 2 <= state.character.effective_max_hp();
+            if encounter.round >= QUICK_RESOLVE_MAX_ROUNDS {
+                print_fancy_message(&format!("Still undecided after {} rounds; back to you.", encounter.round), "yellow");
+                return Ok(None);
+            }
+            if close && !offered_control {
+                offered_control = true;
+                println!("{}", format!("{} is at {}/{} HP.", state.character.name, state.character.hit_points,
+                                       state.character.effective_max_hp()).color(t.warning));
+                if Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("The fight is getting close. Take over your turns again?")
+                    .default(true)
+                    .interact()?
+                {
+                    return Ok(None);
+                }
+            }
+        }
+
+        encounter.advance();
+        encounter.combatants[encounter.turn].economy = TurnEconomy::fresh();
+        for trigger in encounter.take_due_triggers() {
+            let note = format!("Round {}: {}", encounter.round, trigger.description);
+            print_fancy_message(&note, "magenta");
+            encounter.log.push(CombatEvent::Note(note));
+        }
+    }
+}
+
+// The player on autopilot: their hardest-hitting weapon against the most wounded enemy in reach,
+// closing in first when only melee will do
+fn auto_player_turn(state: &mut GameState, encounter: &mut Encounter, roller: &mut impl Roller) {
+    let t = theme();
+    if state.character.hit_points == 0 {
+        if !state.character.is_stable() {
+            resolve_death_save(state, encounter, roller);
+        }
+        return;
+    }
+    let me = encounter.turn;
+    if encounter.combatants[me].economy.action_used {
+        return;
+    }
+
+    let character = &state.character;
+    let average = |w: &Weapon| {
+        DiceExpr::parse(w.damage).map_or(1.0, |d| d.average()) + character.weapon_ability_modifier(w) as f64
+    };
+    let weapon = character
+        .weapons()
+        .into_iter()
+        .filter(|w| character.ammunition_left(w) != Some(0))
+        .max_by(|a, b| average(a).total_cmp(&average(b)));
+    let reach_all = weapon.is_some_and(|w| w.ranged);
+    let enemies = encounter.living_enemies();
+    let in_reach = |encounter: &Encounter| -> Vec<usize> {
+        enemies.iter().copied().filter(|&i| reach_all || encounter.combatants[i].zone == Zone::Engaged).collect()
+    };
+
+    if in_reach(encounter).is_empty() {
+        let zone_rank = |z: Zone| Zone::ALL.iter().position(|&other| other == z);
+        let Some(&nearest) = enemies.iter().min_by_key(|&&i| zone_rank(encounter.combatants[i].zone)) else {
+            return;
+        };
+        let zone = encounter.combatants[nearest].zone.closer();
+        encounter.combatants[nearest].zone = zone;
+        let movement = format!("{} moves to {} with {}.", state.character.name, zone.name(), encounter.combatants[nearest].name);
+        println!("{}", movement.color(t.info));
+        encounter.log.push(CombatEvent::Note(movement));
+    }
+    let Some(target) = in_reach(encounter).into_iter().min_by_key(|&i| encounter.combatants[i].hit_points) else {
+        return;
+    };
+    spend_ammunition(state, weapon);
+    roll_player_attack(state, encounter, weapon, false, RollMode::Normal, target, roller);
+    encounter.combatants[me].economy.action_used = true;
+}
+
+// Companions only track DEX, so on autopilot they fight with a simple finesse weapon:
+// DEX + 2 to hit for 1d6 + DEX against the most wounded enemy
+fn auto_companion_turn(encounter: &mut Encounter, roller: &mut impl Roller) {
+    let t = theme();
+    let attacker = encounter.combatants[encounter.turn].clone();
+    if attacker.is_defeated() {
+        return;
+    }
+    let Some(target_index) = encounter.living_enemies().into_iter().min_by_key(|&i| encounter.combatants[i].hit_points) else {
+        return;
+    };
+    let dex = ability_modifier(attacker.dexterity);
+    let attack_bonus = dex + 2;
+    let roll = roller.roll(20);
+    let total = roll as i32 + attack_bonus;
+    let target_ac = encounter.combatants[target_index].armor_class;
+    let critical = roll == 20;
+    let hit = critical || (roll != 1 && total >= target_ac as i32);
+    let mut damage = 0;
+    if hit {
+        let expr = DiceExpr { count: if critical { 2 } else { 1 }, sides: 6, modifier: dex };
+        damage = expr.roll(roller).1.max(0) as u32;
+        let target = &mut encounter.combatants[target_index];
+        target.hit_points = target.hit_points.saturating_sub(damage);
+    }
+    let target = encounter.combatants[target_index].clone();
+    println!("{} attacks {}: {} vs AC {} — {}", attacker.name.color(t.info), target.name, total, target_ac,
+             if hit { format!("HIT for {}", damage).color(t.good).bold() } else { "MISS".color(t.bad).bold() });
+    encounter.log.push(CombatEvent::Attack {
+        attacker: attacker.name.clone(),
+        target: target.name.clone(),
+        roll,
+        total,
+        target_ac,
+        hit,
+        critical,
+        damage,
+    });
+    if target.is_defeated() {
+        encounter.log.push(CombatEvent::Defeated { name: target.name.clone() });
+        print_fancy_message(&format!("{} is defeated!", target.name), "green");
+    }
+}
+
+// Runs the encounter turn by turn until one side is defeated or the player flees
+pub async fn run_combat(dm: &impl Chat, state: &mut GameState, roller: &mut impl Roller) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    let Some(mut encounter) = state.encounter.clone() else {
+        return Ok(());
+    };
+    // Fights saved before rounds were counted pick up from round 1
+    encounter.round = encounter.round.max(1);
+
+    let outcome = 'combat: loop {
+        if encounter.is_over() {
+            print_fancy_message("Victory! All enemies are defeated.", "green");
+            break CombatOutcome::Victory;
+        }
+        if state.character.is_dead() {
+            print_fancy_message(&format!("{} has fallen...", state.character.name), "red");
+            break CombatOutcome::Fallen;
+        }
+
+        for trigger in encounter.take_due_triggers() {
+            let note = format!("Round {}: {}", encounter.round, trigger.description);
+            print_fancy_message(&note, "magenta");
+            encounter.log.push(CombatEvent::Note(note));
+            narrate(dm, state, &encounter, format!(
+                "COMBAT - round {} begins. A scheduled event happens now: {}
+
+                As the DM, narrate this event in a short paragraph and describe how it changes the fight.",
+                encounter.round, trigger.description
+            )).await?;
+        }
+
+        print_initiative_order(&encounter, state);
+        let turn_index = encounter.turn;
+        encounter.combatants[turn_index].economy = TurnEconomy::fresh();
+        let current = encounter.combatants[turn_index].clone();
+
+        if skip_surprised_turn(&mut encounter) {
+            // Nothing to resolve; the surprise wears off as their turn passes
+        } else if current.is_player() {
+            start_player_turn(state, &mut encounter);
+
+            if state.character.hit_points == 0 {
+                if !state.character.is_stable() {
+                    let prompt = resolve_death_save(state, &mut encounter, roller);
+                    narrate(dm, state, &encounter, prompt).await?;
+                }
+            } else {
+                let me = encounter.turn;
+                while !encounter.combatants[me].economy.is_spent() && state.character.hit_points > 0 && !encounter.is_over() {
+                    let economy = encounter.combatants[me].economy.clone();
+                    println!("{}", economy.summary().dimmed());
+
+                    // Pinned weapons and spells come first while the action is still available
+                    let favorites: Vec<FavoriteAction> = if economy.action_used {
+                        Vec::new()
+                    } else {
+                        state.character.favorites.iter().filter(|f| !matches!(f, FavoriteAction::SkillCheck(_))).cloned().collect()
+                    };
+                    let favorite_labels: Vec<String> = favorites.iter().map(|f| format!("★ {}", f)).collect();
+                    let mut options: Vec<&str> = favorite_labels.iter().map(String::as_str).collect();
+                    if economy.movement_remaining > 0 {
+                        options.push("Move (approach / fall back)");
+                    }
+                    if !economy.action_used {
+                        options.extend(["Attack with a weapon", "Cast damage spell", "Dash (move again)",
+                                        "Disengage (fall back safely)", "Other action (describe)"]);
+                    }
+                    if !economy.bonus_action_used {
+                        options.extend(["Off-hand attack (bonus action)", "Bonus action (describe)"]);
+                    }
+                    options.extend(["Quick-resolve the fight", "Schedule a round event..."]);
+                    if crate::config().debug {
+                        options.push("DM tool: adjust a combatant...");
+                    }
+                    options.extend(["End turn", "Flee / end combat"]);
+                    let choice = Select::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Your turn")
+                        .default(0)
+                        .items(&options)
+                        .interact()?;
+
+                    if let Some(favorite) = favorites.get(choice) {
+                        let prompt = match favorite {
+                            FavoriteAction::WeaponAttack(name) => resolve_player_attack(state, &mut encounter, false, roller, Some(name), None)?,
+                            FavoriteAction::Spell(spell) => resolve_damage_spell(state, Some(&mut encounter), roller, Some(spell))?,
+                            FavoriteAction::SkillCheck(_) => None,
+                        };
+                        if let Some(prompt) = prompt {
+                            encounter.combatants[me].economy.action_used = true;
+                            encounter.combatants[me].economy.record("action", &favorite.to_string());
+                            narrate(dm, state, &encounter, prompt).await?;
+                        }
+                        continue;
+                    }
+
+                    match options[choice] {
+                        "Move (approach / fall back)" => match resolve_move(state, &mut encounter, economy.disengaged, roller)? {
+                            MoveOutcome::Cancelled => {}
+                            MoveOutcome::Moved => {
+                                encounter.combatants[me].economy.movement_remaining -= 1;
+                                encounter.combatants[me].economy.record("movement", "moved one zone");
+                            }
+                            MoveOutcome::Provoked(prompt) => {
+                                encounter.combatants[me].economy.movement_remaining -= 1;
+                                encounter.combatants[me].economy.record("movement", "moved one zone, provoking an opportunity attack");
+                                narrate(dm, state, &encounter, prompt).await?;
+                            }
+                        },
+                        "Attack with a weapon" => {
+                            if let Some(prompt) = resolve_player_attack(state, &mut encounter, false, roller, None, None)? {
+                                encounter.combatants[me].economy.action_used = true;
+                                encounter.combatants[me].economy.record("action", "one weapon attack");
+                                narrate(dm, state, &encounter, prompt).await?;
+                            }
+                        },
+                        "Off-hand attack (bonus action)" => {
+                            if let Some(prompt) = resolve_player_attack(state, &mut encounter, true, roller, None, None)? {
+                                encounter.combatants[me].economy.bonus_action_used = true;
+                                encounter.combatants[me].economy.record("bonus action", "off-hand attack");
+                                narrate(dm, state, &encounter, prompt).await?;
+                            }
+                        },
+                        "Cast damage spell" => {
+                            if let Some(prompt) = resolve_damage_spell(state, Some(&mut encounter), roller, None)? {
+                                encounter.combatants[me].economy.action_used = true;
+                                encounter.combatants[me].economy.record("action", "cast a spell");
+                                narrate(dm, state, &encounter, prompt).await?;
+                            }
+                        },
+                        "Dash (move again)" => {
+                            let economy = &mut encounter.combatants[me].economy;
+                            economy.action_used = true;
+                            economy.movement_remaining += 1;
+                            economy.record("action", "Dash");
+                            encounter.log.push(CombatEvent::Note(format!("{} dashes", state.character.name)));
+                            println!("{}", "You dash, gaining an extra move this turn.".color(t.info));
+                        },
+                        "Disengage (fall back safely)" => {
+                            let economy = &mut encounter.combatants[me].economy;
+                            economy.action_used = true;
+                            economy.disengaged = true;
+                            economy.record("action", "Disengage");
+                            encounter.log.push(CombatEvent::Note(format!("{} disengages", state.character.name)));
+                            println!("{}", "You disengage; moving away won't provoke opportunity attacks this turn.".color(t.info));
+                        },
+                        "Other action (describe)" | "Bonus action (describe)" => {
+                            let bonus = options[choice] == "Bonus action (describe)";
+                            let action: String = Input::with_theme(&ColorfulTheme::default())
+                                .with_prompt(if bonus { "What bonus action do you take? (e.g. Healing Word, Cunning Action)" } else { "What do you do this turn?" })
+                                .interact_text()?;
+                            if bonus {
+                                encounter.combatants[me].economy.bonus_action_used = true;
+                            } else {
+                                encounter.combatants[me].economy.action_used = true;
+                            }
+                            encounter.combatants[me].economy.record(if bonus { "bonus action" } else { "action" }, &action);
+                            encounter.log.push(CombatEvent::Note(format!("{}: {}", state.character.name, action)));
+                            narrate(dm, state, &encounter, format!(
+                                "COMBAT - player turn. {} does the following as {}: {}
+
+                                As the DM, narrate the result of this action within the fight. Keep it to a short paragraph.",
+                                state.character.name, if bonus { "a bonus action" } else { "their action" }, action
+                            )).await?;
+                        },
+                        "Quick-resolve the fight" => {
+                            let first_event = encounter.log.len();
+                            if let Some(outcome) = quick_resolve(state, &mut encounter, roller)? {
+                                break 'combat outcome;
+                            }
+                            // Back to interactive play; the fast-forwarded turns are narrated as one beat
+                            let summary: Vec<String> = encounter.log[first_event..].iter().map(|e| e.brief()).collect();
+                            narrate(dm, state, &encounter, format!(
+                                "COMBAT - the fight was fast-forwarded for a few turns. In order: {}. {} now has {}/{} HP.
+
+                                As the DM, sum up these turns in one short paragraph, then hand the next turn back to the player. Do not change the mechanical outcomes.",
+                                summary.join("; "), state.character.name, state.character.hit_points, state.character.effective_max_hp()
+                            )).await?;
+                            break;
+                        },
+                        "Schedule a round event..." => schedule_round_trigger(&mut encounter)?,
+                        "DM tool: adjust a combatant..." => adjust_combatant(&mut encounter)?,
+                        "End turn" => break,
+                        "Flee / end combat" => {
+                            encounter.log.push(CombatEvent::Note(format!("{} leaves the fight", state.character.name)));
+                            break 'combat CombatOutcome::Fled;
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        } else if matches!(current.kind, CombatantKind::Companion) {
+            // The player directs their companions; an empty answer skips the turn
+            let action: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("What does {} do? (leave empty to hold)", current.name))
+                .allow_empty(true)
+                .interact_text()?;
+            if !action.trim().is_empty() {
+                encounter.log.push(CombatEvent::Note(format!("{}: {}", current.name, action.trim())));
+                narrate(dm, state, &encounter, format!(
+                    "COMBAT - companion turn. {}, {}'s companion, does the following: {}
+
+                    As the DM, narrate the result of this action within the fight. Keep it to a short paragraph.",
+                    current.name, state.character.name, action.trim()
+                )).await?;
+            }
+        } else if crate::config().auto_enemy_turns {
+            // Roll every enemy turn up to the player's next turn, then narrate them as one beat
+            let first_event = encounter.log.len();
+            loop {
+                if !skip_surprised_turn(&mut encounter) {
+                    resolve_monster_turn(state, &mut encounter, true, roller)?;
+                }
+                let next = encounter.next_turn();
+                // Stop at the party or at the end of the round so the start-of-loop checks still run
+                if !encounter.combatants[next].is_enemy() || encounter.is_over() || next <= encounter.turn {
+                    break;
+                }
+                encounter.advance();
+                encounter.combatants[next].economy = TurnEconomy::fresh();
+            }
+
+            let summary: Vec<String> = encounter.log[first_event..].iter().map(|e| e.brief()).collect();
+            if !summary.is_empty() {
+                println!("\n{}", summary.join(", ").color(t.bad));
+                narrate(dm, state, &encounter, format!(
+                    "COMBAT - enemy turns. In order: {}. {} now has {}/{} HP{}.
+
+                    As the DM, narrate these enemy turns together in one short paragraph. Do not change the mechanical outcomes.",
+                    summary.join("; "), state.character.name, state.character.hit_points, state.character.effective_max_hp(),
+                    if state.character.hit_points == 0 { " and is down and unconscious" } else { "" }
+                )).await?;
+            }
+        } else if let Some(prompt) = resolve_monster_turn(state, &mut encounter, false, roller)? {
+            narrate(dm, state, &encounter, prompt).await?;
+        }
+
+        encounter.advance();
+
+        // Keep the fight in the save so it can be resumed
+        state.encounter = Some(encounter.clone());
+        state.last_saved = chrono::Local::now().to_rfc3339();
+        save_game(state)?;
+    };
+
+    end_encounter(dm, state, &encounter, outcome).await
+}
+
+enum CombatOutcome {
+    Victory,
+    Fallen,
+    Fled,
+}
+
+// Hands a finished fight back to the story: final HP goes back to the party, and the DM gets
+// a summary of survivors and notable moments to narrate the wrap-up from
+async fn end_encounter(
+    dm: &impl Chat,
+    state: &mut GameState,
+    encounter: &Encounter,
+    outcome: CombatOutcome,
+) -> Result<(), Box<dyn Error>> {
+    for combatant in encounter.combatants.iter().filter(|c| matches!(c.kind, CombatantKind::Companion)) {
+        if let Some(companion) = state.character.companions.iter_mut().find(|c| c.name == combatant.name) {
+            companion.hp = combatant.hit_points.min(companion.max_hp);
+        }
+    }
+
+    let mut survivors = vec![format!("{} ({}/{} HP)", state.character.name, state.character.hit_points,
+                                     state.character.effective_max_hp())];
+    survivors.extend(encounter.combatants.iter()
+        .filter(|c| !c.is_player() && !c.is_defeated())
+        .map(|c| format!("{} ({}/{} HP)", c.name, c.hit_points, c.max_hit_points)));
+    let fallen: Vec<&str> = encounter.combatants.iter().filter(|c| c.is_defeated()).map(|c| c.name.as_str()).collect();
+    // Crits, kills, and anything noted along the way make the story; routine hits and misses don't
+    let moments: Vec<String> = encounter.log.iter()
+        .filter(|e| matches!(e, CombatEvent::Attack { critical: true, .. } | CombatEvent::Defeated { .. } | CombatEvent::Note(_)))
+        .map(|e| e.brief())
+        .collect();
+    let moments = &moments[moments.len().saturating_sub(8)..];
+
+    let how = match outcome {
+        CombatOutcome::Victory => format!("{} has defeated every enemy.", state.character.name),
+        CombatOutcome::Fallen => format!("{} has died after failing three death saving throws; narrate their final moments solemnly.", state.character.name),
+        CombatOutcome::Fled => format!("{} breaks away and the fight ends for now; describe how they escape or how it winds down.", state.character.name),
+    };
+    let summary = format!(
+        "COMBAT OVER after {} round{}. {}
+        Still standing: {}.
+        Fallen: {}.
+        Notable moments: {}.
+
+        As the DM, give a short cinematic wrap-up of the fight that reflects these outcomes and the HP left, then prompt the player for what they do next.",
+        encounter.round, if encounter.round == 1 { "" } else { "s" }, how,
+        survivors.join(", "),
+        if fallen.is_empty() { "no one".to_string() } else { fallen.join(", ") },
+        if moments.is_empty() { "none".to_string() } else { moments.join("; ") }
+    );
+    narrate(dm, state, encounter, summary).await?;
+
+    if Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Append this fight's statistics to {}?", COMBAT_STATS_FILE))
+        .default(false)
+        .interact()?
+    {
+        let path = crate::save_dir().join(COMBAT_STATS_FILE);
+        match export_combat_stats(encounter, &state.campaign, &path) {
+            Ok(()) => print_fancy_message(&format!("Combat statistics saved to {}", path.display()), "green"),
+            Err(e) => print_fancy_message(&format!("Error exporting combat statistics: {}", e), "red"),
+        }
+    }
+
+    // Spent arrows and bolts can be gathered once the fight is over
+    if state.character.has_spent_ammunition() {
+        let search = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Search the area for spent ammunition? (about half can be recovered)")
+            .default(true)
+            .interact()?;
+        let recovered = state.character.recover_ammunition(search);
+        if !recovered.is_empty() {
+            print_fancy_message(&format!("You recover {}", recovered.join(", ")), "green");
+        }
+    }
+
+    state.encounter = None;
+    state.scene_type = SceneType::Exploration;
+    state.last_saved = chrono::Local::now().to_rfc3339();
+    save_game(state)?;
+    Ok(())
+}
+
+// Per-fight statistics are appended here, in the save directory, one row per combatant, so many fights can be compared
+const COMBAT_STATS_FILE: &str = "combat_stats.csv";
+
+// Attack numbers for one combatant over a whole fight, aggregated from the combat log
+#[derive(Default)]
+struct CombatantStats {
+    name: String,
+    attacks: u32,
+    hits: u32,
+    crits: u32,
+    roll_total: u32,
+    damage_dealt: u32,
+    damage_taken: u32,
+}
+
+fn combat_stats(encounter: &Encounter) -> Vec<CombatantStats> {
+    let mut stats: Vec<CombatantStats> = encounter.combatants.iter()
+        .map(|c| CombatantStats { name: c.name.clone(), ..Default::default() })
+        .collect();
+    fn entry<'a>(stats: &'a mut Vec<CombatantStats>, name: &str) -> &'a mut CombatantStats {
+        let index = match stats.iter().position(|s| s.name == name) {
+            Some(index) => index,
+            None => {
+                stats.push(CombatantStats { name: name.to_string(), ..Default::default() });
+                stats.len() - 1
+            }
+        };
+        &mut stats[index]
+    }
+    for event in &encounter.log {
+        if let CombatEvent::Attack { attacker, target, roll, hit, critical, damage, .. } = event {
+            let attacker = entry(&mut stats, attacker);
+            attacker.attacks += 1;
+            attacker.hits += *hit as u32;
+            attacker.crits += *critical as u32;
+            attacker.roll_total += roll;
+            attacker.damage_dealt += damage;
+            entry(&mut stats, target).damage_taken += damage;
+        }
+    }
+    stats
+}
+
+// Quotes a CSV field when it holds a comma or a quote
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// Appends this fight's statistics to the CSV, writing the header for a new file
+fn export_combat_stats(encounter: &Encounter, campaign: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+    let new_file = !path.exists();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if new_file {
+        writeln!(file, "date,campaign,rounds,combatant,attacks,hits,hit_rate,crits,average_roll,damage_dealt,damage_taken")?;
+    }
+    let date = chrono::Local::now().format("%Y-%m-%d %H:%M");
+    for stats in combat_stats(encounter) {
+        let (hit_rate, average_roll) = if stats.attacks == 0 {
+            (0.0, 0.0)
+        } else {
+            (stats.hits as f64 / stats.attacks as f64, stats.roll_total as f64 / stats.attacks as f64)
+        };
+        writeln!(file, "{},{},{},{},{},{},{:.2},{},{:.1},{},{}", date, csv_field(campaign), encounter.round,
+                 csv_field(&stats.name), stats.attacks, stats.hits, hit_rate, stats.crits, average_roll,
+                 stats.damage_dealt, stats.damage_taken)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScriptedRoller;
+
+    fn fighter() -> GameState {
+        let mut state = GameState::default();
+        state.character.class = "Fighter".to_string();
+        state.character.strength = 16;
+        state
+    }
+
+    fn ogre_encounter() -> Encounter {
+        let ogre = bestiary().into_iter().find(|m| m.name == "Ogre").unwrap();
+        Encounter {
+            combatants: vec![Combatant {
+                name: ogre.name.clone(),
+                initiative: 0,
+                dexterity: ogre.abilities[1],
+                hit_points: ogre.hit_points,
+                max_hit_points: ogre.hit_points,
+                armor_class: ogre.armor_class,
+                kind: CombatantKind::Monster(ogre),
+                zone: Zone::Engaged,
+                economy: TurnEconomy::fresh(),
+                surprised: false,
+                conditions: Vec::new(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn last_attack(encounter: &Encounter) -> (u32, bool, bool, u32) {
+        match encounter.log.iter().rev().find(|e| matches!(e, CombatEvent::Attack { .. })) {
+            Some(CombatEvent::Attack { roll, hit, critical, damage, .. }) => (*roll, *hit, *critical, *damage),
+            _ => panic!("no attack logged"),
+        }
+    }
+
+    #[test]
+    fn natural_20_hits_any_ac_and_doubles_the_damage_dice() {
+        let mut state = fighter();
+        let mut encounter = ogre_encounter();
+        encounter.combatants[0].armor_class = 30;
+        let longsword = find_weapon("Longsword");
+        // d20, then 2d8 for the doubled longsword dice
+        let mut roller = ScriptedRoller::new([20, 4, 6]);
+        roll_player_attack(&mut state, &mut encounter, longsword, false, RollMode::Normal, 0, &mut roller);
+        assert_eq!(last_attack(&encounter), (20, true, true, 4 + 6 + 3));
+        assert_eq!(encounter.combatants[0].hit_points, 68 - 13);
+    }
+
+    #[test]
+    fn natural_20_on_a_spell_attack_doubles_the_damage_dice() {
+        let mut state = GameState::default();
+        let mut encounter = ogre_encounter();
+        encounter.combatants[0].armor_class = 30;
+        let damage = DiceExpr::parse("2d10+1").unwrap();
+        // 4 + 5 + 1 rolled for the spell; the crit rolls 2d10 again
+        let mut roller = ScriptedRoller::new([20, 7, 9]);
+        let taken = roll_spell_attack(&mut state, &encounter.combatants[0], "Chromatic orb", 5, damage, 4 + 5 + 1, &mut roller);
+        assert_eq!(taken, (true, true, 4 + 5 + 1 + 7 + 9));
+        // Without the 20 the same bonus misses AC 30 and deals nothing
+        let taken = roll_spell_attack(&mut state, &encounter.combatants[0], "Chromatic orb", 5, damage, 10, &mut ScriptedRoller::new([19]));
+        assert_eq!(taken, (false, false, 0));
+    }
+
+    #[test]
+    fn natural_1_misses_any_ac_without_rolling_damage() {
+        let mut state = fighter();
+        let mut encounter = ogre_encounter();
+        encounter.combatants[0].armor_class = 1;
+        let mut roller = ScriptedRoller::new([1]);
+        roll_player_attack(&mut state, &mut encounter, find_weapon("Longsword"), false, RollMode::Normal, 0, &mut roller);
+        assert_eq!(last_attack(&encounter), (1, false, false, 0));
+    }
+
+    #[test]
+    fn advantage_keeps_the_higher_die_and_crits_on_either_20() {
+        let mut state = fighter();
+        let mut encounter = ogre_encounter();
+        let mut roller = ScriptedRoller::new([3, 20, 5, 5]);
+        roll_player_attack(&mut state, &mut encounter, find_weapon("Longsword"), false, RollMode::Advantage, 0, &mut roller);
+        assert_eq!(last_attack(&encounter), (20, true, true, 5 + 5 + 3));
+    }
+
+    #[test]
+    fn disadvantage_keeps_the_lower_die() {
+        let mut state = fighter();
+        let mut encounter = ogre_encounter();
+        encounter.combatants[0].armor_class = 15;
+        // 20 and 5 keeps the 5: 5 + 3 STR + 2 proficiency misses AC 15
+        let mut roller = ScriptedRoller::new([20, 5]);
+        roll_player_attack(&mut state, &mut encounter, find_weapon("Longsword"), false, RollMode::Disadvantage, 0, &mut roller);
+        assert_eq!(last_attack(&encounter), (5, false, false, 0));
+    }
+
+    #[test]
+    fn a_storm_gives_monster_ranged_attacks_disadvantage() {
+        let mut state = GameState::default();
+        state.environment.weather = crate::Weather::Storm;
+        state.character.hit_points = 10;
+        state.character.armor_class = 10;
+        let mut encounter = ogre_encounter();
+        let attacker = encounter.combatants[0].clone();
+        let goblin = bestiary().into_iter().find(|m| m.name == "Goblin").unwrap();
+        let (scimitar, shortbow) = (&goblin.attacks[0], &goblin.attacks[1]);
+        // 18 and 3 keeps the 3: 3 + 4 misses AC 10
+        resolve_monster_attack(&mut state, &mut encounter, &attacker, shortbow, "", true, &mut ScriptedRoller::new([18, 3])).unwrap();
+        assert_eq!(state.character.hit_points, 10);
+        // Melee attacks roll a single d20 as usual
+        resolve_monster_attack(&mut state, &mut encounter, &attacker, scimitar, "", true, &mut ScriptedRoller::new([18, 3])).unwrap();
+        assert_eq!(state.character.hit_points, 10 - (3 + 2));
+    }
+
+    #[test]
+    fn death_saves_count_successes_and_failures() {
+        let mut state = GameState::default();
+        state.character.hit_points = 0;
+        let mut encounter = Encounter::default();
+        let mut roller = ScriptedRoller::new([10, 9, 15, 12]);
+        for _ in 0..3 {
+            resolve_death_save(&mut state, &mut encounter, &mut roller);
+        }
+        assert_eq!((state.character.death_save_successes, state.character.death_save_failures), (2, 1));
+        assert!(!state.character.is_stable());
+        resolve_death_save(&mut state, &mut encounter, &mut roller);
+        assert!(state.character.is_stable());
+    }
+
+    #[test]
+    fn natural_1_on_a_death_save_counts_as_two_failures() {
+        let mut state = GameState::default();
+        state.character.hit_points = 0;
+        state.character.death_save_failures = 1;
+        let mut encounter = Encounter::default();
+        resolve_death_save(&mut state, &mut encounter, &mut ScriptedRoller::new([1]));
+        assert_eq!(state.character.death_save_failures, 3);
+        assert!(state.character.is_dead());
+    }
+
+    #[test]
+    fn natural_20_on_a_death_save_brings_the_character_back() {
+        let mut state = GameState::default();
+        state.character.hit_points = 0;
+        state.character.death_save_failures = 2;
+        let mut encounter = Encounter::default();
+        resolve_death_save(&mut state, &mut encounter, &mut ScriptedRoller::new([20]));
+        assert_eq!(state.character.hit_points, 1);
+        assert_eq!(state.character.death_save_failures, 0);
+        assert!(!state.character.is_dead());
+    }
+}
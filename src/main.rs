@@ -1,18 +1,38 @@
 use chrono::Local;
 use colored::*;
-use dialoguer::{theme::ColorfulTheme, Input, Select, MultiSelect};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select, MultiSelect};
 use dotenv::dotenv;
 use indicatif::{ProgressBar, ProgressStyle};
 use rig::{
-    completion::{Chat, Message, AssistantContent},
-    providers::gemini,
+    agent::Agent,
+    completion::{Chat, CompletionModel, Message, AssistantContent, PromptError},
+    message::UserContent,
+    providers::{anthropic, gemini, openai},
     OneOrMany,
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs, path::Path, thread, time::Duration, io};
+use std::{collections::{BTreeSet, HashMap, HashSet, VecDeque}, error::Error, fmt, fs, future::{Future, IntoFuture}, path::{Path, PathBuf}, pin::Pin, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}, thread, time::{Duration, Instant}, io};
+
+mod combat;
+
+use combat::{DamageSpell, Encounter};
 
 const SAVE_FILE: &str = "dnd_adventure_save.json";
+// Written instead of SAVE_FILE when compressed saves are turned on in Settings
+const COMPRESSED_SAVE_FILE: &str = "dnd_adventure_save.dnd.json.gz";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// A single checkpoint slot, separate from the autosave, for snapshots before risky moves
+const QUICKSAVE_FILE: &str = "dnd_quicksave.json";
+const COMPRESSED_QUICKSAVE_FILE: &str = "dnd_quicksave.json.gz";
+// The timeline as it stood before the last rewind
+const REWIND_BACKUP_FILE: &str = "dnd_adventure_save.json.branch.bak";
+const COMPRESSED_REWIND_BACKUP_FILE: &str = "dnd_adventure_save.json.branch.bak.gz";
+// Present while an adventure is being played; left behind if the game crashes
+const SESSION_MARKER_FILE: &str = "dnd_session.lock";
+
+// Bump this and add a step to `migrate` whenever the save format changes
+const SAVE_VERSION: u32 = 6;
 
 const TITLE_ART: &str = r#"
   _____          _____                                                         
@@ -24,12 +44,471 @@ const TITLE_ART: &str = r#"
  
 "#;
 
+// How many recent HP changes the character keeps
+const HP_LOG_LEN: usize = 10;
+
+// How many recent dice rolls the roll history keeps
+const RECENT_ROLLS_LEN: usize = 20;
+
+// One entry in the roll history; context says who rolled and what for
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RollRecord {
+    expr: String,
+    dice: Vec<u32>,
+    total: i32,
+    context: String,
+    timestamp: String,
+}
+
+// One entry in the HP audit trail; turn is the story turn it happened on
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct HpChange {
+    delta: i32,
+    reason: String,
+    turn: usize,
+}
+
+// Crafting: a recipe turns components, gold, and time into an item on a successful tool check
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Recipe {
+    name: String,
+    tool: String,
+    components: Vec<String>,
+    gold: u32,
+    hours: u32,
+    dc: u32,
+    output: String,
+}
+
+fn recipe(name: &str, tool: &str, components: &[&str], gold: u32, hours: u32, dc: u32, output: &str) -> Recipe {
+    Recipe {
+        name: name.to_string(),
+        tool: tool.to_string(),
+        components: components.iter().map(|c| c.to_string()).collect(),
+        gold,
+        hours,
+        dc,
+        output: output.to_string(),
+    }
+}
+
+fn builtin_recipes() -> Vec<Recipe> {
+    vec![
+        recipe("Potion of healing", "Herbalism kit", &["Healing herbs"], 25, 8, 12, "Potion of healing"),
+        recipe("Antitoxin", "Herbalism kit", &["Venom sac"], 25, 8, 13, "Antitoxin"),
+        recipe("Arrows", "Woodcarver's tools", &["Wood"], 1, 2, 10, "Arrows (20)"),
+        recipe("Caltrops", "Smith's tools", &["Iron scraps"], 1, 2, 10, "Caltrops"),
+    ]
+}
+
+// Tool proficiencies from class and background; one granted by both is only held once
+fn starting_tool_proficiencies(class: &str, background: &str) -> BTreeSet<String> {
+    let mut tools: Vec<&str> = match class {
+        "Rogue" => vec!["Thieves' tools"],
+        "Druid" => vec!["Herbalism kit"],
+        "Bard" => vec!["Lute", "Flute", "Lyre"],
+        "Artificer" => vec!["Thieves' tools", "Tinker's tools"],
+        _ => vec![],
+    };
+    tools.extend(match background {
+        "Charlatan" => vec!["Disguise kit", "Forgery kit"],
+        "Criminal" | "Urchin" => vec!["Thieves' tools"],
+        "Entertainer" => vec!["Disguise kit", "Drum"],
+        "Folk Hero" => vec!["Woodcarver's tools"],
+        "Guild Artisan" => vec!["Smith's tools"],
+        "Hermit" => vec!["Herbalism kit"],
+        "Sailor" => vec!["Navigator's tools"],
+        _ => vec![],
+    });
+    tools.into_iter().map(String::from).collect()
+}
+
+// The ability a tool check usually rides on; the player can pick another at roll time
+fn tool_ability(tool: &str) -> Ability {
+    let tool = tool.to_lowercase();
+    if tool.contains("thieves") || tool.contains("tinker") || tool.contains("disguise") {
+        Ability::Dexterity
+    } else if tool.contains("herbalism") || tool.contains("navigator") || tool.contains("cook") {
+        Ability::Wisdom
+    } else if tool.contains("smith") || tool.contains("mason") {
+        Ability::Strength
+    } else if ["lute", "flute", "lyre", "drum", "horn", "viol", "bagpipes", "dulcimer", "pan flute", "shawm"]
+        .iter()
+        .any(|instrument| tool.contains(instrument))
+    {
+        Ability::Charisma
+    } else {
+        Ability::Intelligence
+    }
+}
+
+// Animal companions, familiars, and other creatures that travel with the character
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Companion {
+    name: String,
+    kind: String,
+    hp: u32,
+    max_hp: u32,
+    ac: u32,
+    notes: String,
+    // Only used to roll initiative when the party fights together
+    #[serde(default = "default_companion_dexterity")]
+    dexterity: u32,
+}
+
+fn default_companion_dexterity() -> u32 {
+    10
+}
+
+impl Companion {
+    fn take_damage(&mut self, amount: u32) -> u32 {
+        let lost = amount.min(self.hp);
+        self.hp -= lost;
+        lost
+    }
+    
+    fn heal(&mut self, amount: u32) -> u32 {
+        let healed = amount.min(self.max_hp.saturating_sub(self.hp));
+        self.hp += healed;
+        healed
+    }
+}
+
+impl fmt::Display for Companion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} the {} ({}/{} HP, AC {})", self.name, self.kind, self.hp, self.max_hp, self.ac)?;
+        if self.hp == 0 {
+            write!(f, " - down")?;
+        }
+        if !self.notes.is_empty() {
+            write!(f, " - {}", self.notes)?;
+        }
+        Ok(())
+    }
+}
+
+// Characters can be attuned to at most this many magic items at once
+const MAX_ATTUNED_ITEMS: usize = 3;
+
+// Inventory entries; mundane gear only needs a name
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Item {
+    name: String,
+    #[serde(default)]
+    requires_attunement: bool,
+    #[serde(default)]
+    attuned: bool,
+    #[serde(default)]
+    ac_bonus: i32,
+    #[serde(default)]
+    ability_bonus: Option<(Ability, i32)>,
+    // Shots left for a bow, crossbow, sling or blowgun; None when nobody is counting
+    #[serde(default)]
+    ammo: Option<u32>,
+    // Shots fired since the last fight ended, half of which can be recovered
+    #[serde(default)]
+    ammo_spent: u32,
+}
+
+impl Item {
+    // "Shortbow with 20 arrows" becomes a Shortbow carrying 20 arrows
+    fn new(name: &str) -> Self {
+        if let Some(weapon) = find_weapon(name)
+            && ammunition_kind(weapon).is_some()
+            && let Some(count) = first_number(name)
+        {
+            let name = name.split(" with ").next().unwrap_or(name).trim();
+            return Item {
+                name: name.to_string(),
+                ammo: Some(count),
+                ..Default::default()
+            };
+        }
+        Item {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+    
+    fn is_magic(&self) -> bool {
+        self.requires_attunement || self.ac_bonus != 0 || self.ability_bonus.is_some()
+    }
+    
+    // Items that need attunement only work while attuned
+    fn is_active(&self) -> bool {
+        !self.requires_attunement || self.attuned
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        let mut effects = Vec::new();
+        if self.ac_bonus != 0 {
+            effects.push(format!("{} AC", format_modifier(self.ac_bonus)));
+        }
+        if let Some((ability, delta)) = self.ability_bonus {
+            effects.push(format!("{} {}", format_modifier(delta), ability.abbreviation()));
+        }
+        if let Some(count) = self.ammo {
+            let kind = find_weapon(&self.name).and_then(ammunition_kind).unwrap_or("ammunition");
+            effects.push(format!("{} {}", count, kind));
+        }
+        if self.attuned {
+            effects.push("attuned".to_string());
+        } else if self.requires_attunement {
+            effects.push("requires attunement".to_string());
+        }
+        if !effects.is_empty() {
+            write!(f, " ({})", effects.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+const CONFIG_FILE: &str = "dnd_config.json";
+
+// User preferences, read from CONFIG_FILE and overridable from the command line or environment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Config {
+    #[serde(default = "default_true")]
+    spinner: bool,
+    // Prints every prompt and raw response sent to and from the model
+    #[serde(default)]
+    debug: bool,
+    // Ask the DM for a skill and DC before resolving actions
+    #[serde(default)]
+    auto_dc: bool,
+    // Ask the DM which skill fits an action and offer to roll it
+    #[serde(default)]
+    suggest_skills: bool,
+    // Follow each check, save and attack with a plain-language breakdown of the total
+    #[serde(default)]
+    explain_rolls: bool,
+    #[serde(default)]
+    theme: ThemeName,
+    // Roll every enemy turn in one go and narrate them together
+    #[serde(default)]
+    auto_enemy_turns: bool,
+    // Send only the action and essential state with each action, relying on the preamble for instructions
+    #[serde(default)]
+    lean_prompts: bool,
+    // Where saves live; None means the per-user data directory
+    #[serde(default)]
+    save_dir: Option<String>,
+    // Which model runs the DM; None means the provider's default model
+    #[serde(default)]
+    provider: Provider,
+    #[serde(default)]
+    model: Option<String>,
+    // Ask "Are you sure?" before sending actions that look catastrophic
+    #[serde(default = "default_true")]
+    confirm_risky_actions: bool,
+    #[serde(default = "default_risky_phrases")]
+    risky_phrases: Vec<String>,
+    // Gzip the autosave; pretty JSON stays the default because it's easy to inspect
+    #[serde(default)]
+    compress_saves: bool,
+    // Suggest saving or taking a break every break_reminder_minutes of real time at the action menu
+    #[serde(default)]
+    break_reminders: bool,
+    #[serde(default = "default_break_reminder_minutes")]
+    break_reminder_minutes: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_break_reminder_minutes() -> u64 {
+    60
+}
+
+fn default_risky_phrases() -> Vec<String> {
+    ["jump off", "leap off", "throw myself", "drink unknown", "drink the unknown", "eat unknown", "kill myself", "give away all", "set myself on fire"]
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            spinner: true,
+            debug: false,
+            auto_dc: false,
+            suggest_skills: false,
+            explain_rolls: false,
+            theme: ThemeName::Default,
+            auto_enemy_turns: false,
+            lean_prompts: false,
+            save_dir: None,
+            provider: Provider::default(),
+            model: None,
+            confirm_risky_actions: true,
+            risky_phrases: default_risky_phrases(),
+            compress_saves: false,
+            break_reminders: false,
+            break_reminder_minutes: default_break_reminder_minutes(),
+        }
+    }
+}
+
+static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+
+fn config() -> Config {
+    CONFIG.read().unwrap().clone().unwrap_or_default()
+}
+
+fn set_config(config: Config) {
+    *CONFIG.write().unwrap() = Some(config);
+}
+
+// Config file first, then DND_NO_SPINNER / DND_DEBUG, then command-line flags (--save-dir takes a path)
+fn load_config(args: &[String]) -> Config {
+    let mut config: Config = fs::read_to_string(CONFIG_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    
+    if std::env::var("DND_NO_SPINNER").is_ok_and(|v| !v.is_empty() && v != "0") {
+        config.spinner = false;
+    }
+    if std::env::var("DND_DEBUG").is_ok_and(|v| !v.is_empty() && v != "0") {
+        config.debug = true;
+    }
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-spinner" => config.spinner = false,
+            "--spinner" => config.spinner = true,
+            "--verbose" | "-v" => config.debug = true,
+            "--save-dir" => config.save_dir = args.next().cloned(),
+            other if let Some(dir) = other.strip_prefix("--save-dir=") => config.save_dir = Some(dir.to_string()),
+            _ => {}
+        }
+    }
+    config
+}
+
+impl Config {
+    fn model_name(&self) -> String {
+        self.model.clone().unwrap_or_else(|| self.provider.default_model().to_string())
+    }
+}
+
+fn save_config(config: &Config) -> Result<(), Box<dyn Error>> {
+    fs::write(CONFIG_FILE, serde_json::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+// Output color presets, chosen in Settings
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+enum ThemeName {
+    #[default]
+    Default,
+    HighContrast,
+    LightBackground,
+    ColorblindSafe,
+}
+
+impl ThemeName {
+    const ALL: [ThemeName; 4] = [
+        ThemeName::Default, ThemeName::HighContrast, ThemeName::LightBackground, ThemeName::ColorblindSafe,
+    ];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            ThemeName::Default => "Default",
+            ThemeName::HighContrast => "High contrast",
+            ThemeName::LightBackground => "Light background",
+            ThemeName::ColorblindSafe => "Colorblind-safe",
+        }
+    }
+}
+
+// Colors by role, so the print helpers don't hardcode specific colors
+#[derive(Clone, Copy, Debug)]
+struct Theme {
+    heading: Color,
+    label: Color,
+    value: Color,
+    border: Color,
+    good: Color,
+    bad: Color,
+    warning: Color,
+    info: Color,
+    accent: Color,
+}
+
+impl Theme {
+    fn preset(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Default => Theme {
+                heading: Color::BrightYellow,
+                label: Color::BrightGreen,
+                value: Color::BrightWhite,
+                border: Color::BrightBlue,
+                good: Color::BrightGreen,
+                bad: Color::BrightRed,
+                warning: Color::BrightYellow,
+                info: Color::BrightCyan,
+                accent: Color::BrightMagenta,
+            },
+            ThemeName::HighContrast => Theme {
+                heading: Color::BrightWhite,
+                label: Color::BrightYellow,
+                value: Color::BrightWhite,
+                border: Color::White,
+                good: Color::BrightGreen,
+                bad: Color::BrightRed,
+                warning: Color::BrightYellow,
+                info: Color::BrightWhite,
+                accent: Color::BrightCyan,
+            },
+            ThemeName::LightBackground => Theme {
+                heading: Color::Magenta,
+                label: Color::Blue,
+                value: Color::Black,
+                border: Color::Blue,
+                good: Color::Green,
+                bad: Color::Red,
+                warning: Color::Magenta,
+                info: Color::Blue,
+                accent: Color::Magenta,
+            },
+            // Okabe-Ito palette: blue/orange instead of green/red
+            ThemeName::ColorblindSafe => Theme {
+                heading: Color::TrueColor { r: 240, g: 228, b: 66 },
+                label: Color::TrueColor { r: 86, g: 180, b: 233 },
+                value: Color::BrightWhite,
+                border: Color::TrueColor { r: 0, g: 114, b: 178 },
+                good: Color::TrueColor { r: 86, g: 180, b: 233 },
+                bad: Color::TrueColor { r: 230, g: 159, b: 0 },
+                warning: Color::TrueColor { r: 240, g: 228, b: 66 },
+                info: Color::TrueColor { r: 86, g: 180, b: 233 },
+                accent: Color::TrueColor { r: 204, g: 121, b: 167 },
+            },
+        }
+    }
+}
+
+fn theme() -> Theme {
+    Theme::preset(config().theme)
+}
+
 // Character data structures
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct Character {
     name: String,
     race: String,
     class: String,
+    // Classes taken after the first one; these never add saving throw proficiencies
+    #[serde(default)]
+    multiclasses: Vec<String>,
+    // Levels taken in each of `multiclasses`; the starting class has the rest of `level`
+    #[serde(default)]
+    multiclass_levels: HashMap<String, u32>,
     level: u32,
     strength: u32,
     dexterity: u32,
@@ -40,29 +519,145 @@ struct Character {
     hit_points: u32,
     max_hit_points: u32,
     armor_class: u32,
-    inventory: Vec<String>,
+    inventory: Vec<Item>,
     gold: u32,
     experience: u32,
     background: String,
-    skills: HashMap<String, bool>,
+    skills: HashMap<String, Proficiency>,
+    #[serde(default)]
+    temp_hit_points: u32,
+    #[serde(default)]
+    resistances: Vec<String>,
+    #[serde(default)]
+    death_save_successes: u32,
+    #[serde(default)]
+    death_save_failures: u32,
+    #[serde(default)]
+    ability_modifiers: Vec<AbilityModifier>,
+    #[serde(default)]
+    max_hp_modifiers: Vec<MaxHpModifier>,
+    #[serde(default)]
+    hp_log: VecDeque<HpChange>,
+    #[serde(default)]
+    companions: Vec<Companion>,
+    #[serde(default)]
+    tool_proficiencies: BTreeSet<String>,
+    // Recipes granted during play, on top of the built-in ones
+    #[serde(default)]
+    known_recipes: Vec<Recipe>,
+    #[serde(default)]
+    favorites: Vec<FavoriteAction>,
+    #[serde(default)]
+    active_effects: Vec<ActiveEffect>,
+    #[serde(default)]
+    personality: Personality,
+    // Cantrips and spells ready to cast; for a Wizard, the spells prepared from the spellbook
+    #[serde(default)]
+    known_spells: Vec<Spell>,
+    // Every leveled spell a Wizard has written down, prepared or not
+    #[serde(default)]
+    spellbook: Vec<Spell>,
+    // A short narrative history, written by the player or drafted by the DM at creation
+    #[serde(default)]
+    backstory: String,
+    #[serde(default)]
+    pact_slots_used: u32,
+    // Levels whose ability score improvement (or feat) has already been chosen, so it can't be claimed twice
+    #[serde(default)]
+    asi_taken: HashSet<u32>,
+    #[serde(default)]
+    feats: Vec<String>,
+    #[serde(default)]
+    status: LifeStatus,
+    // Bonus dice waiting for the next check or save, e.g. ("Guidance", 1d4)
+    #[serde(default)]
+    pending_bonus_dice: Vec<(String, DiceExpr)>,
+    // Standing roleplay constraints the DM honors every turn (a vow of silence, a hidden identity)
+    #[serde(default)]
+    dm_notes: String,
+    // Raise Dead's lingering -N to attacks, saves, and checks; it fades by 1 per long rest
+    #[serde(default)]
+    resurrection_penalty: u32,
+}
+
+// How well a character knows a skill; Expertise doubles the proficiency bonus
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum Proficiency {
+    #[default]
+    None,
+    Proficient,
+    Expertise,
+}
+
+// Whether the character is up, stable at 0 HP, or dead; dead sticks until a resurrection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum LifeStatus {
+    #[default]
+    Alive,
+    Stable,
+    Dead,
+}
+
+impl LifeStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            LifeStatus::Alive => "Alive",
+            LifeStatus::Stable => "Stable",
+            LifeStatus::Dead => "Dead",
+        }
+    }
+}
+
+// A spell the character knows or has in their spellbook; level 0 is a cantrip
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Spell {
+    name: String,
+    level: u32,
+}
+
+impl fmt::Display for Spell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            0 => write!(f, "{} (cantrip)", self.name),
+            level => write!(f, "{} (level {})", self.name, level),
+        }
+    }
+}
+
+// Roleplaying hooks the DM weaves into the story; every field is optional
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Personality {
+    ideals: String,
+    bonds: String,
+    flaws: String,
+    // A patron, deity, or other power the character answers to
+    patron: String,
+}
+
+impl Personality {
+    // Labeled, non-empty fields, e.g. ["Bonds: my sister in the city watch"]
+    fn lines(&self) -> Vec<String> {
+        [("Ideals", &self.ideals), ("Bonds", &self.bonds), ("Flaws", &self.flaws), ("Patron or deity", &self.patron)]
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .collect()
+    }
 }
 
 impl Default for Character {
     fn default() -> Self {
         let mut skills = HashMap::new();
-        for skill in &[
-            "Acrobatics", "Animal Handling", "Arcana", "Athletics", "Deception", 
-            "History", "Insight", "Intimidation", "Investigation", "Medicine", 
-            "Nature", "Perception", "Performance", "Persuasion", "Religion", 
-            "Sleight of Hand", "Stealth", "Survival"
-        ] {
-            skills.insert(skill.to_string(), false);
+        for (skill, _) in SKILLS {
+            skills.insert(skill.to_string(), Proficiency::None);
         }
         
         Self {
             name: String::new(),
             race: String::new(),
             class: String::new(),
+            multiclasses: Vec::new(),
+            multiclass_levels: HashMap::new(),
             level: 1,
             strength: 10,
             dexterity: 10,
@@ -78,1012 +673,8417 @@ impl Default for Character {
             experience: 0,
             background: String::new(),
             skills,
+            temp_hit_points: 0,
+            resistances: Vec::new(),
+            death_save_successes: 0,
+            death_save_failures: 0,
+            ability_modifiers: Vec::new(),
+            max_hp_modifiers: Vec::new(),
+            hp_log: VecDeque::new(),
+            companions: Vec::new(),
+            tool_proficiencies: BTreeSet::new(),
+            known_recipes: Vec::new(),
+            favorites: Vec::new(),
+            active_effects: Vec::new(),
+            personality: Personality::default(),
+            known_spells: Vec::new(),
+            spellbook: Vec::new(),
+            backstory: String::new(),
+            pact_slots_used: 0,
+            asi_taken: HashSet::new(),
+            feats: Vec::new(),
+            pending_bonus_dice: Vec::new(),
+            dm_notes: String::new(),
+            status: LifeStatus::Alive,
+            resurrection_penalty: 0,
         }
     }
 }
 
-// Game state
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct GameState {
-    character: Character,
-    campaign: String,
-    current_location: String,
-    current_quest: String,
-    history: Vec<Message>,
-    date_started: String,
-    last_saved: String,
+// A pinned shortcut shown at the top of the action menus
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum FavoriteAction {
+    WeaponAttack(String),
+    Spell(DamageSpell),
+    SkillCheck(String),
 }
 
-impl Default for GameState {
-    fn default() -> Self {
-        Self {
-            character: Character::default(),
-            campaign: String::new(),
-            current_location: String::new(),
-            current_quest: String::new(),
-            history: Vec::new(),
-            date_started: Local::now().to_rfc3339(),
-            last_saved: Local::now().to_rfc3339(),
+impl fmt::Display for FavoriteAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FavoriteAction::WeaponAttack(weapon) => write!(f, "Attack: {}", weapon),
+            FavoriteAction::Spell(spell) => write!(f, "Cast: {}", spell),
+            FavoriteAction::SkillCheck(skill) => write!(f, "Check: {}", skill),
         }
     }
 }
 
-// Display utilities
-fn show_spinner(message: &str, duration_ms: u64) {
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    pb.set_message(message.to_string());
+// Pacing of the current scene, used to steer the action prompt
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum SceneType {
+    #[default]
+    Exploration,
+    Combat,
+    Social,
+    Downtime,
+}
+
+impl SceneType {
+    const ALL: [SceneType; 4] = [SceneType::Exploration, SceneType::Combat, SceneType::Social, SceneType::Downtime];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            SceneType::Exploration => "Exploration",
+            SceneType::Combat => "Combat",
+            SceneType::Social => "Social",
+            SceneType::Downtime => "Downtime",
+        }
+    }
     
-    for _ in 0..duration_ms / 100 {
-        pb.tick();
-        thread::sleep(Duration::from_millis(100));
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scene| scene.name().eq_ignore_ascii_case(name.trim()))
     }
     
-    pb.finish_and_clear();
+    fn pacing_guidance(&self) -> &'static str {
+        match self {
+            SceneType::Exploration => "This is an exploration scene. Favor sensory description, discovery, and hidden details; Perception, Investigation, and Survival checks fit best.",
+            SceneType::Combat => "This is a combat scene. Keep narration tight and tactical, describe enemy positions and threats, and if combat has not started yet, call for initiative.",
+            SceneType::Social => "This is a social scene. Favor dialogue and distinct NPC voices over action; Persuasion, Deception, Insight, and Intimidation checks fit best. Do not escalate to combat unless the player does.",
+            SceneType::Downtime => "This is a downtime scene. Keep the pacing relaxed, summarize the passage of time, and focus on rest, shopping, training, and reflection.",
+        }
+    }
 }
 
-fn print_header() {
-    let title = TITLE_ART.bright_cyan().bold();
-    println!("\n{}", title);
-    println!("{}", "AI Dungeon Master".bright_purple().bold());
-    println!("{}\n", "=".repeat(60).bright_blue());
+// Weather, light and footing for the current scene, set by [ENV: ...] tags or the menu; the defaults impose nothing
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum Weather {
+    #[default]
+    Clear,
+    Rain,
+    HeavyRain,
+    Fog,
+    HeavyFog,
+    Wind,
+    StrongWind,
+    Snow,
+    Storm,
 }
 
-fn print_fancy_message(message: &str, color: &str) {
-    let formatted = match color {
-        "red" => message.bright_red().bold(),
-        "green" => message.bright_green().bold(),
-        "blue" => message.bright_blue().bold(),
-        "yellow" => message.bright_yellow().bold(),
-        "cyan" => message.bright_cyan().bold(),
-        "magenta" => message.bright_magenta().bold(),
-        "white" => message.bright_white().bold(),
-        _ => message.normal(),
-    };
+impl Weather {
+    const ALL: [Weather; 9] = [
+        Weather::Clear, Weather::Rain, Weather::HeavyRain, Weather::Fog, Weather::HeavyFog,
+        Weather::Wind, Weather::StrongWind, Weather::Snow, Weather::Storm,
+    ];
     
-    println!("\n{}", formatted);
+    fn name(&self) -> &'static str {
+        match self {
+            Weather::Clear => "Clear",
+            Weather::Rain => "Rain",
+            Weather::HeavyRain => "Heavy rain",
+            Weather::Fog => "Fog",
+            Weather::HeavyFog => "Heavy fog",
+            Weather::Wind => "Wind",
+            Weather::StrongWind => "Strong wind",
+            Weather::Snow => "Snow",
+            Weather::Storm => "Storm",
+        }
+    }
 }
 
-fn print_character_sheet(character: &Character) {
-    println!("\n{}", "CHARACTER SHEET".bright_yellow().bold());
-    println!("{}", "=".repeat(60).bright_blue());
-    println!("{}: {}", "Name".bright_green(), character.name.bright_white());
-    println!("{}: {} | {}: {}", 
-             "Race".bright_green(), character.race.bright_white(),
-             "Class".bright_green(), character.class.bright_white());
-    println!("{}: {} | {}: {} GP", 
-             "Level".bright_green(), character.level.to_string().bright_white(),
-             "Gold".bright_green(), character.gold.to_string().bright_white());
-    println!("{}", "-".repeat(60).bright_blue());
-    println!("{}: {}/{}", 
-             "Hit Points".bright_green(), 
-             character.hit_points.to_string().bright_white(),
-             character.max_hit_points.to_string().bright_white());
-    println!("{}: {}", 
-             "Armor Class".bright_green(), 
-             character.armor_class.to_string().bright_white());
-    println!("{}", "-".repeat(60).bright_blue());
-    println!("{}", "Abilities".bright_yellow());
-    println!("{}: {} | {}: {}",
-             "STR".bright_green(), character.strength.to_string().bright_white(),
-             "DEX".bright_green(), character.dexterity.to_string().bright_white());
-    println!("{}: {} | {}: {}",
-             "CON".bright_green(), character.constitution.to_string().bright_white(),
-             "INT".bright_green(), character.intelligence.to_string().bright_white());
-    println!("{}: {} | {}: {}",
-             "WIS".bright_green(), character.wisdom.to_string().bright_white(),
-             "CHA".bright_green(), character.charisma.to_string().bright_white());
-    println!("{}", "-".repeat(60).bright_blue());
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum Lighting {
+    #[default]
+    Bright,
+    Dim,
+    Darkness,
+}
+
+impl Lighting {
+    const ALL: [Lighting; 3] = [Lighting::Bright, Lighting::Dim, Lighting::Darkness];
     
-    println!("{}", "Inventory".bright_yellow());
-    if character.inventory.is_empty() {
-        println!("(empty)");
-    } else {
-        for item in &character.inventory {
-            println!("• {}", item);
+    fn name(&self) -> &'static str {
+        match self {
+            Lighting::Bright => "Bright light",
+            Lighting::Dim => "Dim light",
+            Lighting::Darkness => "Darkness",
         }
     }
-    println!("{}", "=".repeat(60).bright_blue());
 }
 
-// File operations
-fn save_game(state: &GameState) -> Result<(), Box<dyn Error>> {
-    // Create a temporary file to write to first
-    let temp_file = format!("{}.tmp", SAVE_FILE);
-    let json = serde_json::to_string_pretty(state)?;
-    
-    // Write to the temporary file first
-    fs::write(&temp_file, &json)?;
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum Terrain {
+    #[default]
+    Normal,
+    Difficult,
+}
+
+impl Terrain {
+    const ALL: [Terrain; 2] = [Terrain::Normal, Terrain::Difficult];
     
-    // Then rename the temporary file to the actual save file
-    // This helps prevent corruption if the program crashes during the write
-    if Path::new(&temp_file).exists() {
-        if Path::new(SAVE_FILE).exists() {
-            fs::remove_file(SAVE_FILE)?;
+    fn name(&self) -> &'static str {
+        match self {
+            Terrain::Normal => "Normal ground",
+            Terrain::Difficult => "Difficult terrain",
         }
-        fs::rename(&temp_file, SAVE_FILE)?;
     }
-    
-    Ok(())
 }
 
-fn load_game() -> Result<GameState, Box<dyn Error>> {
-    if Path::new(SAVE_FILE).exists() {
-        let json = fs::read_to_string(SAVE_FILE)?;
-        let state: GameState = serde_json::from_str(&json)?;
-        Ok(state)
-    } else {
-        Ok(GameState::default())
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+struct Environment {
+    weather: Weather,
+    lighting: Lighting,
+    terrain: Terrain,
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, {}", self.weather.name(), self.lighting.name().to_lowercase(), self.terrain.name().to_lowercase())
     }
 }
 
-// Helper function to extract text from OneOrMany<AssistantContent>
-fn extract_text_from_message(content: &OneOrMany<AssistantContent>) -> String {
-    // Try to extract the text from the first element using the public API
-    let text = match content.first() {
-        AssistantContent::Text(text_content) => text_content.text.clone(),
-        _ => "No readable text content available".to_string(),
-    };
-    
-    text
+// "dim" finds "Dim light", but "heavy" is ambiguous and finds nothing
+fn match_name<T: Copy>(all: &[T], name: impl Fn(&T) -> &'static str, wanted: &str) -> Option<T> {
+    if let Some(exact) = all.iter().find(|v| name(v).eq_ignore_ascii_case(wanted)) {
+        return Some(*exact);
+    }
+    let prefixed: Vec<&T> = all.iter().filter(|v| name(v).to_lowercase().starts_with(&wanted.to_lowercase())).collect();
+    (prefixed.len() == 1).then(|| *prefixed[0])
 }
 
-// Dice rolling utilities
-fn roll_dice(num_dice: u32, sides: u32) -> Vec<u32> {
-    let mut rng = rand::thread_rng();
-    let mut results = Vec::new();
+impl Environment {
+    // Applies each recognized part of "heavy fog, dim light" (or "weather: fog; light: dark"), returning whether any matched
+    fn apply_tag(&mut self, value: &str) -> bool {
+        let mut matched = false;
+        for part in value.split([',', ';', '|']) {
+            let part = part.rsplit([':', '=']).next().unwrap_or(part).trim();
+            let part = if part.eq_ignore_ascii_case("dark") { "Darkness" } else { part };
+            if part.is_empty() {
+                continue;
+            }
+            if let Some(weather) = match_name(&Weather::ALL, Weather::name, part) {
+                self.weather = weather;
+            } else if let Some(lighting) = match_name(&Lighting::ALL, Lighting::name, part) {
+                self.lighting = lighting;
+            } else if let Some(terrain) = match_name(&Terrain::ALL, Terrain::name, part) {
+                self.terrain = terrain;
+            } else {
+                continue;
+            }
+            matched = true;
+        }
+        matched
+    }
+    
+    // Why Perception has disadvantage here: obscuring weather, poor light, or wind drowning out sound
+    fn check_penalty(&self, skill: &str) -> Option<String> {
+        if skill != "Perception" {
+            return None;
+        }
+        match (self.weather, self.lighting) {
+            (Weather::Fog | Weather::HeavyFog | Weather::HeavyRain | Weather::StrongWind | Weather::Storm, _) => Some(self.weather.name().to_string()),
+            (_, Lighting::Dim | Lighting::Darkness) => Some(self.lighting.name().to_string()),
+            _ => None,
+        }
+    }
     
-    for _ in 0..num_dice {
-        results.push(rng.gen_range(1..=sides));
+    // Fine work with tools needs light to see by and shelter from the worst weather
+    fn tool_check_penalty(&self) -> Option<String> {
+        match (self.weather, self.lighting) {
+            (_, Lighting::Darkness) => Some(self.lighting.name().to_string()),
+            (Weather::StrongWind | Weather::Storm, _) => Some(self.weather.name().to_string()),
+            _ => None,
+        }
+    }
+    
+    fn ranged_attack_penalty(&self) -> Option<String> {
+        matches!(self.weather, Weather::StrongWind | Weather::Storm).then(|| self.weather.name().to_string())
     }
     
-    results
+    // The rules the conditions bring, for the DM
+    fn guidance(&self) -> String {
+        let mut rules = Vec::new();
+        if self.check_penalty("Perception").is_some() {
+            rules.push("Perception checks have disadvantage");
+        }
+        if self.ranged_attack_penalty().is_some() {
+            rules.push("ranged weapon attacks have disadvantage");
+        }
+        if self.tool_check_penalty().is_some() {
+            rules.push("tool checks have disadvantage");
+        }
+        if self.terrain == Terrain::Difficult {
+            rules.push("moving costs double");
+        }
+        if rules.is_empty() {
+            format!("Environment: {}.", self)
+        } else {
+            format!("Environment: {} ({}).", self, rules.join("; "))
+        }
+    }
 }
 
-fn print_dice_roll(dice_type: &str, results: &[u32]) {
-    let sum: u32 = results.iter().sum();
-    let dice_results = results
-        .iter()
-        .map(|d| d.to_string())
-        .collect::<Vec<String>>()
-        .join(", ");
+// How much the DM writes per response
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum NarrationLength {
+    Brief,
+    #[default]
+    Normal,
+    Detailed,
+}
+
+impl NarrationLength {
+    const ALL: [NarrationLength; 3] = [NarrationLength::Brief, NarrationLength::Normal, NarrationLength::Detailed];
     
-    println!("{} {} [{}] = {}", 
-             "Rolled".bright_blue(),
-             dice_type.bright_yellow(),
-             dice_results.bright_white(),
-             sum.to_string().bright_green().bold());
+    fn name(&self) -> &'static str {
+        match self {
+            NarrationLength::Brief => "Brief",
+            NarrationLength::Normal => "Normal",
+            NarrationLength::Detailed => "Detailed",
+        }
+    }
+    
+    fn instruction(&self) -> &'static str {
+        match self {
+            NarrationLength::Brief => "Respond in 2-3 sentences.",
+            NarrationLength::Normal => "Respond in one or two short paragraphs.",
+            NarrationLength::Detailed => "Respond in three or four rich paragraphs.",
+        }
+    }
 }
 
-// Function to let the DM narrate the result of a general dice roll
-async fn process_dice_roll(
-    dm: &impl Chat,
-    dice_type: &str,
-    results: &[u32],
-    purpose: &str,
-    state: &mut GameState,
-) -> Result<String, Box<dyn Error>> {
-    // Skip DM narration if no purpose is provided
-    if purpose.is_empty() {
-        return Ok("".to_string());
-    }
-    
-    let sum: u32 = results.iter().sum();
-    let dice_results = results
-        .iter()
-        .map(|d| d.to_string())
-        .collect::<Vec<String>>()
-        .join(", ");
-    
-    let roll_prompt = format!(
-        "The player ({} the {} {}) rolls {} for the following purpose: \"{}\"
-        Dice roll result: {} [{}] = {}
-        
-        As the DM, narrate the outcome of this roll in the context of the current situation.
-        Be descriptive and evocative, explaining how the dice roll affects the player's attempt.
-        Continue the scene after describing the result.",
-        state.character.name,
-        state.character.race,
-        state.character.class,
-        dice_type,
-        purpose,
-        dice_type,
-        dice_results,
-        sum
-    );
-    
-    let response = dm_chat(
-        dm,
-        &roll_prompt,
-        state.history.clone(),
-        "Failed to process dice roll",
-        "The Dungeon Master is interpreting your roll...",
-        1500,
-    )
-    .await?;
-    
-    state.history.push(Message::user(&roll_prompt));
-    state.history.push(Message::assistant(&response));
-    
-    state.last_saved = Local::now().to_rfc3339();
-    save_game(state)?;
-    
-    Ok(response)
+// Genre and tone of the whole campaign, chosen once at the start
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+enum CampaignSetting {
+    #[default]
+    ClassicFantasy,
+    Grimdark,
+    HighMagic,
+    LowMagic,
+    Horror,
+    Nautical,
+    UrbanIntrigue,
 }
 
-// AI DM interactions
-async fn dm_chat<C>(
-    dm: &C,
-    prompt: &str,
-    history: Vec<Message>,
-    error_message: &str,
-    spinner_message: &str,
-    spinner_duration: u64,
-) -> Result<String, Box<dyn Error>>
-where
-    C: Chat,
-{
-    show_spinner(spinner_message, spinner_duration);
+impl CampaignSetting {
+    const ALL: [CampaignSetting; 7] = [
+        CampaignSetting::ClassicFantasy,
+        CampaignSetting::Grimdark,
+        CampaignSetting::HighMagic,
+        CampaignSetting::LowMagic,
+        CampaignSetting::Horror,
+        CampaignSetting::Nautical,
+        CampaignSetting::UrbanIntrigue,
+    ];
     
-    match dm.chat(prompt, history).await {
-        Ok(response) => Ok(response),
-        Err(e) => {
-            print_fancy_message("The Dungeon Master cannot respond...", "red");
-            println!("Error: {}", e);
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                error_message,
-            )))
+    fn name(&self) -> &'static str {
+        match self {
+            CampaignSetting::ClassicFantasy => "Classic fantasy",
+            CampaignSetting::Grimdark => "Dark / grimdark",
+            CampaignSetting::HighMagic => "High magic",
+            CampaignSetting::LowMagic => "Low magic",
+            CampaignSetting::Horror => "Horror",
+            CampaignSetting::Nautical => "Nautical",
+            CampaignSetting::UrbanIntrigue => "Urban intrigue",
+        }
+    }
+    
+    fn guidance(&self) -> &'static str {
+        match self {
+            CampaignSetting::ClassicFantasy => "A classic high-fantasy world of heroes, dungeons, and dragons, where good and evil are clearly drawn.",
+            CampaignSetting::Grimdark => "A bleak, morally gray world where victories are costly, power corrupts, and even heroes make ugly compromises.",
+            CampaignSetting::HighMagic => "A world saturated with magic: spellcasters are common, enchanted wonders fill the cities, and the arcane shapes daily life.",
+            CampaignSetting::LowMagic => "A gritty world where magic is rare, feared, and dangerous; most problems are solved with steel, wits, and coin.",
+            CampaignSetting::Horror => "A world of creeping dread where the unknown is terrifying; build tension slowly, favor atmosphere over gore, and let threats stay half-seen.",
+            CampaignSetting::Nautical => "A world of seas and islands: ships, ports, pirates, storms, and whatever lurks beneath the waves drive the adventure.",
+            CampaignSetting::UrbanIntrigue => "A sprawling city of guilds, nobles, and secret societies, where information, favors, and reputation matter more than swords.",
         }
     }
 }
 
-async fn start_new_campaign(
-    dm: &impl Chat,
+fn choose_campaign_setting() -> CampaignSetting {
+    let names: Vec<&str> = CampaignSetting::ALL.iter().map(|s| s.name()).collect();
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Choose a setting for your campaign")
+        .default(0)
+        .items(&names)
+        .interact()
+        .unwrap_or(0);
+    CampaignSetting::ALL[index]
+}
+
+// Game state
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GameState {
+    #[serde(default)]
+    version: u32,
     character: Character,
-) -> Result<GameState, Box<dyn Error>> {
-    let mut state = GameState {
-        character,
-        date_started: Local::now().to_rfc3339(),
-        last_saved: Local::now().to_rfc3339(),
-        ..Default::default()
-    };
-    
-    // Create campaign prompt
-    let campaign_prompt = format!(
-        "You are the Dungeon Master for a Dungeons & Dragons 5e adventure. 
-        Create an exciting campaign hook and starting location for a {} {} named {}. 
-        The character is level {} with the following stats: 
-        STR {}, DEX {}, CON {}, INT {}, WIS {}, CHA {}.
-        Background: {}.
-        
-        Provide a brief introduction to the campaign setting, including:
-        1. The name of the campaign/adventure
-        2. The starting location (town/city/village name)
-        3. The initial quest or hook to draw the player in
-        4. A brief description of the area and its people
-        
-        Format your response with these section headers but focus on immersive, evocative descriptions rather than mechancial details. Make it engaging and atmospheric!",
-        state.character.race,
-        state.character.class,
-        state.character.name,
-        state.character.level,
-        state.character.strength,
-        state.character.dexterity,
-        state.character.constitution,
-        state.character.intelligence,
-        state.character.wisdom,
-        state.character.charisma,
-        state.character.background
-    );
-    
-    let campaign_response = dm_chat(
-        dm,
-        &campaign_prompt,
-        vec![],
-        "Failed to communicate with the Dungeon Master",
-        "The Dungeon Master is creating your adventure...",
-        5000,
-    )
-    .await?;
-    
-    // Parse the response for campaign details
-    for line in campaign_response.lines() {
-        if line.to_lowercase().contains("campaign") || line.to_lowercase().contains("adventure") {
-            if let Some(name) = line.split(":").nth(1) {
-                state.campaign = name.trim().to_string();
-            }
-        }
-        if line.to_lowercase().contains("location") {
-            if let Some(location) = line.split(":").nth(1) {
-                state.current_location = location.trim().to_string();
-            }
-        }
-        if line.to_lowercase().contains("quest") || line.to_lowercase().contains("hook") {
-            if let Some(quest) = line.split(":").nth(1) {
-                state.current_quest = quest.trim().to_string();
-            }
+    campaign: String,
+    current_location: String,
+    current_quest: String,
+    history: Vec<Message>,
+    date_started: String,
+    last_saved: String,
+    #[serde(default)]
+    encounter: Option<Encounter>,
+    #[serde(default)]
+    scene_type: SceneType,
+    #[serde(default)]
+    director_notes: Vec<String>,
+    #[serde(default)]
+    clock: GameClock,
+    #[serde(default)]
+    visited_locations: Vec<String>,
+    #[serde(default)]
+    setting: CampaignSetting,
+    // Whose turn it is for narrative focus outside combat: 0 is the player, then each companion.
+    // None while spotlight rotation is off.
+    #[serde(default)]
+    spotlight: Option<usize>,
+    #[serde(default)]
+    narration_length: NarrationLength,
+    #[serde(default)]
+    recent_rolls: VecDeque<RollRecord>,
+    // Established world facts ("The innkeeper is named Mara") the DM must not contradict
+    #[serde(default)]
+    canon_facts: Vec<String>,
+    // Known connections between places, from travel and [MAP: A - B] tags
+    #[serde(default)]
+    map_links: Vec<(String, String)>,
+    // Named dice expressions the player rolls often, e.g. "sneak attack" -> 3d6
+    #[serde(default)]
+    macros: HashMap<String, DiceExpr>,
+    // Major story beats in the order they happened, from [EVENT: ...] tags and the Timeline menu
+    #[serde(default)]
+    events: Vec<TimelineEvent>,
+    // Shared loot (the cart, the bag of holding); nothing here counts toward the character's gear
+    #[serde(default)]
+    party_stash: Vec<Item>,
+    #[serde(default)]
+    environment: Environment,
+}
+
+// A dated story beat on the campaign timeline
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct TimelineEvent {
+    day: u64,
+    title: String,
+    description: String,
+}
+
+impl fmt::Display for TimelineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Day {}: {}", self.day, self.title)?;
+        if !self.description.is_empty() {
+            write!(f, " - {}", self.description)?;
         }
+        Ok(())
+    }
+}
+
+// How many of the latest timeline events are restated to the DM each turn
+const TIMELINE_CONTEXT_EVENTS: usize = 8;
+
+// In-game time, counted in minutes since midnight of day 1
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct GameClock {
+    minutes: u64,
+}
+
+impl Default for GameClock {
+    // Adventures begin at 8 in the morning
+    fn default() -> Self {
+        GameClock { minutes: 8 * 60 }
+    }
+}
+
+impl GameClock {
+    fn advance(&mut self, minutes: u64) {
+        self.minutes += minutes;
     }
     
-    // If we couldn't parse the details, use default values
-    if state.campaign.is_empty() {
-        state.campaign = "Mystical Adventure".to_string();
+    fn day(&self) -> u64 {
+        self.minutes / (24 * 60) + 1
     }
-    if state.current_location.is_empty() {
-        state.current_location = "Starting Town".to_string();
+}
+
+impl fmt::Display for GameClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minute_of_day = self.minutes % (24 * 60);
+        write!(f, "Day {}, {:02}:{:02}", self.day(), minute_of_day / 60, minute_of_day % 60)
     }
-    if state.current_quest.is_empty() {
-        state.current_quest = "Find adventure".to_string();
+}
+
+impl GameState {
+    // Number of completed exchanges with the DM
+    fn turn(&self) -> usize {
+        self.history.len() / 2
     }
     
-    // Add to history
-    state.history.push(Message::user(&campaign_prompt));
-    state.history.push(Message::assistant(&campaign_response));
-    
-    // Add a scene-setting message
-    let scene_setting = "Now, describe the opening scene. The player's character has just arrived at the starting location. Provide rich sensory details and introduce an NPC or situation that connects to the quest hook. End with a question or prompt for the player to respond to.";
+    // The party member in the spotlight, if rotation is on
+    fn spotlight_name(&self) -> Option<&str> {
+        match self.spotlight? {
+            0 => Some(&self.character.name),
+            i => self.character.companions.get(i - 1).map(|c| c.name.as_str()).or(Some(&self.character.name)),
+        }
+    }
     
-    let scene_response = dm_chat(
-        dm,
-        scene_setting,
-        state.history.clone(),
-        "Failed to set the scene",
-        "The Dungeon Master is setting the scene...",
-        2500,
-    )
-    .await?;
+    // Records that two places connect, in either direction
+    fn add_map_link(&mut self, a: &str, b: &str) {
+        let (a, b) = (a.trim(), b.trim());
+        if a.is_empty() || b.is_empty() || a.eq_ignore_ascii_case(b) {
+            return;
+        }
+        let known = self.map_links.iter().any(|(x, y)| {
+            (x.eq_ignore_ascii_case(a) && y.eq_ignore_ascii_case(b)) || (x.eq_ignore_ascii_case(b) && y.eq_ignore_ascii_case(a))
+        });
+        if !known {
+            self.map_links.push((a.to_string(), b.to_string()));
+        }
+    }
     
-    state.history.push(Message::user(scene_setting));
-    state.history.push(Message::assistant(&scene_response));
+    // Companions fight at the player's level for encounter budgeting
+    fn party_levels(&self) -> Vec<u32> {
+        vec![self.character.level; self.character.companions.len() + 1]
+    }
     
-    save_game(&state)?;
+    // Round-robin over the player and their companions
+    fn advance_spotlight(&mut self) {
+        if let Some(current) = self.spotlight {
+            self.spotlight = Some((current + 1) % (self.character.companions.len() + 1));
+        }
+    }
     
-    Ok(state)
+    // Adds a roll to the roll history, keeping only the most recent RECENT_ROLLS_LEN
+    fn record_roll(&mut self, expr: &str, dice: &[u32], total: i32, context: &str) {
+        self.recent_rolls.push_back(RollRecord {
+            expr: expr.to_string(),
+            dice: dice.to_vec(),
+            total,
+            context: context.to_string(),
+            timestamp: Local::now().to_rfc3339(),
+        });
+        while self.recent_rolls.len() > RECENT_ROLLS_LEN {
+            self.recent_rolls.pop_front();
+        }
+    }
 }
 
-async fn process_player_action(
-    dm: &impl Chat,
-    action: &str,
-    state: &mut GameState,
-) -> Result<String, Box<dyn Error>> {
-    // Construct the action prompt
-    let action_prompt = format!(
-        "The player ({} the {} {}) takes the following action:\n\n{}
-        
-        Respond as the Dungeon Master, describing the outcome of this action. 
-        Use rich, evocative language to create an immersive experience.
-        If dice rolls would be needed, describe the check but don't roll dice yourself.
-        End with either a question or a prompt that gives the player clear options for what they might do next.
-        If the player attempts something impossible, gently steer them toward better options.",
-        state.character.name,
-        state.character.race,
-        state.character.class,
-        action
-    );
-    
-    let response = dm_chat(
-        dm,
-        &action_prompt,
-        state.history.clone(),
-        "Failed to process your action",
-        "The Dungeon Master is responding...",
-        2000,
-    )
-    .await?;
-    
-    state.history.push(Message::user(&action_prompt));
-    state.history.push(Message::assistant(&response));
-    
-    state.last_saved = Local::now().to_rfc3339();
-    save_game(state)?;
-    
-    Ok(response)
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            version: SAVE_VERSION,
+            character: Character::default(),
+            campaign: String::new(),
+            current_location: String::new(),
+            current_quest: String::new(),
+            history: Vec::new(),
+            date_started: Local::now().to_rfc3339(),
+            last_saved: Local::now().to_rfc3339(),
+            encounter: None,
+            scene_type: SceneType::default(),
+            director_notes: Vec::new(),
+            clock: GameClock::default(),
+            visited_locations: Vec::new(),
+            setting: CampaignSetting::default(),
+            spotlight: None,
+            narration_length: NarrationLength::default(),
+            recent_rolls: VecDeque::new(),
+            canon_facts: Vec::new(),
+            map_links: Vec::new(),
+            macros: HashMap::new(),
+            events: Vec::new(),
+            party_stash: Vec::new(),
+            environment: Environment::default(),
+        }
+    }
 }
 
-async fn roll_skill_check(
-    dm: &impl Chat,
-    skill: &str,
-    roll_result: u32,
-    purpose: &str,
-    state: &mut GameState,
-) -> Result<String, Box<dyn Error>> {
-    // Get the appropriate ability modifier based on the skill
-    let ability_mod = match skill {
-        "Athletics" => (state.character.strength as i32 - 10) / 2,
-        "Acrobatics" | "Sleight of Hand" | "Stealth" => (state.character.dexterity as i32 - 10) / 2,
-        "Arcana" | "History" | "Investigation" | "Nature" | "Religion" => (state.character.intelligence as i32 - 10) / 2,
-        "Animal Handling" | "Insight" | "Medicine" | "Perception" | "Survival" => (state.character.wisdom as i32 - 10) / 2,
-        "Deception" | "Intimidation" | "Performance" | "Persuasion" => (state.character.charisma as i32 - 10) / 2,
-        _ => 0,
-    };
-    
-    // Apply proficiency bonus if proficient
-    let prof_bonus = match state.character.level {
+// Rules helpers
+fn ability_modifier(score: u32) -> i32 {
+    (score as i32 - 10).div_euclid(2)
+}
+
+fn proficiency_bonus(level: u32) -> i32 {
+    match level {
         1..=4 => 2,
         5..=8 => 3,
         9..=12 => 4,
         13..=16 => 5,
         _ => 6,
-    };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Ability {
+    Strength,
+    Dexterity,
+    Constitution,
+    Intelligence,
+    Wisdom,
+    Charisma,
+}
+
+impl Ability {
+    const ALL: [Ability; 6] = [
+        Ability::Strength, Ability::Dexterity, Ability::Constitution,
+        Ability::Intelligence, Ability::Wisdom, Ability::Charisma,
+    ];
     
-    let is_proficient = state.character.skills.get(skill).unwrap_or(&false);
-    let total = roll_result as i32 + ability_mod + if *is_proficient { prof_bonus } else { 0 };
+    fn abbreviation(&self) -> &'static str {
+        match self {
+            Ability::Strength => "STR",
+            Ability::Dexterity => "DEX",
+            Ability::Constitution => "CON",
+            Ability::Intelligence => "INT",
+            Ability::Wisdom => "WIS",
+            Ability::Charisma => "CHA",
+        }
+    }
     
-    let roll_prompt = format!(
-        "The player ({} the {} {}) rolls a {} check for the following purpose: \"{}\"
-        Dice roll: {}
-        Ability modifier: {}
-        Proficiency: {}
-        Total: {}
-        
-        As the DM, first evaluate whether this is an appropriate use of the {} skill.
-        If it is appropriate, interpret this skill check result and describe the outcome.
-        If it's not appropriate, explain why and suggest a better approach or skill.
-        
-        For reference, typical difficulty classes are:
-        - Easy: 10
-        - Medium: 15
-        - Hard: 20
-        - Very Hard: 25
-        - Nearly Impossible: 30
-        
-        Continue the scene after describing the result of this check.",
-        state.character.name,
-        state.character.race,
-        state.character.class,
-        skill,
-        purpose,
-        roll_result,
-        ability_mod,
-        if *is_proficient { format!("Yes (+{})", prof_bonus) } else { "No".to_string() },
-        total,
-        skill
-    );
-    
-    let response = dm_chat(
-        dm,
-        &roll_prompt,
-        state.history.clone(),
-        "Failed to process skill check",
-        "The Dungeon Master is resolving your check...",
-        2000,
-    )
-    .await?;
-    
-    state.history.push(Message::user(&roll_prompt));
-    state.history.push(Message::assistant(&response));
-    
-    state.last_saved = Local::now().to_rfc3339();
-    save_game(state)?;
+    fn name(&self) -> &'static str {
+        match self {
+            Ability::Strength => "Strength",
+            Ability::Dexterity => "Dexterity",
+            Ability::Constitution => "Constitution",
+            Ability::Intelligence => "Intelligence",
+            Ability::Wisdom => "Wisdom",
+            Ability::Charisma => "Charisma",
+        }
+    }
     
-    Ok(response)
+    // Accepts "dex", "DEX" or "dexterity"
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.trim();
+        Self::ALL
+            .into_iter()
+            .find(|a| a.abbreviation().eq_ignore_ascii_case(name) || a.name().eq_ignore_ascii_case(name))
+    }
 }
 
-// Character creation functions
-fn create_character() -> Character {
-    let mut character = Character::default();
-    
-    print_fancy_message("CHARACTER CREATION", "cyan");
-    println!("{}", "Let's create your D&D character!".bright_white());
-    
-    // Get character name
-    let name: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("What is your character's name?")
-        .interact_text()
-        .unwrap_or_else(|_| "Adventurer".to_string());
-    character.name = name;
-    
-    // Choose race
-    let races = vec![
-        "Human", "Elf", "Dwarf", "Halfling", "Gnome",
-        "Half-Elf", "Half-Orc", "Tiefling", "Dragonborn"
-    ];
-    
-    println!("\n{}", "Choose your race:".bright_yellow());
-    let race_index = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Race")
-        .default(0)
-        .items(&races)
-        .interact()
-        .unwrap_or(0);
-    
-    character.race = races[race_index].to_string();
-    
-    // Choose class
-    let classes = vec![
-        "Fighter", "Wizard", "Cleric", "Rogue", "Ranger",
-        "Paladin", "Barbarian", "Bard", "Druid", "Monk",
-        "Sorcerer", "Warlock", "Artificer"
-    ];
-    
-    println!("\n{}", "Choose your class:".bright_yellow());
-    let class_index = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Class")
-        .default(0)
-        .items(&classes)
-        .interact()
-        .unwrap_or(0);
-    
-    character.class = classes[class_index].to_string();
-    
-    // Choose background
-    let backgrounds = vec![
-        "Acolyte", "Charlatan", "Criminal", "Entertainer", "Folk Hero",
-        "Guild Artisan", "Hermit", "Noble", "Outlander", "Sage",
-        "Sailor", "Soldier", "Urchin"
-    ];
-    
-    println!("\n{}", "Choose your background:".bright_yellow());
-    let bg_index = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Background")
-        .default(0)
-        .items(&backgrounds)
-        .interact()
-        .unwrap_or(0);
-    
-    character.background = backgrounds[bg_index].to_string();
+// Homebrew races and classes, merged into the creation lists alongside the built-in ones
+const HOMEBREW_FILE: &str = "homebrew.json";
+const BUILT_IN_RACES: [&str; 9] = ["Human", "Elf", "Dwarf", "Halfling", "Gnome", "Half-Elf", "Half-Orc", "Tiefling", "Dragonborn"];
+const BUILT_IN_CLASSES: [&str; 13] = [
+    "Fighter", "Wizard", "Cleric", "Rogue", "Ranger", "Paladin", "Barbarian", "Bard", "Druid", "Monk",
+    "Sorcerer", "Warlock", "Artificer",
+];
+
+#[derive(Deserialize, Default, Clone, Debug)]
+struct Homebrew {
+    #[serde(default)]
+    races: Vec<serde_json::Value>,
+    #[serde(default)]
+    classes: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct HomebrewRace {
+    name: String,
+    // Passed to the DM so it knows what the race is like
+    #[serde(default)]
+    description: String,
+}
+
+// How quickly a homebrew caster reaches higher spell levels: like a Wizard, or like a Paladin
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SpellProgression {
+    #[default]
+    Full,
+    Half,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct HomebrewClass {
+    name: String,
+    hit_die: u32,
+    saving_throws: Vec<String>,
+    skill_choices: Vec<String>,
+    skill_count: usize,
+    starting_equipment: Vec<String>,
+    #[serde(default)]
+    starting_gold: u32,
+    // None for non-casters
+    #[serde(default)]
+    spellcasting_ability: Option<String>,
+    #[serde(default)]
+    spell_progression: SpellProgression,
+}
+
+impl HomebrewClass {
+    fn save_proficiencies(&self) -> [Ability; 2] {
+        let abilities: Vec<Ability> = self.saving_throws.iter().filter_map(|a| Ability::parse(a)).collect();
+        [abilities[0], abilities[1]]
+    }
     
-    // Roll or assign ability scores
-    println!("\n{}", "How would you like to determine your ability scores?".bright_yellow());
-    let score_methods = vec!["Roll 4d6 (drop lowest)", "Standard Array", "Point Buy"];
-    let score_method = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Method")
-        .default(0)
-        .items(&score_methods)
-        .interact()
-        .unwrap_or(0);
+    fn casting_ability(&self) -> Option<Ability> {
+        self.spellcasting_ability.as_deref().and_then(Ability::parse)
+    }
     
-    let mut scores = Vec::new();
+    // Everything serde can't check: real abilities and skills, a standard hit die, no clash with a built-in class
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is empty".to_string());
+        }
+        if BUILT_IN_CLASSES.iter().any(|c| c.eq_ignore_ascii_case(self.name.trim())) {
+            return Err(format!("{} is already a built-in class", self.name));
+        }
+        if ![6, 8, 10, 12].contains(&self.hit_die) {
+            return Err(format!("hit_die must be 6, 8, 10 or 12, not {}", self.hit_die));
+        }
+        if self.saving_throws.len() != 2 {
+            return Err("saving_throws must name exactly two abilities".to_string());
+        }
+        if let Some(bad) = self.saving_throws.iter().find(|a| Ability::parse(a).is_none()) {
+            return Err(format!("unknown saving throw ability {}", bad));
+        }
+        if let Some(bad) = self.skill_choices.iter().find(|s| skill_ability(s).is_none()) {
+            return Err(format!("unknown skill {}", bad));
+        }
+        if self.skill_count == 0 || self.skill_count > self.skill_choices.len() {
+            return Err(format!("skill_count must be between 1 and the {} skill choices", self.skill_choices.len()));
+        }
+        if let Some(ability) = &self.spellcasting_ability && Ability::parse(ability).is_none() {
+            return Err(format!("unknown spellcasting ability {}", ability));
+        }
+        if self.starting_equipment.is_empty() {
+            return Err("starting_equipment must list at least one item".to_string());
+        }
+        if self.starting_equipment.iter().any(|item| item.trim().is_empty()) {
+            return Err("starting_equipment has an empty item name".to_string());
+        }
+        // The kit is worn as one suit of armor, so a second would be dropped
+        let armor: Vec<&String> = self.starting_equipment.iter().filter(|item| find_armor(item).is_some()).collect();
+        if armor.len() > 1 {
+            return Err(format!("starting_equipment has more than one suit of armor ({})", armor.iter().map(|a| a.as_str()).collect::<Vec<_>>().join(", ")));
+        }
+        Ok(())
+    }
+}
+
+// Only the entries that passed validation
+#[derive(Default, Clone, Debug)]
+struct HomebrewContent {
+    races: Vec<HomebrewRace>,
+    classes: Vec<HomebrewClass>,
+}
+
+static HOMEBREW: RwLock<Option<HomebrewContent>> = RwLock::new(None);
+
+fn homebrew_class(name: &str) -> Option<HomebrewClass> {
+    HOMEBREW.read().unwrap().as_ref()?.classes.iter().find(|c| c.name == name).cloned()
+}
+
+fn homebrew_race(name: &str) -> Option<HomebrewRace> {
+    HOMEBREW.read().unwrap().as_ref()?.races.iter().find(|r| r.name == name).cloned()
+}
+
+fn homebrew_content() -> HomebrewContent {
+    HOMEBREW.read().unwrap().clone().unwrap_or_default()
+}
+
+// Reads HOMEBREW_FILE if there is one, skipping (and reporting) any entry that is missing fields or invalid
+fn load_homebrew() {
+    let Ok(contents) = fs::read_to_string(HOMEBREW_FILE) else {
+        return;
+    };
+    let homebrew: Homebrew = match serde_json::from_str(&contents) {
+        Ok(homebrew) => homebrew,
+        Err(e) => {
+            print_fancy_message(&format!("Could not read {}: {}", HOMEBREW_FILE, e), "red");
+            return;
+        }
+    };
+    let mut content = HomebrewContent::default();
+    for (i, entry) in homebrew.races.into_iter().enumerate() {
+        let taken = |name: &str| {
+            BUILT_IN_RACES.iter().any(|r| r.eq_ignore_ascii_case(name)) || content.races.iter().any(|r| r.name.eq_ignore_ascii_case(name))
+        };
+        match serde_json::from_value::<HomebrewRace>(entry) {
+            Ok(race) if race.name.trim().is_empty() => print_fancy_message(&format!("Skipping homebrew race {}: name is empty", i + 1), "yellow"),
+            Ok(race) if taken(race.name.trim()) => {
+                print_fancy_message(&format!("Skipping homebrew race {}: {} is already a race", i + 1, race.name), "yellow")
+            }
+            Ok(race) => content.races.push(race),
+            Err(e) => print_fancy_message(&format!("Skipping homebrew race {}: {}", i + 1, e), "yellow"),
+        }
+    }
+    for (i, entry) in homebrew.classes.into_iter().enumerate() {
+        match serde_json::from_value::<HomebrewClass>(entry).map_err(|e| e.to_string()).and_then(|class| class.validate().map(|_| class)) {
+            Ok(class) if content.classes.iter().any(|c| c.name.eq_ignore_ascii_case(&class.name)) => {
+                print_fancy_message(&format!("Skipping homebrew class {}: {} is defined twice", i + 1, class.name), "yellow")
+            }
+            Ok(class) => content.classes.push(class),
+            Err(e) => print_fancy_message(&format!("Skipping homebrew class {}: {}", i + 1, e), "yellow"),
+        }
+    }
+    if !content.races.is_empty() || !content.classes.is_empty() {
+        print_fancy_message(&format!("Loaded {} homebrew race(s) and {} class(es) from {}", content.races.len(), content.classes.len(), HOMEBREW_FILE), "green");
+    }
+    *HOMEBREW.write().unwrap() = Some(content);
+}
+
+// Saving throw proficiencies granted by each class
+fn class_save_proficiencies(class: &str) -> [Ability; 2] {
+    if let Some(homebrew) = homebrew_class(class) {
+        return homebrew.save_proficiencies();
+    }
+    match class {
+        "Barbarian" | "Fighter" => [Ability::Strength, Ability::Constitution],
+        "Bard" => [Ability::Dexterity, Ability::Charisma],
+        "Cleric" | "Paladin" | "Warlock" => [Ability::Wisdom, Ability::Charisma],
+        "Druid" | "Wizard" => [Ability::Intelligence, Ability::Wisdom],
+        "Monk" | "Ranger" => [Ability::Strength, Ability::Dexterity],
+        "Rogue" => [Ability::Dexterity, Ability::Intelligence],
+        "Sorcerer" => [Ability::Constitution, Ability::Charisma],
+        "Artificer" => [Ability::Constitution, Ability::Intelligence],
+        _ => [Ability::Strength, Ability::Constitution],
+    }
+}
+
+// The 18 skills and the ability each one is keyed off
+const SKILLS: [(&str, Ability); 18] = [
+    ("Acrobatics", Ability::Dexterity),
+    ("Animal Handling", Ability::Wisdom),
+    ("Arcana", Ability::Intelligence),
+    ("Athletics", Ability::Strength),
+    ("Deception", Ability::Charisma),
+    ("History", Ability::Intelligence),
+    ("Insight", Ability::Wisdom),
+    ("Intimidation", Ability::Charisma),
+    ("Investigation", Ability::Intelligence),
+    ("Medicine", Ability::Wisdom),
+    ("Nature", Ability::Intelligence),
+    ("Perception", Ability::Wisdom),
+    ("Performance", Ability::Charisma),
+    ("Persuasion", Ability::Charisma),
+    ("Religion", Ability::Intelligence),
+    ("Sleight of Hand", Ability::Dexterity),
+    ("Stealth", Ability::Dexterity),
+    ("Survival", Ability::Wisdom),
+];
+
+fn skill_ability(skill: &str) -> Option<Ability> {
+    SKILLS.iter().find(|(name, _)| *name == skill).map(|(_, ability)| *ability)
+}
+
+// Shorthand typed at the action menu: "r 1d20+5", "c stealth", "s dex", each optionally followed by a purpose
+#[derive(Debug, PartialEq)]
+enum QuickCommand {
+    Roll(DiceExpr),
+    Check(&'static str, RollMode),
+    Save(Ability, RollMode),
+}
+
+// A leading "adv" or "dis" on the purpose rolls the check or save with advantage or disadvantage
+fn split_roll_mode(purpose: &str) -> (RollMode, String) {
+    let purpose = purpose.trim();
+    let (first, rest) = purpose.split_once(char::is_whitespace).unwrap_or((purpose, ""));
+    match first.to_lowercase().as_str() {
+        "adv" | "advantage" => (RollMode::Advantage, rest.trim().to_string()),
+        "dis" | "disadvantage" => (RollMode::Disadvantage, rest.trim().to_string()),
+        _ => (RollMode::Normal, purpose.to_string()),
+    }
+}
+
+// "sneak attack on the goblin" finds the longest macro name the input starts with; the rest is the purpose
+fn find_macro<'a>(macros: &'a HashMap<String, DiceExpr>, input: &str) -> Option<(&'a str, &'a DiceExpr, String)> {
+    let input = input.trim();
+    let lower = input.to_lowercase();
+    macros
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.to_lowercase();
+            lower == name || lower.starts_with(&format!("{} ", name))
+        })
+        .max_by_key(|(name, _)| name.len())
+        .map(|(name, expr)| (name.as_str(), expr, input.get(name.len()..).unwrap_or("").trim().to_string()))
+}
+
+fn parse_quick_command(input: &str) -> Result<(QuickCommand, String), String> {
+    let input = input.trim();
+    let (verb, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let rest = rest.trim();
+    let (target, purpose) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
     
-    match score_method {
-        0 => {
-            // Roll 4d6 drop lowest
-            println!("\n{}", "Rolling ability scores (4d6 drop lowest)...".bright_blue());
-            for i in 0..6 {
-                let mut roll = roll_dice(4, 6);
-                roll.sort();
-                roll.remove(0); // Remove lowest die
-                let score: u32 = roll.iter().sum();
-                println!("Roll {}: {} = {}", i + 1, 
-                         roll.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(", "),
-                         score);
-                scores.push(score);
+    match verb.to_lowercase().as_str() {
+        "r" | "roll" => DiceExpr::parse(target)
+            .map(|expr| (QuickCommand::Roll(expr), purpose.trim().to_string()))
+            .ok_or_else(|| format!("\"{}\" isn't dice notation (try 1d20+5)", target)),
+        "c" | "check" => {
+            // Full skill names may span several words ("sleight of hand"); otherwise match a unique prefix
+            let lower = rest.to_lowercase();
+            if let Some((name, _)) = SKILLS.iter().filter(|(name, _)| {
+                let name = name.to_lowercase();
+                lower == name || lower.starts_with(&format!("{} ", name))
+            }).max_by_key(|(name, _)| name.len()) {
+                let (mode, purpose) = split_roll_mode(&rest[name.len()..]);
+                return Ok((QuickCommand::Check(name, mode), purpose));
+            }
+            let target = target.to_lowercase();
+            let matches: Vec<&str> = SKILLS
+                .iter()
+                .map(|(name, _)| *name)
+                .filter(|name| !target.is_empty() && name.to_lowercase().starts_with(&target))
+                .collect();
+            match matches.as_slice() {
+                [skill] => {
+                    let (mode, purpose) = split_roll_mode(purpose);
+                    Ok((QuickCommand::Check(skill, mode), purpose))
+                },
+                [] => Err(format!("No skill matches \"{}\"", target)),
+                _ => Err(format!("\"{}\" could be {}", target, matches.join(", "))),
             }
         },
-        1 => {
-            // Standard Array
-            println!("\n{}", "Using Standard Array: 15, 14, 13, 12, 10, 8".bright_blue());
-            scores = vec![15, 14, 13, 12, 10, 8];
-        },
-        _ => {
-            // Point Buy (simplified)
-            println!("\n{}", "Using Point Buy (27 points)".bright_blue());
-            scores = vec![13, 13, 13, 12, 12, 8];
+        "s" | "save" => Ability::parse(target)
+            .map(|ability| {
+                let (mode, purpose) = split_roll_mode(purpose);
+                (QuickCommand::Save(ability, mode), purpose)
+            })
+            .ok_or_else(|| format!("\"{}\" isn't an ability (try dex or wisdom)", target)),
+        _ => Err("Use r <dice>, c <skill>, or s <ability>, optionally followed by what it's for".to_string()),
+    }
+}
+
+// How long a temporary effect lasts
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum EffectDuration {
+    Rounds(u32),
+    UntilShortRest,
+    UntilLongRest,
+    UntilRemoved,
+}
+
+impl fmt::Display for EffectDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EffectDuration::Rounds(1) => write!(f, "1 round"),
+            EffectDuration::Rounds(n) => write!(f, "{} rounds", n),
+            EffectDuration::UntilShortRest => write!(f, "until a short or long rest"),
+            EffectDuration::UntilLongRest => write!(f, "until a long rest"),
+            EffectDuration::UntilRemoved => write!(f, "until removed"),
         }
     }
-    
-    // Assign ability scores
-    let abilities = vec!["Strength", "Dexterity", "Constitution", "Intelligence", "Wisdom", "Charisma"];
-    let mut assigned_scores = HashMap::new();
-    
-    println!("\n{}", "Assign your ability scores:".bright_yellow());
-    for ability in &abilities {
-        let available_scores: Vec<String> = scores
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        
-        println!("\nAvailable scores: {}", available_scores.join(", "));
-        let score_index = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!("Choose a score for {}", ability))
-            .items(&available_scores)
-            .default(0)
-            .interact()
-            .unwrap_or(0);
-        
-        assigned_scores.insert(ability.to_string(), scores[score_index]);
-        scores.remove(score_index);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RestKind {
+    Short,
+    Long,
+}
+
+// A temporary buff or drain on an ability score, e.g. Enhance Ability or a shadow's touch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AbilityModifier {
+    ability: Ability,
+    delta: i32,
+    source: String,
+    duration: EffectDuration,
+}
+
+// Raises or lowers the HP ceiling without touching the base maximum, e.g. a mummy's rot or variant exhaustion
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct MaxHpModifier {
+    delta: i32,
+    source: String,
+    duration: EffectDuration,
+}
+
+// The HP log line for a change to maximum HP, worded by its direction
+fn max_hp_change_reason(delta: i32, source: &str) -> String {
+    format!("Maximum HP {} ({})", if delta < 0 { "lowered" } else { "raised" }, source)
+}
+
+impl fmt::Display for MaxHpModifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "max HP {} ({}, {})", format_modifier(self.delta), self.source, self.duration)
     }
-    
-    character.strength = *assigned_scores.get("Strength").unwrap_or(&10);
-    character.dexterity = *assigned_scores.get("Dexterity").unwrap_or(&10);
-    character.constitution = *assigned_scores.get("Constitution").unwrap_or(&10);
-    character.intelligence = *assigned_scores.get("Intelligence").unwrap_or(&10);
-    character.wisdom = *assigned_scores.get("Wisdom").unwrap_or(&10);
-    character.charisma = *assigned_scores.get("Charisma").unwrap_or(&10);
-    
-    // Calculate hit points based on class and constitution
-    let con_modifier = (character.constitution as i32 - 10) / 2;
-    let base_hp = match character.class.as_str() {
+}
+
+// Combat rounds are six seconds long
+const ROUNDS_PER_MINUTE: u32 = 10;
+
+// A running spell or similar effect such as Bless or Spirit Guardians, counted down in rounds
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ActiveEffect {
+    name: String,
+    remaining: u32,
+    concentration: bool,
+}
+
+impl fmt::Display for ActiveEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (", self.name)?;
+        if self.remaining >= ROUNDS_PER_MINUTE {
+            write!(f, "{} min", self.remaining.div_ceil(ROUNDS_PER_MINUTE))?;
+        } else if self.remaining == 1 {
+            write!(f, "1 round")?;
+        } else {
+            write!(f, "{} rounds", self.remaining)?;
+        }
+        if self.concentration {
+            write!(f, ", concentration")?;
+        }
+        write!(f, ")")
+    }
+}
+
+fn hit_die(class: &str) -> u32 {
+    if let Some(homebrew) = homebrew_class(class) {
+        return homebrew.hit_die;
+    }
+    match class {
         "Barbarian" => 12,
         "Fighter" | "Paladin" | "Ranger" => 10,
-        "Cleric" | "Druid" | "Monk" => 8,
-        "Bard" | "Rogue" | "Warlock" => 8,
         "Sorcerer" | "Wizard" => 6,
         _ => 8,
-    };
-    
-    character.hit_points = (base_hp as i32 + con_modifier).max(1) as u32;
-    character.max_hit_points = character.hit_points;
-    
-    // Set armor class based on dexterity
-    let dex_modifier = (character.dexterity as i32 - 10) / 2;
-    character.armor_class = (10 + dex_modifier).max(1) as u32;
+    }
+}
+
+// The skills a class picks its proficiencies from
+fn class_skill_list(class: &str) -> Vec<&'static str> {
+    match class {
+        "Barbarian" => vec!["Animal Handling", "Athletics", "Intimidation", "Nature", "Perception", "Survival"],
+        "Bard" => SKILLS.iter().map(|(skill, _)| *skill).collect(),
+        "Cleric" => vec!["History", "Insight", "Medicine", "Persuasion", "Religion"],
+        "Druid" => vec!["Arcana", "Animal Handling", "Insight", "Medicine", "Nature", "Perception", "Religion", "Survival"],
+        "Fighter" => vec!["Acrobatics", "Animal Handling", "Athletics", "History", "Insight", "Intimidation", "Perception", "Survival"],
+        "Monk" => vec!["Acrobatics", "Athletics", "History", "Insight", "Religion", "Stealth"],
+        "Paladin" => vec!["Athletics", "Insight", "Intimidation", "Medicine", "Persuasion", "Religion"],
+        "Ranger" => vec!["Animal Handling", "Athletics", "Insight", "Investigation", "Nature", "Perception", "Stealth", "Survival"],
+        "Rogue" => vec!["Acrobatics", "Athletics", "Deception", "Insight", "Intimidation", "Investigation", "Perception", "Performance", "Persuasion", "Sleight of Hand", "Stealth"],
+        "Sorcerer" => vec!["Arcana", "Deception", "Insight", "Intimidation", "Persuasion", "Religion"],
+        "Warlock" => vec!["Arcana", "Deception", "History", "Intimidation", "Investigation", "Nature", "Religion"],
+        "Wizard" => vec!["Arcana", "History", "Insight", "Investigation", "Medicine", "Religion"],
+        _ => vec!["Arcana", "History", "Investigation", "Nature", "Religion"],
+    }
+}
+
+// Multiclassing into a class grants only part of its proficiencies (PHB table):
+// one skill for Bards, Rangers and Rogues, plus these tools and weapons
+fn multiclass_skill_count(class: &str) -> usize {
+    match class {
+        "Bard" | "Ranger" | "Rogue" => 1,
+        _ => 0,
+    }
+}
+
+fn multiclass_tool_proficiencies(class: &str) -> &'static [&'static str] {
+    match class {
+        "Rogue" => &["Thieves' tools"],
+        "Artificer" => &["Thieves' tools", "Tinker's tools"],
+        _ => &[],
+    }
+}
+
+fn multiclass_weapon_proficiency(class: &str, weapon: &Weapon) -> bool {
+    match class {
+        "Barbarian" | "Fighter" | "Paladin" | "Ranger" => true,
+        "Monk" => weapon.category == WeaponCategory::Simple || weapon.name == "Shortsword",
+        "Warlock" => weapon.category == WeaponCategory::Simple,
+        _ => false,
+    }
+}
+
+// Skills with Expertise a class has earned by a level: Rogues pick two at 1st and 6th, Bards at 3rd and 10th
+fn expertise_granted(class: &str, level: u32) -> usize {
+    let levels: &[u32] = match class {
+        "Rogue" => &[1, 6],
+        "Bard" => &[3, 10],
+        _ => &[],
+    };
+    2 * levels.iter().filter(|l| **l <= level).count()
+}
+
+// Levels that grant an ability score improvement or a feat
+const ASI_LEVELS: [u32; 5] = [4, 8, 12, 16, 19];
+
+// The ability a class casts its spells with; None for non-casters
+fn spellcasting_ability(class: &str) -> Option<Ability> {
+    if let Some(homebrew) = homebrew_class(class) {
+        return homebrew.casting_ability();
+    }
+    match class {
+        "Bard" | "Paladin" | "Sorcerer" | "Warlock" => Some(Ability::Charisma),
+        "Cleric" | "Druid" | "Ranger" => Some(Ability::Wisdom),
+        "Wizard" | "Artificer" => Some(Ability::Intelligence),
+        _ => None,
+    }
+}
+
+// Highest spell level a class can cast at a given character level; None for non-casters
+fn max_spell_level(class: &str, level: u32) -> Option<u32> {
+    if let Some(homebrew) = homebrew_class(class) {
+        homebrew.casting_ability()?;
+        return Some(match homebrew.spell_progression {
+            SpellProgression::Full => level.div_ceil(2).min(9),
+            SpellProgression::Half => if level < 2 { 0 } else { level.div_ceil(4).min(5) },
+        });
+    }
+    match class {
+        "Bard" | "Cleric" | "Druid" | "Sorcerer" | "Wizard" => Some(level.div_ceil(2).min(9)),
+        "Warlock" => Some(level.div_ceil(2).min(5)),
+        "Artificer" => Some(level.div_ceil(4).min(5)),
+        "Paladin" | "Ranger" => Some(if level < 2 { 0 } else { level.div_ceil(4).min(5) }),
+        _ => None,
+    }
+}
+
+// How many leveled spells a class can know (roughly the 5e tables); None when the class
+// prepares from its whole list instead. Wizards count the spells in their spellbook.
+fn spells_known_limit(class: &str, level: u32) -> Option<usize> {
+    let level = level as usize;
+    match class {
+        "Wizard" => Some(6 + 2 * (level - 1)),
+        "Bard" => Some((level + 3).min(22)),
+        "Sorcerer" | "Warlock" => Some((level + 1).min(15)),
+        "Ranger" => Some(if level < 2 { 0 } else { (level / 2 + 1).min(11) }),
+        _ => None,
+    }
+}
+
+// Warlock pact magic by Warlock level: how many slots, all of the same level, back on a short rest
+fn pact_magic_slots(level: u32) -> (u32, u32) {
+    let count = match level {
+        0 | 1 => 1,
+        2..=10 => 2,
+        11..=16 => 3,
+        _ => 4,
+    };
+    (count, level.div_ceil(2).clamp(1, 5))
+}
+
+// Copying a spell into a wizard's spellbook costs 50 gp and 2 hours per spell level
+const SPELL_COPY_GOLD_PER_LEVEL: u32 = 50;
+const SPELL_COPY_HOURS_PER_LEVEL: u32 = 2;
+
+fn format_modifier(modifier: i32) -> String {
+    if modifier >= 0 {
+        format!("+{}", modifier)
+    } else {
+        modifier.to_string()
+    }
+}
+
+// Weapon data (SRD 5.2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WeaponCategory {
+    Simple,
+    Martial,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Weapon {
+    name: &'static str,
+    category: WeaponCategory,
+    damage: &'static str,
+    damage_type: &'static str,
+    finesse: bool,
+    ranged: bool,
+}
+
+const fn weapon(
+    name: &'static str,
+    category: WeaponCategory,
+    damage: &'static str,
+    damage_type: &'static str,
+    finesse: bool,
+    ranged: bool,
+) -> Weapon {
+    Weapon { name, category, damage, damage_type, finesse, ranged }
+}
+
+const WEAPONS: &[Weapon] = &[
+    weapon("Club", WeaponCategory::Simple, "1d4", "bludgeoning", false, false),
+    weapon("Dagger", WeaponCategory::Simple, "1d4", "piercing", true, false),
+    weapon("Greatclub", WeaponCategory::Simple, "1d8", "bludgeoning", false, false),
+    weapon("Handaxe", WeaponCategory::Simple, "1d6", "slashing", false, false),
+    weapon("Javelin", WeaponCategory::Simple, "1d6", "piercing", false, false),
+    weapon("Light hammer", WeaponCategory::Simple, "1d4", "bludgeoning", false, false),
+    weapon("Mace", WeaponCategory::Simple, "1d6", "bludgeoning", false, false),
+    weapon("Quarterstaff", WeaponCategory::Simple, "1d6", "bludgeoning", false, false),
+    weapon("Sickle", WeaponCategory::Simple, "1d4", "slashing", false, false),
+    weapon("Spear", WeaponCategory::Simple, "1d6", "piercing", false, false),
+    weapon("Dart", WeaponCategory::Simple, "1d4", "piercing", true, true),
+    weapon("Light crossbow", WeaponCategory::Simple, "1d8", "piercing", false, true),
+    weapon("Shortbow", WeaponCategory::Simple, "1d6", "piercing", false, true),
+    weapon("Sling", WeaponCategory::Simple, "1d4", "bludgeoning", false, true),
+    weapon("Battleaxe", WeaponCategory::Martial, "1d8", "slashing", false, false),
+    weapon("Flail", WeaponCategory::Martial, "1d8", "bludgeoning", false, false),
+    weapon("Glaive", WeaponCategory::Martial, "1d10", "slashing", false, false),
+    weapon("Greataxe", WeaponCategory::Martial, "1d12", "slashing", false, false),
+    weapon("Greatsword", WeaponCategory::Martial, "2d6", "slashing", false, false),
+    weapon("Halberd", WeaponCategory::Martial, "1d10", "slashing", false, false),
+    weapon("Lance", WeaponCategory::Martial, "1d10", "piercing", false, false),
+    weapon("Longsword", WeaponCategory::Martial, "1d8", "slashing", false, false),
+    weapon("Maul", WeaponCategory::Martial, "2d6", "bludgeoning", false, false),
+    weapon("Morningstar", WeaponCategory::Martial, "1d8", "piercing", false, false),
+    weapon("Pike", WeaponCategory::Martial, "1d10", "piercing", false, false),
+    weapon("Rapier", WeaponCategory::Martial, "1d8", "piercing", true, false),
+    weapon("Scimitar", WeaponCategory::Martial, "1d6", "slashing", true, false),
+    weapon("Shortsword", WeaponCategory::Martial, "1d6", "piercing", true, false),
+    weapon("Trident", WeaponCategory::Martial, "1d8", "piercing", false, false),
+    weapon("Warhammer", WeaponCategory::Martial, "1d8", "bludgeoning", false, false),
+    weapon("War pick", WeaponCategory::Martial, "1d8", "piercing", false, false),
+    weapon("Whip", WeaponCategory::Martial, "1d4", "slashing", true, false),
+    weapon("Blowgun", WeaponCategory::Martial, "1", "piercing", false, true),
+    weapon("Hand crossbow", WeaponCategory::Martial, "1d6", "piercing", false, true),
+    weapon("Heavy crossbow", WeaponCategory::Martial, "1d10", "piercing", false, true),
+    weapon("Longbow", WeaponCategory::Martial, "1d8", "piercing", false, true),
+];
+
+// Finds the weapon an inventory entry refers to, e.g. "Shortbow with 20 arrows"
+fn find_weapon(item: &str) -> Option<&'static Weapon> {
+    let item = item.to_lowercase();
+    WEAPONS
+        .iter()
+        .filter(|w| item.contains(&w.name.to_lowercase()))
+        .max_by_key(|w| w.name.len())
+}
+
+// What a ranged weapon fires, if it needs ammunition at all
+fn ammunition_kind(weapon: &Weapon) -> Option<&'static str> {
+    match weapon.name {
+        "Shortbow" | "Longbow" => Some("arrows"),
+        "Light crossbow" | "Hand crossbow" | "Heavy crossbow" => Some("bolts"),
+        "Sling" => Some("sling bullets"),
+        "Blowgun" => Some("needles"),
+        _ => None,
+    }
+}
+
+// Below this many shots the player is warned after each attack
+const LOW_AMMO_WARNING: u32 = 5;
+
+// A bow swung as a club when the arrows run out; nobody is proficient with these
+const IMPROVISED_WEAPON: Weapon = weapon("Improvised weapon", WeaponCategory::Simple, "1d4", "bludgeoning", false, false);
+
+fn first_number(text: &str) -> Option<u32> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .find(|part| !part.is_empty())
+        .and_then(|part| part.parse().ok())
+}
+
+// Armor data (SRD 5.2); dex_cap is None when the full DEX modifier applies
+#[derive(Clone, Copy, Debug)]
+struct Armor {
+    name: &'static str,
+    base_ac: u32,
+    dex_cap: Option<i32>,
+}
+
+const ARMOR: &[Armor] = &[
+    Armor { name: "Padded armor", base_ac: 11, dex_cap: None },
+    Armor { name: "Leather armor", base_ac: 11, dex_cap: None },
+    Armor { name: "Studded leather", base_ac: 12, dex_cap: None },
+    Armor { name: "Hide armor", base_ac: 12, dex_cap: Some(2) },
+    Armor { name: "Chain shirt", base_ac: 13, dex_cap: Some(2) },
+    Armor { name: "Scale mail", base_ac: 14, dex_cap: Some(2) },
+    Armor { name: "Breastplate", base_ac: 14, dex_cap: Some(2) },
+    Armor { name: "Half plate", base_ac: 15, dex_cap: Some(2) },
+    Armor { name: "Ring mail", base_ac: 14, dex_cap: Some(0) },
+    Armor { name: "Chain mail", base_ac: 16, dex_cap: Some(0) },
+    Armor { name: "Splint armor", base_ac: 17, dex_cap: Some(0) },
+    Armor { name: "Plate armor", base_ac: 18, dex_cap: Some(0) },
+];
+
+fn find_armor(item: &str) -> Option<&'static Armor> {
+    let item = item.to_lowercase();
+    ARMOR
+        .iter()
+        .filter(|a| item.contains(&a.name.to_lowercase()))
+        .max_by_key(|a| a.name.len())
+}
+
+// Weight in pounds of common gear (SRD 5.2); anything not listed is treated as weightless
+const GEAR_WEIGHTS: &[(&str, f64)] = &[
+    ("Padded armor", 8.0), ("Leather armor", 10.0), ("Studded leather", 13.0), ("Hide armor", 12.0),
+    ("Chain shirt", 20.0), ("Scale mail", 45.0), ("Breastplate", 20.0), ("Half plate", 40.0),
+    ("Ring mail", 40.0), ("Chain mail", 55.0), ("Splint armor", 60.0), ("Plate armor", 65.0), ("Shield", 6.0),
+    ("Club", 2.0), ("Dagger", 1.0), ("Greatclub", 10.0), ("Handaxe", 2.0), ("Javelin", 2.0), ("Light hammer", 2.0),
+    ("Mace", 4.0), ("Quarterstaff", 4.0), ("Sickle", 2.0), ("Spear", 3.0), ("Dart", 0.25), ("Light crossbow", 5.0),
+    ("Shortbow", 2.0), ("Battleaxe", 4.0), ("Flail", 2.0), ("Glaive", 6.0), ("Greataxe", 7.0), ("Greatsword", 6.0),
+    ("Halberd", 6.0), ("Lance", 6.0), ("Longsword", 3.0), ("Maul", 10.0), ("Morningstar", 4.0), ("Pike", 18.0),
+    ("Rapier", 2.0), ("Scimitar", 3.0), ("Shortsword", 2.0), ("Trident", 4.0), ("Warhammer", 2.0), ("War pick", 2.0),
+    ("Whip", 3.0), ("Blowgun", 1.0), ("Hand crossbow", 3.0), ("Heavy crossbow", 18.0), ("Longbow", 2.0),
+    ("Burglar's pack", 47.5), ("Diplomat's pack", 39.0), ("Dungeoneer's pack", 61.5), ("Entertainer's pack", 40.0),
+    ("Explorer's pack", 59.0), ("Priest's pack", 24.0), ("Scholar's pack", 10.0),
+    ("Backpack", 5.0), ("Bedroll", 7.0), ("Rations", 2.0), ("Waterskin", 5.0), ("Torch", 1.0), ("Rope", 10.0),
+    ("Spellbook", 3.0), ("Thieves' tools", 1.0), ("Component pouch", 2.0), ("Holy symbol", 1.0), ("Lute", 2.0),
+];
+
+// An item's weight, times the count in parentheses: "Dagger (2)", "Rations (5 days)"
+fn item_weight(item: &str) -> f64 {
+    let lower = item.to_lowercase();
+    let Some((_, weight)) = GEAR_WEIGHTS
+        .iter()
+        .filter(|(name, _)| lower.contains(&name.to_lowercase()))
+        .max_by_key(|(name, _)| name.len())
+    else {
+        return 0.0;
+    };
+    let count = item.rsplit_once('(').and_then(|(_, rest)| first_number(rest)).unwrap_or(1);
+    weight * count as f64
+}
+
+impl Character {
+    fn ability_score(&self, ability: Ability) -> u32 {
+        match ability {
+            Ability::Strength => self.strength,
+            Ability::Dexterity => self.dexterity,
+            Ability::Constitution => self.constitution,
+            Ability::Intelligence => self.intelligence,
+            Ability::Wisdom => self.wisdom,
+            Ability::Charisma => self.charisma,
+        }
+    }
+    
+    // Permanently raises a base score, to at most 20; a higher CON modifier adds HP for every level
+    fn raise_ability(&mut self, ability: Ability, amount: u32, turn: usize) {
+        let before = ability_modifier(self.ability_score(ability));
+        let score = match ability {
+            Ability::Strength => &mut self.strength,
+            Ability::Dexterity => &mut self.dexterity,
+            Ability::Constitution => &mut self.constitution,
+            Ability::Intelligence => &mut self.intelligence,
+            Ability::Wisdom => &mut self.wisdom,
+            Ability::Charisma => &mut self.charisma,
+        };
+        *score = (*score + amount).min(20);
+        let gained = ability_modifier(self.ability_score(ability)) - before;
+        if ability == Ability::Constitution && gained > 0 {
+            let hp = gained as u32 * self.level;
+            self.max_hit_points += hp;
+            self.hit_points += hp;
+            self.log_hp_change(hp as i32, "Constitution increased", turn);
+        }
+    }
+    
+    // ASI levels reached but not yet resolved
+    fn pending_asi_levels(&self) -> Vec<u32> {
+        ASI_LEVELS.iter().copied().filter(|l| *l <= self.level && !self.asi_taken.contains(l)).collect()
+    }
+    
+    // Base score plus active temporary modifiers and magic items, kept within 1-30
+    fn effective_score(&self, ability: Ability) -> u32 {
+        let delta: i32 = self
+            .ability_modifiers
+            .iter()
+            .filter(|m| m.ability == ability)
+            .map(|m| m.delta)
+            .sum();
+        let item_bonus: i32 = self
+            .inventory
+            .iter()
+            .filter(|item| item.is_active())
+            .filter_map(|item| item.ability_bonus)
+            .filter(|(a, _)| *a == ability)
+            .map(|(_, delta)| delta)
+            .sum();
+        (self.ability_score(ability) as i32 + delta + item_bonus).clamp(1, 30) as u32
+    }
+    
+    fn effective_modifier(&self, ability: Ability) -> i32 {
+        ability_modifier(self.effective_score(ability))
+    }
+    
+    fn add_ability_modifier(&mut self, modifier: AbilityModifier, turn: usize) {
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        self.ability_modifiers.push(modifier);
+        self.recompute_derived_stats(previous_con, turn);
+    }
+    
+    fn remove_ability_modifier(&mut self, index: usize, turn: usize) -> AbilityModifier {
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        let removed = self.ability_modifiers.remove(index);
+        self.recompute_derived_stats(previous_con, turn);
+        removed
+    }
+    
+    // Drops modifiers whose duration has run out, returning them
+    fn expire_ability_modifiers(&mut self, expired: impl Fn(&EffectDuration) -> bool, turn: usize) -> Vec<AbilityModifier> {
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        let (gone, kept): (Vec<_>, Vec<_>) = self.ability_modifiers.drain(..).partition(|m| expired(&m.duration));
+        self.ability_modifiers = kept;
+        if !gone.is_empty() {
+            self.recompute_derived_stats(previous_con, turn);
+        }
+        gone
+    }
+    
+    // Counts down round-based modifiers by one round
+    fn tick_ability_modifiers(&mut self, turn: usize) -> Vec<AbilityModifier> {
+        for modifier in self.ability_modifiers.iter_mut() {
+            if let EffectDuration::Rounds(n) = &mut modifier.duration {
+                *n = n.saturating_sub(1);
+            }
+        }
+        self.expire_ability_modifiers(|d| *d == EffectDuration::Rounds(0), turn)
+    }
+    
+    fn max_hp_modifier(&self) -> i32 {
+        self.max_hp_modifiers.iter().map(|m| m.delta).sum()
+    }
+    
+    // Base maximum plus any ceiling modifiers, never below 1
+    fn effective_max_hp(&self) -> u32 {
+        (self.max_hit_points as i32 + self.max_hp_modifier()).max(1) as u32
+    }
+    
+    // Pulls current HP down to a lowered maximum
+    fn clamp_hp_to_max(&mut self, reason: &str, turn: usize) {
+        let max = self.effective_max_hp();
+        if self.hit_points > max {
+            let lost = self.hit_points - max;
+            self.hit_points = max;
+            self.log_hp_change(-(lost as i32), reason, turn);
+        }
+        self.update_status();
+    }
+    
+    fn add_max_hp_modifier(&mut self, modifier: MaxHpModifier, turn: usize) {
+        let reason = max_hp_change_reason(modifier.delta, &modifier.source);
+        self.max_hp_modifiers.push(modifier);
+        self.clamp_hp_to_max(&reason, turn);
+    }
+    
+    fn remove_max_hp_modifier(&mut self, index: usize, turn: usize) -> MaxHpModifier {
+        let removed = self.max_hp_modifiers.remove(index);
+        self.clamp_hp_to_max(&max_hp_change_reason(-removed.delta, &format!("{} ended", removed.source)), turn);
+        removed
+    }
+    
+    fn expire_max_hp_modifiers(&mut self, expired: impl Fn(&EffectDuration) -> bool, turn: usize) -> Vec<MaxHpModifier> {
+        let (gone, kept): (Vec<_>, Vec<_>) = self.max_hp_modifiers.drain(..).partition(|m| expired(&m.duration));
+        self.max_hp_modifiers = kept;
+        if !gone.is_empty() {
+            self.clamp_hp_to_max("Maximum HP lowered", turn);
+        }
+        gone
+    }
+    
+    // Re-derives AC and max HP after an effective ability score changed
+    fn recompute_derived_stats(&mut self, previous_con_modifier: i32, turn: usize) {
+        self.armor_class = self.compute_armor_class();
+        
+        let con_change = (self.effective_modifier(Ability::Constitution) - previous_con_modifier) * self.level as i32;
+        if con_change != 0 {
+            let before = self.hit_points;
+            self.max_hit_points = (self.max_hit_points as i32 + con_change).max(1) as u32;
+            if con_change > 0 && self.hit_points > 0 {
+                self.hit_points += con_change as u32;
+            }
+            self.hit_points = self.hit_points.min(self.effective_max_hp());
+            self.log_hp_change(self.hit_points as i32 - before as i32, "Constitution changed", turn);
+        }
+    }
+    
+    // Records an HP change, keeping only the most recent HP_LOG_LEN entries
+    fn log_hp_change(&mut self, delta: i32, reason: &str, turn: usize) {
+        if delta == 0 {
+            return;
+        }
+        self.hp_log.push_back(HpChange { delta, reason: reason.to_string(), turn });
+        while self.hp_log.len() > HP_LOG_LEN {
+            self.hp_log.pop_front();
+        }
+    }
+    
+    // Best armor carried plus DEX (capped by armor) and a shield; unarmored defense for Barbarians and Monks
+    fn compute_armor_class(&self) -> u32 {
+        let dex = self.effective_modifier(Ability::Dexterity);
+        let armor = self
+            .inventory
+            .iter()
+            .filter_map(|item| find_armor(&item.name))
+            .max_by_key(|a| a.base_ac);
+        let shield = self.inventory.iter().any(|item| item.name.to_lowercase().contains("shield"));
+        let item_bonus: i32 = self
+            .inventory
+            .iter()
+            .filter(|item| item.is_active())
+            .map(|item| item.ac_bonus)
+            .sum();
+        
+        let base = match armor {
+            Some(armor) => armor.base_ac as i32 + armor.dex_cap.map_or(dex, |cap| dex.min(cap)),
+            None => match self.class.as_str() {
+                "Barbarian" => 10 + dex + self.effective_modifier(Ability::Constitution),
+                "Monk" if !shield => 10 + dex + self.effective_modifier(Ability::Wisdom),
+                _ => 10 + dex,
+            },
+        };
+        (base + if shield { 2 } else { 0 } + item_bonus).max(1) as u32
+    }
+    
+    fn carried_weight(&self) -> f64 {
+        self.inventory.iter().map(|item| item_weight(&item.name)).sum()
+    }
+    
+    // Variant encumbrance: over 5 x STR slows the character, over 10 x STR badly, and 15 x STR is the most they can carry
+    fn encumbrance(&self) -> Option<&'static str> {
+        let strength = self.effective_score(Ability::Strength) as f64;
+        match self.carried_weight() {
+            w if w > strength * 15.0 => Some("over capacity: speed drops to 5 ft"),
+            w if w > strength * 10.0 => Some("heavily encumbered: speed -20 ft, disadvantage on STR, DEX and CON rolls"),
+            w if w > strength * 5.0 => Some("encumbered: speed -10 ft"),
+            _ => None,
+        }
+    }
+    
+    // e.g. "72 lb of 240 (encumbered: speed -10 ft)"
+    fn load_summary(&self) -> String {
+        let capacity = self.effective_score(Ability::Strength) * 15;
+        match self.encumbrance() {
+            Some(band) => format!("{} lb of {} ({})", self.carried_weight(), capacity, band),
+            None => format!("{} lb of {}", self.carried_weight(), capacity),
+        }
+    }
+    
+    // Worn armor, shield and weapons from the inventory, e.g. "Chain mail, Shield, Longsword (AC 18)"
+    fn equipped_summary(&self) -> String {
+        let mut gear: Vec<&str> = Vec::new();
+        if let Some(armor) = self
+            .inventory
+            .iter()
+            .filter_map(|item| find_armor(&item.name))
+            .max_by_key(|a| a.base_ac)
+        {
+            gear.push(armor.name);
+        }
+        if self.inventory.iter().any(|item| item.name.to_lowercase().contains("shield")) {
+            gear.push("Shield");
+        }
+        for weapon in self.inventory.iter().filter_map(|item| find_weapon(&item.name)) {
+            if !gear.contains(&weapon.name) {
+                gear.push(weapon.name);
+            }
+        }
+        
+        let gear = if gear.is_empty() { "no armor or weapons".to_string() } else { gear.join(", ") };
+        format!("{} (AC {})", gear, self.armor_class)
+    }
+    
+    fn add_item(&mut self, item: Item, turn: usize) {
+        // Loose ammunition such as "Arrows (20)" goes straight to a weapon that fires it
+        let name = item.name.to_lowercase();
+        if find_weapon(&name).is_none()
+            && let Some(count) = first_number(&name)
+            && let Some(quiver) = self.inventory.iter_mut().find(|i| {
+                i.ammo.is_some() && find_weapon(&i.name).and_then(ammunition_kind).is_some_and(|kind| name.contains(kind))
+            })
+        {
+            quiver.ammo = Some(quiver.ammo.unwrap_or(0) + count);
+            return;
+        }
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        self.inventory.push(item);
+        self.recompute_derived_stats(previous_con, turn);
+    }
+    
+    // Takes an item out of the inventory, ending any attunement, and recomputes what it affected
+    fn remove_item(&mut self, index: usize, turn: usize) -> Item {
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        let mut item = self.inventory.remove(index);
+        item.attuned = false;
+        self.recompute_derived_stats(previous_con, turn);
+        item
+    }
+    
+    // Starting a new concentration effect ends any other one; returns the effects that ended
+    fn add_active_effect(&mut self, effect: ActiveEffect) -> Vec<ActiveEffect> {
+        let ended = if effect.concentration { self.end_concentration() } else { Vec::new() };
+        self.active_effects.push(effect);
+        ended
+    }
+    
+    fn end_concentration(&mut self) -> Vec<ActiveEffect> {
+        let (ended, kept): (Vec<_>, Vec<_>) = self.active_effects.drain(..).partition(|e| e.concentration);
+        self.active_effects = kept;
+        ended
+    }
+    
+    // Counts effects down by the given number of rounds, returning the ones that ran out
+    fn tick_active_effects(&mut self, rounds: u32) -> Vec<ActiveEffect> {
+        for effect in self.active_effects.iter_mut() {
+            effect.remaining = effect.remaining.saturating_sub(rounds);
+        }
+        let (expired, kept): (Vec<_>, Vec<_>) = self.active_effects.drain(..).partition(|e| e.remaining == 0);
+        self.active_effects = kept;
+        expired
+    }
+    
+    // Pins the favorite, or unpins it if it was already pinned; returns whether it is pinned now
+    fn toggle_favorite(&mut self, favorite: FavoriteAction) -> bool {
+        match self.favorites.iter().position(|f| *f == favorite) {
+            Some(index) => {
+                self.favorites.remove(index);
+                false
+            }
+            None => {
+                self.favorites.push(favorite);
+                true
+            }
+        }
+    }
+    
+    fn is_proficient_with_tool(&self, tool: &str) -> bool {
+        self.tool_proficiencies.iter().any(|t| t.eq_ignore_ascii_case(tool))
+    }
+    
+    // Inventory indices that satisfy each component, or the names of the missing ones
+    fn find_components(&self, components: &[String]) -> Result<Vec<usize>, Vec<String>> {
+        let mut used = Vec::new();
+        let mut missing = Vec::new();
+        for component in components {
+            let component_lower = component.to_lowercase();
+            match (0..self.inventory.len())
+                .find(|i| !used.contains(i) && self.inventory[*i].name.to_lowercase().contains(&component_lower))
+            {
+                Some(i) => used.push(i),
+                None => missing.push(component.clone()),
+            }
+        }
+        if missing.is_empty() { Ok(used) } else { Err(missing) }
+    }
+    
+    fn attuned_items(&self) -> Vec<&Item> {
+        self.inventory.iter().filter(|item| item.attuned).collect()
+    }
+    
+    // Toggles attunement on an inventory item, enforcing the three-item limit
+    fn toggle_attunement(&mut self, index: usize, turn: usize) -> Result<bool, String> {
+        let attuned_count = self.attuned_items().len();
+        let item = &self.inventory[index];
+        if !item.requires_attunement {
+            return Err(format!("{} doesn't require attunement.", item.name));
+        }
+        if !item.attuned && attuned_count >= MAX_ATTUNED_ITEMS {
+            return Err(format!(
+                "You are already attuned to {} items. End attunement with one before attuning to {}.",
+                MAX_ATTUNED_ITEMS, item.name
+            ));
+        }
+        
+        let previous_con = self.effective_modifier(Ability::Constitution);
+        let item = &mut self.inventory[index];
+        item.attuned = !item.attuned;
+        let attuned = item.attuned;
+        self.recompute_derived_stats(previous_con, turn);
+        Ok(attuned)
+    }
+    
+    fn skill_proficiency(&self, skill: &str) -> Proficiency {
+        self.skills.get(skill).copied().unwrap_or_default()
+    }
+    
+    fn is_proficient_in(&self, skill: &str) -> bool {
+        self.skill_proficiency(skill) != Proficiency::None
+    }
+    
+    fn has_expertise_in(&self, skill: &str) -> bool {
+        self.skill_proficiency(skill) == Proficiency::Expertise
+    }
+    
+    // The proficiency bonus a skill adds, doubled with Expertise
+    fn skill_proficiency_bonus(&self, skill: &str) -> i32 {
+        match self.skill_proficiency(skill) {
+            Proficiency::None => 0,
+            Proficiency::Proficient => proficiency_bonus(self.level),
+            Proficiency::Expertise => 2 * proficiency_bonus(self.level),
+        }
+    }
+    
+    // Expertise choices the class has granted by now but that haven't been picked yet
+    fn pending_expertise(&self) -> usize {
+        let chosen = self.skills.values().filter(|p| **p == Proficiency::Expertise).count();
+        expertise_granted(&self.class, self.class_level(&self.class)).saturating_sub(chosen)
+    }
+    
+    // Ability modifier plus proficiency bonus when proficient
+    fn skill_modifier(&self, skill: &str) -> i32 {
+        let ability_mod = skill_ability(skill)
+            .map(|ability| self.effective_modifier(ability))
+            .unwrap_or(0);
+        ability_mod + self.skill_proficiency_bonus(skill) - self.resurrection_penalty as i32
+    }
+    
+    // The pieces skill_modifier adds up, named for a roll explanation
+    fn skill_modifier_parts(&self, skill: &str) -> Vec<ModifierPart> {
+        let mut parts = Vec::new();
+        if let Some(ability) = skill_ability(skill) {
+            parts.push((format!("for your {}", ability.abbreviation()), self.effective_modifier(ability)));
+        }
+        match self.skill_proficiency(skill) {
+            Proficiency::None => {}
+            Proficiency::Proficient => parts.push((format!("proficiency (you're proficient in {})", skill), self.skill_proficiency_bonus(skill))),
+            Proficiency::Expertise => parts.push((format!("double proficiency (you have Expertise in {})", skill), self.skill_proficiency_bonus(skill))),
+        }
+        self.push_penalty_part(&mut parts);
+        parts
+    }
+    
+    fn saving_throw_parts(&self, ability: Ability) -> Vec<ModifierPart> {
+        let mut parts = vec![(format!("for your {}", ability.abbreviation()), self.effective_modifier(ability))];
+        if self.is_proficient_in_save(ability) {
+            parts.push((format!("proficiency (your class is proficient in {} saves)", ability.abbreviation()), proficiency_bonus(self.level)));
+        }
+        self.push_penalty_part(&mut parts);
+        parts
+    }
+    
+    fn attack_bonus_parts(&self, weapon: &Weapon) -> Vec<ModifierPart> {
+        let ability = self.weapon_ability(weapon);
+        let mut parts = vec![(format!("for your {}", ability.abbreviation()), self.effective_modifier(ability))];
+        if self.is_proficient_with(weapon) {
+            parts.push((format!("proficiency (you're proficient with the {})", weapon.name.to_lowercase()), proficiency_bonus(self.level)));
+        }
+        self.push_penalty_part(&mut parts);
+        parts
+    }
+    
+    fn push_penalty_part(&self, parts: &mut Vec<ModifierPart>) {
+        if self.resurrection_penalty > 0 {
+            parts.push(("for your resurrection penalty".to_string(), -(self.resurrection_penalty as i32)));
+        }
+    }
+    
+    // 10 + Perception, what hidden threats have to beat to go unnoticed
+    fn passive_perception(&self) -> i32 {
+        10 + self.skill_modifier("Perception")
+    }
+    
+    // Every class taken, starting with the first
+    fn class_label(&self) -> String {
+        std::iter::once(&self.class)
+            .chain(self.multiclasses.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+    
+    // Levels in one class; the starting class holds whatever the multiclasses don't
+    fn class_level(&self, class: &str) -> u32 {
+        if class == self.class {
+            self.level.saturating_sub(self.multiclass_levels.values().sum())
+        } else {
+            self.multiclass_levels.get(class).copied().unwrap_or(0)
+        }
+    }
+    
+    // Takes up a new class with its limited multiclass proficiencies; `skill` and `instrument`
+    // are the player's picks for the classes that grant one. The level itself comes from gain_level
+    fn add_multiclass(&mut self, class: &str, skill: Option<&str>, instrument: Option<&str>) {
+        self.multiclasses.push(class.to_string());
+        if let Some(skill) = skill
+            && let Some(proficiency) = self.skills.get_mut(skill)
+            && *proficiency == Proficiency::None
+        {
+            *proficiency = Proficiency::Proficient;
+        }
+        for tool in multiclass_tool_proficiencies(class).iter().copied().chain(instrument) {
+            self.tool_proficiencies.insert(tool.to_string());
+        }
+    }
+    
+    // Save proficiencies come only from the starting class, never from later multiclasses
+    fn is_proficient_in_save(&self, ability: Ability) -> bool {
+        class_save_proficiencies(&self.class).contains(&ability)
+    }
+    
+    // Every class the character casts spells through, starting class first
+    fn casting_classes(&self) -> Vec<&str> {
+        std::iter::once(&self.class)
+            .chain(self.multiclasses.iter())
+            .map(String::as_str)
+            .filter(|class| spellcasting_ability(class).is_some())
+            .collect()
+    }
+    
+    // Pact magic slots (count, slot level) for Warlocks, whichever class they started in;
+    // only Warlock levels count toward them
+    fn pact_slots(&self) -> Option<(u32, u32)> {
+        self.casting_classes().contains(&"Warlock").then(|| pact_magic_slots(self.class_level("Warlock")))
+    }
+    
+    fn pact_slots_left(&self) -> u32 {
+        self.pact_slots().map_or(0, |(count, _)| count.saturating_sub(self.pact_slots_used))
+    }
+    
+    // Uses a pact slot, returning the level it was cast at; None when none are left
+    fn spend_pact_slot(&mut self) -> Option<u32> {
+        let (_, level) = self.pact_slots()?;
+        if self.pact_slots_left() == 0 {
+            return None;
+        }
+        self.pact_slots_used += 1;
+        Some(level)
+    }
+    
+    // Proficiency + casting ability modifier for the given class
+    fn spell_attack_bonus(&self, class: &str) -> Option<i32> {
+        spellcasting_ability(class).map(|ability| proficiency_bonus(self.level) + self.effective_modifier(ability))
+    }
+    
+    // 8 + proficiency + casting ability modifier for the given class
+    fn spell_save_dc(&self, class: &str) -> Option<u32> {
+        self.spell_attack_bonus(class).map(|bonus| (8 + bonus).max(0) as u32)
+    }
+    
+    fn saving_throw_modifier(&self, ability: Ability) -> i32 {
+        let prof = if self.is_proficient_in_save(ability) { proficiency_bonus(self.level) } else { 0 };
+        self.effective_modifier(ability) + prof - self.resurrection_penalty as i32
+    }
+    
+    fn weapons(&self) -> Vec<&'static Weapon> {
+        self.inventory.iter().filter_map(|item| find_weapon(&item.name)).collect()
+    }
+    
+    fn is_proficient_with(&self, weapon: &Weapon) -> bool {
+        if weapon.name == IMPROVISED_WEAPON.name {
+            return false;
+        }
+        if self.multiclasses.iter().any(|class| multiclass_weapon_proficiency(class, weapon)) {
+            return true;
+        }
+        match self.class.as_str() {
+            "Barbarian" | "Fighter" | "Paladin" | "Ranger" => true,
+            "Bard" | "Rogue" => weapon.category == WeaponCategory::Simple
+                || matches!(weapon.name, "Hand crossbow" | "Longsword" | "Rapier" | "Shortsword"),
+            "Monk" => weapon.category == WeaponCategory::Simple || weapon.name == "Shortsword",
+            "Druid" => matches!(weapon.name, "Club" | "Dagger" | "Dart" | "Javelin" | "Mace"
+                | "Quarterstaff" | "Scimitar" | "Sickle" | "Sling" | "Spear"),
+            "Sorcerer" | "Wizard" => matches!(weapon.name, "Dagger" | "Dart" | "Sling"
+                | "Quarterstaff" | "Light crossbow"),
+            _ => weapon.category == WeaponCategory::Simple,
+        }
+    }
+    
+    // Finesse weapons use the better of STR and DEX, ranged weapons use DEX
+    fn weapon_ability_modifier(&self, weapon: &Weapon) -> i32 {
+        self.effective_modifier(self.weapon_ability(weapon))
+    }
+    
+    fn weapon_ability(&self, weapon: &Weapon) -> Ability {
+        let str_mod = self.effective_modifier(Ability::Strength);
+        let dex_mod = self.effective_modifier(Ability::Dexterity);
+        if (weapon.finesse && dex_mod > str_mod) || (!weapon.finesse && weapon.ranged) {
+            Ability::Dexterity
+        } else {
+            Ability::Strength
+        }
+    }
+    
+    // Wizards write leveled spells into a spellbook and prepare some of them; everyone else just knows their spells
+    fn keeps_spellbook(&self) -> bool {
+        self.class == "Wizard"
+    }
+    
+    // Intelligence modifier + Wizard level (not total level), minimum one
+    fn prepared_spell_limit(&self) -> usize {
+        (self.effective_modifier(Ability::Intelligence) + self.class_level("Wizard") as i32).max(1) as usize
+    }
+    
+    fn prepared_spell_count(&self) -> usize {
+        self.known_spells.iter().filter(|s| s.level > 0).count()
+    }
+    
+    // Adds a spell if the class can cast it at this level and has room to learn it; a Wizard's
+    // leveled spells go into the spellbook and are prepared straight away while there is room.
+    // Levels in other classes don't count toward what the class can learn
+    fn learn_spell(&mut self, spell: Spell) -> Result<(), String> {
+        let class_level = self.class_level(&self.class);
+        let Some(max_level) = max_spell_level(&self.class, class_level) else {
+            return Err(format!("{}s don't cast spells.", self.class));
+        };
+        if spell.level > max_level {
+            return Err(format!("A level {} {} can cast spells up to level {}; {} is level {}.",
+                               class_level, self.class, max_level, spell.name, spell.level));
+        }
+        if self.known_spells.iter().chain(&self.spellbook).any(|s| s.name.eq_ignore_ascii_case(&spell.name)) {
+            return Err(format!("You already know {}.", spell.name));
+        }
+        let in_book = spell.level > 0 && self.keeps_spellbook();
+        let leveled = if in_book { self.spellbook.len() } else { self.prepared_spell_count() };
+        if spell.level > 0
+            && let Some(limit) = spells_known_limit(&self.class, class_level)
+            && leveled >= limit
+        {
+            return Err(format!("A level {} {} can know {} spells and you know {}.", class_level, self.class, limit, leveled));
+        }
+        if in_book {
+            self.spellbook.push(spell.clone());
+            if self.prepared_spell_count() >= self.prepared_spell_limit() {
+                return Ok(());
+            }
+        }
+        self.known_spells.push(spell);
+        Ok(())
+    }
+    
+    // Shots left for a weapon, or None when its ammunition isn't tracked
+    fn ammunition_left(&self, weapon: &Weapon) -> Option<u32> {
+        self.inventory.iter().find(|i| find_weapon(&i.name).is_some_and(|w| w.name == weapon.name)).and_then(|i| i.ammo)
+    }
+    
+    // Uses up one shot, returning how many remain
+    fn spend_ammunition(&mut self, weapon: &Weapon) -> Option<u32> {
+        let item = self.inventory.iter_mut()
+            .find(|i| i.ammo.is_some() && find_weapon(&i.name).is_some_and(|w| w.name == weapon.name))?;
+        let left = item.ammo?.saturating_sub(1);
+        item.ammo = Some(left);
+        item.ammo_spent += 1;
+        Some(left)
+    }
+    
+    fn has_spent_ammunition(&self) -> bool {
+        self.inventory.iter().any(|i| i.ammo_spent > 0)
+    }
+    
+    // Searching the battlefield turns up half the spent shots (rounded down); the rest are lost either way
+    fn recover_ammunition(&mut self, search: bool) -> Vec<String> {
+        let mut recovered = Vec::new();
+        for item in self.inventory.iter_mut().filter(|i| i.ammo_spent > 0) {
+            let found = if search { item.ammo_spent / 2 } else { 0 };
+            if found > 0 {
+                item.ammo = Some(item.ammo.unwrap_or(0) + found);
+                let kind = find_weapon(&item.name).and_then(ammunition_kind).unwrap_or("ammunition");
+                recovered.push(format!("{} {}", found, kind));
+            }
+            item.ammo_spent = 0;
+        }
+        recovered
+    }
+    
+    fn attack_bonus(&self, weapon: &Weapon) -> i32 {
+        let prof = if self.is_proficient_with(weapon) { proficiency_bonus(self.level) } else { 0 };
+        self.weapon_ability_modifier(weapon) + prof - self.resurrection_penalty as i32
+    }
+    
+    // Applies damage through resistances and temporary hit points, returning the HP actually lost
+    fn take_damage(&mut self, amount: u32, damage_type: &str, source: &str, turn: usize) -> u32 {
+        let mut amount = amount;
+        if self.resistances.iter().any(|r| r.eq_ignore_ascii_case(damage_type)) {
+            amount /= 2;
+        }
+        
+        let absorbed = amount.min(self.temp_hit_points);
+        self.temp_hit_points -= absorbed;
+        amount -= absorbed;
+        
+        // Taking damage while already down counts as a failed death save
+        if self.hit_points == 0 && amount > 0 {
+            self.death_save_failures = (self.death_save_failures + 1).min(3);
+        }
+        
+        let lost = amount.min(self.hit_points);
+        self.hit_points -= lost;
+        
+        let mut reason = format!("{} ({})", source, damage_type);
+        if absorbed > 0 {
+            reason.push_str(&format!(", {} absorbed by temporary HP", absorbed));
+        }
+        self.log_hp_change(-(lost as i32), &reason, turn);
+        self.update_status();
+        lost
+    }
+    
+    // The dead can't be healed back; only a resurrection brings them back
+    fn heal(&mut self, amount: u32, source: &str, turn: usize) -> u32 {
+        if self.is_dead() {
+            return 0;
+        }
+        let max = self.effective_max_hp();
+        let healed = amount.min(max - self.hit_points.min(max));
+        self.hit_points += healed;
+        self.log_hp_change(healed as i32, source, turn);
+        if self.hit_points > 0 {
+            self.death_save_successes = 0;
+            self.death_save_failures = 0;
+        }
+        self.update_status();
+        healed
+    }
+    
+    // Re-derives the status after HP or death saves change; death is never undone here
+    fn update_status(&mut self) {
+        if self.status == LifeStatus::Dead || self.death_save_failures >= 3 {
+            self.status = LifeStatus::Dead;
+        } else if self.hit_points == 0 && self.death_save_successes >= 3 {
+            self.status = LifeStatus::Stable;
+        } else {
+            self.status = LifeStatus::Alive;
+        }
+    }
+    
+    fn is_dead(&self) -> bool {
+        self.status == LifeStatus::Dead
+    }
+    
+    fn is_stable(&self) -> bool {
+        self.status == LifeStatus::Stable
+    }
+    
+    // Brings the dead back at 1 HP, carrying the given penalty into the next few long rests
+    fn resurrect(&mut self, source: &str, penalty: u32, turn: usize) {
+        self.status = LifeStatus::Alive;
+        self.death_save_successes = 0;
+        self.death_save_failures = 0;
+        self.hit_points = 1;
+        self.resurrection_penalty = penalty;
+        self.log_hp_change(1, source, turn);
+    }
+}
+
+// Display utilities
+// Starts a spinner that animates on its own until the caller clears it; None when spinners are disabled
+fn show_spinner(message: &str) -> Option<ProgressBar> {
+    if !config().spinner {
+        return None;
+    }
+    
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+            .template("{spinner:.green} {msg}")
+            .unwrap(),
+    );
+    pb.set_message(message.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    Some(pb)
+}
+
+fn print_header() {
+    let t = theme();
+    let title = TITLE_ART.color(t.info).bold();
+    println!("\n{}", title);
+    println!("{}", "AI Dungeon Master".color(t.accent).bold());
+    println!("{}\n", "=".repeat(60).color(t.border));
+}
+
+// Color names map onto theme roles so callers keep passing "red", "green", ...
+// The DM wraps read-aloud descriptions in these, like the boxed text of a published module
+const READ_ALOUD_OPEN: &str = ">>>";
+const READ_ALOUD_CLOSE: &str = "<<<";
+const READ_ALOUD_WIDTH: usize = 68;
+
+// Greedy word wrap, keeping the text's own line breaks
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn print_read_aloud(text: &str) {
+    let t = theme();
+    println!("{}", format!("╔{}╗", "═".repeat(READ_ALOUD_WIDTH + 2)).color(t.accent));
+    for line in wrap_text(text, READ_ALOUD_WIDTH) {
+        let padding = " ".repeat(READ_ALOUD_WIDTH.saturating_sub(line.chars().count()));
+        println!("{} {}{} {}", "║".color(t.accent), line.color(t.info).italic(), padding, "║".color(t.accent));
+    }
+    println!("{}", format!("╚{}╝", "═".repeat(READ_ALOUD_WIDTH + 2)).color(t.accent));
+}
+
+// The DM puts NPC lines in [NPC-SAYS: Mara | "Welcome, traveler."] so they can be set apart by speaker
+const NPC_SAYS_TAG: &str = "NPC-SAYS";
+const DIALOGUE_INDENT: usize = 4;
+
+// Speakers in order of first appearance; the index picks their color, so a name keeps its color all session
+static SPEAKERS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+fn speaker_color(name: &str) -> Color {
+    let t = theme();
+    let palette = [t.info, t.accent, t.heading, t.label];
+    let key = name.trim().to_lowercase();
+    let mut speakers = SPEAKERS.write().unwrap();
+    let index = match speakers.iter().position(|s| *s == key) {
+        Some(index) => index,
+        None => {
+            speakers.push(key);
+            speakers.len() - 1
+        }
+    };
+    palette[index % palette.len()]
+}
+
+fn print_npc_line(name: &str, speech: &str) {
+    let color = speaker_color(name);
+    let speech = speech.trim().trim_matches(['"', '“', '”']);
+    let indent = " ".repeat(DIALOGUE_INDENT);
+    let label = format!("{}: ", name.trim());
+    let width = READ_ALOUD_WIDTH.saturating_sub(DIALOGUE_INDENT + label.chars().count()).max(20);
+    for (i, line) in wrap_text(&format!("“{}”", speech), width).iter().enumerate() {
+        if i == 0 {
+            println!("{}{}{}", indent, label.color(color).bold(), line.color(color));
+        } else {
+            println!("{}{}{}", indent, " ".repeat(label.chars().count()), line.color(color));
+        }
+    }
+}
+
+// Prose with any quoted speech italicized so it stands out from the narration around it
+fn print_prose(text: &str) {
+    let t = theme();
+    let mut out = String::new();
+    let mut segment = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        let opens = !quoted && (c == '"' || c == '“');
+        let closes = quoted && (c == '"' || c == '”');
+        if opens {
+            out.push_str(&segment.color(t.value).to_string());
+            segment.clear();
+        }
+        segment.push(c);
+        if closes {
+            out.push_str(&segment.color(t.info).italic().to_string());
+            segment.clear();
+        }
+        quoted = (quoted || opens) && !closes;
+    }
+    out.push_str(&if quoted { segment.color(t.info).italic() } else { segment.color(t.value) }.to_string());
+    println!("{}", out);
+}
+
+// Narration outside read-aloud boxes, with each [NPC-SAYS] line indented under its speaker's name
+fn print_narration(text: &str) {
+    let open = format!("[{}:", NPC_SAYS_TAG);
+    let mut rest = text;
+    while let Some(start) = rest.find(&open) {
+        let Some(end) = rest[start..].find(']').map(|e| start + e) else {
+            break;
+        };
+        let before = rest[..start].trim();
+        if !before.is_empty() {
+            print_prose(before);
+        }
+        match rest[start + open.len()..end].split_once('|') {
+            Some((name, speech)) => print_npc_line(name, speech),
+            None => print_prose(rest[start..=end].trim()),
+        }
+        rest = &rest[end + 1..];
+    }
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        print_prose(rest);
+    }
+}
+
+// DM narration, with any read-aloud passages set apart in a box and NPC dialogue set apart by speaker
+fn print_dm_text(text: &str) {
+    if !text.contains(READ_ALOUD_OPEN) {
+        print_narration(text);
+        return;
+    }
+    let mut rest = text;
+    while let Some(start) = rest.find(READ_ALOUD_OPEN) {
+        let before = rest[..start].trim();
+        if !before.is_empty() {
+            print_narration(before);
+        }
+        let after = &rest[start + READ_ALOUD_OPEN.len()..];
+        let (boxed, remaining) = match after.find(READ_ALOUD_CLOSE) {
+            Some(end) => (&after[..end], &after[end + READ_ALOUD_CLOSE.len()..]),
+            None => (after, ""),
+        };
+        print_read_aloud(boxed.trim());
+        rest = remaining;
+    }
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        print_narration(rest);
+    }
+}
+
+// DM narration as plain text: read-aloud markers dropped and NPC lines written as Name: "speech"
+fn plain_dm_text(text: &str) -> String {
+    let open = format!("[{}:", NPC_SAYS_TAG);
+    let text = text.replace(READ_ALOUD_OPEN, "").replace(READ_ALOUD_CLOSE, "");
+    let mut out = String::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(&open) {
+        let Some(end) = rest[start..].find(']').map(|e| start + e) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        match rest[start + open.len()..end].split_once('|') {
+            Some((name, speech)) => out.push_str(&format!("{}: \"{}\"", name.trim(), speech.trim().trim_matches(['"', '“', '”']))),
+            None => out.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out.trim().to_string()
+}
+
+// Renders with colors forced off, for text headed somewhere other than this terminal
+fn without_colors<T>(render: impl FnOnce() -> T) -> T {
+    colored::control::set_override(false);
+    let out = render();
+    colored::control::unset_override();
+    out
+}
+
+// Kept alive for the session: on X11 and Wayland the copied text is only served while its owner exists
+static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+
+fn set_clipboard_text(text: &str) -> Result<(), arboard::Error> {
+    let mut clipboard = CLIPBOARD.lock().unwrap();
+    if clipboard.is_none() {
+        *clipboard = Some(arboard::Clipboard::new()?);
+    }
+    clipboard.as_mut().unwrap().set_text(text)
+}
+
+// Copies to the system clipboard, or writes the text under the save directory when there is none (e.g. over SSH)
+fn copy_to_clipboard(text: &str, fallback_file: &str) -> Result<(), Box<dyn Error>> {
+    match set_clipboard_text(text) {
+        Ok(()) => print_fancy_message("Copied to the clipboard", "green"),
+        Err(e) => {
+            let dir = save_dir();
+            fs::create_dir_all(&dir)?;
+            let path = dir.join(fallback_file);
+            fs::write(&path, text)?;
+            print_fancy_message(&format!("No clipboard available ({}); saved to {} instead", e, path.display()), "yellow");
+        }
+    }
+    Ok(())
+}
+
+fn print_fancy_message(message: &str, color: &str) {
+    let t = theme();
+    let formatted = match color {
+        "red" => message.color(t.bad).bold(),
+        "green" => message.color(t.good).bold(),
+        "blue" | "cyan" => message.color(t.info).bold(),
+        "yellow" => message.color(t.warning).bold(),
+        "magenta" => message.color(t.accent).bold(),
+        "white" => message.color(t.value).bold(),
+        _ => message.normal(),
+    };
+    
+    println!("\n{}", formatted);
+}
+
+// The character sheet as printed; render it inside without_colors for plain text
+fn character_sheet_text(character: &Character) -> Result<String, fmt::Error> {
+    use fmt::Write;
+    let t = theme();
+    let mut out = String::new();
+    writeln!(out, "\n{}", "CHARACTER SHEET".color(t.heading).bold())?;
+    writeln!(out, "{}", "=".repeat(60).color(t.border))?;
+    writeln!(out, "{}: {}", "Name".color(t.label), character.name.color(t.value))?;
+    writeln!(out, "{}: {} | {}: {}", 
+             "Race".color(t.label), character.race.color(t.value),
+             "Class".color(t.label), character.class_label().color(t.value))?;
+    writeln!(out, "{}: {} | {}: {} GP", 
+             "Level".color(t.label), character.level.to_string().color(t.value),
+             "Gold".color(t.label), character.gold.to_string().color(t.value))?;
+    writeln!(out, "{}: {}", "Background".color(t.label), character.background.color(t.value))?;
+    for line in character.personality.lines() {
+        writeln!(out, "  • {}", line)?;
+    }
+    if !character.backstory.is_empty() {
+        writeln!(out, "{}: {}", "Backstory".color(t.label), character.backstory)?;
+    }
+    if !character.dm_notes.is_empty() {
+        writeln!(out, "{}: {}", "Notes for the DM".color(t.label), character.dm_notes)?;
+    }
+    writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    let max_hp_note = match character.max_hp_modifier() {
+        0 => String::new(),
+        delta => format!(" (max {})", format_modifier(delta)),
+    };
+    writeln!(out, "{}: {}/{}{}", 
+             "Hit Points".color(t.label), 
+             character.hit_points.to_string().color(t.value),
+             character.effective_max_hp().to_string().color(t.value),
+             max_hp_note.color(t.warning))?;
+    match character.status {
+        LifeStatus::Dead => writeln!(out, "{}", "☠ DEAD — seek a resurrection to continue".color(t.bad).bold())?,
+        LifeStatus::Stable => writeln!(out, "{}: {}", "Status".color(t.label), "Stable (unconscious at 0 HP)".color(t.warning))?,
+        LifeStatus::Alive => {}
+    }
+    if character.resurrection_penalty > 0 {
+        writeln!(out, "{}: -{} to attacks, saves, and ability checks (fades by 1 per long rest)",
+                 "Resurrection".color(t.label), character.resurrection_penalty.to_string().color(t.warning))?;
+    }
+    for modifier in &character.max_hp_modifiers {
+        writeln!(out, "  • {}", modifier)?;
+    }
+    if character.temp_hit_points > 0 {
+        writeln!(out, "{}: {}", "Temporary HP".color(t.label), character.temp_hit_points.to_string().color(t.value))?;
+    }
+    if !character.resistances.is_empty() {
+        writeln!(out, "{}: {}", "Resistances".color(t.label), character.resistances.join(", ").color(t.value))?;
+    }
+    writeln!(out, "{}: {}", 
+             "Armor Class".color(t.label), 
+             character.armor_class.to_string().color(t.value))?;
+    writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    writeln!(out, "{}", "Abilities".color(t.heading))?;
+    let score = |ability: Ability| {
+        let effective = character.effective_score(ability);
+        let base = character.ability_score(ability);
+        if effective == base {
+            effective.to_string().color(t.value)
+        } else {
+            format!("{} ({} base)", effective, base).color(t.accent)
+        }
+    };
+    writeln!(out, "{}: {} | {}: {}",
+             "STR".color(t.label), score(Ability::Strength),
+             "DEX".color(t.label), score(Ability::Dexterity))?;
+    writeln!(out, "{}: {} | {}: {}",
+             "CON".color(t.label), score(Ability::Constitution),
+             "INT".color(t.label), score(Ability::Intelligence))?;
+    writeln!(out, "{}: {} | {}: {}",
+             "WIS".color(t.label), score(Ability::Wisdom),
+             "CHA".color(t.label), score(Ability::Charisma))?;
+    for modifier in &character.ability_modifiers {
+        writeln!(out, "  {} {} {} ({}, {})", "↳".color(t.accent), modifier.ability.abbreviation(),
+                 format_modifier(modifier.delta), modifier.source, modifier.duration)?;
+    }
+    writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    
+    writeln!(out, "{}", "Skills".color(t.heading))?;
+    let proficient: Vec<String> = SKILLS
+        .iter()
+        .filter(|(skill, _)| character.is_proficient_in(skill))
+        .map(|(skill, _)| format!("{} {}{}", skill, format_modifier(character.skill_modifier(skill)),
+                                  if character.has_expertise_in(skill) { " (expertise)" } else { "" }))
+        .collect();
+    if proficient.is_empty() {
+        writeln!(out, "(no proficiencies)")?;
+    } else {
+        writeln!(out, "{}", proficient.join(", "))?;
+    }
+    writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    
+    if !character.tool_proficiencies.is_empty() {
+        writeln!(out, "{}: {}", "Tools".color(t.label), character.tool_proficiencies.iter().cloned().collect::<Vec<_>>().join(", "))?;
+        writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    }
+    
+    for class in character.casting_classes() {
+        if let (Some(ability), Some(attack), Some(dc)) =
+            (spellcasting_ability(class), character.spell_attack_bonus(class), character.spell_save_dc(class))
+        {
+            writeln!(out, "{} ({}, {}): {} to hit, save DC {}", "Spellcasting".color(t.label), class, ability.abbreviation(),
+                     format_modifier(attack).color(t.value), dc.to_string().color(t.value))?;
+        }
+    }
+    if let Some((count, level)) = character.pact_slots() {
+        writeln!(out, "{}: {}/{} at level {} (recovered on a short rest)", "Pact slots".color(t.label),
+                 character.pact_slots_left().to_string().color(t.value), count, level)?;
+    }
+    if !character.known_spells.is_empty() || !character.spellbook.is_empty() {
+        let spells: Vec<String> = character.known_spells.iter().map(|s| s.to_string()).collect();
+        if character.keeps_spellbook() {
+            writeln!(out, "{} ({}/{}): {}", "Prepared".color(t.label), character.prepared_spell_count(),
+                     character.prepared_spell_limit(), if spells.is_empty() { "none".to_string() } else { spells.join(", ") })?;
+            let book: Vec<String> = character.spellbook.iter()
+                .map(|s| if is_ritual(&s.name) { format!("{} [ritual]", s) } else { s.to_string() })
+                .collect();
+            writeln!(out, "{}: {}", "Spellbook".color(t.label), if book.is_empty() { "empty".to_string() } else { book.join(", ") })?;
+        } else {
+            writeln!(out, "{}: {}", "Spells".color(t.label), spells.join(", "))?;
+        }
+        writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    }
+    if !character.feats.is_empty() {
+        writeln!(out, "{}: {}", "Feats".color(t.label), character.feats.join(", "))?;
+        writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+    }
+    
+    writeln!(out, "{} ({})", "Inventory".color(t.heading), character.load_summary())?;
+    if character.inventory.is_empty() {
+        writeln!(out, "(empty)")?;
+    } else {
+        for item in &character.inventory {
+            writeln!(out, "• {}", item)?;
+        }
+    }
+    
+    let attuned = character.attuned_items();
+    if !attuned.is_empty() {
+        writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+        writeln!(out, "{} ({}/{})", "Attuned Items".color(t.heading), attuned.len(), MAX_ATTUNED_ITEMS)?;
+        for item in attuned {
+            writeln!(out, "• {}", item.to_string().color(t.accent))?;
+        }
+    }
+    
+    if !character.companions.is_empty() {
+        writeln!(out, "{}", "-".repeat(60).color(t.border))?;
+        writeln!(out, "{}", "Companions".color(t.heading))?;
+        for companion in &character.companions {
+            writeln!(out, "• {}", companion)?;
+        }
+    }
+    writeln!(out, "{}", "=".repeat(60).color(t.border))?;
+    Ok(out)
+}
+
+fn print_character_sheet(character: &Character) {
+    if let Ok(sheet) = character_sheet_text(character) {
+        print!("{}", sheet);
+    }
+}
+
+fn announce_expired_effects(expired: &[ActiveEffect]) {
+    for effect in expired {
+        print_fancy_message(&format!("{} has ended", effect.name), "magenta");
+    }
+}
+
+// Known places as a node graph: each place in a box, its connections branching off to the right
+fn print_map(state: &GameState) {
+    let t = theme();
+    let mut places: Vec<&str> = state.visited_locations.iter().map(String::as_str).collect();
+    for (a, b) in &state.map_links {
+        for place in [a.as_str(), b.as_str()] {
+            if !places.iter().any(|p| p.eq_ignore_ascii_case(place)) {
+                places.push(place);
+            }
+        }
+    }
+    println!("\n{}", "MAP OF KNOWN PLACES".color(t.heading).bold());
+    println!("{}", "-".repeat(60).color(t.border));
+    if places.is_empty() {
+        println!("(nowhere yet)");
+    }
+    for place in places {
+        let here = place.eq_ignore_ascii_case(&state.current_location);
+        let label = if here { format!("★ {}", place) } else { place.to_string() };
+        let width = label.chars().count() + 2;
+        let neighbors: Vec<&str> = state.map_links.iter()
+            .filter_map(|(a, b)| {
+                if a.eq_ignore_ascii_case(place) {
+                    Some(b.as_str())
+                } else if b.eq_ignore_ascii_case(place) {
+                    Some(a.as_str())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let node = if here { label.color(t.good).bold() } else { label.color(t.value) };
+        println!("{}", format!("┌{}┐", "─".repeat(width)).color(t.border));
+        match neighbors.split_first() {
+            None => println!("{} {} {}", "│".color(t.border), node, "│".color(t.border)),
+            Some((first, rest)) => {
+                let branch = if rest.is_empty() { "────" } else { "──┬─" };
+                println!("{} {} {}{} {}", "│".color(t.border), node, "│".color(t.border), branch.color(t.border), first);
+            }
+        }
+        println!("{}", format!("└{}┘", "─".repeat(width)).color(t.border));
+        let pad = " ".repeat(width + 2);
+        for (i, neighbor) in neighbors.iter().enumerate().skip(1) {
+            let branch = if i == neighbors.len() - 1 { "  └─" } else { "  ├─" };
+            println!("{}{} {}", pad, branch.color(t.border), neighbor);
+        }
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+    println!("{} marks where you are", "★".color(t.good).bold());
+}
+
+fn print_roll_history(state: &GameState) {
+    let t = theme();
+    println!("\n{}", "RECENT ROLLS".color(t.heading).bold());
+    println!("{}", "-".repeat(60).color(t.border));
+    if state.recent_rolls.is_empty() {
+        println!("(no rolls yet)");
+    }
+    for record in state.recent_rolls.iter().rev() {
+        let time = chrono::DateTime::parse_from_rfc3339(&record.timestamp)
+            .map(|time| time.format("%H:%M").to_string())
+            .unwrap_or_default();
+        let dice = record.dice.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ");
+        println!("{}  {:>3}  {} [{}]  {}", time.dimmed(), record.total.to_string().color(t.value).bold(),
+                 record.expr, dice, record.context);
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+}
+
+fn print_hp_history(character: &Character) {
+    let t = theme();
+    println!("\n{}", "RECENT HP CHANGES".color(t.heading).bold());
+    println!("{}", "-".repeat(60).color(t.border));
+    if character.hp_log.is_empty() {
+        println!("(no changes yet)");
+    }
+    for change in &character.hp_log {
+        let delta = format!("{:>+4}", change.delta);
+        let delta = if change.delta < 0 { delta.color(t.bad).bold() } else { delta.color(t.good).bold() };
+        println!("{}  {}  {}", format!("turn {:>3}", change.turn).dimmed(), delta, change.reason);
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+    println!("{}: {}/{}{}", "HP".color(t.label), character.hit_points, character.effective_max_hp(),
+             if character.temp_hit_points > 0 { format!(" (+{} temporary)", character.temp_hit_points) } else { String::new() });
+}
+
+// Lists all 18 skills with their totals, either alphabetically or grouped by ability
+fn print_skills(character: &Character, by_ability: bool) {
+    let t = theme();
+    println!("\n{}", "SKILLS".color(t.heading).bold());
+    println!("{}", "=".repeat(60).color(t.border));
+    
+    let mut skills = SKILLS.to_vec();
+    if by_ability {
+        skills.sort_by_key(|(_, ability)| Ability::ALL.iter().position(|a| a == ability));
+    }
+    
+    for (skill, ability) in skills {
+        let proficient = character.is_proficient_in(skill);
+        let line = format!("{} {:<16} ({}) {:>3}",
+                           match character.skill_proficiency(skill) {
+                               Proficiency::Expertise => "◆",
+                               Proficiency::Proficient => "●",
+                               Proficiency::None => "○",
+                           },
+                           skill,
+                           ability.abbreviation(),
+                           format_modifier(character.skill_modifier(skill)));
+        if proficient {
+            println!("{}", line.color(t.label));
+        } else {
+            println!("{}", line.color(t.value));
+        }
+    }
+    println!("{}", "=".repeat(60).color(t.border));
+    println!("● proficient (+{} proficiency bonus)", proficiency_bonus(character.level));
+    println!("◆ expertise (+{}, double proficiency)", 2 * proficiency_bonus(character.level));
+}
+
+const MAX_COMPARED: usize = 4;
+
+// Side-by-side stats, with the best value in each row highlighted (ties all highlighted, nothing when everyone is equal)
+fn print_character_comparison(characters: &[&Character]) {
+    let t = theme();
+    let width = characters.iter().map(|c| c.name.chars().count()).max().unwrap_or(0).max(12) + 2;
+    let label_width = 18;
+    let row = |label: &str, cells: Vec<(String, i32)>| {
+        let best = cells.iter().map(|(_, v)| *v).max().unwrap_or(0);
+        let highlight = cells.iter().any(|(_, v)| *v != best);
+        let mut line = format!("{:<label_width$}", label).color(t.label).to_string();
+        for (text, value) in cells {
+            let cell = format!("{:<width$}", text);
+            if highlight && value == best {
+                line.push_str(&cell.color(t.good).bold().to_string());
+            } else {
+                line.push_str(&cell.color(t.value).to_string());
+            }
+        }
+        println!("{}", line);
+    };
+    let rule = "=".repeat(label_width + width * characters.len());
+    
+    println!("\n{}", "CHARACTER COMPARISON".color(t.heading).bold());
+    println!("{}", rule.color(t.border));
+    print!("{:<label_width$}", "");
+    for character in characters {
+        print!("{}", format!("{:<width$}", character.name).color(t.heading).bold());
+    }
+    println!();
+    print!("{:<label_width$}", "");
+    for character in characters {
+        print!("{}", format!("{:<width$}", format!("{} {}", character.class_label(), character.level)).color(t.info));
+    }
+    println!();
+    println!("{}", rule.color(t.border));
+    
+    for ability in Ability::ALL {
+        row(ability.name(), characters.iter().map(|c| {
+            let score = c.effective_score(ability);
+            (format!("{} ({})", score, format_modifier(c.effective_modifier(ability))), score as i32)
+        }).collect());
+    }
+    row("Armor Class", characters.iter().map(|c| (c.armor_class.to_string(), c.armor_class as i32)).collect());
+    row("Hit Points", characters.iter().map(|c| {
+        (format!("{}/{}", c.hit_points, c.effective_max_hp()), c.effective_max_hp() as i32)
+    }).collect());
+    row("Passive Perception", characters.iter().map(|c| (c.passive_perception().to_string(), c.passive_perception())).collect());
+    println!("{}", "-".repeat(label_width + width * characters.len()).color(t.border));
+    for (skill, _) in SKILLS {
+        row(skill, characters.iter().map(|c| {
+            let modifier = c.skill_modifier(skill);
+            (format!("{}{}", format_modifier(modifier), match c.skill_proficiency(skill) {
+                Proficiency::Expertise => " ◆",
+                Proficiency::Proficient => " ●",
+                Proficiency::None => "",
+            }), modifier)
+        }).collect());
+    }
+    println!("{}", rule.color(t.border));
+    println!("● proficient; {} marks the best in each row", "highlight".color(t.good).bold());
+}
+
+fn print_combat_reference(character: &Character) {
+    let t = theme();
+    let prof = proficiency_bonus(character.level);
+    let weapons = character.weapons();
+    // Proficiency only counts toward the summary lines when a carried weapon of that kind allows it
+    // (unarmed strikes are always proficient)
+    let proficient_in = |ranged: bool| weapons.iter()
+        .any(|w| w.ranged == ranged && character.is_proficient_with(w));
+    let melee_prof = if proficient_in(false) || weapons.iter().all(|w| w.ranged) { prof } else { 0 };
+    let ranged_prof = if proficient_in(true) { prof } else { 0 };
+    println!("\n{}", "COMBAT REFERENCE".color(t.heading).bold());
+    println!("{}", "=".repeat(60).color(t.border));
+    println!("{}: {} | {}: {} | {}: {}",
+             "AC".color(t.label), character.armor_class.to_string().color(t.value),
+             "Initiative".color(t.label), format_modifier(character.effective_modifier(Ability::Dexterity)).color(t.value),
+             "Proficiency".color(t.label), format_modifier(prof).color(t.value));
+    println!("{}: {} | {}: {}",
+             "Melee (STR)".color(t.label),
+             format_modifier(character.effective_modifier(Ability::Strength) + melee_prof).color(t.value),
+             "Ranged (DEX)".color(t.label),
+             format_modifier(character.effective_modifier(Ability::Dexterity) + ranged_prof).color(t.value));
+    println!("{}", "-".repeat(60).color(t.border));
+    
+    println!("{}", "Weapons".color(t.heading));
+    if weapons.is_empty() {
+        println!("Unarmed strike: {} to hit, 1 + STR bludgeoning",
+                 format_modifier(character.effective_modifier(Ability::Strength) + prof));
+    }
+    for weapon in weapons {
+        let damage_mod = character.weapon_ability_modifier(weapon);
+        println!("• {}: {} to hit, {}{} {}{}",
+                 weapon.name.color(t.value),
+                 format_modifier(character.attack_bonus(weapon)).color(t.label),
+                 weapon.damage,
+                 if damage_mod != 0 { format_modifier(damage_mod) } else { String::new() },
+                 weapon.damage_type,
+                 if character.is_proficient_with(weapon) { "" } else { " (not proficient)" });
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+    
+    println!("{}", "Actions".color(t.heading));
+    println!("Attack, Cast a Spell, Dash, Disengage, Dodge, Help, Hide, Ready, Search, Use an Object");
+    println!("{}", "=".repeat(60).color(t.border));
+}
+
+// Where every save file lives: the configured directory, else the per-user data directory
+// (XDG on Linux, Application Support on macOS, AppData on Windows), else the working directory
+fn save_dir() -> PathBuf {
+    if let Some(dir) = config().save_dir {
+        return PathBuf::from(dir);
+    }
+    directories::ProjectDirs::from("", "", "dnd_ai_dm")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// A save slot in the configured format first, then in the other one (left over from before the setting changed)
+fn slot_paths(plain: &str, compressed: &str) -> [PathBuf; 2] {
+    let (preferred, other) = if config().compress_saves { (compressed, plain) } else { (plain, compressed) };
+    [save_dir().join(preferred), save_dir().join(other)]
+}
+
+fn save_paths() -> [PathBuf; 2] {
+    slot_paths(SAVE_FILE, COMPRESSED_SAVE_FILE)
+}
+
+fn quicksave_paths() -> [PathBuf; 2] {
+    slot_paths(QUICKSAVE_FILE, COMPRESSED_QUICKSAVE_FILE)
+}
+
+// Writes a slot in the configured format; only one format may exist, so toggling compression
+// converts the slot on its next write
+fn write_slot([path, stale]: [PathBuf; 2], state: &GameState) -> Result<(), Box<dyn Error>> {
+    write_save(state, &path)?;
+    if stale.exists() {
+        fs::remove_file(stale)?;
+    }
+    Ok(())
+}
+
+fn existing_save_path() -> Option<PathBuf> {
+    save_paths().into_iter().find(|path| path.exists())
+}
+
+// Saves used to be written to the working directory; move one found there into the save directory
+fn migrate_legacy_save() {
+    let legacy = Path::new(SAVE_FILE);
+    let target = save_dir().join(SAVE_FILE);
+    if !legacy.exists() || target.exists() || fs::canonicalize(legacy).ok() == fs::canonicalize(&target).ok() {
+        return;
+    }
+    let moved = fs::create_dir_all(save_dir())
+        .and_then(|_| fs::copy(legacy, &target))
+        .and_then(|_| fs::remove_file(legacy));
+    match moved {
+        Ok(()) => print_fancy_message(&format!("Moved your saved adventure to {}", target.display()), "yellow"),
+        Err(e) => print_fancy_message(&format!("Could not move {} to {}: {}", SAVE_FILE, target.display(), e), "red"),
+    }
+}
+
+// Autosaves are debounced: within AUTOSAVE_INTERVAL of the last write a save is only marked pending,
+// and pending changes are written once the game is idle at the action menu or the adventure ends
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+static LAST_SAVE: Mutex<Option<Instant>> = Mutex::new(None);
+static SAVE_PENDING: AtomicBool = AtomicBool::new(false);
+
+// File operations
+fn save_game(state: &GameState) -> Result<(), Box<dyn Error>> {
+    let due = LAST_SAVE.lock().unwrap().is_none_or(|last| last.elapsed() >= AUTOSAVE_INTERVAL);
+    if !due {
+        SAVE_PENDING.store(true, Ordering::Relaxed);
+        return Ok(());
+    }
+    flush_save(state)
+}
+
+// Writes right away, for explicit saves and anything that must be on disk before continuing
+fn flush_save(state: &GameState) -> Result<(), Box<dyn Error>> {
+    write_slot(save_paths(), state)?;
+    *LAST_SAVE.lock().unwrap() = Some(Instant::now());
+    SAVE_PENDING.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+fn flush_pending_save(state: &GameState) -> Result<(), Box<dyn Error>> {
+    if SAVE_PENDING.load(Ordering::Relaxed) {
+        flush_save(state)?;
+    }
+    Ok(())
+}
+
+// Written like the autosave, so it honors compressed saves too
+fn quick_save(state: &GameState) -> Result<(), Box<dyn Error>> {
+    write_slot(quicksave_paths(), state)
+}
+
+// The quicksave in either format, if one exists
+fn quick_load() -> Result<Option<GameState>, Box<dyn Error>> {
+    match quicksave_paths().into_iter().find(|path| path.exists()) {
+        Some(path) => Ok(Some(read_save(&path)?)),
+        None => Ok(None),
+    }
+}
+
+// CRC-32 (IEEE), as the gzip trailer requires
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// A minimal gzip member: fixed header, deflate stream, then CRC-32 and length
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![GZIP_MAGIC[0], GZIP_MAGIC[1], 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+// Reads gzip from this game or from the gzip tool, skipping the optional header fields
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let corrupt = || io::Error::other("The compressed save is damaged.");
+    if data.len() < 18 || data[..2] != GZIP_MAGIC || data[2] != 8 {
+        return Err(Box::new(corrupt()));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 4 != 0 {
+        pos += 2 + u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+    }
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            pos += data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or_else(corrupt)? + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        pos += 2;
+    }
+    let body = data.get(pos..data.len() - 8).ok_or_else(corrupt)?;
+    let decoded = miniz_oxide::inflate::decompress_to_vec(body).map_err(|_| corrupt())?;
+    let crc = u32::from_le_bytes(data[data.len() - 8..data.len() - 4].try_into()?);
+    if crc != crc32(&decoded) {
+        return Err(Box::new(corrupt()));
+    }
+    Ok(decoded)
+}
+
+// Loads a save in either format, telling them apart by the gzip magic bytes rather than the name
+fn read_save(path: &Path) -> Result<GameState, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let json = if bytes.starts_with(&GZIP_MAGIC) { gunzip(&bytes)? } else { bytes };
+    migrate(serde_json::from_slice(&json)?)
+}
+
+fn write_save(state: &GameState, save_file: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(save_dir())?;
+    
+    // Create a temporary file to write to first
+    let mut temp_file = save_file.as_os_str().to_owned();
+    temp_file.push(".tmp");
+    let temp_file = PathBuf::from(temp_file);
+    let json = serde_json::to_string_pretty(state)?;
+    let bytes = if save_file.to_string_lossy().ends_with(".gz") { gzip(json.as_bytes()) } else { json.into_bytes() };
+    
+    // Write to the temporary file first
+    fs::write(&temp_file, &bytes)?;
+    
+    // Then rename the temporary file to the actual save file
+    // This helps prevent corruption if the program crashes during the write
+    if temp_file.exists() {
+        if save_file.exists() {
+            fs::remove_file(save_file)?;
+        }
+        fs::rename(&temp_file, save_file)?;
+    }
+    
+    Ok(())
+}
+
+// Records which campaign is in play so a crash can be noticed on the next launch
+fn open_session_marker(state: &GameState) {
+    let _ = fs::create_dir_all(save_dir());
+    let _ = fs::write(save_dir().join(SESSION_MARKER_FILE), &state.campaign);
+}
+
+fn clear_session_marker() {
+    let _ = fs::remove_file(save_dir().join(SESSION_MARKER_FILE));
+}
+
+// True when the game last exited mid-adventure without returning to the menu
+fn unclean_shutdown() -> bool {
+    save_dir().join(SESSION_MARKER_FILE).exists()
+}
+
+fn load_game() -> Result<GameState, Box<dyn Error>> {
+    match existing_save_path() {
+        Some(save_file) => read_save(&save_file),
+        None => Ok(GameState::default()),
+    }
+}
+
+// The newest readable save, autosave or quicksave, for the one-key Continue on the main menu
+struct RecentSave {
+    state: GameState,
+    modified: chrono::DateTime<Local>,
+    quicksave: bool,
+}
+
+fn most_recent_save() -> Option<RecentSave> {
+    let quicksaves = quicksave_paths();
+    let mut saves: Vec<(PathBuf, std::time::SystemTime)> = save_paths()
+        .into_iter()
+        .chain(quicksaves.clone())
+        .filter_map(|path| fs::metadata(&path).and_then(|m| m.modified()).ok().map(|modified| (path, modified)))
+        .collect();
+    saves.sort_by_key(|(_, modified)| *modified);
+    saves.into_iter().rev().find_map(|(path, modified)| {
+        let state = read_save(&path).ok().filter(|state| !state.campaign.is_empty())?;
+        Some(RecentSave { state, modified: modified.into(), quicksave: quicksaves.contains(&path) })
+    })
+}
+
+// "just now", "5 minutes ago", "2 hours ago", "yesterday", "3 days ago"
+fn time_ago(then: chrono::DateTime<Local>) -> String {
+    let minutes = Local::now().signed_duration_since(then).num_minutes().max(0);
+    match minutes {
+        0 => "just now".to_string(),
+        1 => "a minute ago".to_string(),
+        2..=59 => format!("{} minutes ago", minutes),
+        60..=119 => "an hour ago".to_string(),
+        120..=1439 => format!("{} hours ago", minutes / 60),
+        1440..=2879 => "yesterday".to_string(),
+        _ => format!("{} days ago", minutes / 1440),
+    }
+}
+
+impl RecentSave {
+    fn label(&self) -> String {
+        format!(
+            "Continue {} ({}, last played {}{})",
+            self.state.campaign,
+            self.state.character.name,
+            time_ago(self.modified),
+            if self.quicksave { ", quicksave" } else { "" }
+        )
+    }
+}
+
+// Upgrades a save from any earlier format version, one step at a time
+fn migrate(mut value: serde_json::Value) -> Result<GameState, Box<dyn Error>> {
+    let mut version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+    if version > SAVE_VERSION {
+        return Err(Box::new(io::Error::other(format!(
+            "This save was written by a newer version of the game (save version {}, this build supports up to {}).",
+            version, SAVE_VERSION
+        ))));
+    }
+    
+    while version < SAVE_VERSION {
+        let state = value
+            .as_object_mut()
+            .ok_or_else(|| io::Error::other("Save file is not a JSON object"))?;
+        match version {
+            // v0 -> v1: unversioned saves predate combat and scene tracking, and
+            // gave wizards a generic "Staff" that the weapon table doesn't know
+            0 => {
+                state.entry("encounter").or_insert(serde_json::Value::Null);
+                state.entry("scene_type").or_insert(serde_json::json!("Exploration"));
+                if let Some(character) = state.get_mut("character").and_then(|c| c.as_object_mut()) {
+                    character.entry("temp_hit_points").or_insert(serde_json::json!(0));
+                    character.entry("resistances").or_insert(serde_json::json!([]));
+                    character.entry("death_save_successes").or_insert(serde_json::json!(0));
+                    character.entry("death_save_failures").or_insert(serde_json::json!(0));
+                    if let Some(inventory) = character.get_mut("inventory").and_then(|i| i.as_array_mut()) {
+                        for item in inventory.iter_mut() {
+                            if item.as_str() == Some("Staff") {
+                                *item = serde_json::json!("Quarterstaff");
+                            }
+                        }
+                    }
+                }
+            }
+            // v1 -> v2: inventory entries became structured items
+            1 => {
+                if let Some(inventory) = state
+                    .get_mut("character")
+                    .and_then(|c| c.get_mut("inventory"))
+                    .and_then(|i| i.as_array_mut())
+                {
+                    for item in inventory.iter_mut() {
+                        if let Some(name) = item.as_str() {
+                            *item = serde_json::json!({ "name": name });
+                        }
+                    }
+                }
+            }
+            // v2 -> v3: ranged weapons track their ammunition instead of naming it
+            2 => {
+                if let Some(inventory) = state
+                    .get_mut("character")
+                    .and_then(|c| c.get_mut("inventory"))
+                    .and_then(|i| i.as_array_mut())
+                {
+                    for item in inventory.iter_mut() {
+                        let Some(parsed) = item.get("name").and_then(|n| n.as_str()).map(Item::new) else {
+                            continue;
+                        };
+                        if let (Some(count), Some(fields)) = (parsed.ammo, item.as_object_mut()) {
+                            fields.insert("name".to_string(), serde_json::json!(parsed.name));
+                            fields.insert("ammo".to_string(), serde_json::json!(count));
+                        }
+                    }
+                }
+            }
+            // v3 -> v4: death became a status instead of being read off the death saves
+            3 => {
+                if let Some(character) = state.get_mut("character").and_then(|c| c.as_object_mut()) {
+                    let count = |key: &str| character.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+                    let status = if count("death_save_failures") >= 3 {
+                        "Dead"
+                    } else if count("hit_points") == 0 && count("death_save_successes") >= 3 {
+                        "Stable"
+                    } else {
+                        "Alive"
+                    };
+                    character.insert("status".to_string(), serde_json::json!(status));
+                }
+            }
+            // v4 -> v5: skill proficiency gained a third state for Expertise
+            4 => {
+                if let Some(skills) = state
+                    .get_mut("character")
+                    .and_then(|c| c.get_mut("skills"))
+                    .and_then(|s| s.as_object_mut())
+                {
+                    for proficiency in skills.values_mut() {
+                        if let Some(proficient) = proficiency.as_bool() {
+                            *proficiency = serde_json::json!(if proficient { "Proficient" } else { "None" });
+                        }
+                    }
+                }
+            }
+            // v5 -> v6: wizards keep leveled spells in a spellbook, and what they knew before counts as prepared
+            5 => {
+                if let Some(character) = state.get_mut("character").and_then(|c| c.as_object_mut())
+                    && character.get("class").and_then(|c| c.as_str()) == Some("Wizard")
+                {
+                    let leveled: Vec<serde_json::Value> = character
+                        .get("known_spells")
+                        .and_then(|s| s.as_array())
+                        .map(|spells| spells.iter().filter(|s| s.get("level").and_then(|l| l.as_u64()).unwrap_or(0) > 0).cloned().collect())
+                        .unwrap_or_default();
+                    character.insert("spellbook".to_string(), serde_json::json!(leveled));
+                }
+            }
+            _ => unreachable!("no migration defined from save version {}", version),
+        }
+        version += 1;
+        state.insert("version".to_string(), serde_json::json!(version));
+    }
+    
+    Ok(serde_json::from_value(value)?)
+}
+
+// Portable adventure archives for sharing a campaign as a single file
+const ARCHIVE_MAGIC: &str = "dnd-ai-dm-adventure";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct TranscriptEntry {
+    speaker: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct AdventureArchive {
+    magic: String,
+    version: u32,
+    exported_at: String,
+    state: GameState,
+    transcript: Vec<TranscriptEntry>,
+}
+
+fn build_transcript(history: &[Message]) -> Vec<TranscriptEntry> {
+    history
+        .iter()
+        .filter_map(|message| match message {
+            Message::User { content } => match content.first() {
+                UserContent::Text(text) if text.text.starts_with(DIRECTOR_NOTE_PREFIX) => Some(TranscriptEntry {
+                    speaker: "Director's note".to_string(),
+                    text: text.text,
+                }),
+                UserContent::Text(text) if text.text.starts_with(RECAP_PREFIX) => Some(TranscriptEntry {
+                    speaker: "Recap request".to_string(),
+                    text: text.text,
+                }),
+                UserContent::Text(text) => Some(TranscriptEntry { speaker: "Player".to_string(), text: text.text }),
+                _ => None,
+            },
+            Message::Assistant { content } => Some(TranscriptEntry {
+                speaker: "Dungeon Master".to_string(),
+                text: extract_text_from_message(content),
+            }),
+        })
+        .collect()
+}
+
+// The DM's answer to the most recent recap request
+fn last_recap(history: &[Message]) -> Option<String> {
+    build_transcript(history)
+        .windows(2)
+        .rev()
+        .find(|pair| pair[0].speaker == "Recap request" && pair[1].speaker == "Dungeon Master")
+        .map(|pair| pair[1].text.clone())
+}
+
+fn export_adventure(state: &GameState, path: &str) -> Result<(), Box<dyn Error>> {
+    let archive = AdventureArchive {
+        magic: ARCHIVE_MAGIC.to_string(),
+        version: ARCHIVE_VERSION,
+        exported_at: Local::now().to_rfc3339(),
+        state: state.clone(),
+        transcript: build_transcript(&state.history),
+    };
+    fs::write(path, serde_json::to_string_pretty(&archive)?)?;
+    Ok(())
+}
+
+// Validates the archive header before trusting the rest of the file
+fn import_adventure(path: &str) -> Result<GameState, Box<dyn Error>> {
+    let json = fs::read_to_string(path)?;
+    let value: serde_json::Value = serde_json::from_str(&json)
+        .map_err(|_| io::Error::other("This file is not a valid adventure archive."))?;
+    
+    if value.get("magic").and_then(|m| m.as_str()) != Some(ARCHIVE_MAGIC) {
+        return Err(Box::new(io::Error::other("This file is not an AI Dungeon Master adventure archive.")));
+    }
+    // Only local saves predate versioning; an archive without a version can't be migrated safely
+    let version = value
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| io::Error::other("The adventure archive has no version, so it can't be imported safely."))?;
+    if version > ARCHIVE_VERSION as u64 {
+        return Err(Box::new(io::Error::other(format!(
+            "This adventure was exported by a newer version of the game (archive version {}, this build supports up to {}). Please update to import it.",
+            version, ARCHIVE_VERSION
+        ))));
+    }
+    
+    let state = value
+        .get("state")
+        .cloned()
+        .ok_or_else(|| io::Error::other("The adventure archive has no game state."))?;
+    if state.get("version").and_then(|v| v.as_u64()).is_none() {
+        return Err(Box::new(io::Error::other("The adventure archive's game state has no save version, so it can't be imported safely.")));
+    }
+    migrate(state)
+}
+
+// Short skill keys used by Foundry-style character imports, in SKILLS order
+const VTT_SKILL_KEYS: [&str; 18] = [
+    "acr", "ani", "arc", "ath", "dec", "his", "ins", "itm", "inv",
+    "med", "nat", "prc", "prf", "per", "rel", "slt", "ste", "sur",
+];
+
+// Maps the character onto a simplified Foundry VTT (dnd5e) actor so it can be imported into a tabletop.
+// Target schema:
+//   name, type ("character")
+//   system.abilities.<str|dex|con|int|wis|cha>: { value, mod, proficient (save, 0/1), save }
+//   system.attributes: { hp: { value, max, temp }, ac: { value }, prof }
+//   system.details: { race, background, class, level, xp: { value } }
+//   system.skills.<key>: { ability, value (proficiency, 0/1/2 for expertise), total }
+//   system.currency: { gp }
+//   system.traits: { dr: { value }, toolProf: [..] }
+//   items: [{ name, type ("weapon" | "equipment" | "loot"), system: { attunement, attuned, armor?, damage? } }]
+fn character_to_vtt(character: &Character) -> Result<serde_json::Value, Box<dyn Error>> {
+    let missing: Vec<&str> = [
+        ("name", character.name.trim().is_empty()),
+        ("race", character.race.trim().is_empty()),
+        ("class", character.class.trim().is_empty()),
+        ("level", character.level == 0),
+    ]
+    .into_iter()
+    .filter(|(_, missing)| *missing)
+    .map(|(field, _)| field)
+    .collect();
+    if !missing.is_empty() {
+        return Err(Box::new(io::Error::other(format!(
+            "The character is missing required fields for a VTT export: {}",
+            missing.join(", ")
+        ))));
+    }
+    
+    let abilities: serde_json::Map<String, serde_json::Value> = Ability::ALL
+        .iter()
+        .map(|ability| {
+            (ability.abbreviation().to_lowercase(), serde_json::json!({
+                "value": character.effective_score(*ability),
+                "mod": character.effective_modifier(*ability),
+                "proficient": character.is_proficient_in_save(*ability) as u8,
+                "save": character.saving_throw_modifier(*ability),
+            }))
+        })
+        .collect();
+    
+    let skills: serde_json::Map<String, serde_json::Value> = SKILLS
+        .iter()
+        .zip(VTT_SKILL_KEYS)
+        .map(|((skill, ability), key)| {
+            (key.to_string(), serde_json::json!({
+                "ability": ability.abbreviation().to_lowercase(),
+                "value": character.skill_proficiency(skill) as u8,
+                "total": character.skill_modifier(skill),
+            }))
+        })
+        .collect();
+    
+    let items: Vec<serde_json::Value> = character
+        .inventory
+        .iter()
+        .map(|item| {
+            let mut system = serde_json::json!({
+                "attunement": item.requires_attunement,
+                "attuned": item.attuned,
+            });
+            let kind = if let Some(weapon) = find_weapon(&item.name) {
+                system["damage"] = serde_json::json!({ "parts": [[weapon.damage, weapon.damage_type]] });
+                "weapon"
+            } else if let Some(armor) = find_armor(&item.name) {
+                system["armor"] = serde_json::json!({ "value": armor.base_ac, "dex": armor.dex_cap });
+                "equipment"
+            } else if item.name.to_lowercase().contains("shield") {
+                system["armor"] = serde_json::json!({ "value": 2 });
+                "equipment"
+            } else {
+                "loot"
+            };
+            serde_json::json!({ "name": item.name, "type": kind, "system": system })
+        })
+        .collect();
+    
+    Ok(serde_json::json!({
+        "name": character.name,
+        "type": "character",
+        "system": {
+            "abilities": abilities,
+            "attributes": {
+                "hp": {
+                    "value": character.hit_points,
+                    "max": character.effective_max_hp(),
+                    "temp": character.temp_hit_points,
+                },
+                "ac": { "value": character.armor_class },
+                "prof": proficiency_bonus(character.level),
+            },
+            "details": {
+                "race": character.race,
+                "background": character.background,
+                "class": character.class_label(),
+                "level": character.level,
+                "xp": { "value": character.experience },
+            },
+            "skills": skills,
+            "currency": { "gp": character.gold },
+            "traits": {
+                "dr": { "value": character.resistances },
+                "toolProf": character.tool_proficiencies,
+            },
+        },
+        "items": items,
+    }))
+}
+
+fn export_character_vtt(character: &Character, path: &str) -> Result<(), Box<dyn Error>> {
+    let actor = character_to_vtt(character)?;
+    fs::write(path, serde_json::to_string_pretty(&actor)?)?;
+    Ok(())
+}
+
+// Lowercase, dash-separated form of a name that is safe to use in a file name
+fn slugify(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect::<String>()
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn default_export_path(state: &GameState) -> String {
+    let slug = slugify(&state.campaign);
+    format!("{}-{}.adventure.json", if slug.is_empty() { "adventure" } else { &slug }, Local::now().format("%Y%m%d"))
+}
+
+// Retired characters are archived here, each adventure export next to a plain-text final status
+const RETIRED_DIR: &str = "completed_adventures";
+
+fn retired_dir() -> PathBuf {
+    save_dir().join(RETIRED_DIR)
+}
+
+// Where the character and campaign stood on the day they retired
+fn final_status(state: &GameState) -> String {
+    let character = &state.character;
+    let list = |items: Vec<String>| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+    [
+        format!("{} the {} {}, level {}", character.name, character.race, character.class_label(), character.level),
+        format!("Campaign: {} ({})", state.campaign, state.setting.name()),
+        format!("Retired: {}", Local::now().format("%Y-%m-%d")),
+        format!("Time in the world: {} ({} turns played)", state.clock, state.turn()),
+        format!("{} | HP {}/{} | AC {} | XP {} | Gold {}", character.status.name(), character.hit_points, character.effective_max_hp(),
+                character.armor_class, character.experience, character.gold),
+        format!("Last seen: {}", state.current_location),
+        format!("Unfinished quest: {}", state.current_quest),
+        format!("Places visited: {}", list(state.visited_locations.clone())),
+        format!("Feats: {}", list(character.feats.clone())),
+        format!("Spells: {}", list(character.known_spells.iter().map(|s| s.to_string()).collect())),
+        format!("Inventory: {}", list(character.inventory.iter().map(|i| i.to_string()).collect())),
+        format!("Companions: {}", list(character.companions.iter().map(|c| c.name.clone()).collect())),
+    ]
+    .join("\n")
+        + "\n"
+}
+
+// Archives the adventure and its final status, then clears the autosave so the campaign is closed
+fn retire_character(state: &GameState) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(retired_dir())?;
+    // A second retirement of the same campaign on the same day gets "-2", "-3", ... rather than overwriting
+    let name = default_export_path(state);
+    let stem = name.trim_end_matches(".adventure.json");
+    let status_path = |archive: &Path| archive.with_extension("").with_extension("status.txt");
+    let archive = (1..)
+        .map(|n| if n == 1 { name.clone() } else { format!("{}-{}.adventure.json", stem, n) })
+        .map(|name| retired_dir().join(name))
+        .find(|archive| !archive.exists() && !status_path(archive).exists())
+        .expect("some numbered archive name is free");
+    export_adventure(state, &archive.to_string_lossy())?;
+    fs::write(status_path(&archive), final_status(state))?;
+    while let Some(save_file) = existing_save_path() {
+        fs::remove_file(save_file)?;
+    }
+    // The quicksave goes too, or Continue and Quick load would bring the retired campaign back
+    for quicksave in quicksave_paths() {
+        if quicksave.exists() {
+            fs::remove_file(quicksave)?;
+        }
+    }
+    SAVE_PENDING.store(false, Ordering::Relaxed);
+    Ok(archive)
+}
+
+fn print_timeline(events: &[TimelineEvent]) {
+    let t = theme();
+    println!("\n{}", "CAMPAIGN TIMELINE".color(t.heading).bold());
+    println!("{}", "=".repeat(60).color(t.border));
+    if events.is_empty() {
+        println!("(nothing recorded yet)");
+    }
+    let mut day = None;
+    for event in events {
+        if day != Some(event.day) {
+            day = Some(event.day);
+            println!("{}", format!("Day {}", event.day).color(t.label).bold());
+        }
+        println!("  • {}", event.title.color(t.value));
+        if !event.description.is_empty() {
+            println!("    {}", event.description);
+        }
+    }
+    println!("{}", "=".repeat(60).color(t.border));
+}
+
+// Shows the timeline and lets the player record or strike out events
+fn timeline_action(state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    print_timeline(&state.events);
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Timeline")
+        .default(0)
+        .items(&["Add an event", "Remove an event", "Back"])
+        .interact()?;
+    match choice {
+        0 => {
+            let title: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Event (e.g. \"The bridge falls\")")
+                .interact_text()?;
+            let description: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Details (optional)")
+                .allow_empty(true)
+                .interact_text()?;
+            if let Some(event) = parse_event_tag(&format!("{}|{}", title, description), state.clock.day()) {
+                state.events.push(event);
+                save_game(state)?;
+            }
+        },
+        1 if !state.events.is_empty() => {
+            let options: Vec<String> = state.events.iter().map(|e| e.to_string()).collect();
+            let index = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Remove which event?")
+                .default(options.len() - 1)
+                .items(&options)
+                .interact()?;
+            let removed = state.events.remove(index);
+            save_game(state)?;
+            print_fancy_message(&format!("Removed from the timeline: {}", removed.title), "green");
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+// Raise Dead: a 500 gp diamond brings the character back weakened
+const RAISE_DEAD_COST: u32 = 500;
+const RESURRECTION_PENALTY: u32 = 4;
+
+// The ways back from death: paying for Raise Dead, or whatever bargain the DM offers
+async fn resurrection_action(dm: &impl Chat, state: &mut GameState) -> Result<Option<String>, Box<dyn Error>> {
+    let raise_dead = format!("Raise Dead ({} gp diamond, -{} penalty that fades with long rests)", RAISE_DEAD_COST, RESURRECTION_PENALTY);
+    let options = [raise_dead.as_str(), "Ask the DM for another way back", "Back"];
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} is dead. How do they return?", state.character.name))
+        .default(0)
+        .items(&options)
+        .interact()?;
+    let turn = state.turn();
+    let action = match choice {
+        0 => {
+            if state.character.gold < RAISE_DEAD_COST {
+                print_fancy_message(&format!("Raise Dead needs {} gp; the party has {} gp.", RAISE_DEAD_COST, state.character.gold), "red");
+                return Ok(None);
+            }
+            state.character.gold -= RAISE_DEAD_COST;
+            state.character.resurrect("Raise Dead", RESURRECTION_PENALTY, turn);
+            format!(
+                "My companions spend a {} gp diamond on Raise Dead and I return to life at 1 HP, weakened (-{} to attacks, saves, and checks until I've had {} long rests). Narrate my return.",
+                RAISE_DEAD_COST, RESURRECTION_PENALTY, RESURRECTION_PENALTY
+            )
+        }
+        1 => {
+            let offer = process_player_action(dm, &format!(
+                "{} is dead and the party cannot afford Raise Dead. As the DM, offer one path back from death that fits the story so far (a divine bargain, a quest, a debt to a powerful NPC) and name its price plainly. Do not resolve it yet.",
+                state.character.name
+            ), None, state).await?;
+            print_fancy_message("Dungeon Master:", "cyan");
+            print_dm_text(&offer);
+            let accepted = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Accept these terms?")
+                .default(true)
+                .interact()?;
+            if !accepted {
+                save_game(state)?;
+                return Ok(None);
+            }
+            let turn = state.turn();
+            state.character.resurrect("Returned from death", RESURRECTION_PENALTY, turn);
+            format!(
+                "I accept the price and return to life at 1 HP, weakened (-{} to attacks, saves, and checks until I've had {} long rests). Narrate my return and what I now owe.",
+                RESURRECTION_PENALTY, RESURRECTION_PENALTY
+            )
+        }
+        _ => return Ok(None),
+    };
+    print_fancy_message(&format!("{} lives again!", state.character.name), "green");
+    Ok(Some(process_player_action(dm, &action, None, state).await?))
+}
+
+// A retired character ready for a new campaign: fully rested, with the old campaign noted in the backstory
+fn veteran_for_sequel(character: &Character, campaign: &str) -> Character {
+    let mut veteran = character.clone();
+    veteran.active_effects.clear();
+    veteran.temp_hit_points = 0;
+    veteran.death_save_successes = 0;
+    veteran.death_save_failures = 0;
+    veteran.status = LifeStatus::Alive;
+    veteran.resurrection_penalty = 0;
+    veteran.pact_slots_used = 0;
+    veteran.hp_log.clear();
+    veteran.hit_points = veteran.effective_max_hp();
+    let note = format!("A veteran of {}.", campaign);
+    veteran.backstory = if veteran.backstory.is_empty() { note } else { format!("{} {}", veteran.backstory, note) };
+    veteran
+}
+
+// Offers the characters in the retired archive for a new campaign; None means make a new character
+fn choose_retired_character() -> Result<Option<Character>, Box<dyn Error>> {
+    let mut veterans = retired_characters();
+    if veterans.is_empty() {
+        return Ok(None);
+    }
+    
+    let mut options = vec!["Create a new character".to_string()];
+    options.extend(veterans.iter().map(|(label, _)| format!("Bring back {}", label)));
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Who is this adventure about?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    Ok(choice.checked_sub(1).map(|index| veterans.swap_remove(index).1))
+}
+
+// Every character in the retired archive, ready for a sequel, labeled with where they came from
+fn retired_characters() -> Vec<(String, Character)> {
+    let Ok(entries) = fs::read_dir(retired_dir()) else {
+        return Vec::new();
+    };
+    let mut veterans: Vec<(String, Character)> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.to_string_lossy().ends_with(".adventure.json"))
+        .filter_map(|path| import_adventure(&path.to_string_lossy()).ok())
+        .map(|state| {
+            let label = format!("{} ({} {}) from {}", state.character.name, state.character.class_label(),
+                                state.character.level, state.campaign);
+            (label, veteran_for_sequel(&state.character, &state.campaign))
+        })
+        .collect();
+    veterans.sort_by(|a, b| a.0.cmp(&b.0));
+    veterans
+}
+
+// Lets the player branch the story from an earlier DM response, backing up the current timeline first
+fn rewind_history(state: &mut GameState) -> Result<bool, Box<dyn Error>> {
+    let beats: Vec<(usize, String)> = state
+        .history
+        .iter()
+        .enumerate()
+        .filter_map(|(i, message)| match message {
+            Message::Assistant { content } => Some((i, extract_text_from_message(content))),
+            _ => None,
+        })
+        .collect();
+    
+    if beats.len() < 2 {
+        print_fancy_message("There is nothing to rewind yet.", "yellow");
+        return Ok(false);
+    }
+    
+    let items: Vec<String> = beats
+        .iter()
+        .enumerate()
+        .map(|(n, (_, text))| {
+            let first_line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("").trim();
+            let preview: String = first_line.chars().take(70).collect();
+            format!("Turn {}: {}", n + 1, preview)
+        })
+        .collect();
+    
+    let beat_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Rewind to which turn? (later turns will be discarded)")
+        .default(items.len() - 1)
+        .items(&items)
+        .interact()?;
+    
+    let (history_index, _) = beats[beat_index];
+    if history_index + 1 == state.history.len() {
+        print_fancy_message("You are already at that point in the story.", "yellow");
+        return Ok(false);
+    }
+    
+    let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Discard everything after turn {}?", beat_index + 1))
+        .default(false)
+        .interact()?;
+    if !confirmed {
+        return Ok(false);
+    }
+    
+    // Keep the pre-rewind timeline recoverable
+    let backup_paths = slot_paths(REWIND_BACKUP_FILE, COMPRESSED_REWIND_BACKUP_FILE);
+    let backup_file = backup_paths[0].clone();
+    write_slot(backup_paths, state)?;
+    
+    state.history.truncate(history_index + 1);
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    print_fancy_message(&format!("Rewound to turn {}. The previous timeline was saved to {}", beat_index + 1, backup_file.display()), "green");
+    Ok(true)
+}
+
+// Splits "[TAG: value]" markers out of a DM response, returning the cleaned text and the tags
+fn extract_tags(response: &str) -> (String, Vec<(String, String)>) {
+    let mut text = String::new();
+    let mut tags = Vec::new();
+    let mut rest = response;
+    
+    while let Some(start) = rest.find('[') {
+        let Some(end) = rest[start..].find(']').map(|e| start + e) else {
+            break;
+        };
+        let inner = &rest[start + 1..end];
+        match inner.split_once(':') {
+            // Dialogue tags stay in the text for print_dm_text to lay out
+            Some((tag, _)) if tag == NPC_SAYS_TAG => text.push_str(&rest[..=end]),
+            Some((tag, value)) if !tag.is_empty() && tag.chars().all(|c| c.is_ascii_uppercase() || c == '-' || c == '_') => {
+                text.push_str(&rest[..start]);
+                tags.push((tag.to_string(), value.trim().to_string()));
+            }
+            _ => text.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    
+    // Collapse the blank lines left behind by removed tags
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    (lines.join("\n").trim().to_string(), tags)
+}
+
+// Applies state changes the DM requested through tags
+fn apply_dm_tags(state: &mut GameState, tags: &[(String, String)]) {
+    let turn = state.turn();
+    for (tag, value) in tags {
+        match tag.as_str() {
+            "SCENE" => {
+                if let Some(scene) = SceneType::parse(value) {
+                    state.scene_type = scene;
+                }
+            },
+            "ENV" => {
+                let changed = state.environment.apply_tag(value);
+                if changed {
+                    print_fancy_message(&format!("Environment: {}", state.environment), "cyan");
+                }
+            },
+            "LEARN" => {
+                let Some(spell) = parse_learn_tag(value) else {
+                    continue;
+                };
+                let name = spell.to_string();
+                match state.character.learn_spell(spell) {
+                    Ok(()) => print_fancy_message(&format!("You learn {}", name), "green"),
+                    Err(reason) => print_fancy_message(&format!("Can't learn {}: {}", name, reason), "yellow"),
+                }
+            },
+            "FACT" => {
+                let fact = value.trim();
+                if !fact.is_empty() && !state.canon_facts.iter().any(|f| f.eq_ignore_ascii_case(fact)) {
+                    println!("{}", format!("(canon: {})", fact).dimmed());
+                    state.canon_facts.push(fact.to_string());
+                }
+            },
+            "BONUS" => {
+                if let Some((source, expr)) = parse_bonus_tag(value) {
+                    print_fancy_message(&format!("{} grants you +{} on your next check or save", source, expr), "green");
+                    state.character.pending_bonus_dice.push((source, expr));
+                }
+            },
+            "EVENT" => {
+                if let Some(event) = parse_event_tag(value, state.clock.day()) {
+                    println!("{}", format!("(timeline: {})", event).dimmed());
+                    state.events.push(event);
+                }
+            },
+            "MAP" => {
+                // "Oakvale - Old Mill"; a bare hyphen only splits names that don't contain one
+                if let Some((a, b)) = value.split_once(" - ").or_else(|| value.split_once('-')) {
+                    state.add_map_link(a, b);
+                }
+            },
+            "MAX_HP" => {
+                if let Some(modifier) = parse_max_hp_tag(value) {
+                    print_fancy_message(&format!("Your {}", modifier), if modifier.delta < 0 { "red" } else { "green" });
+                    state.character.add_max_hp_modifier(modifier, turn);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// "The bridge falls | The ogres cut off Oakvale from the south", dated to the current in-game day
+fn parse_event_tag(value: &str, day: u64) -> Option<TimelineEvent> {
+    let (title, description) = value.split_once('|').unwrap_or((value, ""));
+    let title = title.trim();
+    (!title.is_empty()).then(|| TimelineEvent { day, title: title.to_string(), description: description.trim().to_string() })
+}
+
+// "-4 Mummy rot" lasts until removed; "-1 Exhaustion (long rest)" ends on the next long rest
+fn parse_max_hp_tag(value: &str) -> Option<MaxHpModifier> {
+    let (delta, source) = value.trim().split_once(char::is_whitespace)?;
+    let delta: i32 = delta.trim_start_matches('+').parse().ok()?;
+    let source = source.trim();
+    let (source, duration) = match source.strip_suffix("(long rest)") {
+        Some(source) => (source.trim(), EffectDuration::UntilLongRest),
+        None => (source, EffectDuration::UntilRemoved),
+    };
+    (delta != 0 && !source.is_empty()).then(|| MaxHpModifier { delta, source: source.to_string(), duration })
+}
+
+// "Fireball (level 3)", "Fireball 3" or "Light (cantrip)"; a bare name is taken as level 1
+fn parse_learn_tag(value: &str) -> Option<Spell> {
+    let value = value.trim();
+    let level = if value.to_lowercase().contains("cantrip") { 0 } else { first_number(value).unwrap_or(1).min(9) };
+    let name = value
+        .split(|c: char| c == '(' || c.is_ascii_digit())
+        .next()
+        .unwrap_or(value)
+        .trim()
+        .trim_end_matches("level")
+        .trim();
+    (!name.is_empty()).then(|| Spell { name: name.to_string(), level })
+}
+
+// Advances one level (HP by average or roll), then resolves any ability score improvements still owed
+fn level_up_action(state: &mut GameState, roller: &mut impl Roller) -> Result<(), Box<dyn Error>> {
+    let turn = state.turn();
+    let character = &mut state.character;
+    if character.pending_asi_levels().is_empty() {
+        if character.level >= 20 {
+            print_fancy_message("You are already level 20.", "yellow");
+            return Ok(());
+        }
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Advance {} to level {}?", character.name, character.level + 1))
+            .default(true)
+            .interact()?;
+        if !confirmed {
+            return Ok(());
+        }
+        
+        let class = choose_level_up_class(character)?;
+        let roll_hp = choose_hit_point_method(&class)?;
+        gain_level(character, &class, roll_hp, roller, turn);
+    }
+    
+    choose_ability_improvements(character, turn)?;
+    choose_expertise(character);
+    character.armor_class = character.compute_armor_class();
+    save_game(state)?;
+    Ok(())
+}
+
+// The class a new level goes into: one already taken, or a new one picked up as a multiclass
+fn choose_level_up_class(character: &mut Character) -> Result<String, Box<dyn Error>> {
+    let mut taken: Vec<String> = std::iter::once(&character.class).chain(character.multiclasses.iter()).cloned().collect();
+    let mut options: Vec<String> = taken.iter()
+        .map(|class| format!("{} (level {})", class, character.class_level(class)))
+        .collect();
+    options.push("Multiclass into a new class".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Level up in which class?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    if choice < taken.len() {
+        return Ok(taken.swap_remove(choice));
+    }
+    
+    let homebrew = homebrew_content();
+    let classes: Vec<&str> = BUILT_IN_CLASSES.iter().copied()
+        .chain(homebrew.classes.iter().map(|c| c.name.as_str()))
+        .filter(|class| !taken.iter().any(|t| t == class))
+        .collect();
+    let class = classes[Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("New class")
+        .default(0)
+        .items(&classes)
+        .interact()?].to_string();
+    
+    let skills: Vec<&str> = class_skill_list(&class)
+        .into_iter()
+        .filter(|skill| character.skills.get(*skill) == Some(&Proficiency::None))
+        .collect();
+    let skill = if multiclass_skill_count(&class) > 0 && !skills.is_empty() {
+        Some(skills[Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{} skill proficiency", class))
+            .default(0)
+            .items(&skills)
+            .interact()?])
+    } else {
+        None
+    };
+    let instrument = if class == "Bard" {
+        let instruments = ["Lute", "Flute", "Lyre", "Drum", "Horn", "Viol"];
+        Some(instruments[Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Musical instrument proficiency")
+            .default(0)
+            .items(&instruments)
+            .interact()?])
+    } else {
+        None
+    };
+    character.add_multiclass(&class, skill, instrument);
+    print_fancy_message(&format!("{} takes up the {} class", character.name, class), "green");
+    Ok(class)
+}
+
+// Whether level-up hit points are rolled on the class hit die (true) or taken as its average
+fn choose_hit_point_method(class: &str) -> Result<bool, Box<dyn Error>> {
+    let die = hit_die(class);
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Hit points")
+        .default(0)
+        .items(&[format!("Average ({})", die / 2 + 1), format!("Roll 1d{}", die)])
+        .interact()?;
+    Ok(choice == 1)
+}
+
+// Advances one level in `class`, adding its hit die (average or rolled) plus CON to maximum and current HP
+fn gain_level(character: &mut Character, class: &str, roll_hp: bool, roller: &mut impl Roller, turn: usize) {
+    let die = hit_die(class);
+    let con = character.effective_modifier(Ability::Constitution);
+    let rolled = if roll_hp {
+        let roll = roller.roll(die);
+        print_dice_roll(&format!("1d{}", die), &[roll]);
+        roll
+    } else {
+        die / 2 + 1
+    };
+    let gain = (rolled as i32 + con).max(1) as u32;
+    character.level += 1;
+    if class != character.class {
+        *character.multiclass_levels.entry(class.to_string()).or_insert(0) += 1;
+    }
+    character.max_hit_points += gain;
+    character.hit_points += gain;
+    character.log_hp_change(gain as i32, &format!("Reached level {}", character.level), turn);
+    print_fancy_message(&format!("{} is now level {} (+{} max HP)", character.name, character.level, gain), "green");
+}
+
+// Resolves every ability score improvement (or feat) still owed; "Decide later" leaves it pending
+fn choose_ability_improvements(character: &mut Character, turn: usize) -> Result<(), Box<dyn Error>> {
+    for asi_level in character.pending_asi_levels() {
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Level {} improvement", asi_level))
+            .default(0)
+            .items(&["+2 to one ability", "+1 to two abilities", "Take a feat", "Decide later"])
+            .interact()?;
+        let names: Vec<&str> = Ability::ALL.iter().map(|a| a.name()).collect();
+        match choice {
+            0 => {
+                let ability = Ability::ALL[Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Ability (+2)")
+                    .items(&names)
+                    .default(0)
+                    .interact()?];
+                character.raise_ability(ability, 2, turn);
+            },
+            1 => {
+                let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Two abilities (+1 each)")
+                    .items(&names)
+                    .interact()?;
+                if chosen.len() != 2 {
+                    print_fancy_message("Pick exactly two abilities; the improvement is still waiting.", "yellow");
+                    continue;
+                }
+                for index in chosen {
+                    character.raise_ability(Ability::ALL[index], 1, turn);
+                }
+            },
+            2 => {
+                let feat: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Feat (e.g. Alert, War Caster)")
+                    .interact_text()?;
+                character.feats.push(feat.trim().to_string());
+            },
+            _ => continue,
+        }
+        character.asi_taken.insert(asi_level);
+        print_fancy_message(&format!("Level {} improvement applied", asi_level), "green");
+    }
+    Ok(())
+}
+
+// Builds a new character up to a higher starting level, one level-up at a time
+fn start_at_level(character: &mut Character, level: u32, roller: &mut impl Roller) -> Result<(), Box<dyn Error>> {
+    let roll_hp = choose_hit_point_method(&character.class)?;
+    let class = character.class.clone();
+    while character.level < level {
+        gain_level(character, &class, roll_hp, roller, 0);
+    }
+    // A new character's sheet starts without an HP audit trail
+    character.hp_log.clear();
+    choose_ability_improvements(character, 0)?;
+    choose_expertise(character);
+    Ok(())
+}
+
+// Picks any Expertise the class is owed from the character's proficient skills
+fn choose_expertise(character: &mut Character) {
+    let owed = character.pending_expertise();
+    if owed == 0 {
+        return;
+    }
+    let candidates: Vec<&str> = SKILLS
+        .iter()
+        .map(|(skill, _)| *skill)
+        .filter(|skill| character.skill_proficiency(skill) == Proficiency::Proficient)
+        .collect();
+    let wanted = owed.min(candidates.len());
+    if wanted == 0 {
+        return;
+    }
+    let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Choose {} skill{} for Expertise (double proficiency)", wanted, if wanted == 1 { "" } else { "s" }))
+        .items(&candidates)
+        .interact()
+        .unwrap_or_default();
+    if chosen.len() != wanted {
+        print_fancy_message(&format!("Pick exactly {}; Expertise can be chosen at the next level up.", wanted), "yellow");
+        return;
+    }
+    for index in chosen {
+        character.skills.insert(candidates[index].to_string(), Proficiency::Expertise);
+    }
+}
+
+// Learns a spell by choice; wizards copying from a found spellbook or scroll pay in gold and time
+fn learn_spell_action(state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let Some(max_level) = max_spell_level(&state.character.class, state.character.level) else {
+        print_fancy_message(&format!("{}s don't cast spells.", state.character.class), "yellow");
+        return Ok(());
+    };
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Spell name")
+        .interact_text()?;
+    let level: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Spell level (0 for a cantrip, up to {})", max_level))
+        .default(1.min(max_level))
+        .interact_text()?;
+    let spell = Spell { name: name.trim().to_string(), level };
+    
+    let copying = state.character.class == "Wizard" && spell.level > 0 && Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Copy it from a spellbook or scroll you found?")
+        .default(true)
+        .interact()?;
+    let (gold, hours) = if copying {
+        (SPELL_COPY_GOLD_PER_LEVEL * spell.level, SPELL_COPY_HOURS_PER_LEVEL * spell.level)
+    } else {
+        (0, 0)
+    };
+    if state.character.gold < gold {
+        print_fancy_message(&format!("Copying {} takes {} gp of inks; you have {} gp.", spell, gold, state.character.gold), "red");
+        return Ok(());
+    }
+    
+    let name = spell.to_string();
+    if let Err(reason) = state.character.learn_spell(spell.clone()) {
+        print_fancy_message(&reason, "red");
+        return Ok(());
+    }
+    if state.character.spellbook.contains(&spell) && !state.character.known_spells.contains(&spell) {
+        print_fancy_message(&format!("{} is in your spellbook but not prepared; you're at your limit of {}", name, state.character.prepared_spell_limit()), "yellow");
+    }
+    if copying {
+        state.character.gold -= gold;
+        state.clock.advance(hours as u64 * 60);
+        announce_expired_effects(&state.character.tick_active_effects(hours * 60 * ROUNDS_PER_MINUTE));
+        print_fancy_message(&format!("You spend {} hours and {} gp copying {} into your spellbook", hours, gold, name), "green");
+    } else {
+        print_fancy_message(&format!("You learn {}", name), "green");
+    }
+    save_game(state)?;
+    Ok(())
+}
+
+// What the character carries after items move to or from the stash, flagged when it slows them
+fn report_load(character: &Character) {
+    let color = if character.encumbrance().is_some() { "yellow" } else { "blue" };
+    print_fancy_message(&format!("{} now carries {}", character.name, character.load_summary()), color);
+}
+
+// Moves inventory items into the party stash; AC, item bonuses and carried weight are recomputed without them
+fn move_to_stash_action(state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    if state.character.inventory.is_empty() {
+        print_fancy_message("You aren't carrying anything.", "yellow");
+        return Ok(());
+    }
+    let labels: Vec<String> = state.character.inventory.iter().map(|i| i.to_string()).collect();
+    let mut chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Items to move to the party stash")
+        .items(&labels)
+        .interact()?;
+    if chosen.is_empty() {
+        return Ok(());
+    }
+    let turn = state.turn();
+    let ac_before = state.character.armor_class;
+    chosen.sort_unstable_by(|a, b| b.cmp(a));
+    let mut moved: Vec<Item> = chosen.into_iter().map(|i| state.character.remove_item(i, turn)).collect();
+    moved.reverse();
+    let names: Vec<String> = moved.iter().map(|i| i.name.clone()).collect();
+    state.party_stash.extend(moved);
+    print_fancy_message(&format!("Moved to the stash: {}", names.join(", ")), "green");
+    if state.character.armor_class != ac_before {
+        print_fancy_message(&format!("AC is now {}", state.character.armor_class), "yellow");
+    }
+    report_load(&state.character);
+    save_game(state)?;
+    Ok(())
+}
+
+fn take_from_stash_action(state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    if state.party_stash.is_empty() {
+        print_fancy_message("The party stash is empty.", "yellow");
+        return Ok(());
+    }
+    let labels: Vec<String> = state.party_stash.iter().map(|i| i.to_string()).collect();
+    let mut chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Items to take from the party stash")
+        .items(&labels)
+        .interact()?;
+    if chosen.is_empty() {
+        return Ok(());
+    }
+    let turn = state.turn();
+    let ac_before = state.character.armor_class;
+    chosen.sort_unstable_by(|a, b| b.cmp(a));
+    let mut taken: Vec<Item> = chosen.into_iter().map(|i| state.party_stash.remove(i)).collect();
+    taken.reverse();
+    let names: Vec<String> = taken.iter().map(|i| i.name.clone()).collect();
+    for item in taken {
+        state.character.add_item(item, turn);
+    }
+    print_fancy_message(&format!("Taken from the stash: {}", names.join(", ")), "green");
+    if state.character.armor_class != ac_before {
+        print_fancy_message(&format!("AC is now {}", state.character.armor_class), "yellow");
+    }
+    report_load(&state.character);
+    save_game(state)?;
+    Ok(())
+}
+
+// Chooses which spellbook spells are prepared; cantrips are always ready
+fn prepare_spells_action(state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let character = &mut state.character;
+    if character.spellbook.is_empty() {
+        print_fancy_message("Your spellbook is empty; use Learn a spell to copy spells into it.", "yellow");
+        return Ok(());
+    }
+    let limit = character.prepared_spell_limit();
+    let labels: Vec<String> = character.spellbook.iter().map(|s| s.to_string()).collect();
+    let prepared: Vec<bool> = character.spellbook.iter().map(|s| character.known_spells.contains(s)).collect();
+    let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Prepare up to {} spells (INT modifier + level)", limit))
+        .items(&labels)
+        .defaults(&prepared)
+        .interact()?;
+    if chosen.len() > limit {
+        print_fancy_message(&format!("You can prepare {} spells; you chose {}. Nothing changed.", limit, chosen.len()), "red");
+        return Ok(());
+    }
+    let chosen: Vec<Spell> = chosen.into_iter().map(|i| character.spellbook[i].clone()).collect();
+    character.known_spells.retain(|s| s.level == 0);
+    character.known_spells.extend(chosen);
+    print_fancy_message(&format!("{} of {} spells prepared", character.prepared_spell_count(), limit), "green");
+    save_game(state)?;
+    Ok(())
+}
+
+// Wizard rituals from the Player's Handbook; SRD names are listed alongside the named versions
+const RITUAL_SPELLS: [&str; 20] = [
+    "Alarm", "Comprehend Languages", "Detect Magic", "Find Familiar", "Identify", "Illusory Script",
+    "Tenser's Floating Disk", "Floating Disk", "Unseen Servant", "Gentle Repose", "Magic Mouth", "Skywrite",
+    "Feign Death", "Leomund's Tiny Hut", "Tiny Hut", "Phantom Steed", "Water Breathing",
+    "Rary's Telepathic Bond", "Telepathic Bond", "Contact Other Plane",
+];
+const RITUAL_EXTRA_MINUTES: u32 = 10;
+
+fn is_ritual(name: &str) -> bool {
+    RITUAL_SPELLS.iter().any(|ritual| ritual.eq_ignore_ascii_case(name.trim()))
+}
+
+// Casts a ritual straight from the spellbook, prepared or not: ten extra minutes and no spell slot
+async fn ritual_cast_action(dm: &impl Chat, state: &mut GameState) -> Result<Option<String>, Box<dyn Error>> {
+    let rituals: Vec<Spell> = state.character.spellbook.iter().filter(|s| is_ritual(&s.name)).cloned().collect();
+    if rituals.is_empty() {
+        print_fancy_message("Your spellbook has no ritual spells.", "yellow");
+        return Ok(None);
+    }
+    let labels: Vec<String> = rituals.iter().map(|s| s.to_string()).collect();
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Ritual to cast (+{} minutes, no slot)", RITUAL_EXTRA_MINUTES))
+        .default(0)
+        .items(&labels)
+        .interact()?;
+    let spell = &rituals[index];
+    state.clock.advance(RITUAL_EXTRA_MINUTES as u64);
+    announce_expired_effects(&state.character.tick_active_effects(RITUAL_EXTRA_MINUTES * ROUNDS_PER_MINUTE));
+    print_fancy_message(&format!("You spend {} minutes casting {} as a ritual", RITUAL_EXTRA_MINUTES, spell.name), "green");
+    let action = format!(
+        "I cast {} as a ritual from my spellbook, taking {} extra minutes and using no spell slot.",
+        spell.name, RITUAL_EXTRA_MINUTES
+    );
+    Ok(Some(process_player_action(dm, &action, None, state).await?))
+}
+
+// Spell descriptions from earlier lookups, keyed by lowercase name, so each spell costs one request ever
+const SPELL_CACHE_FILE: &str = "spell_cache.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SpellReference {
+    name: String,
+    // 0 for a cantrip
+    level: u32,
+    casting_time: String,
+    range: String,
+    components: String,
+    duration: String,
+    description: String,
+}
+
+fn spell_cache_path() -> PathBuf {
+    save_dir().join(SPELL_CACHE_FILE)
+}
+
+fn load_spell_cache() -> HashMap<String, SpellReference> {
+    fs::read_to_string(spell_cache_path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_spell_cache(cache: &HashMap<String, SpellReference>) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(save_dir())?;
+    fs::write(spell_cache_path(), serde_json::to_string_pretty(cache)?)?;
+    Ok(())
+}
+
+fn parse_spell_reference(response: &str) -> Option<SpellReference> {
+    let json = &response[response.find('{')?..=response.rfind('}')?];
+    serde_json::from_str(json).ok()
+}
+
+fn print_spell_reference(spell: &SpellReference) {
+    let t = theme();
+    let level = if spell.level == 0 { "Cantrip".to_string() } else { format!("Level {}", spell.level) };
+    println!("\n{} {}", spell.name.to_uppercase().color(t.heading).bold(), format!("({})", level).color(t.info));
+    println!("{}", "=".repeat(60).color(t.border));
+    for (label, value) in [
+        ("Casting time", &spell.casting_time),
+        ("Range", &spell.range),
+        ("Components", &spell.components),
+        ("Duration", &spell.duration),
+    ] {
+        println!("{:<14}{}", label.color(t.label), value.color(t.value));
+    }
+    println!("{}", "-".repeat(60).color(t.border));
+    for line in wrap_text(&spell.description, 60) {
+        println!("{}", line.color(t.value));
+    }
+    println!("{}", "=".repeat(60).color(t.border));
+}
+
+// Looks a spell up in the cache, asking the DM (outside the story history) only on a miss
+async fn look_up_spell(dm: &impl Chat, name: &str) -> Result<Option<SpellReference>, Box<dyn Error>> {
+    let t = theme();
+    let key = name.trim().to_lowercase();
+    let mut cache = load_spell_cache();
+    if let Some(spell) = cache.get(&key) {
+        println!("{}", "(from the spell cache)".dimmed());
+        return Ok(Some(spell.clone()));
+    }
+    
+    let prompt = format!(
+        "Give the D&D 5th Edition rules reference for the spell \"{}\".
+        Reply with only a JSON object with these fields:
+        {{\"name\": \"...\", \"level\": 0 for a cantrip or the spell level,
+        \"casting_time\": \"...\", \"range\": \"...\", \"components\": \"V, S, M (material)\",
+        \"duration\": \"...\", \"description\": \"what the spell does, including damage, saves and higher-level effects\"}}",
+        name.trim()
+    );
+    let response = dm_chat(
+        dm,
+        &prompt,
+        Vec::new(),
+        "Failed to look up the spell",
+        "The Dungeon Master is consulting the Player's Handbook...",
+    )
+    .await?;
+    
+    let Some(spell) = parse_spell_reference(&response) else {
+        print_fancy_message("The spell came back in an unexpected format; here it is as written:", "yellow");
+        println!("{}", response.color(t.value));
+        return Ok(None);
+    };
+    cache.insert(key, spell.clone());
+    cache.insert(spell.name.to_lowercase(), spell.clone());
+    save_spell_cache(&cache)?;
+    Ok(Some(spell))
+}
+
+async fn look_up_spell_action(dm: &impl Chat, state: &GameState) -> Result<(), Box<dyn Error>> {
+    let mut options: Vec<String> = state.character.known_spells.iter().map(|s| s.name.clone()).collect();
+    let known = options.len();
+    options.push("Another spell...".to_string());
+    options.push("Clear the spell cache".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Look up which spell?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    
+    let name = if choice < known {
+        options[choice].clone()
+    } else if choice == known {
+        Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Spell name")
+            .interact_text()?
+    } else {
+        let cached = load_spell_cache().values().map(|s| s.name.to_lowercase()).collect::<HashSet<_>>().len();
+        if cached > 0 {
+            fs::remove_file(spell_cache_path())?;
+        }
+        print_fancy_message(&format!("Cleared {} cached spell{}", cached, if cached == 1 { "" } else { "s" }), "green");
+        return Ok(());
+    };
+    
+    if let Some(spell) = look_up_spell(dm, &name).await? {
+        print_spell_reference(&spell);
+    }
+    Ok(())
+}
+
+// Helper function to extract text from OneOrMany<AssistantContent>
+fn extract_text_from_message(content: &OneOrMany<AssistantContent>) -> String {
+    // Try to extract the text from the first element using the public API
+    match content.first() {
+        AssistantContent::Text(text_content) => text_content.text.clone(),
+        _ => "No readable text content available".to_string(),
+    }
+}
+
+// Dice rolling utilities
+
+// Where die results come from; rules code takes a Roller so it can be driven by fixed rolls
+trait Roller {
+    fn roll(&mut self, sides: u32) -> u32;
+}
+
+// Real randomness, used everywhere in play
+struct ThreadRoller;
+
+impl Roller for ThreadRoller {
+    fn roll(&mut self, sides: u32) -> u32 {
+        rand::thread_rng().gen_range(1..=sides)
+    }
+}
+
+// Returns a predetermined sequence of results, for deterministic checks of the dice-driven rules
+#[cfg(test)]
+struct ScriptedRoller {
+    rolls: VecDeque<u32>,
+}
+
+#[cfg(test)]
+impl ScriptedRoller {
+    fn new(rolls: impl IntoIterator<Item = u32>) -> Self {
+        ScriptedRoller { rolls: rolls.into_iter().collect() }
+    }
+}
+
+#[cfg(test)]
+impl Roller for ScriptedRoller {
+    fn roll(&mut self, sides: u32) -> u32 {
+        let roll = self.rolls.pop_front().expect("ScriptedRoller ran out of rolls");
+        assert!((1..=sides).contains(&roll), "scripted roll {} doesn't fit a d{}", roll, sides);
+        roll
+    }
+}
+
+fn roll_dice(roller: &mut impl Roller, num_dice: u32, sides: u32) -> Vec<u32> {
+    (0..num_dice).map(|_| roller.roll(sides)).collect()
+}
+
+// How many times a rolled set of ability scores may be thrown out during character creation
+const MAX_ABILITY_REROLLS: u32 = 2;
+
+// Full arrays rolled side by side when the player wants to pick the best of several
+const ABILITY_ARRAYS_TO_COMPARE: usize = 3;
+
+// Rolls 4d6 and drops the lowest die, returning every die rolled and the kept total
+fn roll_ability_score(roller: &mut impl Roller) -> (Vec<u32>, u32) {
+    let dice = roll_dice(roller, 4, 6);
+    let lowest = dice.iter().min().copied().unwrap_or(0);
+    let total = dice.iter().sum::<u32>() - lowest;
+    
+    (dice, total)
+}
+
+// Rolls several full six-score arrays, shows them with totals and modifiers, and returns the one the player picks
+fn choose_rolled_array(roller: &mut impl Roller) -> Vec<u32> {
+    let t = theme();
+    println!("\n{}", format!("Rolling {} arrays (4d6 drop lowest)...", ABILITY_ARRAYS_TO_COMPARE).color(t.info));
+    let arrays: Vec<Vec<u32>> = (0..ABILITY_ARRAYS_TO_COMPARE)
+        .map(|_| (0..6).map(|_| roll_ability_score(roller).1).collect())
+        .collect();
+    
+    let summaries: Vec<String> = arrays
+        .iter()
+        .enumerate()
+        .map(|(i, array)| {
+            let scores: Vec<String> = array
+                .iter()
+                .map(|s| format!("{} ({})", s, format_modifier(ability_modifier(*s))))
+                .collect();
+            let total: u32 = array.iter().sum();
+            let modifiers: i32 = array.iter().map(|s| ability_modifier(*s)).sum();
+            format!("Array {}: {} | total {}, modifiers {}", i + 1, scores.join(", "), total, format_modifier(modifiers))
+        })
+        .collect();
+    for summary in &summaries {
+        println!("{}", summary);
+    }
+    
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Which array will you use?")
+        .default(0)
+        .items(&summaries)
+        .interact()
+        .unwrap_or(0);
+    arrays[choice].clone()
+}
+
+// Formats a 4d6 roll with the dropped (lowest) die struck through
+fn format_ability_roll(dice: &[u32]) -> String {
+    let t = theme();
+    let dropped = dice
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, d)| **d)
+        .map(|(i, _)| i);
+    
+    dice.iter()
+        .enumerate()
+        .map(|(i, d)| {
+            if Some(i) == dropped {
+                d.to_string().dimmed().strikethrough().to_string()
+            } else {
+                d.to_string().color(t.value).to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Dice notation such as "2d6+3", "d20" or a flat "1"
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+struct DiceExpr {
+    count: u32,
+    sides: u32,
+    modifier: i32,
+}
+
+impl DiceExpr {
+    fn parse(notation: &str) -> Option<Self> {
+        let notation: String = notation.chars().filter(|c| !c.is_whitespace()).collect();
+        let notation = notation.to_lowercase();
+        
+        // Split off a trailing +N / -N modifier
+        let (dice_part, modifier) = match notation.rfind(['+', '-']) {
+            Some(pos) if pos > 0 => (&notation[..pos], notation[pos..].parse::<i32>().ok()?),
+            _ => (notation.as_str(), 0),
+        };
+        
+        match dice_part.split_once('d') {
+            Some((count, sides)) => {
+                let count = if count.is_empty() { 1 } else { count.parse().ok()? };
+                let sides = sides.parse().ok()?;
+                if count == 0 || count > 100 || sides == 0 {
+                    return None;
+                }
+                Some(Self { count, sides, modifier })
+            }
+            None => Some(Self { count: 0, sides: 0, modifier: dice_part.parse::<i32>().ok()? + modifier }),
+        }
+    }
+    
+    fn roll(&self, roller: &mut impl Roller) -> (Vec<u32>, i32) {
+        let dice = roll_dice(roller, self.count, self.sides);
+        let total = dice.iter().sum::<u32>() as i32 + self.modifier;
+        (dice, total)
+    }
+    
+    fn average(&self) -> f64 {
+        self.count as f64 * (self.sides as f64 + 1.0) / 2.0 + self.modifier as f64
+    }
+    
+    // Critical hits roll twice the dice; the modifier is only added once
+    fn critical(&self) -> Self {
+        Self { count: self.count * 2, ..*self }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+enum RollMode {
+    #[default]
+    Normal,
+    Advantage,
+    Disadvantage,
+}
+
+impl RollMode {
+    const ALL: [RollMode; 3] = [RollMode::Normal, RollMode::Advantage, RollMode::Disadvantage];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            RollMode::Normal => "Normal",
+            RollMode::Advantage => "Advantage",
+            RollMode::Disadvantage => "Disadvantage",
+        }
+    }
+    
+    // Advantage and disadvantage cancel out, however many sources each has
+    fn combine(self, other: RollMode) -> RollMode {
+        match (self, other) {
+            (RollMode::Normal, mode) | (mode, RollMode::Normal) => mode,
+            (a, b) if a == b => a,
+            _ => RollMode::Normal,
+        }
+    }
+}
+
+// Folds in disadvantage from the environment, saying where it came from
+fn with_environment(mode: RollMode, penalty: Option<String>, what: &str) -> RollMode {
+    let Some(reason) = penalty else {
+        return mode;
+    };
+    print_fancy_message(&format!("{}: disadvantage on {}", reason, what), "yellow");
+    mode.combine(RollMode::Disadvantage)
+}
+
+// Rolls a d20 (two with advantage or disadvantage), returning every die and the one that counts
+fn roll_d20(roller: &mut impl Roller, mode: RollMode) -> (Vec<u32>, u32) {
+    let dice = roll_dice(roller, if mode == RollMode::Normal { 1 } else { 2 }, 20);
+    let kept = kept_d20(&dice, mode);
+    (dice, kept)
+}
+
+// The die that counts out of a d20 roll
+fn kept_d20(dice: &[u32], mode: RollMode) -> u32 {
+    match mode {
+        RollMode::Normal => dice.first().copied().unwrap_or(0),
+        RollMode::Advantage => dice.iter().copied().max().unwrap_or(0),
+        RollMode::Disadvantage => dice.iter().copied().min().unwrap_or(0),
+    }
+}
+
+// Both dice matter to the story: "rolled 4 and 17, kept 17 with advantage"
+fn describe_d20(dice: &[u32], kept: u32, mode: RollMode) -> String {
+    match mode {
+        RollMode::Normal => kept.to_string(),
+        _ => format!(
+            "rolled {}, kept {} with {}",
+            dice.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(" and "),
+            kept,
+            mode.name().to_lowercase()
+        ),
+    }
+}
+
+// A bonus die spent on a check or save, such as Guidance's 1d4
+struct BonusRoll {
+    source: String,
+    expr: DiceExpr,
+    total: i32,
+}
+
+// A player's d20 roll as made: every die thrown, the one kept, and any bonus die spent on it
+struct D20Roll {
+    dice: Vec<u32>,
+    kept: u32,
+    mode: RollMode,
+    bonus: Option<BonusRoll>,
+}
+
+impl D20Roll {
+    fn bonus(&self) -> i32 {
+        self.bonus.as_ref().map_or(0, |b| b.total)
+    }
+    
+    fn total(&self, modifier: i32) -> i32 {
+        self.kept as i32 + modifier + self.bonus()
+    }
+    
+    // For the DM, e.g. "rolled 4 and 17, kept 17 with advantage, +3 from Guidance (1d4)"
+    fn describe(&self) -> String {
+        let roll = describe_d20(&self.dice, self.kept, self.mode);
+        match &self.bonus {
+            Some(bonus) => format!("{}, {} from {} ({})", roll, format_modifier(bonus.total), bonus.source, bonus.expr),
+            None => roll,
+        }
+    }
+}
+
+// One named piece of a roll's modifier, e.g. ("for your DEX", 2)
+type ModifierPart = (String, i32);
+
+// Plain-language account of a d20 total, for players learning the rules
+fn explain_roll(roll: &D20Roll, what: &str, parts: &[ModifierPart], total: i32) -> String {
+    let die = match roll.mode {
+        RollMode::Normal => format!("You rolled {} on the d20", roll.kept),
+        mode => format!(
+            "You rolled {} on the d20s and kept {} for {}",
+            roll.dice.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(" and "),
+            roll.kept,
+            mode.name().to_lowercase()
+        ),
+    };
+    let mut added: Vec<String> = parts.iter().map(|(label, value)| format!("{} {}", format_modifier(*value), label)).collect();
+    if let Some(bonus) = &roll.bonus {
+        added.push(format!("{} from {} ({})", format_modifier(bonus.total), bonus.source, bonus.expr));
+    }
+    let added = match added.split_last() {
+        None => "with nothing added".to_string(),
+        Some((last, [])) => format!("added {}", last),
+        Some((last, rest)) => format!("added {} and {}", rest.join(", "), last),
+    };
+    format!("{} for your {}, {} for a total of {}.", die, what, added, total)
+}
+
+// Only with "Explain my rolls" turned on in Settings
+fn print_roll_explanation(roll: &D20Roll, what: &str, parts: &[ModifierPart], total: i32) {
+    if config().explain_rolls {
+        println!("{}", explain_roll(roll, what, parts, total).color(theme().info).italic());
+    }
+}
+
+// Offers any pending bonus die on a check or save; the one applied is rolled and used up
+fn offer_bonus_die(state: &mut GameState, roller: &mut impl Roller, what: &str) -> Result<Option<BonusRoll>, Box<dyn Error>> {
+    let t = theme();
+    let pending = &state.character.pending_bonus_dice;
+    if pending.is_empty() {
+        return Ok(None);
+    }
+    let labels: Vec<String> = pending.iter().map(|(source, expr)| format!("Apply {} (+{})", source, expr)).collect();
+    let index = if labels.len() == 1 {
+        let apply = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{} to this {}?", labels[0], what))
+            .default(true)
+            .interact()?;
+        if !apply {
+            return Ok(None);
+        }
+        0
+    } else {
+        let mut options = labels;
+        options.push("Save them for later".to_string());
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Bonus die for this {}?", what))
+            .default(0)
+            .items(&options)
+            .interact()?;
+        if choice == options.len() - 1 {
+            return Ok(None);
+        }
+        choice
+    };
+    
+    let (source, expr) = state.character.pending_bonus_dice.remove(index);
+    let (dice, total) = expr.roll(roller);
+    state.record_roll(&expr.to_string(), &dice, total, &format!("{} on a {}", source, what));
+    print_dice_roll(&expr.to_string(), &dice);
+    println!("{} adds {}", source, format_modifier(total).color(t.good).bold());
+    save_game(state)?;
+    Ok(Some(BonusRoll { source, expr, total }))
+}
+
+// "Guidance 1d4" or "Bardic Inspiration from Lyra 1d8": the dice come last
+fn parse_bonus_tag(value: &str) -> Option<(String, DiceExpr)> {
+    let (source, dice) = value.trim().rsplit_once(char::is_whitespace)?;
+    let expr = DiceExpr::parse(dice)?;
+    let source = source.trim();
+    (!source.is_empty()).then(|| (source.to_string(), expr))
+}
+
+// Notation for the roll history, e.g. "1d20+5 (advantage)"
+fn d20_expr(modifier: i32, mode: RollMode) -> String {
+    match mode {
+        RollMode::Normal => format!("1d20{}", format_modifier(modifier)),
+        _ => format!("1d20{} ({})", format_modifier(modifier), mode.name().to_lowercase()),
+    }
+}
+
+fn print_d20_roll(dice: &[u32], kept: u32, mode: RollMode) {
+    let t = theme();
+    if mode == RollMode::Normal {
+        print_dice_roll("d20", dice);
+    } else {
+        println!("{}: {} → {}", mode.name(), dice.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", "),
+                 kept.to_string().color(t.value).bold());
+    }
+}
+
+fn choose_roll_mode() -> Result<RollMode, Box<dyn Error>> {
+    let modes: Vec<&str> = RollMode::ALL.iter().map(|m| m.name()).collect();
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Roll with")
+        .default(0)
+        .items(&modes)
+        .interact()?;
+    Ok(RollMode::ALL[index])
+}
+
+// Chance that d20 + modifier meets or beats the DC
+fn save_success_chance(dc: u32, modifier: i32) -> f64 {
+    let needed = dc as i32 - modifier;
+    ((21 - needed).clamp(0, 20)) as f64 / 20.0
+}
+
+impl fmt::Display for DiceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "{}", self.modifier);
+        }
+        write!(f, "{}d{}", self.count, self.sides)?;
+        if self.modifier != 0 {
+            write!(f, "{}", format_modifier(self.modifier))?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for DiceExpr {
+    type Error = String;
+    
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| format!("invalid dice notation: {}", value))
+    }
+}
+
+impl From<DiceExpr> for String {
+    fn from(expr: DiceExpr) -> Self {
+        expr.to_string()
+    }
+}
+
+fn print_dice_roll(dice_type: &str, results: &[u32]) {
+    let t = theme();
+    let sum: u32 = results.iter().sum();
+    let dice_results = results
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    
+    println!("{} {} [{}] = {}", 
+             "Rolled".color(t.border),
+             dice_type.color(t.heading),
+             dice_results.color(t.value),
+             sum.to_string().color(t.label).bold());
+}
+
+// Function to let the DM narrate the result of a general dice roll
+async fn process_dice_roll(
+    dm: &impl Chat,
+    dice_type: &str,
+    results: &[u32],
+    modifier: i32,
+    purpose: &str,
+    state: &mut GameState,
+) -> Result<String, Box<dyn Error>> {
+    // Skip DM narration if no purpose is provided
+    if purpose.is_empty() {
+        return Ok("".to_string());
+    }
+    
+    let sum = results.iter().sum::<u32>() as i32 + modifier;
+    let dice_results = results
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    
+    let roll_prompt = format!(
+        "The player ({} the {} {}) rolls {} for the following purpose: \"{}\"
+        Dice roll result: {} [{}]{} = {}
+        
+        As the DM, narrate the outcome of this roll in the context of the current situation.
+        Be descriptive and evocative, explaining how the dice roll affects the player's attempt.
+        Continue the scene after describing the result.",
+        state.character.name,
+        state.character.race,
+        state.character.class,
+        dice_type,
+        purpose,
+        dice_type,
+        dice_results,
+        if modifier != 0 { format!(" {}", format_modifier(modifier)) } else { String::new() },
+        sum
+    );
+    
+    let response = dm_chat(
+        dm,
+        &roll_prompt,
+        state.history.clone(),
+        "Failed to process dice roll",
+        "The Dungeon Master is interpreting your roll...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&roll_prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+// AI DM interactions
+const DM_PREAMBLE: &str = "You are an expert Dungeon Master for a Dungeons & Dragons 5th Edition game. 
+            
+            Your role is to create an immersive, engaging, and dynamic D&D experience in a text-based format. You will:
+            
+            1. Create rich, evocative descriptions of locations, NPCs, monsters, and scenarios
+            2. Respond to player actions by narrating outcomes and advancing the story
+            3. Incorporate D&D rules when appropriate, but prioritize storytelling over strict rule adherence
+            4. Craft a compelling narrative that responds to player choices
+            5. Present interesting challenges, puzzles, and combat encounters
+            6. Maintain consistent world details and NPC personalities
+            
+            Important guidelines:
+            - Use vivid, sensory language to create immersion
+            - Keep descriptions concise but evocative
+            - Present clear options for the player but allow creative actions
+            - Balance combat, exploration, and social interaction
+            - Adapt the story based on player choices
+            - Include elements of mystery and discovery
+            - Create memorable NPCs with distinct personalities
+            - Wrap read-aloud descriptions of new locations and NPCs in >>> and <<< (e.g. >>>The cavern opens into...<<<), keeping rules talk and options outside them
+            - Put each line an NPC speaks on its own line as [NPC-SAYS: Name | \"What they say\"], using the same name for the same NPC every time
+            
+            Always respond in character as the Dungeon Master and make the adventure feel like a real D&D session. Present options in an open-ended way that encourages player agency and creativity.";
+const DM_TEMPERATURE: f64 = 0.7;
+
+// Model providers the DM can run on; each reads its API key from the environment
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+enum Provider {
+    #[default]
+    Gemini,
+    OpenAi,
+    Anthropic,
+}
+
+impl Provider {
+    const ALL: [Provider; 3] = [Provider::Gemini, Provider::OpenAi, Provider::Anthropic];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Gemini => "Gemini",
+            Provider::OpenAi => "OpenAI",
+            Provider::Anthropic => "Anthropic",
+        }
+    }
+    
+    fn key_var(&self) -> &'static str {
+        match self {
+            Provider::Gemini => "GEMINI_API_KEY",
+            Provider::OpenAi => "OPENAI_API_KEY",
+            Provider::Anthropic => "ANTHROPIC_API_KEY",
+        }
+    }
+    
+    fn default_model(&self) -> &'static str {
+        match self {
+            Provider::Gemini => "gemini-2.5-flash-preview-04-17",
+            Provider::OpenAi => "gpt-4o",
+            Provider::Anthropic => "claude-3-5-sonnet-latest",
+        }
+    }
+}
+
+// Object-safe view of an agent: rig's Chat returns an opaque future, so agents of different providers can't share a Box<dyn Chat>
+trait DmAgent: Send + Sync {
+    fn chat_boxed(&self, prompt: Message, history: Vec<Message>) -> Pin<Box<dyn Future<Output = Result<String, PromptError>> + Send + '_>>;
+}
+
+impl<M: CompletionModel> DmAgent for Agent<M> {
+    fn chat_boxed(&self, prompt: Message, history: Vec<Message>) -> Pin<Box<dyn Future<Output = Result<String, PromptError>> + Send + '_>> {
+        Box::pin(self.chat(prompt, history).into_future())
+    }
+}
+
+// Builds an agent with the shared preamble; fails if the provider's API key isn't set
+fn build_agent(provider: Provider, model: &str) -> Result<Arc<dyn DmAgent>, Box<dyn Error>> {
+    let key = std::env::var(provider.key_var())
+        .map_err(|_| io::Error::other(format!("{} is not set (add it to your .env file)", provider.key_var())))?;
+    Ok(match provider {
+        Provider::Gemini => Arc::new(
+            gemini::Client::new(&key).agent(model).preamble(DM_PREAMBLE).temperature(DM_TEMPERATURE).build(),
+        ),
+        Provider::OpenAi => Arc::new(
+            openai::Client::new(&key).agent(model).preamble(DM_PREAMBLE).temperature(DM_TEMPERATURE).build(),
+        ),
+        Provider::Anthropic => Arc::new(
+            anthropic::ClientBuilder::new(&key)
+                .build()
+                .agent(model)
+                .preamble(DM_PREAMBLE)
+                .temperature(DM_TEMPERATURE)
+                .max_tokens(4096)
+                .build(),
+        ),
+    })
+}
+
+// The agent sits behind a lock so the model can be switched mid-campaign. History lives in GameState
+// and every agent gets the same preamble, so the conversation carries straight over.
+struct DungeonMaster {
+    agent: RwLock<Arc<dyn DmAgent>>,
+    label: RwLock<String>,
+}
+
+impl DungeonMaster {
+    fn new(provider: Provider, model: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(DungeonMaster {
+            agent: RwLock::new(build_agent(provider, model)?),
+            label: RwLock::new(format!("{} {}", provider.name(), model)),
+        })
+    }
+    
+    fn label(&self) -> String {
+        self.label.read().unwrap().clone()
+    }
+    
+    fn swap(&self, agent: Arc<dyn DmAgent>, label: String) {
+        *self.agent.write().unwrap() = agent;
+        *self.label.write().unwrap() = label;
+    }
+}
+
+impl Chat for DungeonMaster {
+    fn chat(
+        &self,
+        prompt: impl Into<Message> + Send,
+        chat_history: Vec<Message>,
+    ) -> impl IntoFuture<Output = Result<String, PromptError>, IntoFuture: Send> {
+        // Clone the agent out so the lock isn't held while the request is in flight
+        let agent = self.agent.read().unwrap().clone();
+        let prompt = prompt.into();
+        async move { agent.chat_boxed(prompt, chat_history).await }
+    }
+}
+
+// Switches the DM to another provider or model, after a test message proves the credentials work
+async fn switch_dm_model(dm: &DungeonMaster) -> Result<(), Box<dyn Error>> {
+    print_fancy_message(&format!("The DM is running on {}", dm.label()), "cyan");
+    let options: Vec<String> = Provider::ALL
+        .iter()
+        .map(|p| {
+            if std::env::var(p.key_var()).is_ok() {
+                p.name().to_string()
+            } else {
+                format!("{} ({} not set)", p.name(), p.key_var())
+            }
+        })
+        .collect();
+    let current = Provider::ALL.iter().position(|p| *p == config().provider).unwrap_or(0);
+    let index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Provider")
+        .default(current)
+        .items(&options)
+        .interact()?;
+    let provider = Provider::ALL[index];
+    let model: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Model")
+        .default(if provider == config().provider { config().model_name() } else { provider.default_model().to_string() })
+        .interact_text()?;
+    let model = model.trim().to_string();
+    
+    let agent = match build_agent(provider, &model) {
+        Ok(agent) => agent,
+        Err(e) => {
+            print_fancy_message(&format!("Can't switch: {}", e), "red");
+            return Ok(());
+        }
+    };
+    let spinner = show_spinner(&format!("Checking {} {}...", provider.name(), model));
+    let check = agent.chat_boxed(Message::user("Reply with the single word: ready"), vec![]).await;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+    if let Err(e) = check {
+        print_fancy_message(&format!("{} {} didn't respond ({}). Keeping {}.", provider.name(), model, e, dm.label()), "red");
+        return Ok(());
+    }
+    
+    dm.swap(agent, format!("{} {}", provider.name(), model));
+    let mut settings = config();
+    settings.provider = provider;
+    settings.model = Some(model);
+    save_config(&settings)?;
+    set_config(settings);
+    print_fancy_message(&format!("The DM is now {}. The story picks up where it left off.", dm.label()), "green");
+    Ok(())
+}
+
+async fn dm_chat<C>(
+    dm: &C,
+    prompt: &str,
+    history: Vec<Message>,
+    error_message: &str,
+    spinner_message: &str,
+) -> Result<String, Box<dyn Error>>
+where
+    C: Chat,
+{
+    let debug = config().debug;
+    if debug {
+        println!("{}", format!("[debug] prompt ({} messages of history):\n{}", history.len(), prompt).dimmed());
+    }
+    
+    // The spinner runs for exactly as long as the request is in flight
+    let spinner = show_spinner(spinner_message);
+    let result = dm.chat(prompt, history).await;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+    
+    match result {
+        Ok(response) => {
+            if debug {
+                println!("{}", format!("[debug] raw response:\n{}", response).dimmed());
+            }
+            Ok(response)
+        },
+        Err(e) => {
+            print_fancy_message("The Dungeon Master cannot respond...", "red");
+            println!("Error: {}", e);
+            Err(Box::new(io::Error::other(error_message)))
+        }
+    }
+}
+
+async fn start_new_campaign(
+    dm: &impl Chat,
+    character: Character,
+    setting: CampaignSetting,
+) -> Result<GameState, Box<dyn Error>> {
+    let mut state = GameState {
+        character,
+        setting,
+        date_started: Local::now().to_rfc3339(),
+        last_saved: Local::now().to_rfc3339(),
+        ..Default::default()
+    };
+    
+    // Create campaign prompt
+    let campaign_prompt = format!(
+        "You are the Dungeon Master for a Dungeons & Dragons 5e adventure. 
+        Create an exciting campaign hook and starting location for a {} {} named {}. 
+        The character is level {} with the following stats: 
+        STR {}, DEX {}, CON {}, INT {}, WIS {}, CHA {}.
+        Background: {}.{}
+        
+        Setting ({}): {}
+        
+        Provide a brief introduction to the campaign setting, including:
+        1. The name of the campaign/adventure
+        2. The starting location (town/city/village name)
+        3. The initial quest or hook to draw the player in
+        4. A brief description of the area and its people
+        
+        Format your response with these section headers but focus on immersive, evocative descriptions rather than mechancial details. Make it engaging and atmospheric!",
+        state.character.race,
+        state.character.class,
+        state.character.name,
+        state.character.level,
+        state.character.strength,
+        state.character.dexterity,
+        state.character.constitution,
+        state.character.intelligence,
+        state.character.wisdom,
+        state.character.charisma,
+        state.character.background,
+        if state.character.backstory.is_empty() {
+            String::new()
+        } else {
+            format!("\n        Backstory: {}\n        Let the opening reflect this history.", state.character.backstory)
+        },
+        state.setting.name(),
+        state.setting.guidance()
+    );
+    
+    let campaign_response = dm_chat(
+        dm,
+        &campaign_prompt,
+        vec![],
+        "Failed to communicate with the Dungeon Master",
+        "The Dungeon Master is creating your adventure...",
+    )
+    .await?;
+    
+    // Parse the response for campaign details
+    for line in campaign_response.lines() {
+        if (line.to_lowercase().contains("campaign") || line.to_lowercase().contains("adventure"))
+            && let Some(name) = line.split(":").nth(1)
+        {
+            state.campaign = name.trim().to_string();
+        }
+        if line.to_lowercase().contains("location")
+            && let Some(location) = line.split(":").nth(1)
+        {
+            state.current_location = location.trim().to_string();
+        }
+        if (line.to_lowercase().contains("quest") || line.to_lowercase().contains("hook"))
+            && let Some(quest) = line.split(":").nth(1)
+        {
+            state.current_quest = quest.trim().to_string();
+        }
+    }
+    
+    // If we couldn't parse the details, use default values
+    if state.campaign.is_empty() {
+        state.campaign = "Mystical Adventure".to_string();
+    }
+    if state.current_location.is_empty() {
+        state.current_location = "Starting Town".to_string();
+    }
+    state.visited_locations.push(state.current_location.clone());
+    if state.current_quest.is_empty() {
+        state.current_quest = "Find adventure".to_string();
+    }
+    
+    // Add to history
+    state.history.push(Message::user(&campaign_prompt));
+    state.history.push(Message::assistant(&campaign_response));
+    
+    personalize_quest(dm, &mut state).await?;
+    
+    // Add a scene-setting message
+    let scene_setting = format!(
+        "Now, describe the opening scene. The player's character has just arrived at the starting location. Provide rich sensory details and introduce an NPC or situation that connects to the quest hook. End with a question or prompt for the player to respond to. {}",
+        state.narration_length.instruction()
+    );
+    
+    let scene_response = dm_chat(
+        dm,
+        &scene_setting,
+        state.history.clone(),
+        "Failed to set the scene",
+        "The Dungeon Master is setting the scene...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&scene_setting));
+    state.history.push(Message::assistant(&scene_response));
+    
+    save_game(&state)?;
+    
+    Ok(state)
+}
+
+// Optional creation step: the DM drafts a backstory the player can accept, reroll, or edit, or the player writes their own
+async fn write_backstory(dm: &impl Chat, character: &mut Character) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    let start = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Backstory")
+        .default(0)
+        .items(&["Have the DM draft one", "Write my own", "Skip"])
+        .interact()?;
+    match start {
+        0 => {},
+        1 => {
+            let backstory: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Your backstory")
+                .allow_empty(true)
+                .interact_text()?;
+            character.backstory = backstory.trim().to_string();
+            return Ok(());
+        },
+        _ => return Ok(()),
+    }
+    
+    let mut details = vec![format!("Background: {}", character.background)];
+    details.extend(character.personality.lines());
+    let prompt = format!(
+        "Write a short, evocative backstory (one paragraph, at most five sentences) for {}, a {} {}.
+        {}
+        
+        Give them a home, a formative event, and an unresolved thread an adventure could pick up. Reply with the backstory only.",
+        character.name, character.race, character.class, details.join("\n        ")
+    );
+    
+    loop {
+        let draft = dm_chat(
+            dm,
+            &prompt,
+            vec![],
+            "Failed to draft a backstory",
+            "The Dungeon Master is imagining your past...",
+        )
+        .await?;
+        let draft = draft.trim().to_string();
+        print_fancy_message("Backstory", "cyan");
+        println!("{}", draft.color(t.value));
+        
+        let choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Keep this backstory?")
+            .default(0)
+            .items(&["Accept", "Reroll", "Edit", "Skip"])
+            .interact()?;
+        match choice {
+            0 => character.backstory = draft,
+            1 => continue,
+            2 => {
+                let edited: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Backstory")
+                    .with_initial_text(&draft)
+                    .interact_text()?;
+                character.backstory = edited.trim().to_string();
+            },
+            _ => {},
+        }
+        return Ok(());
+    }
+}
+
+// Asks the DM to rework the quest hook around the character's own story before the first scene
+async fn personalize_quest(dm: &impl Chat, state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let mut details = vec![format!("Background: {}", state.character.background)];
+    details.extend(state.character.personality.lines());
+    if !state.character.backstory.is_empty() {
+        details.push(format!("Backstory: {}", state.character.backstory));
+    }
+    
+    let prompt = format!(
+        "Before the opening scene, make the quest personal for {}. Their details:
+        {}
+        
+        Revise the central quest hook (currently: \"{}\") so it grows directly out of these details - an old bond in danger, an ideal put to the test, a flaw that complicates things, or a patron's demand.
+        Briefly describe the connection, then give the revised hook in one or two sentences on its own line starting with \"Quest:\".",
+        state.character.name,
+        details.join("\n        "),
+        state.current_quest
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to personalize the quest",
+        "The Dungeon Master is weaving in your story...",
+    )
+    .await?;
+    
+    if let Some(quest) = response
+        .lines()
+        .find_map(|line| line.trim().trim_start_matches(['*', '#', ' ']).strip_prefix("Quest:"))
+        .map(|quest| quest.trim().trim_matches('*').trim())
+        .filter(|quest| !quest.is_empty())
+    {
+        state.current_quest = quest.to_string();
+    }
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    Ok(())
+}
+
+const DIRECTOR_NOTE_PREFIX: &str = "[DIRECTOR'S NOTE]";
+
+// XP budgets for the party, added only to prompts that ask the DM to stage a fight
+fn encounter_budget_guidance(state: &GameState) -> String {
+    let levels = state.party_levels();
+    format!(
+        " Encounter budget for this party (adjusted XP per the DMG): {}. Size the threat to fit, usually Medium, Deadly only when the story calls for it.",
+        combat::EncounterDifficulty::ALL.iter()
+            .map(|&d| format!("{} {}", d.name(), combat::encounter_budget(&levels, d)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// Standing out-of-character context repeated with each in-fiction prompt
+fn campaign_context(state: &GameState) -> String {
+    let mut context = format!(
+        "\n\nCampaign setting ({}): {} Keep the tone consistent with it.\n\nIn-game time: {}.\n\nLength: {}",
+        state.setting.name(), state.setting.guidance(), state.clock, state.narration_length.instruction()
+    );
+    let magic_items: Vec<String> = state
+        .character
+        .inventory
+        .iter()
+        .filter(|item| item.is_magic() && item.is_active())
+        .map(|item| item.to_string())
+        .collect();
+    if !magic_items.is_empty() {
+        context.push_str(&format!("\n\nMagic items in effect: {}", magic_items.join(", ")));
+    }
+    if !state.character.active_effects.is_empty() {
+        let effects: Vec<String> = state.character.active_effects.iter().map(|e| e.to_string()).collect();
+        context.push_str(&format!("\n\nSpell effects active on the player: {}", effects.join(", ")));
+    }
+    if !state.character.companions.is_empty() {
+        context.push_str("\n\nCompanions traveling with the player (keep them present in the scene and give them small moments):\n");
+        for companion in &state.character.companions {
+            context.push_str(&format!("- {}\n", companion));
+        }
+    }
+    if !state.character.tool_proficiencies.is_empty() {
+        context.push_str(&format!(
+            "\n\nThe player is proficient with: {}. Add their proficiency bonus (+{}) when they use these, e.g. thieves' tools on a lock.",
+            state.character.tool_proficiencies.iter().cloned().collect::<Vec<_>>().join(", "), proficiency_bonus(state.character.level)
+        ));
+    }
+    if let Some(race) = homebrew_race(&state.character.race) && !race.description.is_empty() {
+        context.push_str(&format!("\n\n{} is a homebrew race: {}", race.name, race.description));
+    }
+    if !state.character.dm_notes.is_empty() {
+        context.push_str(&format!(
+            "\n\nAlways respect these traits of {} in how you portray them and how the world reacts: {}",
+            state.character.name, state.character.dm_notes
+        ));
+    }
+    if state.environment != Environment::default() {
+        context.push_str(&format!("\n\n{}", state.environment.guidance()));
+    }
+    if !state.party_stash.is_empty() {
+        let stash: Vec<String> = state.party_stash.iter().map(|i| i.to_string()).collect();
+        context.push_str(&format!("\n\nThe party's shared stash (not carried by {}): {}.", state.character.name, stash.join(", ")));
+    }
+    if !state.canon_facts.is_empty() {
+        context.push_str(&format!("\n\nEstablished facts (never contradict these): {}.", state.canon_facts.join("; ")));
+    }
+    if !state.events.is_empty() {
+        let recent = &state.events[state.events.len().saturating_sub(TIMELINE_CONTEXT_EVENTS)..];
+        let recent: Vec<String> = recent.iter().map(|e| e.to_string()).collect();
+        context.push_str(&format!("\n\nThe story so far, most recent last (it is now day {}): {}.", state.clock.day(), recent.join("; ")));
+    }
+    if !state.director_notes.is_empty() {
+        context.push_str("\n\nStanding director's notes from the player (out of character; honor them, never narrate them):\n");
+        for note in &state.director_notes {
+            context.push_str(&format!("- {}\n", note));
+        }
+    }
+    context
+}
+
+// Sends an out-of-character instruction the DM should follow from now on
+async fn send_director_note(
+    dm: &impl Chat,
+    note: &str,
+    state: &mut GameState,
+) -> Result<String, Box<dyn Error>> {
+    let prompt = format!(
+        "{} {}
+        
+        This is an out-of-character instruction from the player, not an action in the story.
+        Follow it from now on. Acknowledge it in one short out-of-character sentence and do not narrate any events.",
+        DIRECTOR_NOTE_PREFIX, note
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to send director's note",
+        "The Dungeon Master is taking note...",
+    )
+    .await?;
+    
+    state.director_notes.push(note.to_string());
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+// Crafts from a built-in or learned recipe, resolving the tool check before the DM narrates it
+async fn craft(dm: &impl Chat, state: &mut GameState, roller: &mut impl Roller) -> Result<Option<String>, Box<dyn Error>> {
+    let t = theme();
+    let recipes: Vec<Recipe> = builtin_recipes()
+        .into_iter()
+        .chain(state.character.known_recipes.iter().cloned())
+        .collect();
+    let mut options: Vec<String> = recipes
+        .iter()
+        .map(|r| format!("{} ({}; {}; {} gp, {} hours, DC {})", r.name, r.tool, r.components.join(", "), r.gold, r.hours, r.dc))
+        .collect();
+    options.push("Learn a recipe".to_string());
+    options.push("Add a tool proficiency".to_string());
+    options.push("Back".to_string());
+    
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Craft what?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    
+    if choice == recipes.len() {
+        let name: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Recipe name")
+            .interact_text()?;
+        let tool: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Tool used (e.g. Smith's tools)")
+            .interact_text()?;
+        let components: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Components, comma separated")
+            .interact_text()?;
+        let gold: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Gold cost")
+            .default(0)
+            .interact_text()?;
+        let hours: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Hours of work")
+            .default(4)
+            .interact_text()?;
+        let dc: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Tool check DC")
+            .default(12)
+            .interact_text()?;
+        let name = name.trim().to_string();
+        state.character.known_recipes.push(Recipe {
+            output: name.clone(),
+            name,
+            tool: tool.trim().to_string(),
+            components: components.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect(),
+            gold,
+            hours,
+            dc,
+        });
+        save_game(state)?;
+        print_fancy_message("Recipe learned!", "green");
+        return Ok(None);
+    }
+    if choice == recipes.len() + 1 {
+        let tool: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Tool (e.g. Alchemist's supplies)")
+            .interact_text()?;
+        let tool = tool.trim();
+        if state.character.is_proficient_with_tool(tool) {
+            print_fancy_message(&format!("You are already proficient with {}", tool), "yellow");
+            return Ok(None);
+        }
+        state.character.tool_proficiencies.insert(tool.to_string());
+        save_game(state)?;
+        print_fancy_message(&format!("You are now proficient with {}", tool), "green");
+        return Ok(None);
+    }
+    let Some(recipe) = recipes.get(choice) else {
+        return Ok(None);
+    };
+    
+    let character = &state.character;
+    if !character.is_proficient_with_tool(&recipe.tool) {
+        print_fancy_message(&format!("You need proficiency with {} to craft {}.", recipe.tool, recipe.name), "red");
+        return Ok(None);
+    }
+    let components = match character.find_components(&recipe.components) {
+        Ok(indices) => indices,
+        Err(missing) => {
+            print_fancy_message(&format!("Missing components for {}: {}", recipe.name, missing.join(", ")), "red");
+            return Ok(None);
+        }
+    };
+    if character.gold < recipe.gold {
+        print_fancy_message(&format!("{} costs {} gp in materials; you have {} gp.", recipe.name, recipe.gold, character.gold), "red");
+        return Ok(None);
+    }
+    
+    // Components, gold, and time are spent whether or not the check succeeds
+    let mut components = components;
+    components.sort_unstable_by(|a, b| b.cmp(a));
+    for index in components {
+        state.character.inventory.remove(index);
+    }
+    state.character.gold -= recipe.gold;
+    state.clock.advance(recipe.hours as u64 * 60);
+    announce_expired_effects(&state.character.tick_active_effects(recipe.hours * 60 * ROUNDS_PER_MINUTE));
+    
+    let ability_mod = state.character.effective_modifier(Ability::Intelligence);
+    let mode = with_environment(RollMode::Normal, state.environment.tool_check_penalty(), "tool checks");
+    let (dice, roll) = roll_d20(roller, mode);
+    let total = roll as i32 + ability_mod + proficiency_bonus(state.character.level);
+    state.record_roll(&d20_expr(total - roll as i32, mode), &dice, total,
+                      &format!("{} check: crafting {}", recipe.tool, recipe.name));
+    print_fancy_message(&format!("{} Check (DC {})", recipe.tool, recipe.dc), "yellow");
+    print_d20_roll(&dice, roll, mode);
+    let check = ResolvedCheck { skill: recipe.tool.clone(), dc: recipe.dc, roll, total };
+    println!("Total: {} — {}", total.to_string().color(t.value).bold(),
+             if check.succeeded() { "SUCCESS".color(t.good).bold() } else { "FAILURE".color(t.bad).bold() });
+    
+    if check.succeeded() {
+        let turn = state.turn();
+        state.character.add_item(Item::new(&recipe.output), turn);
+        print_fancy_message(&format!("Crafted {}!", recipe.output), "green");
+    } else {
+        print_fancy_message("The materials are ruined.", "red");
+    }
+    
+    let action = format!(
+        "I spend {} hours crafting {} with my {}, using {} and {} gp of materials.",
+        recipe.hours, recipe.output, recipe.tool, recipe.components.join(", "), recipe.gold
+    );
+    Ok(Some(process_player_action(dm, &action, Some(&check), state).await?))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TravelPace {
+    Cautious,
+    Normal,
+    Quick,
+}
+
+impl TravelPace {
+    const ALL: [TravelPace; 3] = [TravelPace::Cautious, TravelPace::Normal, TravelPace::Quick];
+    
+    fn description(&self) -> &'static str {
+        match self {
+            TravelPace::Cautious => "Cautious (2 mph, alert to danger)",
+            TravelPace::Normal => "Normal (3 mph)",
+            TravelPace::Quick => "Quick (4 mph, easier to ambush)",
+        }
+    }
+    
+    fn miles_per_hour(&self) -> u64 {
+        match self {
+            TravelPace::Cautious => 2,
+            TravelPace::Normal => 3,
+            TravelPace::Quick => 4,
+        }
+    }
+}
+
+// Rough distances for places the player can't measure
+const TRAVEL_DISTANCES: [(&str, u64); 4] = [
+    ("Nearby (about 3 miles)", 3),
+    ("A few hours away (about 12 miles)", 12),
+    ("A day's journey (about 24 miles)", 24),
+    ("Several days away (about 72 miles)", 72),
+];
+
+// Travel time in minutes, assuming eight hours on the road per day
+fn travel_minutes(miles: u64, pace: TravelPace) -> u64 {
+    let road_minutes = miles * 60 / pace.miles_per_hour();
+    let full_days = road_minutes / (8 * 60);
+    road_minutes + full_days * 16 * 60
+}
+
+// One encounter check per four hours on the road; 18+ on a d20 means trouble.
+// Returns who noticed whom first for each encounter.
+fn roll_travel_encounters(
+    roller: &mut impl Roller,
+    character: &Character,
+    miles: u64,
+    pace: TravelPace,
+) -> Vec<&'static str> {
+    let checks = (miles / pace.miles_per_hour()).div_ceil(4).max(1);
+    let mut encounters = Vec::new();
+    for _ in 0..checks {
+        let roll = roller.roll(20);
+        if roll < 18 {
+            continue;
+        }
+        let perception = roller.roll(20) as i32 + character.skill_modifier("Perception")
+            + match pace {
+                TravelPace::Cautious => 5,
+                TravelPace::Normal => 0,
+                TravelPace::Quick => -5,
+            };
+        encounters.push(if perception >= 12 {
+            "the player spots the threat before it spots them"
+        } else {
+            "the threat catches the player by surprise"
+        });
+    }
+    encounters
+}
+
+// Journey to a known or new location: advances the clock, rolls for encounters, and has the DM narrate the arrival
+async fn travel(dm: &impl Chat, state: &mut GameState, roller: &mut impl Roller) -> Result<Option<String>, Box<dyn Error>> {
+    let mut destinations: Vec<String> = state
+        .visited_locations
+        .iter()
+        .filter(|l| **l != state.current_location)
+        .cloned()
+        .collect();
+    destinations.push("Somewhere new...".to_string());
+    destinations.push("Cancel".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Where to?")
+        .default(0)
+        .items(&destinations)
+        .interact()?;
+    let destination = match destinations[choice].as_str() {
+        "Cancel" => return Ok(None),
+        "Somewhere new..." => {
+            let place: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Destination")
+                .interact_text()?;
+            place.trim().to_string()
+        }
+        known => known.to_string(),
+    };
+    
+    let distances: Vec<&str> = TRAVEL_DISTANCES.iter().map(|(label, _)| *label).collect();
+    let miles = TRAVEL_DISTANCES[Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("How far is it?")
+        .default(1)
+        .items(&distances)
+        .interact()?].1;
+    let paces: Vec<&str> = TravelPace::ALL.iter().map(|p| p.description()).collect();
+    let pace = TravelPace::ALL[Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Travel pace")
+        .default(1)
+        .items(&paces)
+        .interact()?];
+    
+    let minutes = travel_minutes(miles, pace);
+    let departed = state.clock;
+    state.clock.advance(minutes);
+    print_fancy_message(&format!("Traveling to {}: {} → {}", destination, departed, state.clock), "blue");
+    announce_expired_effects(&state.character.tick_active_effects((minutes as u32).saturating_mul(ROUNDS_PER_MINUTE)));
+    
+    let encounters = roll_travel_encounters(roller, &state.character, miles, pace);
+    let mut encounter_text = String::new();
+    let mut narrated = false;
+    for situation in &encounters {
+        print_fancy_message(&format!("Trouble on the road: {}!", situation), "red");
+        let fight = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Set it up as a combat encounter? (no lets the DM narrate it)")
+            .default(true)
+            .interact()?;
+        if fight && let Some(encounter) = combat::setup_encounter(state, roller)? {
+            state.encounter = Some(encounter);
+            state.scene_type = SceneType::Combat;
+            combat::run_combat(dm, state, roller).await?;
+            if state.character.is_dead() {
+                return Ok(None);
+            }
+            encounter_text.push_str(&format!(" They fought off an encounter on the road ({}).", situation));
+        } else {
+            encounter_text.push_str(&format!(" Narrate a random encounter on the road in which {}, and resolve it briefly.", situation));
+            narrated = true;
+        }
+    }
+    if narrated {
+        encounter_text.push_str(&encounter_budget_guidance(state));
+    }
+    
+    let origin = std::mem::replace(&mut state.current_location, destination.clone());
+    if !state.visited_locations.contains(&origin) {
+        state.visited_locations.push(origin.clone());
+    }
+    if !state.visited_locations.contains(&destination) {
+        state.visited_locations.push(destination.clone());
+    }
+    state.add_map_link(&origin, &destination);
+    
+    let prompt = format!(
+        "The player ({} the {} {}) travels from {} to {}, about {} miles at a {} pace. The journey takes them from {} to {}.{}
+        
+        As the DM, summarize the journey in a few sentences, then describe their arrival at {} and end with what they see and can do there.{}",
+        state.character.name, state.character.race, state.character.class,
+        origin, destination, miles, pace.description().to_lowercase(), departed, state.clock,
+        encounter_text, destination, campaign_context(state)
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to narrate the journey",
+        "The road stretches ahead...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    let (text, tags) = extract_tags(&response);
+    apply_dm_tags(state, &tags);
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(Some(text))
+}
+
+// Hours of sleep a long rest needs before an interruption no longer spoils it
+const LONG_REST_MIN_SLEEP_HOURS: u32 = 6;
+
+// A long rest somewhere unsafe is interrupted on 18+ on a d20; returns how many hours in it happens
+fn roll_rest_interruption(roller: &mut impl Roller) -> Option<u32> {
+    (roller.roll(20) >= 18).then(|| roller.roll(8))
+}
+
+// Short or long rest: restores the character, expires effects, and lets the DM narrate the downtime.
+// Long rests outside safe places can be interrupted; returns None if the character died fighting it off.
+async fn take_rest(
+    dm: &impl Chat,
+    state: &mut GameState,
+    kind: RestKind,
+    safe: bool,
+    roller: &mut impl Roller,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut kind = kind;
+    let mut rest_minutes = if kind == RestKind::Long { 8 * 60 } else { 60 };
+    let mut interruption_text = String::new();
+    if kind == RestKind::Long
+        && !safe
+        && let Some(hours) = roll_rest_interruption(roller)
+    {
+        print_fancy_message(&format!("Your rest is interrupted after {} hours!", hours), "red");
+        let fight = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Set it up as a combat encounter? (no lets the DM narrate it)")
+            .default(true)
+            .interact()?;
+        if fight && let Some(encounter) = combat::setup_encounter(state, roller)? {
+            state.encounter = Some(encounter);
+            state.scene_type = SceneType::Combat;
+            combat::run_combat(dm, state, roller).await?;
+            if state.character.is_dead() {
+                return Ok(None);
+            }
+            interruption_text = format!(" After {} hours they were attacked, fought off the threat, and went back to sleep.", hours);
+        } else if hours < LONG_REST_MIN_SLEEP_HOURS {
+            // Too little sleep for a long rest; it counts as a short one
+            print_fancy_message(&format!("Less than {} hours of sleep: this only counts as a short rest", LONG_REST_MIN_SLEEP_HOURS), "yellow");
+            kind = RestKind::Short;
+            rest_minutes = hours * 60;
+            interruption_text = format!(
+                " After {} hours something disturbed the camp and the rest was cut short. Narrate the interruption and resolve it briefly.{}",
+                hours, encounter_budget_guidance(state)
+            );
+        } else {
+            interruption_text = format!(
+                " Near dawn, after {} hours, something disturbed the camp. Narrate the interruption and resolve it briefly.{}",
+                hours, encounter_budget_guidance(state)
+            );
+        }
+    }
+    
+    let turn = state.turn();
+    let character = &mut state.character;
+    let mut summary = Vec::new();
+    let mut hit_die_roll = None;
+    
+    let expired = match kind {
+        RestKind::Short => character.expire_ability_modifiers(|d| {
+            matches!(d, EffectDuration::UntilShortRest | EffectDuration::Rounds(_))
+        }, turn),
+        RestKind::Long => character.expire_ability_modifiers(|d| *d != EffectDuration::UntilRemoved, turn),
+    };
+    let expired_max_hp = match kind {
+        RestKind::Short => character.expire_max_hp_modifiers(|d| {
+            matches!(d, EffectDuration::UntilShortRest | EffectDuration::Rounds(_))
+        }, turn),
+        RestKind::Long => character.expire_max_hp_modifiers(|d| *d != EffectDuration::UntilRemoved, turn),
+    };
+    
+    match kind {
+        RestKind::Short => {
+            // Spend one hit die
+            let die = hit_die(&character.class);
+            let roll = roller.roll(die) as i32;
+            let healing = (roll + character.effective_modifier(Ability::Constitution)).max(1) as u32;
+            print_dice_roll(&format!("1d{} hit die", die), &[roll as u32]);
+            hit_die_roll = Some((format!("1d{}{}", die, format_modifier(character.effective_modifier(Ability::Constitution))), roll as u32, healing));
+            let healed = if character.hit_points > 0 { character.heal(healing, "Short rest (hit die)", turn) } else { 0 };
+            summary.push(format!("spent a hit die and recovered {} HP", healed));
+        },
+        RestKind::Long => {
+            // A long rest starts a fresh day, so the old audit trail is cleared
+            let recovered = character.effective_max_hp() as i32 - character.hit_points as i32;
+            character.hp_log.clear();
+            character.log_hp_change(recovered, "Long rest", turn);
+            character.hit_points = character.effective_max_hp();
+            character.temp_hit_points = 0;
+            for companion in character.companions.iter_mut() {
+                companion.hp = companion.max_hp;
+            }
+            character.death_save_successes = 0;
+            character.death_save_failures = 0;
+            character.update_status();
+            summary.push("recovered all hit points".to_string());
+            if character.resurrection_penalty > 0 {
+                character.resurrection_penalty -= 1;
+                summary.push(match character.resurrection_penalty {
+                    0 => "shook off the last of the resurrection's weakness".to_string(),
+                    left => format!("felt the resurrection's weakness ease to -{}", left),
+                });
+            }
+        },
+    }
+    
+    if character.pact_slots_used > 0 {
+        character.pact_slots_used = 0;
+        summary.push("regained pact magic slots".to_string());
+    }
+    
+    for modifier in &expired {
+        summary.push(format!("{} ({} {}) wore off", modifier.source,
+                             modifier.ability.abbreviation(), format_modifier(modifier.delta)));
+    }
+    for modifier in &expired_max_hp {
+        summary.push(format!("{} (max HP {}) wore off", modifier.source, format_modifier(modifier.delta)));
+    }
+    
+    for effect in character.tick_active_effects(rest_minutes * ROUNDS_PER_MINUTE) {
+        summary.push(format!("{} ended", effect.name));
+    }
+    state.clock.advance(rest_minutes as u64);
+    let rest_name = if kind == RestKind::Long { "long rest" } else { "short rest" };
+    print_fancy_message(&format!("{} takes a {}: {}", character.name, rest_name, summary.join(", ")), "green");
+    
+    let prompt = format!(
+        "I take a {} ({}). I now have {}/{} HP.{}
+        
+        Briefly narrate the rest in the current location. Only interrupt it if something in the story demands it.",
+        rest_name, summary.join(", "), character.hit_points, character.effective_max_hp(), interruption_text
+    );
+    if let Some((expr, roll, healing)) = hit_die_roll {
+        state.record_roll(&expr, &[roll], healing as i32, "Hit die (short rest)");
+    }
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to narrate the rest",
+        "Resting...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(Some(response))
+}
+
+// Add, hurt, heal, or dismiss companions
+fn manage_companions(character: &mut Character) -> Result<(), Box<dyn Error>> {
+    let mut options: Vec<String> = character.companions.iter().map(|c| c.to_string()).collect();
+    options.push("Add a companion".to_string());
+    options.push("Back".to_string());
+    
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Companions")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    
+    if choice == character.companions.len() {
+        let name: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Name")
+            .interact_text()?;
+        let kind: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Kind of creature (e.g. owl familiar, wolf)")
+            .interact_text()?;
+        let max_hp: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Hit points")
+            .default(5)
+            .interact_text()?;
+        let ac: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Armor class")
+            .default(12)
+            .interact_text()?;
+        let dexterity: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Dexterity score")
+            .default(10)
+            .interact_text()?;
+        let notes: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Notes (optional)")
+            .allow_empty(true)
+            .interact_text()?;
+        let companion = Companion {
+            name: name.trim().to_string(),
+            kind: kind.trim().to_string(),
+            hp: max_hp.max(1),
+            max_hp: max_hp.max(1),
+            ac,
+            notes: notes.trim().to_string(),
+            dexterity: dexterity.clamp(1, 30),
+        };
+        print_fancy_message(&format!("{} joins you", companion.name), "green");
+        character.companions.push(companion);
+        return Ok(());
+    }
+    let Some(companion) = character.companions.get_mut(choice) else {
+        return Ok(());
+    };
+    
+    let actions = ["Take damage", "Heal", "Edit notes", "Dismiss", "Back"];
+    let action = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(companion.name.clone())
+        .default(0)
+        .items(&actions)
+        .interact()?;
+    match actions[action] {
+        "Take damage" | "Heal" => {
+            let amount: u32 = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Amount")
+                .interact_text()?;
+            if actions[action] == "Take damage" {
+                let lost = companion.take_damage(amount);
+                print_fancy_message(&format!("{} loses {} HP ({}/{})", companion.name, lost, companion.hp, companion.max_hp), "red");
+            } else {
+                let healed = companion.heal(amount);
+                print_fancy_message(&format!("{} regains {} HP ({}/{})", companion.name, healed, companion.hp, companion.max_hp), "green");
+            }
+        },
+        "Edit notes" => {
+            companion.notes = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Notes")
+                .with_initial_text(companion.notes.clone())
+                .allow_empty(true)
+                .interact_text()?;
+        },
+        "Dismiss" => {
+            let removed = character.companions.remove(choice);
+            print_fancy_message(&format!("{} parts ways with you", removed.name), "yellow");
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+// Records a magic item the DM handed out
+fn add_magic_item() -> Result<Item, Box<dyn Error>> {
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Item name (e.g. Ring of Protection)")
+        .interact_text()?;
+    
+    let requires_attunement = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Does it require attunement?")
+        .default(false)
+        .interact()?;
+    
+    let ac_bonus: i32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("AC bonus (0 for none)")
+        .default(0)
+        .interact_text()?;
+    
+    let mut abilities = vec!["None"];
+    abilities.extend(Ability::ALL.iter().map(|a| a.abbreviation()));
+    let ability_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Ability score bonus")
+        .default(0)
+        .items(&abilities)
+        .interact()?;
+    let ability_bonus = if ability_index == 0 {
+        None
+    } else {
+        let delta: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Bonus to the score")
+            .default(2)
+            .interact_text()?;
+        Some((Ability::ALL[ability_index - 1], delta))
+    };
+    
+    Ok(Item {
+        name: name.trim().to_string(),
+        requires_attunement,
+        attuned: false,
+        ac_bonus,
+        ability_bonus,
+        ..Default::default()
+    })
+}
+
+// Adds a buff or drain from a spell, potion, or monster
+fn add_ability_effect(character: &mut Character, turn: usize) -> Result<(), Box<dyn Error>> {
+    let abilities: Vec<&str> = Ability::ALL.iter().map(|a| a.abbreviation()).collect();
+    let ability_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Which ability is affected?")
+        .default(0)
+        .items(&abilities)
+        .interact()?;
+    
+    let delta: i32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Change to the score (e.g. 2 or -4)")
+        .interact_text()?;
+    
+    let source: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Source (e.g. Potion of Giant Strength, Shadow's touch)")
+        .interact_text()?;
+    
+    let duration = choose_effect_duration()?;
+    
+    character.add_ability_modifier(AbilityModifier {
+        ability: Ability::ALL[ability_index],
+        delta,
+        source: source.trim().to_string(),
+        duration,
+    }, turn);
+    Ok(())
+}
+
+// Returns any concentration effects the new one replaced
+fn add_spell_effect(character: &mut Character) -> Result<Vec<ActiveEffect>, Box<dyn Error>> {
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Effect (e.g. Bless, Spirit Guardians)")
+        .interact_text()?;
+    
+    let units = ["Rounds", "Minutes", "Hours"];
+    let unit = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Duration measured in")
+        .default(1)
+        .items(&units)
+        .interact()?;
+    let amount: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Number of {}", units[unit].to_lowercase()))
+        .default(1)
+        .interact_text()?;
+    let remaining = match unit {
+        0 => amount,
+        1 => amount * ROUNDS_PER_MINUTE,
+        _ => amount * 60 * ROUNDS_PER_MINUTE,
+    };
+    
+    let concentration = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Does it require concentration?")
+        .default(true)
+        .interact()?;
+    
+    Ok(character.add_active_effect(ActiveEffect { name: name.trim().to_string(), remaining: remaining.max(1), concentration }))
+}
+
+fn add_max_hp_effect(character: &mut Character, turn: usize) -> Result<(), Box<dyn Error>> {
+    let delta: i32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Change to maximum HP (e.g. -4)")
+        .interact_text()?;
+    
+    let source: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Source (e.g. Mummy rot, Exhaustion)")
+        .interact_text()?;
+    
+    let duration = choose_effect_duration()?;
+    
+    character.add_max_hp_modifier(MaxHpModifier {
+        delta,
+        source: source.trim().to_string(),
+        duration,
+    }, turn);
+    Ok(())
+}
+
+fn choose_effect_duration() -> Result<EffectDuration, Box<dyn Error>> {
+    let durations = ["Rounds", "Until a short rest", "Until a long rest", "Until removed"];
+    Ok(match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("How long does it last?")
+        .default(2)
+        .items(&durations)
+        .interact()?
+    {
+        0 => EffectDuration::Rounds(
+            Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Number of rounds")
+                .default(10)
+                .interact_text()?,
+        ),
+        1 => EffectDuration::UntilShortRest,
+        2 => EffectDuration::UntilLongRest,
+        _ => EffectDuration::UntilRemoved,
+    })
+}
+
+// A check the rules have already resolved, so the DM narrates rather than decides it
+struct ResolvedCheck {
+    // A skill, or a tool for tool checks
+    skill: String,
+    dc: u32,
+    roll: u32,
+    total: i32,
+}
+
+impl ResolvedCheck {
+    fn succeeded(&self) -> bool {
+        self.total >= self.dc as i32
+    }
+    
+    // e.g. "Success (17 vs DC 15)"
+    fn verdict(&self) -> String {
+        format!("{} ({} vs DC {})", if self.succeeded() { "Success" } else { "Failure" }, self.total, self.dc)
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckSuggestion {
+    skill: Option<String>,
+    dc: Option<u32>,
+}
+
+// Asks the DM whether an action needs a check, and if so which skill and DC.
+// The exchange is kept out of the history so it doesn't steer the story.
+async fn suggest_check(
+    dm: &impl Chat,
+    action: &str,
+    state: &GameState,
+) -> Result<Option<(&'static str, u32)>, Box<dyn Error>> {
+    let prompt = format!(
+        "The player ({} the {} {}) proposes the following action:\n\n{}
+        
+        Do not narrate anything. Decide whether this action needs an ability check.
+        Reply with only a JSON object: {{\"skill\": \"Athletics\", \"dc\": 15}} using one of the 18 D&D skills,
+        or {{\"skill\": null, \"dc\": null}} if no check is needed.",
+        state.character.name, state.character.race, state.character.class, action
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to get a DC suggestion",
+        "The Dungeon Master is sizing up your action...",
+    )
+    .await?;
+    
+    Ok(parse_check_suggestion(&response))
+}
+
+fn parse_check_suggestion(response: &str) -> Option<(&'static str, u32)> {
+    let json = &response[response.find('{')?..=response.rfind('}')?];
+    let suggestion: CheckSuggestion = serde_json::from_str(json).ok()?;
+    let skill = suggestion.skill?;
+    let skill = SKILLS.iter().map(|(name, _)| *name).find(|name| name.eq_ignore_ascii_case(skill.trim()))?;
+    Some((skill, suggestion.dc?.clamp(5, 30)))
+}
+
+#[derive(Deserialize)]
+struct SkillSuggestion {
+    skill: Option<String>,
+}
+
+// Asks the DM only which skill best fits an action, leaving the DC to the usual check flow
+async fn suggest_skill(
+    dm: &impl Chat,
+    action: &str,
+    state: &GameState,
+) -> Result<Option<&'static str>, Box<dyn Error>> {
+    let prompt = format!(
+        "The player ({} the {} {}) proposes the following action:\n\n{}
+        
+        Do not narrate anything. Name the single D&D skill that best fits this action, if any.
+        Reply with only a JSON object: {{\"skill\": \"Athletics\"}} using one of the 18 D&D skills,
+        or {{\"skill\": null}} if the action needs no check.",
+        state.character.name, state.character.race, state.character.class, action
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to get a skill suggestion",
+        "The Dungeon Master is considering your action...",
+    )
+    .await?;
+    
+    Ok(parse_skill_suggestion(&response))
+}
+
+fn parse_skill_suggestion(response: &str) -> Option<&'static str> {
+    let json = &response[response.find('{')?..=response.rfind('}')?];
+    let suggestion: SkillSuggestion = serde_json::from_str(json).ok()?;
+    let skill = suggestion.skill?;
+    SKILLS.iter().map(|(name, _)| *name).find(|name| name.eq_ignore_ascii_case(skill.trim()))
+}
+
+const ATTACK_WORDS: [&str; 8] = ["attack", "stab", "shoot", "hit", "kill", "strike", "punch", "fireball"];
+
+// Words allowed between an attack word and its target, as in "shoot at Bram"
+const ATTACK_LINKS: [&str; 3] = ["at", "on", "toward"];
+
+fn lowercase_words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Local heuristic for typos that would be catastrophic: a configured risky phrase, or an attack word aimed at a companion.
+// The companion has to be the object ("hit Bram", "shoot at Bram"), so "strike a deal with Bram" goes through
+fn risky_action_reason(action: &str, character: &Character, phrases: &[String]) -> Option<String> {
+    let lower = action.to_lowercase();
+    if let Some(phrase) = phrases.iter().find(|p| !p.trim().is_empty() && lower.contains(&p.trim().to_lowercase())) {
+        return Some(format!("it mentions \"{}\"", phrase.trim()));
+    }
+    let words = lowercase_words(action);
+    for (i, word) in words.iter().enumerate() {
+        if !ATTACK_WORDS.contains(&word.as_str()) {
+            continue;
+        }
+        let target: Vec<&String> = words[i + 1..].iter().skip_while(|w| ATTACK_LINKS.contains(&w.as_str())).collect();
+        let companion = character.companions.iter().find(|c| {
+            let name = lowercase_words(&c.name);
+            !name.is_empty() && target.len() >= name.len() && name.iter().zip(&target).all(|(a, b)| a == *b)
+        });
+        if let Some(companion) = companion {
+            return Some(format!("it looks like an attack on your companion {}", companion.name));
+        }
+    }
+    None
+}
+
+// The full per-action prompt, restating the DM's instructions every turn
+fn verbose_action_prompt(action: &str, check_text: &str, state: &GameState) -> String {
+    format!(
+        "The player ({} the {} {}) takes the following action:\n\n{}{}
+        
+        Equipped: {}
+        
+        Respond as the Dungeon Master, describing the outcome of this action. 
+        Use rich, evocative language to create an immersive experience.
+        If dice rolls would be needed, describe the check but don't roll dice yourself.
+        End with either a question or a prompt that gives the player clear options for what they might do next.
+        If the player attempts something impossible, gently steer them toward better options.
+        
+        Current scene: {}. {}
+        If the scene clearly shifts, add a tag on its own line such as [SCENE: Social] (Exploration, Combat, Social, or Downtime).
+        When the weather, light, or footing changes, add a tag such as [ENV: Heavy fog, Dim light, Difficult terrain] (weather: Clear, Rain, Heavy rain, Fog, Heavy fog, Wind, Strong wind, Snow, Storm; light: Bright, Dim, Darkness; terrain: Normal, Difficult).
+        If an effect lowers the player's maximum HP, add a tag such as [MAX_HP: -4 Mummy rot], ending with (long rest) if a long rest clears it.
+        If the player learns a spell from a scroll, spellbook, or teacher, add a tag such as [LEARN: Fireball (level 3)].
+        When you establish a lasting fact (a name, a place, who owes whom), add a tag such as [FACT: The innkeeper of the Gilded Goose is Mara].
+        When you reveal that two places connect (a road, a tunnel, a river crossing), add a tag such as [MAP: Oakvale - Old Mill].
+        When someone grants the player a bonus die for their next check or save (Guidance, Bardic Inspiration), add a tag such as [BONUS: Guidance 1d4].
+        When a major story beat happens (a villain revealed, a town lost, a quest completed), add a tag such as [EVENT: The bridge falls | The ogres cut Oakvale off from the south].{}",
+        state.character.name,
+        state.character.race,
+        state.character.class,
+        action,
+        check_text,
+        state.character.equipped_summary(),
+        state.scene_type.name(),
+        state.scene_type.pacing_guidance(),
+        campaign_context(state)
+    )
+}
+
+// Just the action and the state the DM can't infer; the preamble already covers how to respond
+fn lean_action_prompt(action: &str, check_text: &str, state: &GameState) -> String {
+    format!(
+        "Player action: {}{}
+        State: {}/{} HP, {}, at {}, scene {}.{}
+        Tags when needed: [SCENE: ...], [MAX_HP: -N source], [LEARN: spell (level N)], [FACT: new lasting fact], [MAP: place - place], [EVENT: title | description], [BONUS: source dice], [ENV: weather, light, terrain].{}",
+        action,
+        check_text,
+        state.character.hit_points,
+        state.character.effective_max_hp(),
+        state.character.equipped_summary(),
+        state.current_location,
+        state.scene_type.name(),
+        active_conditions(&state.character),
+        campaign_context(state)
+    )
+}
+
+// Temporary effects on the character, as a short sentence for lean prompts
+fn active_conditions(character: &Character) -> String {
+    let mut conditions: Vec<String> = character
+        .ability_modifiers
+        .iter()
+        .map(|m| format!("{} {} ({})", m.ability.abbreviation(), format_modifier(m.delta), m.source))
+        .collect();
+    conditions.extend(character.max_hp_modifiers.iter().map(|m| m.to_string()));
+    conditions.extend(character.active_effects.iter().map(|e| e.to_string()));
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" Effects: {}.", conditions.join(", "))
+    }
+}
+
+// Rough token count (about four characters per token), for debug output only
+fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+async fn process_player_action(
+    dm: &impl Chat,
+    action: &str,
+    check: Option<&ResolvedCheck>,
+    state: &mut GameState,
+) -> Result<String, Box<dyn Error>> {
+    let check_text = match check {
+        Some(check) => format!(
+            "\n\nThe rules have already resolved this as a DC {} {} check: rolled {} for a total of {} - {}. Narrate an outcome consistent with that result and do not call for another roll.",
+            check.dc, check.skill, check.roll, check.total,
+            if check.succeeded() { "SUCCESS" } else { "FAILURE" }
+        ),
+        None => String::new(),
+    };
+    
+    // Spotlight rotation frames each non-combat beat around the next party member in turn
+    let spotlight = state.spotlight_name().filter(|_| state.scene_type != SceneType::Combat).map(str::to_string);
+    let check_text = match &spotlight {
+        Some(name) => format!(
+            "{}\n\nSpotlight: this beat belongs to {}. Frame the response around them, giving them the focus and a moment to speak or act, while the rest of the party stays in the background.",
+            check_text, name
+        ),
+        None => check_text,
+    };
+    
+    let action_prompt = if config().lean_prompts {
+        let lean = lean_action_prompt(action, &check_text, state);
+        if config().debug {
+            let verbose = verbose_action_prompt(action, &check_text, state);
+            println!("{}", format!(
+                "[debug] lean prompt ~{} tokens vs ~{} verbose, saving ~{}",
+                estimate_tokens(&lean), estimate_tokens(&verbose),
+                estimate_tokens(&verbose).saturating_sub(estimate_tokens(&lean))
+            ).dimmed());
+        }
+        lean
+    } else {
+        verbose_action_prompt(action, &check_text, state)
+    };
+    
+    let response = dm_chat(
+        dm,
+        &action_prompt,
+        state.history.clone(),
+        "Failed to process your action",
+        "The Dungeon Master is responding...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&action_prompt));
+    state.history.push(Message::assistant(&response));
+    
+    let (text, tags) = extract_tags(&response);
+    apply_dm_tags(state, &tags);
+    if spotlight.is_some() {
+        state.advance_spotlight();
+    }
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(text)
+}
+
+async fn roll_skill_check(
+    dm: &impl Chat,
+    skill: &str,
+    roll: &D20Roll,
+    purpose: &str,
+    dc: Option<u32>,
+    state: &mut GameState,
+) -> Result<String, Box<dyn Error>> {
+    // Get the appropriate ability modifier based on the skill
+    let ability_mod = skill_ability(skill)
+        .map(|ability| state.character.effective_modifier(ability))
+        .unwrap_or(0);
+    
+    // Apply proficiency bonus if proficient, doubled with Expertise
+    let prof_bonus = state.character.skill_proficiency_bonus(skill);
+    let total = roll.total(state.character.skill_modifier(skill));
+    print_roll_explanation(roll, &format!("{} check", skill), &state.character.skill_modifier_parts(skill), total);
+    
+    // With a DC set at the table the outcome is already decided; the DM only narrates it
+    let adjudication = match dc {
+        Some(dc) => {
+            let check = ResolvedCheck { skill: skill.to_string(), dc, roll: roll.kept, total };
+            format!(
+                "The DC was set at the table and the rules have already resolved this check: {}.
+        Narrate an outcome consistent with that result and do not call for another roll.",
+                check.verdict().to_uppercase()
+            )
+        }
+        None => "For reference, typical difficulty classes are:
+        - Easy: 10
+        - Medium: 15
+        - Hard: 20
+        - Very Hard: 25
+        - Nearly Impossible: 30".to_string(),
+    };
+    
+    let roll_prompt = format!(
+        "The player ({} the {} {}) rolls a {} check for the following purpose: \"{}\"
+        Dice roll: {}
+        Ability modifier: {}
+        Proficiency: {}
+        Total: {}
+        
+        As the DM, first evaluate whether this is an appropriate use of the {} skill.
+        If it is appropriate, interpret this skill check result and describe the outcome.
+        If it's not appropriate, explain why and suggest a better approach or skill.
+        
+        {}
+        
+        Continue the scene after describing the result of this check.",
+        state.character.name,
+        state.character.race,
+        state.character.class,
+        skill,
+        purpose,
+        roll.describe(),
+        ability_mod,
+        match state.character.skill_proficiency(skill) {
+            Proficiency::Expertise => format!("Expertise (+{})", prof_bonus),
+            Proficiency::Proficient => format!("Yes (+{})", prof_bonus),
+            Proficiency::None => "No".to_string(),
+        },
+        total,
+        skill,
+        adjudication
+    );
+    
+    let response = dm_chat(
+        dm,
+        &roll_prompt,
+        state.history.clone(),
+        "Failed to process skill check",
+        "The Dungeon Master is resolving your check...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&roll_prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+async fn roll_saving_throw(
+    dm: &impl Chat,
+    ability: Ability,
+    roll: &D20Roll,
+    purpose: &str,
+    state: &mut GameState,
+) -> Result<String, Box<dyn Error>> {
+    let modifier = state.character.saving_throw_modifier(ability);
+    let total = roll.total(modifier);
+    print_roll_explanation(roll, &format!("{} saving throw", ability.name()), &state.character.saving_throw_parts(ability), total);
+    
+    let roll_prompt = format!(
+        "The player ({} the {} {}) makes a {} saving throw against the following: \"{}\"
+        Dice roll: {}
+        Save modifier: {} ({})
+        Total: {}
+        
+        As the DM, decide the DC if you haven't already, say whether the save succeeds, and describe the outcome.
+        Continue the scene after describing the result.",
+        state.character.name,
+        state.character.race,
+        state.character.class,
+        ability.name(),
+        purpose,
+        roll.describe(),
+        format_modifier(modifier),
+        if state.character.is_proficient_in_save(ability) { "proficient" } else { "not proficient" },
+        total
+    );
+    
+    let response = dm_chat(
+        dm,
+        &roll_prompt,
+        state.history.clone(),
+        "Failed to process saving throw",
+        "The Dungeon Master is evaluating your saving throw...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&roll_prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+// Performs a parsed quick command, reusing the same handlers as the menus
+async fn run_quick_command(
+    dm: &impl Chat,
+    command: QuickCommand,
+    purpose: &str,
+    state: &mut GameState,
+    roller: &mut impl Roller,
+) -> Result<String, Box<dyn Error>> {
+    let t = theme();
+    match command {
+        QuickCommand::Roll(expr) => {
+            let (dice, total) = expr.roll(roller);
+            state.record_roll(&expr.to_string(), &dice, total, if purpose.is_empty() { "Quick roll" } else { purpose });
+            print_fancy_message(&format!("Dice Roll: {}", expr), "yellow");
+            print_dice_roll(&expr.to_string(), &dice);
+            if expr.modifier != 0 {
+                println!("Total: {}", total.to_string().color(t.good).bold());
+            }
+            process_dice_roll(dm, &expr.to_string(), &dice, expr.modifier, purpose, state).await
+        },
+        QuickCommand::Check(skill, mode) => {
+            let mode = with_environment(mode, state.environment.check_penalty(skill), &format!("{} checks", skill));
+            let (dice, kept) = roll_d20(roller, mode);
+            print_fancy_message(&format!("{} Check", skill), "yellow");
+            print_d20_roll(&dice, kept, mode);
+            let bonus = offer_bonus_die(state, roller, &format!("{} check", skill))?;
+            let roll = D20Roll { dice, kept, mode, bonus };
+            let total = roll.total(state.character.skill_modifier(skill));
+            state.record_roll(&d20_expr(state.character.skill_modifier(skill), mode), &roll.dice, total, &format!("{} check", skill));
+            println!("Total: {} ({})", total.to_string().color(t.good).bold(),
+                     format_modifier(state.character.skill_modifier(skill) + roll.bonus()));
+            if purpose.is_empty() {
+                return Ok(String::new());
+            }
+            roll_skill_check(dm, skill, &roll, purpose, None, state).await
+        },
+        QuickCommand::Save(ability, mode) => {
+            let (dice, kept) = roll_d20(roller, mode);
+            print_fancy_message(&format!("{} Saving Throw", ability.name()), "yellow");
+            print_d20_roll(&dice, kept, mode);
+            let bonus = offer_bonus_die(state, roller, &format!("{} saving throw", ability.name()))?;
+            let roll = D20Roll { dice, kept, mode, bonus };
+            let modifier = state.character.saving_throw_modifier(ability);
+            state.record_roll(&d20_expr(modifier, mode), &roll.dice, roll.total(modifier),
+                              &format!("{} saving throw", ability.name()));
+            println!("Total: {} ({})", roll.total(modifier).to_string().color(t.good).bold(),
+                     format_modifier(modifier + roll.bonus()));
+            if purpose.is_empty() {
+                return Ok(String::new());
+            }
+            roll_saving_throw(dm, ability, &roll, purpose, state).await
+        },
+    }
+}
+
+// Character creation functions
+fn create_character() -> Character {
+    let t = theme();
+    let mut character = Character::default();
+    let mut roller = ThreadRoller;
+    
+    print_fancy_message("CHARACTER CREATION", "cyan");
+    println!("{}", "Let's create your D&D character!".color(t.value));
+    
+    // Get character name
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("What is your character's name?")
+        .interact_text()
+        .unwrap_or_else(|_| "Adventurer".to_string());
+    character.name = name;
+    
+    let homebrew = homebrew_content();
+    
+    // Choose race
+    let mut races: Vec<&str> = BUILT_IN_RACES.to_vec();
+    races.extend(homebrew.races.iter().map(|r| r.name.as_str()));
+    
+    println!("\n{}", "Choose your race:".color(t.heading));
+    let race_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Race")
+        .default(0)
+        .items(&races)
+        .interact()
+        .unwrap_or(0);
+    
+    character.race = races[race_index].to_string();
+    
+    // Choose class
+    let mut classes: Vec<&str> = BUILT_IN_CLASSES.to_vec();
+    classes.extend(homebrew.classes.iter().map(|c| c.name.as_str()));
+    
+    println!("\n{}", "Choose your class:".color(t.heading));
+    let class_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Class")
+        .default(0)
+        .items(&classes)
+        .interact()
+        .unwrap_or(0);
+    
+    character.class = classes[class_index].to_string();
+    
+    // Choose background
+    let backgrounds = vec![
+        "Acolyte", "Charlatan", "Criminal", "Entertainer", "Folk Hero",
+        "Guild Artisan", "Hermit", "Noble", "Outlander", "Sage",
+        "Sailor", "Soldier", "Urchin"
+    ];
+    
+    println!("\n{}", "Choose your background:".color(t.heading));
+    let bg_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Background")
+        .default(0)
+        .items(&backgrounds)
+        .interact()
+        .unwrap_or(0);
+    
+    character.background = backgrounds[bg_index].to_string();
+    character.tool_proficiencies = starting_tool_proficiencies(&character.class, &character.background);
+    
+    let add_personality = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Add ideals, bonds, flaws, or a patron for the DM to build on?")
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if add_personality {
+        let ask = |prompt: &str| -> String {
+            Input::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .allow_empty(true)
+                .interact_text()
+                .map(|s: String| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        character.personality = Personality {
+            ideals: ask("Ideals (optional)"),
+            bonds: ask("Bonds (optional)"),
+            flaws: ask("Flaws (optional)"),
+            patron: ask("Patron or deity (optional)"),
+        };
+    }
+    
+    // Roll or assign ability scores
+    println!("\n{}", "How would you like to determine your ability scores?".color(t.heading));
+    let score_methods = vec!["Roll 4d6 (drop lowest)", "Standard Array", "Point Buy"];
+    let score_method = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Method")
+        .default(0)
+        .items(&score_methods)
+        .interact()
+        .unwrap_or(0);
+    
+    let mut scores = Vec::new();
+    
+    match score_method {
+        0 => {
+            // Roll 4d6 drop lowest
+            let roll_options = vec![
+                "Roll one array (rerolls allowed)".to_string(),
+                format!("Roll {} arrays and pick one", ABILITY_ARRAYS_TO_COMPARE),
+            ];
+            let multiple = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("How to roll")
+                .default(0)
+                .items(&roll_options)
+                .interact()
+                .unwrap_or(0) == 1;
+            if multiple {
+                scores = choose_rolled_array(&mut roller);
+            } else {
+                println!("\n{}", "Rolling ability scores (4d6 drop lowest)...".color(t.info));
+                let mut rerolls_left = MAX_ABILITY_REROLLS;
+                loop {
+                    scores.clear();
+                    for i in 0..6 {
+                        let (dice, score) = roll_ability_score(&mut roller);
+                        println!("Roll {}: [{}] = {}", i + 1,
+                                 format_ability_roll(&dice),
+                                 score.to_string().color(t.good).bold());
+                        scores.push(score);
+                    }
+                    
+                    if rerolls_left == 0 {
+                        println!("{}", "No rerolls left; these are your scores.".color(t.warning));
+                        break;
+                    }
+                    let keep_options = vec!["Keep".to_string(), format!("Reroll all ({} left)", rerolls_left)];
+                    let keep = Select::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("Scores: {}", scores.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ")))
+                        .default(0)
+                        .items(&keep_options)
+                        .interact()
+                        .unwrap_or(0);
+                    if keep == 0 {
+                        break;
+                    }
+                    rerolls_left -= 1;
+                    println!("\n{}", "Rerolling ability scores...".color(t.info));
+                }
+            }
+        },
+        1 => {
+            // Standard Array
+            println!("\n{}", "Using Standard Array: 15, 14, 13, 12, 10, 8".color(t.info));
+            scores = vec![15, 14, 13, 12, 10, 8];
+        },
+        _ => {
+            // Point Buy (simplified)
+            println!("\n{}", "Using Point Buy (27 points)".color(t.info));
+            scores = vec![13, 13, 13, 12, 12, 8];
+        }
+    }
+    
+    // Assign ability scores
+    let abilities = vec!["Strength", "Dexterity", "Constitution", "Intelligence", "Wisdom", "Charisma"];
+    let mut assigned_scores = HashMap::new();
+    
+    println!("\n{}", "Assign your ability scores:".color(t.heading));
+    for ability in &abilities {
+        let available_scores: Vec<String> = scores
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        
+        println!("\nAvailable scores: {}", available_scores.join(", "));
+        let score_index = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Choose a score for {}", ability))
+            .items(&available_scores)
+            .default(0)
+            .interact()
+            .unwrap_or(0);
+        
+        assigned_scores.insert(ability.to_string(), scores[score_index]);
+        scores.remove(score_index);
+    }
+    
+    character.strength = *assigned_scores.get("Strength").unwrap_or(&10);
+    character.dexterity = *assigned_scores.get("Dexterity").unwrap_or(&10);
+    character.constitution = *assigned_scores.get("Constitution").unwrap_or(&10);
+    character.intelligence = *assigned_scores.get("Intelligence").unwrap_or(&10);
+    character.wisdom = *assigned_scores.get("Wisdom").unwrap_or(&10);
+    character.charisma = *assigned_scores.get("Charisma").unwrap_or(&10);
+    
+    // Calculate hit points based on class and constitution
+    let con_modifier = ability_modifier(character.constitution);
+    let base_hp = hit_die(&character.class) as i32;
+    
+    character.hit_points = (base_hp + con_modifier).max(1) as u32;
+    character.max_hit_points = character.hit_points;
+    
+    // Choose skill proficiencies
+    println!("\n{}", "Choose skill proficiencies:".color(t.heading));
+    
+    // How many skills they can choose
+    let class_homebrew = homebrew_class(&character.class);
+    let num_skills = match character.class.as_str() {
+        _ if let Some(homebrew) = &class_homebrew => homebrew.skill_count,
+        "Rogue" => 4,
+        "Bard" | "Ranger" => 3,
+        _ => 2,
+    };
+    
+    println!("Your class ({}) lets you choose {} skill proficiencies:", 
+             character.class.color(t.label), num_skills.to_string().color(t.label));
+    
+    // Filter available skills based on class
+    let available_skills: Vec<&str> = match character.class.as_str() {
+        _ if let Some(homebrew) = &class_homebrew => homebrew.skill_choices.iter().map(String::as_str).collect(),
+        class => class_skill_list(class),
+    };
+    
+    // Safety check - ensure num_skills doesn't exceed available skills
+    let max_selectable = std::cmp::min(num_skills, available_skills.len());
+    
+    // Use a safer approach with MultiSelect
+    let skill_selections = if !available_skills.is_empty() {
+        // Create a string explaining selection
+        let prompt = format!("Select {} skills (space to select, enter to confirm)", max_selectable);
+        
+        // Store the theme in a variable to extend its lifetime
+        let theme = ColorfulTheme::default();
+        
+        // Use MultiSelect directly
+        MultiSelect::with_theme(&theme)
+            .with_prompt(prompt)
+            .items(&available_skills)
+            .interact()
+            .unwrap_or_else(|_| vec![])
+    } else {
+        vec![]
+    };
+    
+    // Apply the selections safely
+    for &index in &skill_selections {
+        if index < available_skills.len() {
+            let skill = available_skills[index];
+            character.skills.insert(skill.to_string(), Proficiency::Proficient);
+        }
+    }
+    choose_expertise(&mut character);
+    
+    // Starting above level 1 runs the level-up steps for each level in between
+    let starting_level: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Starting level (1-20)")
+        .default(1)
+        .validate_with(|level: &u32| -> Result<(), &str> {
+            if (1..=20).contains(level) { Ok(()) } else { Err("Choose a level from 1 to 20") }
+        })
+        .interact_text()
+        .unwrap_or(1);
+    if starting_level > 1 && let Err(e) = start_at_level(&mut character, starting_level, &mut roller) {
+        print_fancy_message(&format!("Error leveling up: {}", e), "red");
+    }
+    
+    // Starting equipment based on class
+    let (equipment, armor, purse) = equipment_for(&character.class);
+    character.inventory.extend(equipment);
+    if let Some(armor) = armor {
+        character.inventory.push(Item::new(armor.name));
+    }
+    character.gold = purse.gp;
+    
+    add_common_gear(&mut character);
+    
+    // Armor class from the starting armor, shield, and DEX
+    character.armor_class = character.compute_armor_class();
+    
+    print_fancy_message("Character Created Successfully!", "green");
+    print_character_sheet(&character);
+    
+    character
+}
+
+// Coin a character sets out with; only gold is tracked so far
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Purse {
+    gp: u32,
+}
+
+// Standard starting kit for a class: the gear, the armor worn (if any), and the purse.
+// Shields stay with the gear; the armor is added to the inventory so AC follows from it
+fn equipment_for(class: &str) -> (Vec<Item>, Option<&'static Armor>, Purse) {
+    if let Some(homebrew) = homebrew_class(class) {
+        let armor = homebrew.starting_equipment.iter().find_map(|item| find_armor(item));
+        let items = homebrew
+            .starting_equipment
+            .iter()
+            .filter(|item| armor.is_none() || find_armor(item).is_none())
+            .map(|item| Item::new(item))
+            .collect();
+        return (items, armor, Purse { gp: homebrew.starting_gold });
+    }
+    let (items, armor, gold): (&[&str], Option<&str>, u32) = match class {
+        "Artificer" => (&["Light crossbow with 20 bolts", "Dagger (2)", "Thieves' tools", "Dungeoneer's pack"], Some("Studded leather"), 15),
+        "Barbarian" => (&["Greataxe", "Handaxe (2)", "Javelin (4)", "Explorer's pack"], None, 10),
+        "Bard" => (&["Rapier", "Dagger", "Lute", "Diplomat's pack"], Some("Leather armor"), 15),
+        "Cleric" => (&["Mace", "Shield", "Light crossbow with 20 bolts", "Holy symbol", "Priest's pack"], Some("Scale mail"), 15),
+        "Druid" => (&["Scimitar", "Wooden shield", "Druidic focus", "Explorer's pack"], Some("Leather armor"), 15),
+        "Fighter" => (&["Longsword", "Shield", "Dungeoneer's pack"], Some("Chain mail"), 10),
+        "Monk" => (&["Shortsword", "Dart (10)", "Dungeoneer's pack"], None, 5),
+        "Paladin" => (&["Longsword", "Shield", "Javelin (5)", "Holy symbol", "Priest's pack"], Some("Chain mail"), 10),
+        "Ranger" => (&["Shortsword (2)", "Longbow with 20 arrows", "Dungeoneer's pack"], Some("Scale mail"), 10),
+        "Rogue" => (&["Shortsword", "Shortbow with 20 arrows", "Dagger (2)", "Thieves' tools", "Burglar's pack"], Some("Leather armor"), 30),
+        "Sorcerer" => (&["Light crossbow with 20 bolts", "Dagger (2)", "Component pouch", "Dungeoneer's pack"], None, 15),
+        "Warlock" => (&["Light crossbow with 20 bolts", "Dagger (2)", "Component pouch", "Scholar's pack"], Some("Leather armor"), 15),
+        "Wizard" => (&["Spellbook", "Quarterstaff", "Component pouch", "Scholar's pack"], None, 25),
+        _ => (&["Adventurer's pack", "Simple weapon"], None, 20),
+    };
+    (items.iter().map(|item| Item::new(item)).collect(), armor.and_then(find_armor), Purse { gp: gold })
+}
+
+// Gear every new character starts with, whatever their class
+fn add_common_gear(character: &mut Character) {
+    character.inventory.push(Item::new("Backpack"));
+    character.inventory.push(Item::new("Bedroll"));
+    character.inventory.push(Item::new("Rations (5 days)"));
+    character.inventory.push(Item::new("Waterskin"));
+    character.inventory.push(Item::new("Torch (3)"));
+}
+
+// Ready-to-play level 1 characters for players who want to skip creation
+struct Pregen {
+    title: &'static str,
+    name: &'static str,
+    race: &'static str,
+    class: &'static str,
+    background: &'static str,
+    // STR, DEX, CON, INT, WIS, CHA, racial bonuses included
+    scores: [u32; 6],
+    skills: &'static [&'static str],
+    // Skills from the list above that start with Expertise
+    expertise: &'static [&'static str],
+    equipment: &'static [&'static str],
+    gold: u32,
+}
+
+const PREGENS: &[Pregen] = &[
+    Pregen {
+        title: "Valiant Fighter",
+        name: "Bram Ironhand",
+        race: "Human",
+        class: "Fighter",
+        background: "Soldier",
+        scores: [16, 14, 15, 9, 13, 11],
+        skills: &["Athletics", "Perception"],
+        expertise: &[],
+        equipment: &["Longsword", "Shield", "Chain mail", "Dungeoneer's pack"],
+        gold: 10,
+    },
+    Pregen {
+        title: "Sneaky Rogue",
+        name: "Nim Quickfingers",
+        race: "Halfling",
+        class: "Rogue",
+        background: "Criminal",
+        scores: [8, 17, 14, 12, 13, 10],
+        skills: &["Acrobatics", "Perception", "Sleight of Hand", "Stealth"],
+        expertise: &["Sleight of Hand", "Stealth"],
+        equipment: &["Shortsword", "Shortbow with 20 arrows", "Leather armor", "Thieves' tools"],
+        gold: 30,
+    },
+    Pregen {
+        title: "Wise Cleric",
+        name: "Sister Maren",
+        race: "Dwarf",
+        class: "Cleric",
+        background: "Acolyte",
+        scores: [13, 10, 16, 8, 16, 12],
+        skills: &["Medicine", "Religion"],
+        expertise: &[],
+        equipment: &["Mace", "Scale mail", "Shield", "Holy symbol"],
+        gold: 15,
+    },
+    Pregen {
+        title: "Clever Wizard",
+        name: "Elowen Vale",
+        race: "Elf",
+        class: "Wizard",
+        background: "Sage",
+        scores: [8, 16, 13, 16, 12, 10],
+        skills: &["Arcana", "History"],
+        expertise: &[],
+        equipment: &["Spellbook", "Quarterstaff", "Component pouch", "Scholar's pack"],
+        gold: 25,
+    },
+];
+
+impl Pregen {
+    fn build(&self, name: &str) -> Character {
+        let mut character = Character {
+            name: name.to_string(),
+            race: self.race.to_string(),
+            class: self.class.to_string(),
+            background: self.background.to_string(),
+            strength: self.scores[0],
+            dexterity: self.scores[1],
+            constitution: self.scores[2],
+            intelligence: self.scores[3],
+            wisdom: self.scores[4],
+            charisma: self.scores[5],
+            gold: self.gold,
+            ..Default::default()
+        };
+        character.tool_proficiencies = starting_tool_proficiencies(self.class, self.background);
+        character.hit_points = (hit_die(self.class) as i32 + ability_modifier(character.constitution)).max(1) as u32;
+        character.max_hit_points = character.hit_points;
+        for skill in self.skills {
+            character.skills.insert(skill.to_string(), Proficiency::Proficient);
+        }
+        for skill in self.expertise {
+            character.skills.insert(skill.to_string(), Proficiency::Expertise);
+        }
+        for item in self.equipment {
+            character.inventory.push(Item::new(item));
+        }
+        add_common_gear(&mut character);
+        character.armor_class = character.compute_armor_class();
+        character
+    }
+}
+
+// Offers the pregenerated characters; None means the player wants to build their own
+fn choose_pregenerated_character() -> Option<Character> {
+    let mut options = vec!["Create your own character".to_string()];
+    options.extend(PREGENS.iter().map(|p| format!("{} - {} {} ({})", p.title, p.race, p.class, p.background)));
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Who will you play?")
+        .default(0)
+        .items(&options)
+        .interact()
+        .unwrap_or(0);
+    let pregen = PREGENS.get(choice.checked_sub(1)?)?;
+    
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Character name")
+        .default(pregen.name.to_string())
+        .interact_text()
+        .unwrap_or_else(|_| pregen.name.to_string());
+    let character = pregen.build(name.trim());
+    
+    print_fancy_message(&format!("You are {}, the {}", character.name, pregen.title), "green");
+    print_character_sheet(&character);
+    Some(character)
+}
+
+const RECAP_PREFIX: &str = "[RECAP]";
+
+// Asks the DM for an in-character "last time on our adventure" paragraph, kept in history as its own turn
+async fn recap_adventure(dm: &impl Chat, state: &mut GameState) -> Result<String, Box<dyn Error>> {
+    let prompt = format!(
+        "{} We are resuming the campaign after a break.
+        
+        In character as the narrator, give a single short paragraph beginning \"Last time on our adventure...\" that recaps
+        the most important events so far and where {} stands now ({}, quest: {}).
+        Do not advance the story or describe any new events; end by handing control back to the player.",
+        RECAP_PREFIX, state.character.name, state.current_location, state.current_quest
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to recap the adventure",
+        "The Dungeon Master recalls your tale...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+// Shows where the player left off, then hands over to the gameplay loop
+async fn resume_adventure(dm: &DungeonMaster, state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    print_fancy_message(&format!("Continuing your adventure in {}...", state.campaign), "blue");
+    println!("Location: {} | Quest: {}", 
+             state.current_location.color(t.value),
+             state.current_quest.color(t.value));
+    
+    let options = ["Hear a recap from the Dungeon Master", "Show the last message"];
+    let choice = if state.history.is_empty() {
+        1
+    } else {
+        Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("How would you like to pick up the story?")
+            .default(0)
+            .items(&options)
+            .interact()?
+    };
+    
+    if options[choice] == "Hear a recap from the Dungeon Master" {
+        let recap = recap_adventure(dm, state).await?;
+        print_fancy_message("Last time on our adventure...", "cyan");
+        print_dm_text(&recap);
+        if Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Copy the recap to the clipboard?")
+            .default(false)
+            .interact()?
+        {
+            let slug = slugify(&state.character.name);
+            let fallback = format!("{}.recap.txt", if slug.is_empty() { "character" } else { &slug });
+            if let Err(e) = copy_to_clipboard(&plain_dm_text(&recap), &fallback) {
+                print_fancy_message(&format!("Error copying: {}", e), "red");
+            }
+        }
+    } else if let Some(Message::Assistant { content }) = state.history.last() {
+        // Extract the last AI message to show to the player
+        print_fancy_message("Previously in your adventure:", "cyan");
+        // Extract and display just the text content from OneOrMany
+        let text = extract_text_from_message(content);
+        print_dm_text(&text);
+    }
+    
+    run_adventure(dm, state).await
+}
+
+// Adventure gameplay loop shared by new and continued campaigns
+// Rolls a chosen skill check from the menu and hands the result to the DM
+async fn skill_check_action(
+    dm: &impl Chat,
+    skill: &str,
+    state: &mut GameState,
+    roller: &mut impl Roller,
+    purpose: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let t = theme();
+    // Roll the d20 (two with advantage or disadvantage)
+    let mode = with_environment(choose_roll_mode()?, state.environment.check_penalty(skill), &format!("{} checks", skill));
+    let (d20_results, roll_result) = roll_d20(roller, mode);
+    
+    // Print the roll
+    print_fancy_message(&format!("{} Check", skill), "yellow");
+    print_d20_roll(&d20_results, roll_result, mode);
+    let bonus = offer_bonus_die(state, roller, &format!("{} check", skill))?;
+    let roll = D20Roll { dice: d20_results, kept: roll_result, mode, bonus };
+    
+    // Get ability modifier
+    let ability_mod = skill_ability(skill)
+        .map(|ability| state.character.effective_modifier(ability))
+        .unwrap_or(0);
+    
+    // Calculate proficiency bonus
+    let prof_bonus = state.character.skill_proficiency_bonus(skill);
+    
+    // Calculate total
+    let total = roll.total(state.character.skill_modifier(skill));
+    
+    println!("Ability modifier: {}", ability_mod);
+    if state.character.has_expertise_in(skill) {
+        println!("Proficiency bonus: +{} (expertise)", prof_bonus);
+    } else if prof_bonus > 0 {
+        println!("Proficiency bonus: +{}", prof_bonus);
+    }
+    if let Some(bonus) = &roll.bonus {
+        println!("{}: {}", bonus.source, format_modifier(bonus.total));
+    }
+    println!("Total: {}", total.to_string().color(t.good).bold());
+    
+    let dc = ask_dc(skill, roll_result, total)?;
+    
+    // Ask the player what they're rolling for, unless the check came from a described action
+    let purpose: String = match purpose {
+        Some(purpose) => purpose.to_string(),
+        None => Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("What are you trying to do with this check?")
+            .interact_text()?,
+    };
+    state.record_roll(&d20_expr(state.character.skill_modifier(skill), mode), &roll.dice, total,
+                      &format!("{} check: {}", skill, purpose));
+    
+    roll_skill_check(dm, skill, &roll, &purpose, dc, state).await
+}
+
+// 5e group check: everyone in the party rolls the same skill and the group succeeds if at least half meet the DC
+async fn group_check_action(
+    dm: &impl Chat,
+    state: &mut GameState,
+    roller: &mut impl Roller,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let t = theme();
+    if state.character.companions.is_empty() {
+        print_fancy_message("A group check needs companions in the party; use Roll a skill check instead.", "yellow");
+        return Ok(None);
+    }
+    
+    let skills: Vec<&str> = SKILLS.iter().map(|(name, _)| *name).collect();
+    let skill_index = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Skill the whole party rolls")
+        .default(0)
+        .items(&skills)
+        .interact()?;
+    let skill = skills[skill_index];
+    let dc: u32 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} DC", skill))
+        .default(12)
+        .interact_text()?;
+    let mode = with_environment(choose_roll_mode()?, state.environment.check_penalty(skill), &format!("{} checks", skill));
+    let purpose: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("What is the party trying to do?")
+        .interact_text()?;
+    
+    // Companions only track DEX, so other skills ask for their modifier
+    let mut members = vec![(state.character.name.clone(), state.character.skill_modifier(skill))];
+    for companion in &state.character.companions {
+        let modifier = if skill_ability(skill) == Some(Ability::Dexterity) {
+            ability_modifier(companion.dexterity)
+        } else {
+            Input::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("{}'s {} modifier", companion.name, skill))
+                .default(0)
+                .interact_text()?
+        };
+        members.push((companion.name.clone(), modifier));
+    }
+    
+    print_fancy_message(&format!("Group {} Check (DC {})", skill, dc), "yellow");
+    let mut results = Vec::new();
+    let mut successes = 0;
+    for (index, (name, modifier)) in members.iter().enumerate() {
+        let (dice, kept) = roll_d20(roller, mode);
+        // Only the player's own roll can take their bonus die
+        let bonus = if index == 0 { offer_bonus_die(state, roller, &format!("{} check", skill))? } else { None };
+        let roll = D20Roll { dice, kept, mode, bonus };
+        let check = ResolvedCheck { skill: skill.to_string(), dc, roll: kept, total: roll.total(*modifier) };
+        let verdict = check.verdict();
+        if check.succeeded() {
+            successes += 1;
+            println!("{}: {} {} → {}", name.color(t.value), roll.describe(), format_modifier(*modifier), verdict.color(t.good));
+        } else {
+            println!("{}: {} {} → {}", name.color(t.value), roll.describe(), format_modifier(*modifier), verdict.color(t.bad));
+        }
+        state.record_roll(&d20_expr(*modifier, mode), &roll.dice, check.total, &format!("{} group {} check: {}", name, skill, purpose));
+        results.push(format!("{}: {}", name, verdict));
+    }
+    let group_success = successes * 2 >= members.len();
+    let outcome = format!("{} of {} succeeded — the group {}", successes, members.len(),
+                          if group_success { "SUCCEEDS" } else { "FAILS" });
+    if group_success {
+        println!("{}", outcome.color(t.good).bold());
+    } else {
+        println!("{}", outcome.color(t.bad).bold());
+    }
+    
+    let prompt = format!(
+        "The party makes a group {} check (DC {}) for the following purpose: \"{}\"
+        Individual results:
+        {}
+        A group check succeeds when at least half the party succeeds: {}.
+        The rules have already resolved this check. Narrate an outcome consistent with the group result, showing how the stronger members covered for the weaker ones (or failed to), and do not call for another roll.
+        
+        Continue the scene after describing the result of this check.",
+        skill,
+        dc,
+        purpose,
+        results.join("\n        "),
+        outcome
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to process group check",
+        "The Dungeon Master is resolving the group check...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(Some(response))
+}
+
+// Which class a multiclass caster casts through; None when the character has no casting class
+fn choose_casting_class(character: &Character) -> Result<Option<String>, Box<dyn Error>> {
+    let classes = character.casting_classes();
+    if classes.len() < 2 {
+        return Ok(classes.first().map(|c| c.to_string()));
+    }
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Cast as which class?")
+        .default(0)
+        .items(&classes)
+        .interact()?;
+    Ok(Some(classes[choice].to_string()))
+}
+
+// A known DC settles a check here instead of leaving it to the DM; prints the verdict when set
+fn ask_dc(skill: &str, roll: u32, total: i32) -> Result<Option<u32>, Box<dyn Error>> {
+    let t = theme();
+    let dc: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Target DC (leave empty to let the DM decide)")
+        .allow_empty(true)
+        .validate_with(|input: &String| -> Result<(), &str> {
+            if input.trim().is_empty() || input.trim().parse::<u32>().is_ok() { Ok(()) } else { Err("Enter a number like 15") }
+        })
+        .interact_text()?;
+    let dc = dc.trim().parse::<u32>().ok();
+    if let Some(dc) = dc {
+        let check = ResolvedCheck { skill: skill.to_string(), dc, roll, total };
+        if check.succeeded() {
+            println!("{}", check.verdict().color(t.good).bold());
+        } else {
+            println!("{}", check.verdict().color(t.bad).bold());
+        }
+    }
+    Ok(dc)
+}
+
+// d20 + ability + proficiency (when proficient) with a tool or instrument, narrated by the DM
+async fn tool_check_action(
+    dm: &impl Chat,
+    state: &mut GameState,
+    roller: &mut impl Roller,
+) -> Result<String, Box<dyn Error>> {
+    let t = theme();
+    let mut options: Vec<String> = state.character.tool_proficiencies.iter().cloned().collect();
+    options.push("Another tool or instrument...".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Which tool?")
+        .default(0)
+        .items(&options)
+        .interact()?;
+    let tool = if choice < state.character.tool_proficiencies.len() {
+        options[choice].clone()
+    } else {
+        let tool: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Tool or instrument")
+            .interact_text()?;
+        tool.trim().to_string()
+    };
+    
+    let abilities: Vec<&str> = Ability::ALL.iter().map(|a| a.name()).collect();
+    let default_ability = Ability::ALL.iter().position(|&a| a == tool_ability(&tool)).unwrap_or(0);
+    let ability = Ability::ALL[Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Ability")
+        .default(default_ability)
+        .items(&abilities)
+        .interact()?];
+    
+    let mode = with_environment(choose_roll_mode()?, state.environment.tool_check_penalty(), "tool checks");
+    let (dice, roll) = roll_d20(roller, mode);
+    let is_proficient = state.character.is_proficient_with_tool(&tool);
+    let prof_bonus = if is_proficient { proficiency_bonus(state.character.level) } else { 0 };
+    let ability_mod = state.character.effective_modifier(ability);
+    
+    print_fancy_message(&format!("{} Check ({})", tool, ability.abbreviation()), "yellow");
+    print_d20_roll(&dice, roll, mode);
+    let bonus = offer_bonus_die(state, roller, &format!("{} check", tool))?;
+    let d20 = D20Roll { dice, kept: roll, mode, bonus };
+    let total = d20.total(ability_mod + prof_bonus);
+    println!("Ability modifier: {}", ability_mod);
+    if is_proficient {
+        println!("Proficiency bonus: +{}", prof_bonus);
+    }
+    if let Some(bonus) = &d20.bonus {
+        println!("{}: {}", bonus.source, format_modifier(bonus.total));
+    }
+    println!("Total: {}", total.to_string().color(t.good).bold());
+    let mut parts = vec![(format!("for your {}", ability.abbreviation()), ability_mod)];
+    if is_proficient {
+        parts.push((format!("proficiency (you're proficient with {})", tool), prof_bonus));
+    }
+    print_roll_explanation(&d20, &format!("{} check", tool), &parts, total);
+    
+    let dc = ask_dc(&tool, roll, total)?;
+    let purpose: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("What are you doing with the {}?", tool))
+        .interact_text()?;
+    state.record_roll(&d20_expr(ability_mod + prof_bonus, mode), &d20.dice, total, &format!("{} check: {}", tool, purpose));
+    
+    let adjudication = match dc {
+        Some(dc) => format!(
+            "The DC was set at the table and the rules have already resolved this check: {}. Narrate an outcome consistent with that result and do not call for another roll.",
+            ResolvedCheck { skill: tool.clone(), dc, roll, total }.verdict().to_uppercase()
+        ),
+        None => "Decide a fitting DC, say whether the check succeeds, and describe the outcome.".to_string(),
+    };
+    let prompt = format!(
+        "The player ({} the {} {}) makes a {} check using {}: \"{}\"
+        Dice roll: {}
+        Ability modifier: {} ({})
+        Tool proficiency: {}
+        Total: {}
+        
+        {}
+        Continue the scene after describing the result.",
+        state.character.name,
+        state.character.race,
+        state.character.class,
+        ability.name(),
+        tool,
+        purpose.trim(),
+        d20.describe(),
+        format_modifier(ability_mod),
+        ability.abbreviation(),
+        if is_proficient { format!("Yes (+{})", prof_bonus) } else { "No".to_string() },
+        total,
+        adjudication
+    );
+    
+    let response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to process tool check",
+        "The Dungeon Master is resolving your check...",
+    )
+    .await?;
+    
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&response));
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    
+    Ok(response)
+}
+
+// Casts a damage spell outside combat and lets the DM decide who it hits
+async fn cast_spell_action(
+    dm: &impl Chat,
+    state: &mut GameState,
+    roller: &mut impl Roller,
+    favorite: Option<&DamageSpell>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(prompt) = combat::resolve_damage_spell(state, None, roller, favorite)? else {
+        return Ok(None);
+    };
+    let dm_response = dm_chat(
+        dm,
+        &prompt,
+        state.history.clone(),
+        "Failed to resolve spell",
+        "The Dungeon Master is resolving your spell...",
+    )
+    .await?;
+    state.history.push(Message::user(&prompt));
+    state.history.push(Message::assistant(&dm_response));
+    state.last_saved = Local::now().to_rfc3339();
+    save_game(state)?;
+    Ok(Some(dm_response))
+}
+
+async fn run_adventure(dm: &DungeonMaster, state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let result = adventure_loop(dm, state).await;
+    // However the session ended, debounced changes still reach the disk
+    flush_pending_save(state)?;
+    result
+}
+
+// Real time since the adventure loop started, for the optional break reminders
+struct SessionTimer {
+    started: Instant,
+    reminders: u64,
+}
+
+impl SessionTimer {
+    fn start() -> Self {
+        SessionTimer { started: Instant::now(), reminders: 0 }
+    }
+    
+    // Once per configured interval, a gentle suggestion to save or step away; never blocks the menu
+    fn check(&mut self) {
+        let settings = config();
+        if !settings.break_reminders || settings.break_reminder_minutes == 0 {
+            return;
+        }
+        let minutes = self.started.elapsed().as_secs() / 60;
+        let due = minutes / settings.break_reminder_minutes;
+        if due > self.reminders {
+            self.reminders = due;
+            let played = match (minutes / 60, minutes % 60) {
+                (0, m) => format!("{} minutes", m),
+                (h, 0) => format!("{} hour{}", h, if h == 1 { "" } else { "s" }),
+                (h, m) => format!("{} hour{} {} minutes", h, if h == 1 { "" } else { "s" }, m),
+            };
+            print_fancy_message(&format!("You've been playing for {}. A good moment to save and stretch your legs.", played), "cyan");
+        }
+    }
+}
+
+// Menu entries still open while the character is dead
+const DEAD_ACTIONS: [&str; 13] = [
+    "Show character sheet",
+    "Roll history",
+    "Director's notes",
+    "Canon facts",
+    "Timeline",
+    "Switch DM model",
+    "Rewind to turn...",
+    "Export adventure",
+    "Copy to clipboard",
+    "Save game",
+    "Quick load",
+    "Retire character",
+    "Return to main menu",
+];
+
+async fn adventure_loop(dm: &DungeonMaster, state: &mut GameState) -> Result<(), Box<dyn Error>> {
+    let mut roller = ThreadRoller;
+    let mut timer = SessionTimer::start();
+    open_session_marker(state);
+    loop {
+        let t = theme();
+        // Coalesced saves from the last action are written while the player reads the menu
+        flush_pending_save(state)?;
+        timer.check();
+        println!("\n{}", "-".repeat(60).color(t.border));
+        println!("{}: {} | {}: {}", 
+                 "Location".color(t.label), state.current_location.color(t.value),
+                 "Quest".color(t.label), state.current_quest.color(t.value));
+        let dead = state.character.is_dead();
+        println!("{}: {}/{} HP{} | {}: {} AC", 
+                 state.character.name.color(t.heading),
+                 state.character.hit_points.to_string().color(t.value),
+                 state.character.effective_max_hp().to_string().color(t.value),
+                 match state.character.status {
+                     LifeStatus::Dead => " ☠ DEAD".color(t.bad).bold(),
+                     LifeStatus::Stable => " (stable)".color(t.warning),
+                     LifeStatus::Alive => "".normal(),
+                 },
+                 "AC".color(t.label),
+                 state.character.armor_class.to_string().color(t.value));
+        println!("{}: {} | {}: {}", "Scene".color(t.label), state.scene_type.name().color(t.value),
+                 "Time".color(t.label), state.clock.to_string().color(t.value));
+        if state.environment != Environment::default() {
+            println!("{}: {}", "Environment".color(t.label), state.environment.to_string().color(t.value));
+        }
+        if !state.character.active_effects.is_empty() {
+            let effects: Vec<String> = state.character.active_effects.iter().map(|e| e.to_string()).collect();
+            println!("{}: {}", "Effects".color(t.label), effects.join(", ").color(t.accent));
+        }
+        if !state.character.pending_bonus_dice.is_empty() {
+            let bonuses: Vec<String> = state.character.pending_bonus_dice.iter().map(|(source, expr)| format!("{} (+{})", source, expr)).collect();
+            println!("{}: {}", "Bonus dice".color(t.label), bonuses.join(", ").color(t.accent));
+        }
+        if let Some(name) = state.spotlight_name() {
+            println!("{}: {}", "Spotlight".color(t.label), name.color(t.heading));
+        }
+        if let Some(encounter) = &state.encounter {
+            println!("{} {} {} (round {})", "⚔".color(t.bad), "In combat with".color(t.bad).bold(),
+                     encounter.living_enemies().len().to_string().color(t.value), encounter.round.max(1));
+        }
+        println!("{}", "-".repeat(60).color(t.border));
+        
+        // Show player options
+        println!("\n{}", "What would you like to do?".color(t.info));
+        let combat_action = if state.encounter.is_some() { "Resume combat" } else { "Start combat" };
+        // Pinned favorites come first for one-tap use
+        let favorite_labels: Vec<String> = if dead {
+            Vec::new()
+        } else {
+            state.character.favorites.iter().map(|f| format!("★ {}", f)).collect()
+        };
+        let mut actions: Vec<&str> = favorite_labels.iter().map(String::as_str).collect();
+        actions.extend([
+            "Take an action", 
+            "Quick roll (r 1d20+5 / c stealth / s dex)",
+            "Roll a skill check", 
+            "Group check",
+            "Roll a tool check",
+            "Roll a dice", 
+            "Roll history",
+            "Roll macro",
+            "Define macro",
+            combat_action,
+            "Show character sheet",
+            "HP history / adjust HP",
+            "View skills",
+            "Compare characters",
+            "Combat reference",
+            "Cast damage spell",
+            "Ability effects",
+            "Bonus dice",
+            "Spell effects / concentration",
+            "Attune / end attunement",
+            "Move to stash",
+            "Take from stash",
+            "Companions",
+            "Spotlight rotation",
+            "Craft",
+            "Learn a spell",
+            "Look up spell",
+            "Level up",
+            "Travel",
+            "Show map",
+            "Take a rest",
+            "Set scene type",
+            "Set environment",
+            "Narration length",
+            "Director's notes",
+            "Character notes",
+            "Canon facts",
+            "Timeline",
+            "Switch DM model",
+            "Rewind to turn...",
+            "Export adventure",
+            "Export character (VTT)",
+            "Copy to clipboard",
+            "Save game",
+            "Quick save",
+            "Quick load",
+            "Retire character",
+            "Return to main menu"
+        ]);
+        // The dead can only look back over the adventure, seek a way back, or leave it
+        if dead {
+            actions.retain(|a| DEAD_ACTIONS.contains(a));
+            actions.insert(0, "Seek resurrection");
+        }
+        // Only wizards prepare from a spellbook and cast its rituals
+        if !dead
+            && state.character.keeps_spellbook()
+            && let Some(index) = actions.iter().position(|a| *a == "Learn a spell")
+        {
+            actions.splice(index + 1..index + 1, ["Prepare from spellbook", "Ritual cast"]);
+        }
+        
+        let action_choice = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Choose an action")
+            .default(0)
+            .items(&actions)
+            .interact()?;
+        
+        if !dead && let Some(favorite) = state.character.favorites.get(action_choice).cloned() {
+            let dm_response = match favorite {
+                FavoriteAction::SkillCheck(skill) => Some(skill_check_action(dm, &skill, state, &mut roller, None).await?),
+                FavoriteAction::Spell(spell) => cast_spell_action(dm, state, &mut roller, Some(&spell)).await?,
+                FavoriteAction::WeaponAttack(weapon) => {
+                    print_fancy_message(&format!("{} is used from the combat menu; start combat to attack with it.", weapon), "yellow");
+                    None
+                }
+            };
+            if let Some(dm_response) = dm_response {
+                print_fancy_message("Dungeon Master:", "cyan");
+                print_dm_text(&dm_response);
+            }
+            continue;
+        }
+        
+        match actions[action_choice] {
+            "Take an action" => {
+                let player_action: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What would you like to do? (describe your action)")
+                    .interact_text()?;
+                
+                let settings = config();
+                if settings.confirm_risky_actions
+                    && let Some(reason) = risky_action_reason(&player_action, &state.character, &settings.risky_phrases)
+                    && !Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("This looks risky ({}). Are you sure?", reason))
+                        .default(false)
+                        .interact()?
+                {
+                    continue;
+                }
+                
+                let mut check = None;
+                if config().auto_dc
+                    && let Some((skill, dc)) = suggest_check(dm, &player_action, state).await?
+                    && Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("This looks like a DC {} {} check — roll?", dc, skill))
+                        .default(true)
+                        .interact()?
+                {
+                    let mode = with_environment(RollMode::Normal, state.environment.check_penalty(skill), &format!("{} checks", skill));
+                    let (dice, roll) = roll_d20(&mut roller, mode);
+                    let total = roll as i32 + state.character.skill_modifier(skill);
+                    state.record_roll(&d20_expr(state.character.skill_modifier(skill), mode), &dice, total,
+                                      &format!("{} check (DC {})", skill, dc));
+                    print_fancy_message(&format!("{} Check (DC {})", skill, dc), "yellow");
+                    print_d20_roll(&dice, roll, mode);
+                    let resolved = ResolvedCheck { skill: skill.to_string(), dc, roll, total };
+                    println!("Total: {} — {}", total.to_string().color(t.value).bold(),
+                             if resolved.succeeded() { "SUCCESS".color(t.good).bold() } else { "FAILURE".color(t.bad).bold() });
+                    let d20 = D20Roll { dice, kept: roll, mode, bonus: None };
+                    print_roll_explanation(&d20, &format!("{} check", skill), &state.character.skill_modifier_parts(skill), total);
+                    check = Some(resolved);
+                } else if !config().auto_dc
+                    && config().suggest_skills
+                    && let Some(skill) = suggest_skill(dm, &player_action, state).await?
+                    && Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("Sounds like {} ({}) — roll it?", skill, format_modifier(state.character.skill_modifier(skill))))
+                        .default(true)
+                        .interact()?
+                {
+                    let dm_response = skill_check_action(dm, skill, state, &mut roller, Some(&player_action)).await?;
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                    continue;
+                }
+                
+                let dm_response = process_player_action(dm, &player_action, check.as_ref(), state).await?;
+                print_fancy_message("Dungeon Master:", "cyan");
+                print_dm_text(&dm_response);
+            },
+            "Quick roll (r 1d20+5 / c stealth / s dex)" => {
+                let input: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Roll")
+                    .validate_with(|input: &String| -> Result<(), String> {
+                        parse_quick_command(input).map(|_| ())
+                    })
+                    .interact_text()?;
+                let (command, purpose) = parse_quick_command(&input)?;
+                let dm_response = run_quick_command(dm, command, &purpose, state, &mut roller).await?;
+                if !dm_response.is_empty() {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Roll a skill check" => {
+                let skills: Vec<&str> = SKILLS.iter().map(|(name, _)| *name).collect();
+                let mut options = skills.clone();
+                options.push("Pin / unpin a skill in favorites...");
+                
+                let skill_index = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Choose a skill to check")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                if skill_index == skills.len() {
+                    let index = Select::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Skill to pin or unpin")
+                        .default(0)
+                        .items(&skills)
+                        .interact()?;
+                    let pinned = state.character.toggle_favorite(FavoriteAction::SkillCheck(skills[index].to_string()));
+                    print_fancy_message(&format!("{} {} favorites", skills[index], if pinned { "pinned to" } else { "removed from" }), "green");
+                    save_game(state)?;
+                    continue;
+                }
+                
+                let dm_response = skill_check_action(dm, skills[skill_index], state, &mut roller, None).await?;
+                print_fancy_message("Dungeon Master:", "cyan");
+                print_dm_text(&dm_response);
+            },
+            "Group check" => {
+                if let Some(dm_response) = group_check_action(dm, state, &mut roller).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Roll a tool check" => {
+                let dm_response = tool_check_action(dm, state, &mut roller).await?;
+                print_fancy_message("Dungeon Master:", "cyan");
+                print_dm_text(&dm_response);
+            },
+            "Roll a dice" => {
+                let dice_types = vec!["d4", "d6", "d8", "d10", "d12", "d20", "d100"];
+                let dice_type_index = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Choose a dice type")
+                    .default(5) // d20 is default
+                    .items(&dice_types)
+                    .interact()?;
+                
+                let sides = match dice_types[dice_type_index] {
+                    "d4" => 4,
+                    "d6" => 6,
+                    "d8" => 8,
+                    "d10" => 10,
+                    "d12" => 12,
+                    "d20" => 20,
+                    "d100" => 100,
+                    _ => 6,
+                };
+                
+                let num_dice: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("How many dice?")
+                    .default("1".to_string())
+                    .interact_text()?;
+                
+                let num_dice = num_dice.parse::<u32>().unwrap_or(1);
+                
+                // Ask what the roll is for
+                let purpose: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What are you rolling for? (optional)")
+                    .allow_empty(true)
+                    .interact_text()?;
+                
+                let results = roll_dice(&mut roller, num_dice, sides);
+                state.record_roll(&format!("{}d{}", num_dice, sides), &results, results.iter().sum::<u32>() as i32,
+                                  if purpose.is_empty() { "Dice roll" } else { &purpose });
+                
+                if purpose.is_empty() {
+                    print_fancy_message("Dice Roll", "yellow");
+                } else {
+                    print_fancy_message(&format!("Dice Roll: {}", purpose), "yellow");
+                }
+                print_dice_roll(&format!("{}d{}", num_dice, sides), &results);
+                
+                // Let the DM narrate the roll result if a purpose was provided
+                if !purpose.is_empty() {
+                    let dm_response = process_dice_roll(
+                        dm, 
+                        &format!("{}d{}", num_dice, sides), 
+                        &results, 
+                        0,
+                        &purpose, 
+                        state
+                    ).await?;
+                    
+                    if !dm_response.is_empty() {
+                        print_fancy_message("Dungeon Master:", "cyan");
+                        print_dm_text(&dm_response);
+                    }
+                }
+            },
+            "Start combat" => {
+                if let Some(encounter) = combat::setup_encounter(state, &mut roller)? {
+                    state.encounter = Some(encounter);
+                    state.scene_type = SceneType::Combat;
+                    combat::run_combat(dm, state, &mut roller).await?;
+                }
+            },
+            "Resume combat" => {
+                combat::run_combat(dm, state, &mut roller).await?;
+            },
+            "Show character sheet" => {
+                print_character_sheet(&state.character);
+                if !state.party_stash.is_empty() {
+                    let stash: Vec<String> = state.party_stash.iter().map(|i| i.to_string()).collect();
+                    println!("{}: {}", "Party stash".color(theme().label), stash.join(", "));
+                }
+            },
+            "View skills" => {
+                let orders = vec!["Alphabetical", "By ability"];
+                let order = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Sort skills")
+                    .default(0)
+                    .items(&orders)
+                    .interact()?;
+                print_skills(&state.character, order == 1);
+            },
+            "Compare characters" => {
+                // Anyone you could build a party from: you, retired veterans, and the pregenerated heroes
+                let veterans = retired_characters();
+                let pregens: Vec<Character> = PREGENS.iter().map(|p| p.build(p.name)).collect();
+                let mut candidates: Vec<(String, &Character)> = vec![(format!("{} (you)", state.character.name), &state.character)];
+                candidates.extend(veterans.iter().map(|(label, c)| (format!("{} (retired)", label), c)));
+                candidates.extend(PREGENS.iter().zip(&pregens).map(|(p, c)| (format!("{} - {} {} (pregenerated)", p.name, p.race, p.class), c)));
+                let labels: Vec<&str> = candidates.iter().map(|(label, _)| label.as_str()).collect();
+                let mut defaults = vec![false; candidates.len()];
+                defaults[0] = true;
+                let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Characters to compare (2 to {}; space to select, enter to confirm)", MAX_COMPARED))
+                    .items(&labels)
+                    .defaults(&defaults)
+                    .interact()?;
+                if chosen.len() < 2 || chosen.len() > MAX_COMPARED {
+                    print_fancy_message(&format!("Pick between 2 and {} characters to compare.", MAX_COMPARED), "yellow");
+                    continue;
+                }
+                let characters: Vec<&Character> = chosen.iter().map(|&i| candidates[i].1).collect();
+                print_character_comparison(&characters);
+            },
+            "Combat reference" => {
+                print_combat_reference(&state.character);
+            },
+            "Cast damage spell" => {
+                if let Some(dm_response) = cast_spell_action(dm, state, &mut roller, None).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Roll history" => {
+                print_roll_history(state);
+            },
+            "Roll macro" => {
+                if state.macros.is_empty() {
+                    print_fancy_message("No macros yet. Use Define macro to name a roll like \"sneak attack\" = 3d6.", "yellow");
+                    continue;
+                }
+                let mut names: Vec<String> = state.macros.iter().map(|(name, expr)| format!("{} ({})", name, expr)).collect();
+                names.sort();
+                println!("{} {}", "Macros:".color(t.label), names.join(", "));
+                let input: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Macro (optionally followed by what it's for)")
+                    .validate_with(|input: &String| -> Result<(), &str> {
+                        find_macro(&state.macros, input).map(|_| ()).ok_or("No macro by that name")
+                    })
+                    .interact_text()?;
+                let Some((name, expr, purpose)) = find_macro(&state.macros, &input) else {
+                    continue;
+                };
+                let (name, expr) = (name.to_string(), *expr);
+                let (dice, total) = expr.roll(&mut roller);
+                state.record_roll(&expr.to_string(), &dice, total, if purpose.is_empty() { &name } else { &purpose });
+                print_fancy_message(&format!("{}: {}", name, expr), "yellow");
+                print_dice_roll(&expr.to_string(), &dice);
+                if expr.modifier != 0 {
+                    println!("Total: {}", total.to_string().color(t.good).bold());
+                }
+                let dm_response = process_dice_roll(dm, &format!("{} ({})", expr, name), &dice, expr.modifier, &purpose, state).await?;
+                if !dm_response.is_empty() {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Define macro" => {
+                let name: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Macro name (e.g. sneak attack)")
+                    .interact_text()?;
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    continue;
+                }
+                let existing = state.macros.keys().find(|key| key.eq_ignore_ascii_case(&name)).cloned();
+                let notation: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt(if existing.is_some() { "Dice expression (leave empty to delete the macro)" } else { "Dice expression (e.g. 3d6+2)" })
+                    .with_initial_text(existing.as_ref().map(|key| state.macros[key].to_string()).unwrap_or_default())
+                    .allow_empty(true)
+                    .validate_with(|input: &String| -> Result<(), &str> {
+                        if input.trim().is_empty() || DiceExpr::parse(input).is_some() { Ok(()) } else { Err("Enter dice notation like 3d6+2") }
+                    })
+                    .interact_text()?;
+                if let Some(key) = &existing {
+                    state.macros.remove(key);
+                }
+                match DiceExpr::parse(&notation) {
+                    Some(expr) => {
+                        state.macros.insert(name.clone(), expr);
+                        print_fancy_message(&format!("Macro {} = {}", name, expr), "green");
+                    }
+                    None if existing.is_some() => print_fancy_message(&format!("Deleted macro {}", name), "green"),
+                    None => continue,
+                }
+                save_game(state)?;
+            },
+            "HP history / adjust HP" => {
+                print_hp_history(&state.character);
+                let mut options = vec!["Take damage".to_string(), "Heal".to_string(), "Change maximum HP".to_string()];
+                options.extend(state.character.max_hp_modifiers.iter().map(|m| format!("Remove: {}", m)));
+                options.push("Back".to_string());
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Adjust hit points")
+                    .default(options.len() - 1)
+                    .items(&options)
+                    .interact()?;
+                if choice == options.len() - 1 {
+                    continue;
+                }
+                if choice >= 2 {
+                    let turn = state.turn();
+                    if choice == 2 {
+                        add_max_hp_effect(&mut state.character, turn)?;
+                    } else {
+                        let removed = state.character.remove_max_hp_modifier(choice - 3, turn);
+                        print_fancy_message(&format!("Removed {}", removed.source), "green");
+                    }
+                    save_game(state)?;
+                    print_hp_history(&state.character);
+                    continue;
+                }
+                
+                let amount: u32 = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Amount")
+                    .interact_text()?;
+                let reason: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Reason (e.g. trap, potion of healing)")
+                    .interact_text()?;
+                let turn = state.turn();
+                if choice == 0 {
+                    let damage_type: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Damage type")
+                        .default("bludgeoning".to_string())
+                        .interact_text()?;
+                    let lost = state.character.take_damage(amount, damage_type.trim(), reason.trim(), turn);
+                    print_fancy_message(&format!("You lose {} HP", lost), "red");
+                } else {
+                    let healed = state.character.heal(amount, reason.trim(), turn);
+                    print_fancy_message(&format!("You regain {} HP", healed), "green");
+                }
+                save_game(state)?;
+                print_hp_history(&state.character);
+            },
+            "Bonus dice" => {
+                let mut options = vec!["Grant a bonus die".to_string()];
+                options.extend(state.character.pending_bonus_dice.iter().map(|(source, expr)| format!("Remove: {} (+{})", source, expr)));
+                options.push("Back".to_string());
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Bonus dice for your next check or save")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                if choice == 0 {
+                    let source: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("From (e.g. Guidance, Bardic Inspiration)")
+                        .interact_text()?;
+                    let dice: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Die")
+                        .default("1d4".to_string())
+                        .validate_with(|input: &String| -> Result<(), &str> {
+                            DiceExpr::parse(input).map(|_| ()).ok_or("Use dice notation like 1d4 or 1d8")
+                        })
+                        .interact_text()?;
+                    if let Some((source, expr)) = parse_bonus_tag(&format!("{} {}", source.trim(), dice.trim())) {
+                        print_fancy_message(&format!("{} (+{}) is ready for your next check or save", source, expr), "green");
+                        state.character.pending_bonus_dice.push((source, expr));
+                        save_game(state)?;
+                    }
+                } else if choice < options.len() - 1 {
+                    let (source, _) = state.character.pending_bonus_dice.remove(choice - 1);
+                    save_game(state)?;
+                    print_fancy_message(&format!("Removed {}", source), "green");
+                }
+            },
+            "Ability effects" => {
+                let mut options = vec!["Add an effect".to_string()];
+                options.extend(state.character.ability_modifiers.iter().map(|m| {
+                    format!("Remove: {} {} ({}, {})", m.ability.abbreviation(), format_modifier(m.delta), m.source, m.duration)
+                }));
+                options.push("Back".to_string());
+                
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Temporary ability score changes")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                if choice == 0 {
+                    let turn = state.turn();
+                    add_ability_effect(&mut state.character, turn)?;
+                } else if choice < options.len() - 1 {
+                    let turn = state.turn();
+                    let removed = state.character.remove_ability_modifier(choice - 1, turn);
+                    print_fancy_message(&format!("Removed {}", removed.source), "green");
+                } else {
+                    continue;
+                }
+                save_game(state)?;
+                println!("{}: {}/{} | {}: {}",
+                         "HP".color(t.label), state.character.hit_points, state.character.effective_max_hp(),
+                         "AC".color(t.label), state.character.armor_class);
+            },
+            "Companions" => {
+                manage_companions(&mut state.character)?;
+                save_game(state)?;
+            },
+            "Spotlight rotation" => {
+                if state.spotlight.is_some() {
+                    state.spotlight = None;
+                    print_fancy_message("Spotlight rotation is off", "blue");
+                } else if state.character.companions.is_empty() {
+                    print_fancy_message("Spotlight rotation needs a party; add a companion first.", "yellow");
+                } else {
+                    state.spotlight = Some(0);
+                    print_fancy_message("Spotlight rotation is on; each action outside combat centers on the next party member", "green");
+                }
+                save_game(state)?;
+            },
+            "Spell effects / concentration" => {
+                let mut options = vec!["Add an effect".to_string()];
+                if state.character.active_effects.iter().any(|e| e.concentration) {
+                    options.push("End concentration".to_string());
+                }
+                options.extend(state.character.active_effects.iter().map(|e| format!("End: {}", e)));
+                options.push("Back".to_string());
+                
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Active spell effects")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                match options[choice].as_str() {
+                    "Add an effect" => {
+                        let ended = add_spell_effect(&mut state.character)?;
+                        announce_expired_effects(&ended);
+                    },
+                    "End concentration" => announce_expired_effects(&state.character.end_concentration()),
+                    "Back" => continue,
+                    _ => {
+                        let first_effect = options.len() - 1 - state.character.active_effects.len();
+                        let ended = state.character.active_effects.remove(choice - first_effect);
+                        announce_expired_effects(&[ended]);
+                    },
+                }
+                save_game(state)?;
+            },
+            "Move to stash" => move_to_stash_action(state)?,
+            "Take from stash" => take_from_stash_action(state)?,
+            "Attune / end attunement" => {
+                let magic: Vec<usize> = (0..state.character.inventory.len())
+                    .filter(|&i| state.character.inventory[i].requires_attunement)
+                    .collect();
+                let mut options: Vec<String> = magic
+                    .iter()
+                    .map(|&i| {
+                        let item = &state.character.inventory[i];
+                        let verb = if item.attuned { "End attunement" } else { "Attune" };
+                        format!("{}: {}", verb, item)
+                    })
+                    .collect();
+                options.push("Add a magic item".to_string());
+                options.push("Back".to_string());
+                
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Attuned to {}/{} items", state.character.attuned_items().len(), MAX_ATTUNED_ITEMS))
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                if choice < magic.len() {
+                    let turn = state.turn();
+                    match state.character.toggle_attunement(magic[choice], turn) {
+                        Ok(attuned) => {
+                            let item = &state.character.inventory[magic[choice]];
+                            let verb = if attuned { "You attune to" } else { "You end your attunement with" };
+                            print_fancy_message(&format!("{} {}", verb, item.name), "green");
+                            save_game(state)?;
+                        },
+                        Err(message) => print_fancy_message(&message, "red"),
+                    }
+                } else if choice == magic.len() {
+                    let item = add_magic_item()?;
+                    print_fancy_message(&format!("Added {}", item), "green");
+                    let turn = state.turn();
+                    state.character.add_item(item, turn);
+                    save_game(state)?;
+                }
+            },
+            "Learn a spell" => learn_spell_action(state)?,
+            "Prepare from spellbook" => prepare_spells_action(state)?,
+            "Ritual cast" => {
+                if let Some(dm_response) = ritual_cast_action(dm, state).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Look up spell" => look_up_spell_action(dm, state).await?,
+            "Seek resurrection" => {
+                if let Some(dm_response) = resurrection_action(dm, state).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Level up" => level_up_action(state, &mut roller)?,
+            "Craft" => {
+                if let Some(dm_response) = craft(dm, state, &mut roller).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Show map" => {
+                print_map(state);
+            },
+            "Travel" => {
+                if let Some(dm_response) = travel(dm, state, &mut roller).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Take a rest" => {
+                let rests = ["Short rest (1 hour, spend a hit die)", "Long rest (8 hours, full recovery)", "Back"];
+                let rest = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What kind of rest?")
+                    .default(0)
+                    .items(&rests)
+                    .interact()?;
+                let kind = match rest {
+                    0 => RestKind::Short,
+                    1 => RestKind::Long,
+                    _ => continue,
+                };
+                let safe = kind == RestKind::Short || Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Where are you resting?")
+                    .default(0)
+                    .items(&["Somewhere safe (a paid inn, a guarded keep)", "In the wild or somewhere dangerous"])
+                    .interact()? == 0;
+                if let Some(dm_response) = take_rest(dm, state, kind, safe, &mut roller).await? {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&dm_response);
+                }
+            },
+            "Set scene type" => {
+                let scenes: Vec<&str> = SceneType::ALL.iter().map(|scene| scene.name()).collect();
+                let current = SceneType::ALL.iter().position(|scene| *scene == state.scene_type).unwrap_or(0);
+                let scene_index = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What kind of scene is this?")
+                    .default(current)
+                    .items(&scenes)
+                    .interact()?;
+                state.scene_type = SceneType::ALL[scene_index];
+                print_fancy_message(&format!("Scene set to {}", state.scene_type.name()), "green");
+            },
+            "Set environment" => {
+                let weathers: Vec<&str> = Weather::ALL.iter().map(|w| w.name()).collect();
+                let weather = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Weather")
+                    .default(Weather::ALL.iter().position(|w| *w == state.environment.weather).unwrap_or(0))
+                    .items(&weathers)
+                    .interact()?;
+                let lights: Vec<&str> = Lighting::ALL.iter().map(|l| l.name()).collect();
+                let lighting = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Light")
+                    .default(Lighting::ALL.iter().position(|l| *l == state.environment.lighting).unwrap_or(0))
+                    .items(&lights)
+                    .interact()?;
+                let terrains: Vec<&str> = Terrain::ALL.iter().map(|t| t.name()).collect();
+                let terrain = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Terrain")
+                    .default(Terrain::ALL.iter().position(|t| *t == state.environment.terrain).unwrap_or(0))
+                    .items(&terrains)
+                    .interact()?;
+                state.environment = Environment { weather: Weather::ALL[weather], lighting: Lighting::ALL[lighting], terrain: Terrain::ALL[terrain] };
+                print_fancy_message(&format!("Environment set to {}", state.environment), "green");
+                save_game(state)?;
+            },
+            "Narration length" => {
+                let lengths: Vec<&str> = NarrationLength::ALL.iter().map(|length| length.name()).collect();
+                let current = NarrationLength::ALL.iter().position(|length| *length == state.narration_length).unwrap_or(1);
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("How much should the DM write?")
+                    .default(current)
+                    .items(&lengths)
+                    .interact()?;
+                state.narration_length = NarrationLength::ALL[choice];
+                print_fancy_message(&format!("Narration set to {}", state.narration_length.name()), "green");
+                save_game(state)?;
+            },
+            "Director's notes" => {
+                let mut options = vec!["Add a note".to_string()];
+                options.extend(state.director_notes.iter().map(|note| format!("Remove: {}", note)));
+                options.push("Back".to_string());
+                
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Out-of-character notes the DM follows")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                if choice == 0 {
+                    let note: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Note to the DM (e.g. \"keep combat scenes short\")")
+                        .interact_text()?;
+                    let dm_response = send_director_note(dm, note.trim(), state).await?;
+                    print_fancy_message("Dungeon Master (out of character):", "magenta");
+                    println!("{}", dm_response.dimmed());
+                } else if choice < options.len() - 1 {
+                    let removed = state.director_notes.remove(choice - 1);
+                    save_game(state)?;
+                    print_fancy_message(&format!("Removed note: {}", removed), "green");
+                }
+            },
+            "Character notes" => {
+                if !state.character.dm_notes.is_empty() {
+                    println!("{}: {}", "Current notes".color(t.label), state.character.dm_notes);
+                }
+                let notes: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Traits the DM always honors (e.g. \"has taken a vow of silence\"; empty to clear)")
+                    .with_initial_text(&state.character.dm_notes)
+                    .allow_empty(true)
+                    .interact_text()?;
+                state.character.dm_notes = notes.trim().to_string();
+                save_game(state)?;
+                print_fancy_message(if state.character.dm_notes.is_empty() { "Character notes cleared" } else { "Character notes saved" }, "green");
+            },
+            "Canon facts" => {
+                let mut options = vec!["Add a fact".to_string()];
+                options.extend(state.canon_facts.iter().cloned());
+                options.push("Back".to_string());
+                
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Facts the DM keeps consistent")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                if choice == 0 {
+                    let fact: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Fact (e.g. \"The mayor's name is Aldric\")")
+                        .interact_text()?;
+                    state.canon_facts.push(fact.trim().to_string());
+                    save_game(state)?;
+                } else if choice < options.len() - 1 {
+                    let index = choice - 1;
+                    let edit = Select::with_theme(&ColorfulTheme::default())
+                        .with_prompt(&state.canon_facts[index])
+                        .default(0)
+                        .items(&["Edit", "Remove", "Back"])
+                        .interact()?;
+                    match edit {
+                        0 => {
+                            let fact: String = Input::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Fact")
+                                .with_initial_text(&state.canon_facts[index])
+                                .interact_text()?;
+                            state.canon_facts[index] = fact.trim().to_string();
+                            save_game(state)?;
+                        },
+                        1 => {
+                            let removed = state.canon_facts.remove(index);
+                            save_game(state)?;
+                            print_fancy_message(&format!("Removed fact: {}", removed), "green");
+                        },
+                        _ => {}
+                    }
+                }
+            },
+            "Timeline" => timeline_action(state)?,
+            "Switch DM model" => {
+                switch_dm_model(dm).await?;
+            },
+            "Rewind to turn..." => {
+                if rewind_history(state)?
+                    && let Some(Message::Assistant { content }) = state.history.last()
+                {
+                    print_fancy_message("Dungeon Master:", "cyan");
+                    print_dm_text(&extract_text_from_message(content));
+                }
+            },
+            "Export adventure" => {
+                let path: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Export to file")
+                    .default(default_export_path(state))
+                    .interact_text()?;
+                match export_adventure(state, &path) {
+                    Ok(_) => print_fancy_message(&format!("Adventure exported to {}", path), "green"),
+                    Err(e) => print_fancy_message(&format!("Error exporting adventure: {}", e), "red"),
+                }
+            },
+            "Export character (VTT)" => {
+                let slug = slugify(&state.character.name);
+                let path: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Export to file")
+                    .default(format!("{}.vtt.json", if slug.is_empty() { "character" } else { &slug }))
+                    .interact_text()?;
+                match export_character_vtt(&state.character, &path) {
+                    Ok(_) => print_fancy_message(&format!("Character exported to {}", path), "green"),
+                    Err(e) => print_fancy_message(&format!("Error exporting character: {}", e), "red"),
+                }
+            },
+            "Copy to clipboard" => {
+                let options = ["Character sheet", "Latest recap", "Last DM message"];
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What to copy")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                let text = match options[choice] {
+                    "Character sheet" => without_colors(|| character_sheet_text(&state.character)).ok(),
+                    "Latest recap" => last_recap(&state.history).map(|recap| plain_dm_text(&recap)),
+                    _ => state.history.iter().rev().find_map(|message| match message {
+                        Message::Assistant { content } => Some(plain_dm_text(&extract_text_from_message(content))),
+                        _ => None,
+                    }),
+                };
+                let slug = slugify(&state.character.name);
+                let slug = if slug.is_empty() { "character".to_string() } else { slug };
+                let fallback = match options[choice] {
+                    "Character sheet" => format!("{}.sheet.txt", slug),
+                    "Latest recap" => format!("{}.recap.txt", slug),
+                    _ => format!("{}.dm.txt", slug),
+                };
+                match text {
+                    Some(text) => {
+                        if let Err(e) = copy_to_clipboard(&text, &fallback) {
+                            print_fancy_message(&format!("Error copying: {}", e), "red");
+                        }
+                    }
+                    None => print_fancy_message("Nothing to copy yet", "yellow"),
+                }
+            },
+            "Quick save" => {
+                state.last_saved = Local::now().to_rfc3339();
+                match quick_save(state) {
+                    Ok(_) => print_fancy_message("Quicksaved. Use Quick load to come back to this moment.", "green"),
+                    Err(e) => print_fancy_message(&format!("Error quicksaving: {}", e), "red"),
+                }
+            },
+            "Quick load" => {
+                match quick_load() {
+                    Ok(Some(snapshot)) => {
+                        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                            .with_prompt(format!("Go back to the quicksave from {}? Everything since then is lost.",
+                                                 chrono::DateTime::parse_from_rfc3339(&snapshot.last_saved)
+                                                     .map(|time| time.format("%H:%M").to_string())
+                                                     .unwrap_or_else(|_| snapshot.last_saved.clone())))
+                            .default(false)
+                            .interact()?;
+                        if confirmed {
+                            *state = snapshot;
+                            flush_save(state)?;
+                            print_fancy_message(&format!("Quickloaded at turn {}", state.turn()), "green");
+                            if let Some(Message::Assistant { content }) = state.history.last() {
+                                print_dm_text(&extract_text_from_message(content));
+                            }
+                        }
+                    },
+                    Ok(None) => print_fancy_message("There is no quicksave yet.", "yellow"),
+                    Err(e) => print_fancy_message(&format!("Error loading the quicksave: {}", e), "red"),
+                }
+            },
+            "Save game" => {
+                match flush_save(state) {
+                    Ok(_) => print_fancy_message("Game saved successfully!", "green"),
+                    Err(e) => print_fancy_message(&format!("Error saving game: {}", e), "red"),
+                }
+            },
+            "Retire character" => {
+                let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Retire {} and close {}? The adventure is archived and can't be continued.",
+                                         state.character.name, state.campaign))
+                    .default(false)
+                    .interact()?;
+                if !confirmed {
+                    continue;
+                }
+                match retire_character(state) {
+                    Ok(path) => print_fancy_message(&format!("{} retires. The adventure is archived at {}", state.character.name, path.display()), "green"),
+                    Err(e) => {
+                        print_fancy_message(&format!("Error archiving the adventure: {}", e), "red");
+                        continue;
+                    }
+                }
+                
+                let sequel = format!("Start a sequel with {} in a new setting", state.character.name);
+                let options = [sequel.as_str(), "Start a new campaign with a new character", "Return to main menu"];
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What next?")
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                let character = match choice {
+                    0 => veteran_for_sequel(&state.character, &state.campaign),
+                    1 => {
+                        let mut character = choose_pregenerated_character().unwrap_or_else(create_character);
+                        write_backstory(dm, &mut character).await?;
+                        character
+                    }
+                    _ => {
+                        clear_session_marker();
+                        break;
+                    }
+                };
+                let setting = choose_campaign_setting();
+                *state = start_new_campaign(dm, character, setting).await?;
+                open_session_marker(state);
+                print_fancy_message(&format!("Welcome to {}", state.campaign), "yellow");
+                if let Some(Message::Assistant { content }) = state.history.last() {
+                    print_dm_text(&extract_text_from_message(content));
+                }
+            },
+            "Return to main menu" => {
+                print_fancy_message("Returning to main menu...", "blue");
+                clear_session_marker();
+                break;
+            },
+            _ => unreachable!(),
+        }
+    }
     
-    // Choose skill proficiencies
-    println!("\n{}", "Choose skill proficiencies:".bright_yellow());
+    Ok(())
+}
+
+// Standalone NPC generator for people running their own table
+const NPC_DIR: &str = "npcs";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Npc {
+    name: String,
+    race: String,
+    role: String,
+    // Short stat line, e.g. "AC 12, HP 22, STR 10 DEX 14 CON 12 INT 13 WIS 11 CHA 16"
+    stats: String,
+    personality: Vec<String>,
+    secret: String,
+    voice: String,
+}
+
+impl Npc {
+    fn to_markdown(&self) -> String {
+        let mut md = format!("# {}\n\n*{} {}*\n\n**Stats:** {}\n\n## Personality\n\n", self.name, self.race, self.role, self.stats);
+        for trait_ in &self.personality {
+            md.push_str(&format!("- {}\n", trait_));
+        }
+        md.push_str(&format!("\n## Secret\n\n{}\n\n## Voice & mannerisms\n\n{}\n", self.secret, self.voice));
+        md
+    }
+}
+
+fn print_npc(npc: &Npc) {
+    let t = theme();
+    println!("\n{}", "=".repeat(60).color(t.border));
+    println!("{} - {} {}", npc.name.color(t.heading).bold(), npc.race.color(t.value), npc.role.color(t.value));
+    println!("{}", "-".repeat(60).color(t.border));
+    println!("{}: {}", "Stats".color(t.label), npc.stats);
+    println!("{}:", "Personality".color(t.label));
+    for trait_ in &npc.personality {
+        println!("  • {}", trait_);
+    }
+    println!("{}: {}", "Secret".color(t.label), npc.secret);
+    println!("{}: {}", "Voice".color(t.label), npc.voice);
+    println!("{}", "=".repeat(60).color(t.border));
+}
+
+fn parse_npc(response: &str) -> Option<Npc> {
+    let json = &response[response.find('{')?..=response.rfind('}')?];
+    serde_json::from_str(json).ok()
+}
+
+// Writes the NPC into NPC_DIR and returns the paths written
+fn save_npc(npc: &Npc, json: bool, markdown: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    fs::create_dir_all(NPC_DIR)?;
+    let slug = slugify(&npc.name);
+    let base = Path::new(NPC_DIR).join(if slug.is_empty() { "npc" } else { &slug });
+    let mut written = Vec::new();
+    if json {
+        let path = base.with_extension("json");
+        fs::write(&path, serde_json::to_string_pretty(npc)?)?;
+        written.push(path.display().to_string());
+    }
+    if markdown {
+        let path = base.with_extension("md");
+        fs::write(&path, npc.to_markdown())?;
+        written.push(path.display().to_string());
+    }
+    Ok(written)
+}
+
+// Needs no campaign: the DM agent is asked on its own, without any story history
+async fn generate_npc(dm: &impl Chat) -> Result<(), Box<dyn Error>> {
+    let t = theme();
+    let role: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Role or archetype (e.g. shifty fence, retired knight, temple acolyte)")
+        .interact_text()?;
     
-    // How many skills they can choose
-    let num_skills = match character.class.as_str() {
-        "Rogue" => 4,
-        "Bard" | "Ranger" => 3,
-        _ => 2,
-    };
+    let prompt = format!(
+        "I am a Dungeon Master preparing for my own D&D 5th Edition session, not a player in your game.
+        Create one memorable NPC for this role: {}
+        
+        Reply with only a JSON object with these fields:
+        {{\"name\": \"...\", \"race\": \"...\", \"role\": \"...\",
+        \"stats\": \"a one-line stat block: AC, HP and the six ability scores\",
+        \"personality\": [\"two or three short personality traits\"],
+        \"secret\": \"something the NPC hides\",
+        \"voice\": \"how they speak and a distinctive mannerism\"}}",
+        role.trim()
+    );
     
-    println!("Your class ({}) lets you choose {} skill proficiencies:", 
-             character.class.bright_green(), num_skills.to_string().bright_green());
+    let response = dm_chat(
+        dm,
+        &prompt,
+        Vec::new(),
+        "Failed to generate an NPC",
+        "The Dungeon Master is dreaming up a character...",
+    )
+    .await?;
     
-    // Filter available skills based on class
-    let available_skills: Vec<&str> = match character.class.as_str() {
-        "Barbarian" => vec!["Animal Handling", "Athletics", "Intimidation", "Nature", "Perception", "Survival"],
-        "Bard" => vec!["Acrobatics", "Animal Handling", "Arcana", "Athletics", "Deception", 
-                        "History", "Insight", "Intimidation", "Investigation", "Medicine", 
-                        "Nature", "Perception", "Performance", "Persuasion", "Religion", 
-                        "Sleight of Hand", "Stealth", "Survival"],
-        "Cleric" => vec!["History", "Insight", "Medicine", "Persuasion", "Religion"],
-        "Druid" => vec!["Arcana", "Animal Handling", "Insight", "Medicine", "Nature", "Perception", "Religion", "Survival"],
-        "Fighter" => vec!["Acrobatics", "Animal Handling", "Athletics", "History", "Insight", "Intimidation", "Perception", "Survival"],
-        "Monk" => vec!["Acrobatics", "Athletics", "History", "Insight", "Religion", "Stealth"],
-        "Paladin" => vec!["Athletics", "Insight", "Intimidation", "Medicine", "Persuasion", "Religion"],
-        "Ranger" => vec!["Animal Handling", "Athletics", "Insight", "Investigation", "Nature", "Perception", "Stealth", "Survival"],
-        "Rogue" => vec!["Acrobatics", "Athletics", "Deception", "Insight", "Intimidation", "Investigation", "Perception", "Performance", "Persuasion", "Sleight of Hand", "Stealth"],
-        "Sorcerer" => vec!["Arcana", "Deception", "Insight", "Intimidation", "Persuasion", "Religion"],
-        "Warlock" => vec!["Arcana", "Deception", "History", "Intimidation", "Investigation", "Nature", "Religion"],
-        "Wizard" => vec!["Arcana", "History", "Insight", "Investigation", "Medicine", "Religion"],
-        _ => vec!["Arcana", "History", "Investigation", "Nature", "Religion"],
+    let Some(npc) = parse_npc(&response) else {
+        print_fancy_message("The NPC came back in an unexpected format; here it is as written:", "yellow");
+        println!("{}", response.color(t.value));
+        return Ok(());
     };
     
-    // Safety check - ensure num_skills doesn't exceed available skills
-    let max_selectable = std::cmp::min(num_skills as usize, available_skills.len());
+    print_npc(&npc);
     
-    // Use a safer approach with MultiSelect
-    let skill_selections = if !available_skills.is_empty() {
-        // Create a string explaining selection
-        let prompt = format!("Select {} skills (space to select, enter to confirm)", max_selectable);
-        
-        // Store the theme in a variable to extend its lifetime
-        let theme = ColorfulTheme::default();
-        
-        // Use MultiSelect directly
-        MultiSelect::with_theme(&theme)
-            .with_prompt(prompt)
-            .items(&available_skills)
-            .interact()
-            .unwrap_or_else(|_| vec![])
-    } else {
-        vec![]
+    let formats = vec!["JSON and Markdown", "JSON", "Markdown", "Don't save"];
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Save this NPC to {}/?", NPC_DIR))
+        .default(0)
+        .items(&formats)
+        .interact()?;
+    let (json, markdown) = match formats[choice] {
+        "JSON and Markdown" => (true, true),
+        "JSON" => (true, false),
+        "Markdown" => (false, true),
+        _ => return Ok(()),
     };
     
-    // Apply the selections safely
-    for &index in &skill_selections {
-        if index < available_skills.len() {
-            let skill = available_skills[index];
-            character.skills.insert(skill.to_string(), true);
-        }
+    match save_npc(&npc, json, markdown) {
+        Ok(paths) => print_fancy_message(&format!("Saved {}", paths.join(" and ")), "green"),
+        Err(e) => print_fancy_message(&format!("Could not save the NPC: {}", e), "red"),
     }
     
-    // Starting equipment based on class
-    match character.class.as_str() {
-        "Fighter" => {
-            character.inventory.push("Longsword".to_string());
-            character.inventory.push("Shield".to_string());
-            character.inventory.push("Chain mail".to_string());
-            character.inventory.push("Dungeoneer's pack".to_string());
-            character.armor_class = 16; // Chain mail
-            character.gold = 10;
-        },
-        "Wizard" => {
-            character.inventory.push("Spellbook".to_string());
-            character.inventory.push("Staff".to_string());
-            character.inventory.push("Component pouch".to_string());
-            character.inventory.push("Scholar's pack".to_string());
-            character.gold = 25;
-        },
-        "Cleric" => {
-            character.inventory.push("Mace".to_string());
-            character.inventory.push("Scale mail".to_string());
-            character.inventory.push("Shield".to_string());
-            character.inventory.push("Holy symbol".to_string());
-            character.armor_class = 14 + (dex_modifier.min(2)) as u32; // Scale mail
-            character.gold = 15;
-        },
-        "Rogue" => {
-            character.inventory.push("Shortsword".to_string());
-            character.inventory.push("Shortbow with 20 arrows".to_string());
-            character.inventory.push("Leather armor".to_string());
-            character.inventory.push("Thieves' tools".to_string());
-            character.armor_class = 11 + dex_modifier as u32; // Leather armor
-            character.gold = 30;
-        },
-        _ => {
-            character.inventory.push("Adventurer's pack".to_string());
-            character.inventory.push("Simple weapon".to_string());
-            character.gold = 20;
-        }
-    };
-    
-    // Add common items
-    character.inventory.push("Backpack".to_string());
-    character.inventory.push("Bedroll".to_string());
-    character.inventory.push("Rations (5 days)".to_string());
-    character.inventory.push("Waterskin".to_string());
-    character.inventory.push("Torch (3)".to_string());
-    
-    print_fancy_message("Character Created Successfully!", "green");
-    print_character_sheet(&character);
-    
-    character
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let _ = dotenv().ok();
-    let gemini = gemini::Client::from_env();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    set_config(load_config(&args));
+    load_homebrew();
+    migrate_legacy_save();
     
-    let dungeon_master = gemini
-        .agent("gemini-2.5-flash-preview-04-17")
-        .preamble(
-            "You are an expert Dungeon Master for a Dungeons & Dragons 5th Edition game. 
-            
-            Your role is to create an immersive, engaging, and dynamic D&D experience in a text-based format. You will:
-            
-            1. Create rich, evocative descriptions of locations, NPCs, monsters, and scenarios
-            2. Respond to player actions by narrating outcomes and advancing the story
-            3. Incorporate D&D rules when appropriate, but prioritize storytelling over strict rule adherence
-            4. Craft a compelling narrative that responds to player choices
-            5. Present interesting challenges, puzzles, and combat encounters
-            6. Maintain consistent world details and NPC personalities
-            
-            Important guidelines:
-            - Use vivid, sensory language to create immersion
-            - Keep descriptions concise but evocative
-            - Present clear options for the player but allow creative actions
-            - Balance combat, exploration, and social interaction
-            - Adapt the story based on player choices
-            - Include elements of mystery and discovery
-            - Create memorable NPCs with distinct personalities
-            
-            Always respond in character as the Dungeon Master and make the adventure feel like a real D&D session. Present options in an open-ended way that encourages player agency and creativity."
-        )
-        .temperature(0.7)
-        .build();
+    let dungeon_master = DungeonMaster::new(config().provider, &config().model_name())?;
+    
+    // The marker only survives if the last session never made it back to the menu
+    let mut resumed = false;
+    if unclean_shutdown() {
+        match load_game() {
+            Ok(mut state) if !state.campaign.is_empty() => {
+                print_fancy_message("It looks like the last session ended unexpectedly.", "yellow");
+                let resume = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Resume {} from the last autosave?", state.campaign))
+                    .default(true)
+                    .interact()?;
+                clear_session_marker();
+                if resume {
+                    resumed = true;
+                    resume_adventure(&dungeon_master, &mut state).await?;
+                }
+            }
+            _ => clear_session_marker(),
+        }
+    }
+    
+    // --continue skips the menu and picks up the most recent save
+    if !resumed && args.iter().any(|arg| arg == "--continue") {
+        match most_recent_save() {
+            Some(mut recent) => resume_adventure(&dungeon_master, &mut recent.state).await?,
+            None => print_fancy_message("No saved adventure to continue.", "yellow"),
+        }
+    }
     
     // Main game loop
     loop {
         print_header();
         
-        let selections = vec!["Start New Adventure", "Continue Saved Adventure", "View Rules & Commands", "Quit"];
+        // The newest save gets a one-key Continue at the top
+        let recent = most_recent_save();
+        let continue_label = recent.as_ref().map(RecentSave::label);
+        let mut selections = vec!["Start New Adventure", "Continue Saved Adventure", "Import Adventure", "Generate NPC", "Settings", "View Rules & Commands", "Quit"];
+        if let Some(label) = &continue_label {
+            selections.insert(0, label);
+        }
         let selection = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Choose an option:")
             .default(0)
             .items(&selections)
             .interact()?;
         
-        match selection {
-            0 => {
-                // Start New Adventure
+        match selections[selection] {
+            label if continue_label.as_deref() == Some(label) => {
+                if let Some(mut recent) = recent {
+                    resume_adventure(&dungeon_master, &mut recent.state).await?;
+                }
+            },
+            "Start New Adventure" => {
                 print_fancy_message("Starting a new adventure...", "cyan");
                 
-                // Create a character
-                let character = create_character();
+                // Bring back a retired veteran or create a character
+                let character = match choose_retired_character()? {
+                    Some(veteran) => veteran,
+                    None => {
+                        let mut character = choose_pregenerated_character().unwrap_or_else(create_character);
+                        write_backstory(&dungeon_master, &mut character).await?;
+                        character
+                    }
+                };
+                let setting = choose_campaign_setting();
                 
                 // Start the campaign with the new character
-                let mut state = start_new_campaign(&dungeon_master, character).await?;
+                let mut state = start_new_campaign(&dungeon_master, character, setting).await?;
                 
                 print_fancy_message(&format!("Welcome to {}", state.campaign), "yellow");
                 
                 // Extract the last AI message to show to the player
-                if let Some(message) = state.history.last() {
-                    if let Message::Assistant { content } = message {
-                        // Extract and display just the text content from OneOrMany
-                        // Extract the text from the message content
-                        let text = extract_text_from_message(content);
-                        println!("{}", text.bright_white());
-                    }
+                if let Some(Message::Assistant { content }) = state.history.last() {
+                    // Extract and display just the text content from OneOrMany
+                    let text = extract_text_from_message(content);
+                    print_dm_text(&text);
                 }
                 
-                // Adventure gameplay loop
-                loop {
-                    println!("\n{}", "-".repeat(60).bright_blue());
-                    println!("{}: {} | {}: {}", 
-                             "Location".bright_green(), state.current_location.bright_white(),
-                             "Quest".bright_green(), state.current_quest.bright_white());
-                    println!("{}: {}/{} HP | {}: {} AC", 
-                             state.character.name.bright_yellow(),
-                             state.character.hit_points.to_string().bright_white(),
-                             state.character.max_hit_points.to_string().bright_white(),
-                             "AC".bright_yellow(),
-                             state.character.armor_class.to_string().bright_white());
-                    println!("{}", "-".repeat(60).bright_blue());
-                    
-                    // Show player options
-                    println!("\n{}", "What would you like to do?".bright_cyan());
-                    let actions = vec![
-                        "Take an action", 
-                        "Roll a skill check", 
-                        "Roll a dice", 
-                        "Show character sheet",
-                        "Save game",
-                        "Return to main menu"
-                    ];
-                    
-                    let action_choice = Select::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Choose an action")
-                        .default(0)
-                        .items(&actions)
-                        .interact()?;
-                    
-                    match action_choice {
-                        0 => {
-                            // Take an action
-                            let player_action: String = Input::with_theme(&ColorfulTheme::default())
-                                .with_prompt("What would you like to do? (describe your action)")
-                                .interact_text()?;
-                            
-                            let dm_response = process_player_action(&dungeon_master, &player_action, &mut state).await?;
-                            print_fancy_message("Dungeon Master:", "cyan");
-                            println!("{}", dm_response.bright_white());
-                        },
-                        1 => {
-                            // Roll a skill check
-                            let skills = vec![
-                                "Acrobatics", "Animal Handling", "Arcana", "Athletics", "Deception", 
-                                "History", "Insight", "Intimidation", "Investigation", "Medicine", 
-                                "Nature", "Perception", "Performance", "Persuasion", "Religion", 
-                                "Sleight of Hand", "Stealth", "Survival"
-                            ];
-                            
-                            let skill_index = Select::with_theme(&ColorfulTheme::default())
-                                .with_prompt("Choose a skill to check")
-                                .default(0)
-                                .items(&skills)
-                                .interact()?;
-                            
-                            let skill = skills[skill_index];
-                            let is_proficient = state.character.skills.get(skill).unwrap_or(&false);
-                            
-                            // Roll the d20
-                            let d20_results = roll_dice(1, 20);
-                            let roll_result = d20_results[0];
-                            
-                            // Print the roll
-                            print_fancy_message(&format!("{} Check", skill), "yellow");
-                            print_dice_roll("d20", &d20_results);
-                            
-                            // Get ability modifier
-                            let ability_mod = match skill {
-                                "Athletics" => (state.character.strength as i32 - 10) / 2,
-                                "Acrobatics" | "Sleight of Hand" | "Stealth" => (state.character.dexterity as i32 - 10) / 2,
-                                "Arcana" | "History" | "Investigation" | "Nature" | "Religion" => (state.character.intelligence as i32 - 10) / 2,
-                                "Animal Handling" | "Insight" | "Medicine" | "Perception" | "Survival" => (state.character.wisdom as i32 - 10) / 2,
-                                "Deception" | "Intimidation" | "Performance" | "Persuasion" => (state.character.charisma as i32 - 10) / 2,
-                                _ => 0,
-                            };
-                            
-                            // Calculate proficiency bonus
-                            let prof_bonus = match state.character.level {
-                                1..=4 => 2,
-                                5..=8 => 3,
-                                9..=12 => 4,
-                                13..=16 => 5,
-                                _ => 6,
-                            };
-                            
-                            // Calculate total
-                            let total = roll_result as i32 + ability_mod + if *is_proficient { prof_bonus } else { 0 };
-                            
-                            println!("Ability modifier: {}", ability_mod);
-                            if *is_proficient {
-                                println!("Proficiency bonus: +{}", prof_bonus);
-                            }
-                            println!("Total: {}", total.to_string().bright_green().bold());
-                            
-                            // Ask the player what they're rolling for
-                            let purpose: String = Input::with_theme(&ColorfulTheme::default())
-                                .with_prompt("What are you trying to do with this check?")
-                                .interact_text()?;
-
-                            // Process the skill check with the DM
-                            let dm_response = roll_skill_check(&dungeon_master, skill, roll_result, &purpose, &mut state).await?;
-                            print_fancy_message("Dungeon Master:", "cyan");
-                            println!("{}", dm_response.bright_white());
-                        },
-                        2 => {
-                            // Roll dice
-                            let dice_types = vec!["d4", "d6", "d8", "d10", "d12", "d20", "d100"];
-                            let dice_type_index = Select::with_theme(&ColorfulTheme::default())
-                                .with_prompt("Choose a dice type")
-                                .default(5) // d20 is default
-                                .items(&dice_types)
-                                .interact()?;
-                            
-                            let sides = match dice_types[dice_type_index] {
-                                "d4" => 4,
-                                "d6" => 6,
-                                "d8" => 8,
-                                "d10" => 10,
-                                "d12" => 12,
-                                "d20" => 20,
-                                "d100" => 100,
-                                _ => 6,
-                            };
-                            
-                            let num_dice: String = Input::with_theme(&ColorfulTheme::default())
-                                .with_prompt("How many dice?")
-                                .default("1".to_string())
-                                .interact_text()?;
-                            
-                            let num_dice = num_dice.parse::<u32>().unwrap_or(1);
-                            
-                            // Ask what the roll is for
-                            let purpose: String = Input::with_theme(&ColorfulTheme::default())
-                                .with_prompt("What are you rolling for? (optional)")
-                                .allow_empty(true)
-                                .interact_text()?;
-                            
-                            let results = roll_dice(num_dice, sides);
-                            
-                            if purpose.is_empty() {
-                                print_fancy_message("Dice Roll", "yellow");
-                            } else {
-                                print_fancy_message(&format!("Dice Roll: {}", purpose), "yellow");
-                            }
-                            print_dice_roll(&format!("{}d{}", num_dice, sides), &results);
-                            
-                            // Let the DM narrate the roll result if a purpose was provided
-                            if !purpose.is_empty() {
-                                let dm_response = process_dice_roll(
-                                    &dungeon_master, 
-                                    &format!("{}d{}", num_dice, sides), 
-                                    &results, 
-                                    &purpose, 
-                                    &mut state
-                                ).await?;
-                                
-                                if !dm_response.is_empty() {
-                                    print_fancy_message("Dungeon Master:", "cyan");
-                                    println!("{}", dm_response.bright_white());
-                                }
-                            }
-                        },
-                        3 => {
-                            // Show character sheet
-                            print_character_sheet(&state.character);
-                        },
-                        4 => {
-                            // Save game
-                            match save_game(&state) {
-                                Ok(_) => print_fancy_message("Game saved successfully!", "green"),
-                                Err(e) => print_fancy_message(&format!("Error saving game: {}", e), "red"),
-                            }
-                        },
-                        5 => {
-                            // Return to main menu
-                            print_fancy_message("Returning to main menu...", "blue");
-                            break;
-                        },
-                        _ => unreachable!(),
-                    }
-                }
+                run_adventure(&dungeon_master, &mut state).await?;
             },
-            1 => {
-                // Continue Saved Adventure
+            "Continue Saved Adventure" => {
                 match load_game() {
                     Ok(mut state) => {
                         if state.campaign.is_empty() {
@@ -1092,260 +9092,211 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             continue;
                         }
                         
-                        print_fancy_message(&format!("Continuing your adventure in {}...", state.campaign), "blue");
-                        println!("Location: {} | Quest: {}", 
-                                 state.current_location.yellow(),
-                                 state.current_quest.yellow());
-                        
-                        // Extract the last AI message to show to the player
-                        if let Some(message) = state.history.last() {
-                            if let Message::Assistant { content } = message {
-                                print_fancy_message("Previously in your adventure:", "cyan");
-                                // Extract and display just the text content from OneOrMany
-                                // Extract the text from the message content
-                                let text = extract_text_from_message(content);
-                                println!("{}", text.bright_white());
-                            }
-                        }
-                        
-                        // Continue adventure gameplay loop
-                        loop {
-                            println!("\n{}", "-".repeat(60).bright_blue());
-                            println!("{}: {} | {}: {}", 
-                                     "Location".bright_green(), state.current_location.bright_white(),
-                                     "Quest".bright_green(), state.current_quest.bright_white());
-                            println!("{}: {}/{} HP | {}: {} AC", 
-                                     state.character.name.bright_yellow(),
-                                     state.character.hit_points.to_string().bright_white(),
-                                     state.character.max_hit_points.to_string().bright_white(),
-                                     "AC".bright_yellow(),
-                                     state.character.armor_class.to_string().bright_white());
-                            println!("{}", "-".repeat(60).bright_blue());
-                            
-                            // Show player options
-                            println!("\n{}", "What would you like to do?".bright_cyan());
-                            let actions = vec![
-                                "Take an action", 
-                                "Roll a skill check", 
-                                "Roll a dice", 
-                                "Show character sheet",
-                                "Save game",
-                                "Return to main menu"
-                            ];
-                            
-                            let action_choice = Select::with_theme(&ColorfulTheme::default())
-                                .with_prompt("Choose an action")
-                                .default(0)
-                                .items(&actions)
+                        resume_adventure(&dungeon_master, &mut state).await?;
+                    }
+                    Err(_) => {
+                        print_fancy_message("No saved adventure found or error loading save!", "red");
+                        thread::sleep(Duration::from_secs(2));
+                    }
+                }
+            },
+            "Import Adventure" => {
+                let path: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Path to the adventure archive")
+                    .interact_text()?;
+                
+                match import_adventure(path.trim()) {
+                    Ok(mut state) => {
+                        if existing_save_path().is_some() {
+                            let overwrite = Confirm::with_theme(&ColorfulTheme::default())
+                                .with_prompt("This will replace your current saved adventure. Continue?")
+                                .default(false)
                                 .interact()?;
-                            
-                            match action_choice {
-                                0 => {
-                                    // Take an action
-                                    let player_action: String = Input::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("What would you like to do? (describe your action)")
-                                        .interact_text()?;
-                                    
-                                    let dm_response = process_player_action(&dungeon_master, &player_action, &mut state).await?;
-                                    print_fancy_message("Dungeon Master:", "cyan");
-                                    println!("{}", dm_response.bright_white());
-                                },
-                                1 => {
-                                    // Roll a skill check
-                                    let skills = vec![
-                                        "Acrobatics", "Animal Handling", "Arcana", "Athletics", "Deception", 
-                                        "History", "Insight", "Intimidation", "Investigation", "Medicine", 
-                                        "Nature", "Perception", "Performance", "Persuasion", "Religion", 
-                                        "Sleight of Hand", "Stealth", "Survival"
-                                    ];
-                                    
-                                    let skill_index = Select::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("Choose a skill to check")
-                                        .default(0)
-                                        .items(&skills)
-                                        .interact()?;
-                                    
-                                    let skill = skills[skill_index];
-                                    let is_proficient = state.character.skills.get(skill).unwrap_or(&false);
-                                    
-                                    // Roll the d20
-                                    let d20_results = roll_dice(1, 20);
-                                    let roll_result = d20_results[0];
-                                    
-                                    // Print the roll
-                                    print_fancy_message(&format!("{} Check", skill), "yellow");
-                                    print_dice_roll("d20", &d20_results);
-                                    
-                                    // Get ability modifier
-                                    let ability_mod = match skill {
-                                        "Athletics" => (state.character.strength as i32 - 10) / 2,
-                                        "Acrobatics" | "Sleight of Hand" | "Stealth" => (state.character.dexterity as i32 - 10) / 2,
-                                        "Arcana" | "History" | "Investigation" | "Nature" | "Religion" => (state.character.intelligence as i32 - 10) / 2,
-                                        "Animal Handling" | "Insight" | "Medicine" | "Perception" | "Survival" => (state.character.wisdom as i32 - 10) / 2,
-                                        "Deception" | "Intimidation" | "Performance" | "Persuasion" => (state.character.charisma as i32 - 10) / 2,
-                                        _ => 0,
-                                    };
-                                    
-                                    // Calculate proficiency bonus
-                                    let prof_bonus = match state.character.level {
-                                        1..=4 => 2,
-                                        5..=8 => 3,
-                                        9..=12 => 4,
-                                        13..=16 => 5,
-                                        _ => 6,
-                                    };
-                                    
-                                    // Calculate total
-                                    let total = roll_result as i32 + ability_mod + if *is_proficient { prof_bonus } else { 0 };
-                                    
-                                    println!("Ability modifier: {}", ability_mod);
-                                    if *is_proficient {
-                                        println!("Proficiency bonus: +{}", prof_bonus);
-                                    }
-                                    println!("Total: {}", total.to_string().bright_green().bold());
-                                    
-                                    // Ask the player what they're rolling for
-                                    let purpose: String = Input::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("What are you trying to do with this check?")
-                                        .interact_text()?;
-
-                                    // Process the skill check with the DM
-                                    let dm_response = roll_skill_check(&dungeon_master, skill, roll_result, &purpose, &mut state).await?;
-                                    print_fancy_message("Dungeon Master:", "cyan");
-                                    println!("{}", dm_response.bright_white());
-                                },
-                                2 => {
-                                    // Roll dice
-                                    let dice_types = vec!["d4", "d6", "d8", "d10", "d12", "d20", "d100"];
-                                    let dice_type_index = Select::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("Choose a dice type")
-                                        .default(5) // d20 is default
-                                        .items(&dice_types)
-                                        .interact()?;
-                                    
-                                    let sides = match dice_types[dice_type_index] {
-                                        "d4" => 4,
-                                        "d6" => 6,
-                                        "d8" => 8,
-                                        "d10" => 10,
-                                        "d12" => 12,
-                                        "d20" => 20,
-                                        "d100" => 100,
-                                        _ => 6,
-                                    };
-                                    
-                                    let num_dice: String = Input::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("How many dice?")
-                                        .default("1".to_string())
-                                        .interact_text()?;
-                                    
-                                    let num_dice = num_dice.parse::<u32>().unwrap_or(1);
-                                    
-                                    // Ask what the roll is for
-                                    let purpose: String = Input::with_theme(&ColorfulTheme::default())
-                                        .with_prompt("What are you rolling for? (optional)")
-                                        .allow_empty(true)
-                                        .interact_text()?;
-                                    
-                                    let results = roll_dice(num_dice, sides);
-                                    
-                                    if purpose.is_empty() {
-                                        print_fancy_message("Dice Roll", "yellow");
-                                    } else {
-                                        print_fancy_message(&format!("Dice Roll: {}", purpose), "yellow");
-                                    }
-                                    print_dice_roll(&format!("{}d{}", num_dice, sides), &results);
-                                    
-                                    // Let the DM narrate the roll result if a purpose was provided
-                                    if !purpose.is_empty() {
-                                        let dm_response = process_dice_roll(
-                                            &dungeon_master, 
-                                            &format!("{}d{}", num_dice, sides), 
-                                            &results, 
-                                            &purpose, 
-                                            &mut state
-                                        ).await?;
-                                        
-                                        if !dm_response.is_empty() {
-                                            print_fancy_message("Dungeon Master:", "cyan");
-                                            println!("{}", dm_response.bright_white());
-                                        }
-                                    }
-                                },
-                                3 => {
-                                    // Show character sheet
-                                    print_character_sheet(&state.character);
-                                },
-                                4 => {
-                                    // Save game
-                                    match save_game(&state) {
-                                        Ok(_) => print_fancy_message("Game saved successfully!", "green"),
-                                        Err(e) => print_fancy_message(&format!("Error saving game: {}", e), "red"),
-                                    }
-                                },
-                                5 => {
-                                    // Return to main menu
-                                    print_fancy_message("Returning to main menu...", "blue");
-                                    break;
-                                },
-                                _ => unreachable!(),
+                            if !overwrite {
+                                continue;
                             }
                         }
+                        flush_save(&state)?;
+                        print_fancy_message(&format!("Imported {}", state.campaign), "green");
+                        resume_adventure(&dungeon_master, &mut state).await?;
                     }
-                    Err(_) => {
-                        print_fancy_message("No saved adventure found or error loading save!", "red");
+                    Err(e) => {
+                        print_fancy_message(&format!("Could not import adventure: {}", e), "red");
                         thread::sleep(Duration::from_secs(2));
                     }
                 }
             },
-            2 => {
-                // View Rules & Commands
+            "Generate NPC" => {
+                if let Err(e) = generate_npc(&dungeon_master).await {
+                    print_fancy_message(&format!("{}", e), "red");
+                }
+            },
+            "Settings" => {
+                let mut settings = config();
+                let options = vec![
+                    format!("Loading spinner: {}", if settings.spinner { "on" } else { "off" }),
+                    format!("Debug output (show prompts sent to the model): {}", if settings.debug { "on" } else { "off" }),
+                    format!("Suggest a skill and DC before actions: {}", if settings.auto_dc { "on" } else { "off" }),
+                    format!("Suggest a skill to roll for actions: {}", if settings.suggest_skills { "on" } else { "off" }),
+                    format!("Explain my rolls (plain-language breakdown of each total): {}", if settings.explain_rolls { "on" } else { "off" }),
+                    format!("Color theme: {}", settings.theme.name()),
+                    format!("Auto-resolve enemy turns in combat: {}", if settings.auto_enemy_turns { "on" } else { "off" }),
+                    format!("Lean prompts (cheaper, fewer instructions per action): {}", if settings.lean_prompts { "on" } else { "off" }),
+                    format!("Confirm risky-sounding actions: {}", if settings.confirm_risky_actions { "on" } else { "off" }),
+                    "Edit risky phrases".to_string(),
+                    format!("Compress saves (gzip, much smaller for long campaigns): {}", if settings.compress_saves { "on" } else { "off" }),
+                    format!("Break reminders: {}", if settings.break_reminders { "on" } else { "off" }),
+                    format!("Break reminder interval: {} minutes", settings.break_reminder_minutes),
+                    "Back".to_string(),
+                ];
+                let choice = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Settings (saved to {})", CONFIG_FILE))
+                    .default(0)
+                    .items(&options)
+                    .interact()?;
+                
+                match choice {
+                    0 => settings.spinner = !settings.spinner,
+                    1 => settings.debug = !settings.debug,
+                    2 => settings.auto_dc = !settings.auto_dc,
+                    3 => settings.suggest_skills = !settings.suggest_skills,
+                    4 => settings.explain_rolls = !settings.explain_rolls,
+                    5 => {
+                        let themes: Vec<&str> = ThemeName::ALL.iter().map(|t| t.name()).collect();
+                        let current = ThemeName::ALL.iter().position(|t| *t == settings.theme).unwrap_or(0);
+                        let index = Select::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Color theme")
+                            .default(current)
+                            .items(&themes)
+                            .interact()?;
+                        settings.theme = ThemeName::ALL[index];
+                    },
+                    6 => settings.auto_enemy_turns = !settings.auto_enemy_turns,
+                    7 => settings.lean_prompts = !settings.lean_prompts,
+                    8 => settings.confirm_risky_actions = !settings.confirm_risky_actions,
+                    9 => {
+                        let phrases: String = Input::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Risky phrases (comma-separated; attacks on companions are always checked)")
+                            .with_initial_text(settings.risky_phrases.join(", "))
+                            .allow_empty(true)
+                            .interact_text()?;
+                        settings.risky_phrases = phrases.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+                    },
+                    10 => settings.compress_saves = !settings.compress_saves,
+                    11 => settings.break_reminders = !settings.break_reminders,
+                    12 => {
+                        settings.break_reminder_minutes = Input::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Minutes of play between break reminders")
+                            .default(settings.break_reminder_minutes)
+                            .validate_with(|minutes: &u64| if *minutes > 0 { Ok(()) } else { Err("Must be at least 1 minute") })
+                            .interact_text()?;
+                    },
+                    _ => {}
+                }
+                if choice < options.len() - 1 {
+                    save_config(&settings)?;
+                    set_config(settings);
+                    print_fancy_message("Settings saved!", "green");
+                }
+            },
+            "View Rules & Commands" => {
+                let t = theme();
                 print_fancy_message("D&D ADVENTURE RULES & COMMANDS", "blue");
-                println!("{}", "Welcome to AI Dungeon Master!".bright_cyan());
-                println!("{}", "Experience D&D 5th Edition in a text-based adventure with an AI Dungeon Master.".bright_white());
+                println!("{}", "Welcome to AI Dungeon Master!".color(t.info));
+                println!("{}", "Experience D&D 5th Edition in a text-based adventure with an AI Dungeon Master.".color(t.value));
                 
-                println!("\n{}", "Game Features:".bright_yellow());
+                println!("\n{}", "Game Features:".color(t.heading));
                 println!("• Character creation with D&D 5e races, classes and abilities");
                 println!("• Interactive storytelling with an AI Dungeon Master");
                 println!("• Skill checks and dice rolling");
                 println!("• Character progression");
                 println!("• Save and load your adventure");
+                println!("• Generate NPC - Create a ready-to-use NPC for a game you run yourself and save it to {}/", NPC_DIR);
                 
-                println!("\n{}", "How to Play:".bright_yellow());
-                println!("• Create a character or load a saved game");
+                println!("\n{}", "How to Play:".color(t.heading));
+                println!("• Create a character, pick a pregenerated one, or load a saved game");
+                println!("• Pick a campaign setting (grimdark, horror, nautical, ...) to set the tone of the whole adventure");
                 println!("• The DM will describe scenes and situations");
                 println!("• Choose actions for your character to take");
                 println!("• Roll skill checks when attempting difficult tasks");
                 println!("• Engage in combat, exploration, and social interaction");
                 
-                println!("\n{}", "Commands during play:".bright_yellow());
-                println!("• Take an action - Describe what your character does");
-                println!("• Roll a skill check - Test your character's abilities with specific purpose");
+                println!("\n{}", "Commands during play:".color(t.heading));
+                println!("• Take an action - Describe what your character does (turn on DC suggestions in Settings to roll against a DC first, or skill suggestions to be offered the fitting skill, e.g. Athletics for climbing a wall); actions that look catastrophic, like jumping off something or attacking a companion, ask \"Are you sure?\" first (phrases and toggle in Settings)");
+                println!("• Quick roll - Type r 1d20+5, c stealth, or s dex (add adv/dis for advantage or disadvantage, and what it's for to involve the DM) to skip the menus");
+                println!("• Roll a skill check - Test your character's abilities with specific purpose; enter a DC to settle success or failure yourself; turn on \"Explain my rolls\" in Settings to see how each check, save, and attack total was reached");
+                println!("• Group check - You and every companion roll the same skill (sneaking past guards, crossing a rope bridge); the group succeeds if at least half of you meet the DC");
+                println!("• Roll a tool check - Use thieves' tools, an herbalism kit, an instrument and so on, adding your proficiency bonus when proficient");
+                println!("• Favorites - Pin skills, spells, and weapons from their lists; they appear at the top of the menus");
                 println!("• Roll dice - Roll any dice combination (1d20, 2d6, etc.) with optional purpose");
+                println!("• Define macro / Roll macro - Name a dice expression you roll often (\"sneak attack\" = 3d6) and roll it by typing its name, optionally followed by what it's for; macros are kept in your save");
+                println!("• Roll history - The last {} rolls (checks, saves, attacks, damage, and raw dice) with what they were for; kept in your save", RECENT_ROLLS_LEN);
                 println!("• Show character sheet - View your character's stats");
-                println!("• Save game - Save your progress");
+                println!("• Spell effects / concentration - Track Bless, Haste and the like; they count down in combat and as time passes, and ending concentration drops them all");
+                println!("• HP history / adjust HP - The last {} HP changes and why they happened; record damage, healing, or effects that lower your maximum HP", HP_LOG_LEN);
+                println!("• View skills - All 18 skills with their total modifiers");
+                println!("• Compare characters - Up to {} characters side by side (you, retired veterans, pregenerated heroes): abilities, AC, HP, and every skill, with the best in each row highlighted", MAX_COMPARED);
+                println!("• Start combat - Fight monsters turn by turn; setup rates the fight Easy to Deadly against your party's XP budget; the turn order shows colored HP bars, or just unhurt / bloodied for enemies if you hide their HP during setup; enemy attacks roll against your AC and deal real damage; badly wounded enemies may fall back from melee, and you can spend your reaction on an opportunity attack as they go; monsters use average or rolled HP; initiative is rolled for everyone at once and you can override any roll; you can schedule events for the start of a later round; ambushes start with a surprise round where caught-off-guard combatants lose their first turn (turn on auto-resolve in Settings to roll all enemy turns at once); Quick-resolve plays out a minor fight on its own and offers you the controls back if it turns close");
+                println!("• Ammunition - Bows, crossbows and slings spend a shot per attack and warn you when running low; after a fight you can search for half of what you fired");
+                println!("• DM tool (debug mode) - On your combat turn, adjust an enemy's HP, AC or conditions to rebalance a fight without restarting it");
+                println!("  Enemies are engaged, near, or far; melee needs engaged, and each turn has one move and one action (Dash, Disengage)");
+                println!("  Your turn shows what's left: action, bonus action (off-hand attacks, quick spells), reaction (offered when an enemy hits you), movement");
+                println!("• Combat reference - Attack bonuses, weapon damage, AC and common combat actions");
+                println!("• Cast damage spell - Roll a spell's damage (e.g. 8d6) with optional half-on-save handling; in combat, pick targets or catch every enemy at one distance and each rolls its own save; Warlocks spend pact slots, which always cast at their highest level and come back on a short rest");
+                println!("• Ability effects - Track temporary buffs and drains; checks, attacks, AC and HP use the changed scores");
+                println!("• Bonus dice - Guidance, Bardic Inspiration and the like (the DM grants them too); you're offered each on your next check or save, and it's used up once rolled");
+                println!("• Attune / end attunement - Bond with up to {} magic items; their AC and ability bonuses only apply while attuned", MAX_ATTUNED_ITEMS);
+                println!("• Move to stash / Take from stash - Keep heavy or shared loot in the party's common pool; stashed items don't count as carried, so they leave your load and encumbrance, and armor and magic items stop applying (and attunement ends) until you take them back");
+                println!("• Companions - Familiars and animal companions with their own HP; they roll initiative and take turns you direct in combat");
+                println!("• Spotlight rotation - Outside combat, each action is framed around the next party member in turn so everyone gets a moment");
+                println!("• Craft - Turn components, gold, and time into items with a tool check (needs the tool proficiency)");
+                println!("• Learn a spell - Add a spell your class can cast at your level; wizards copying from a found spellbook spend 50 gp and 2 hours per spell level");
+                println!("• Prepare from spellbook (wizards) - Choose which spellbook spells are ready to cast, up to INT modifier + level");
+                println!("• Ritual cast (wizards) - Cast a ritual spell from your spellbook without a slot, prepared or not; it takes {} extra minutes", RITUAL_EXTRA_MINUTES);
+                println!("• Look up spell - Level, casting time, range, components, duration and effect for any spell; each spell is fetched once and kept in {} (clear it from the same menu)", SPELL_CACHE_FILE);
+                println!("• Level up - Gain a level and its hit points; at levels {} choose +2 to one ability, +1 to two, or a feat (each level's choice can only be made once)",
+                         ASI_LEVELS.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", "));
+                println!("• Travel - Journey to a known or new place at a cautious, normal, or quick pace; time passes and the road may bring encounters");
+                println!("• Show map - A sketch of the places you know and how they connect, with your current location marked");
+                println!("• Take a rest - Short rest spends a hit die, long rest restores HP; effects that last until a rest end; a long rest in the wild may be interrupted, and less than {} hours of sleep only counts as a short rest", LONG_REST_MIN_SLEEP_HOURS);
+                println!("• Set scene type - Tell the DM whether this is exploration, combat, social, or downtime");
+                println!("• Set environment - Weather, light, and terrain (the DM also sets them as the scene changes); fog, heavy rain, dim light, or darkness give disadvantage on Perception, strong wind or storms on ranged weapon attacks (yours and the enemy's), and darkness, strong wind, or storms on tool checks");
+                println!("• Narration length - Brief, Normal or Detailed; controls how much the DM writes each turn");
+                println!("• Director's notes - Out-of-character instructions the DM keeps following (\"shorter scenes\", \"add a romance subplot\")");
+                println!("• Character notes - In-character traits of your hero the DM respects every turn (a vow of silence, a hidden identity, a stutter); shown on your sheet and sent with every action");
+                println!("• Canon facts - Names, places, and other facts the DM has established (it records them as it goes); add or correct them so the story stays consistent");
+                println!("• Timeline - The campaign's major events by in-game day, recorded by the DM as they happen; add or remove entries, and the latest {} are kept in the DM's memory", TIMELINE_CONTEXT_EVENTS);
+                println!("• Switch DM model - Move the campaign to another provider (Gemini, OpenAI, Anthropic) or model, e.g. a cheaper one for routine play; the story and history carry over and the choice is remembered");
+                println!("• Rewind to turn - Branch the story from an earlier moment (the old timeline is backed up)");
+                println!("• Export adventure - Bundle the whole campaign into one file to share (import it from the main menu)");
+                println!("• Export character (VTT) - Save your character as Foundry-style JSON to import into a virtual tabletop");
+                println!("• Copy to clipboard - Copy the character sheet, latest recap, or last DM message as plain text (saved to a file if there is no clipboard)");
+                println!("• Save game - Save your progress (the game also autosaves, and offers to resume if it closed unexpectedly); turn on break reminders in Settings for a nudge to save or rest every so often");
+                println!("• Quick save / Quick load - One checkpoint slot, separate from the autosave; snapshot before a risky fight and go back if it goes wrong");
+                println!("• Retire character - End the campaign: it is archived with a final status in {}/ in your save directory, and you can start a sequel with the same character in a new setting or begin fresh (retired characters can also be brought back from Start New Adventure)", RETIRED_DIR);
+                println!("• Seek resurrection - Only while dead (three failed death saves), when most actions are locked: pay {} gp for Raise Dead or accept the DM's price for another way back; either returns you at 1 HP with -{} to attacks, saves, and checks, easing by 1 per long rest", RAISE_DEAD_COST, RESURRECTION_PENALTY);
                 
-                println!("\n{}", "Roll Purpose Feature:".bright_yellow());
+                println!("\n{}", "Roll Purpose Feature:".color(t.heading));
                 println!("• When rolling skill checks or dice, you can specify what you're trying to accomplish");
                 println!("• The Dungeon Master will evaluate if your approach is appropriate");
                 println!("• For skill checks, your purpose is required to provide context");
                 println!("• For general dice rolls, a purpose is optional but enriches the narrative");
                 
-                println!("\n{}", "Basic D&D Concepts:".bright_yellow());
+                println!("\n{}", "Basic D&D Concepts:".color(t.heading));
                 println!("• Ability Scores - Six core attributes (STR, DEX, CON, INT, WIS, CHA)");
                 println!("• Skill Checks - Roll d20 + ability modifier + proficiency (if applicable)");
                 println!("• Difficulty Class (DC) - Target number to beat on skill checks");
                 println!("• Hit Points (HP) - Your character's health");
                 println!("• Armor Class (AC) - How difficult you are to hit in combat");
                 
-                println!("\n{}", "Press Enter to return to the main menu...".bright_cyan());
+                println!("\n{}", "Press Enter to return to the main menu...".color(t.info));
                 let _: String = Input::with_theme(&ColorfulTheme::default())
                     .with_prompt("")
                     .allow_empty(true)
                     .interact_text()?;
             },
-            3 => {
-                // Quit
+            "Quit" => {
+                clear_session_marker();
                 print_fancy_message("Thanks for playing AI Dungeon Master!", "cyan");
                 thread::sleep(Duration::from_secs(1));
                 break;
@@ -1356,3 +9307,365 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // A save as each format version wrote it, with only the fields that version required
+    fn save_fixture(version: Option<u32>, character: serde_json::Value) -> serde_json::Value {
+        let mut save = serde_json::json!({
+            "character": character,
+            "campaign": "The Sunless Citadel",
+            "current_location": "Oakhurst",
+            "current_quest": "Find the missing adventurers",
+            "history": [],
+            "date_started": "2025-01-01T00:00:00+00:00",
+            "last_saved": "2025-01-02T00:00:00+00:00",
+        });
+        if let Some(version) = version {
+            save["version"] = serde_json::json!(version);
+        }
+        save
+    }
+    
+    fn character_fixture(inventory: serde_json::Value, skills: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "name": "Elara", "race": "Elf", "class": "Wizard", "level": 2,
+            "strength": 8, "dexterity": 14, "constitution": 12, "intelligence": 16, "wisdom": 12, "charisma": 10,
+            "hit_points": 12, "max_hit_points": 12, "armor_class": 12,
+            "inventory": inventory, "gold": 10, "experience": 300, "background": "Sage",
+            "skills": skills,
+        })
+    }
+    
+    #[test]
+    fn v0_saves_gain_combat_fields_and_a_quarterstaff() {
+        let save = save_fixture(None, character_fixture(serde_json::json!(["Staff", "Spellbook"]), serde_json::json!({ "Arcana": true })));
+        let state = migrate(save).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        assert_eq!(state.scene_type, SceneType::Exploration);
+        assert_eq!(state.character.inventory[0].name, "Quarterstaff");
+        assert_eq!(state.character.skills["Arcana"], Proficiency::Proficient);
+    }
+    
+    #[test]
+    fn v1_saves_turn_inventory_names_into_items() {
+        let save = save_fixture(Some(1), character_fixture(serde_json::json!(["Quarterstaff", "Spellbook"]), serde_json::json!({ "Arcana": true })));
+        let state = migrate(save).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        let names: Vec<&str> = state.character.inventory.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["Quarterstaff", "Spellbook"]);
+    }
+    
+    #[test]
+    fn v2_saves_count_the_ammunition_named_on_a_weapon() {
+        let save = save_fixture(Some(2), character_fixture(
+            serde_json::json!([{ "name": "Shortbow with 20 arrows" }]),
+            serde_json::json!({ "Arcana": false }),
+        ));
+        let state = migrate(save).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        assert_eq!(state.character.inventory[0].name, "Shortbow");
+        assert_eq!(state.character.inventory[0].ammo, Some(20));
+    }
+    
+    #[test]
+    fn v3_saves_read_death_off_the_failed_death_saves() {
+        let mut character = character_fixture(serde_json::json!([]), serde_json::json!({}));
+        character["hit_points"] = serde_json::json!(0);
+        character["death_save_failures"] = serde_json::json!(3);
+        let state = migrate(save_fixture(Some(3), character)).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        assert!(state.character.is_dead());
+    }
+    
+    #[test]
+    fn v4_saves_turn_boolean_skills_into_proficiency_levels() {
+        let mut character = character_fixture(serde_json::json!([]), serde_json::json!({ "Arcana": true, "Stealth": false }));
+        character["status"] = serde_json::json!("Alive");
+        let state = migrate(save_fixture(Some(4), character)).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        assert_eq!(state.character.skills["Arcana"], Proficiency::Proficient);
+        assert_eq!(state.character.skills["Stealth"], Proficiency::None);
+    }
+    
+    #[test]
+    fn v5_saves_copy_a_wizards_leveled_spells_into_the_spellbook() {
+        let mut character = character_fixture(serde_json::json!([]), serde_json::json!({ "Arcana": "Proficient" }));
+        character["status"] = serde_json::json!("Alive");
+        character["known_spells"] = serde_json::json!([{ "name": "Fire Bolt", "level": 0 }, { "name": "Shield", "level": 1 }]);
+        let state = migrate(save_fixture(Some(5), character)).unwrap();
+        assert_eq!(state.version, SAVE_VERSION);
+        let spellbook: Vec<&str> = state.character.spellbook.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(spellbook, ["Shield"]);
+        assert_eq!(state.character.known_spells.len(), 2);
+    }
+    
+    #[test]
+    fn saves_from_a_newer_version_are_refused() {
+        let save = save_fixture(Some(SAVE_VERSION + 1), character_fixture(serde_json::json!([]), serde_json::json!({})));
+        assert!(migrate(save).is_err());
+    }
+    
+    #[test]
+    fn archives_without_a_version_are_refused() {
+        let path = std::env::temp_dir().join(format!("dnd-unversioned-{}.adventure.json", std::process::id()));
+        let import = |archive: serde_json::Value| {
+            fs::write(&path, archive.to_string()).unwrap();
+            import_adventure(&path.to_string_lossy())
+        };
+        let character = character_fixture(serde_json::json!([]), serde_json::json!({}));
+        let versioned = save_fixture(Some(1), character.clone());
+        assert!(import(serde_json::json!({ "magic": ARCHIVE_MAGIC, "version": ARCHIVE_VERSION, "state": versioned.clone() })).is_ok());
+        assert!(import(serde_json::json!({ "magic": ARCHIVE_MAGIC, "state": versioned })).is_err());
+        let unversioned = save_fixture(None, character);
+        assert!(import(serde_json::json!({ "magic": ARCHIVE_MAGIC, "version": ARCHIVE_VERSION, "state": unversioned })).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[test]
+    fn artificers_are_proficient_in_constitution_and_intelligence_saves() {
+        assert_eq!(class_save_proficiencies("Artificer"), [Ability::Constitution, Ability::Intelligence]);
+    }
+    
+    #[test]
+    fn tools_granted_by_both_class_and_background_are_held_once() {
+        for background in ["Criminal", "Urchin"] {
+            let tools = starting_tool_proficiencies("Artificer", background);
+            assert_eq!(tools.iter().filter(|t| *t == "Thieves' tools").count(), 1);
+            assert!(tools.contains("Tinker's tools"));
+        }
+    }
+    
+    #[test]
+    fn pact_magic_follows_the_warlock_slot_table() {
+        let table = [(1, (1, 1)), (2, (2, 1)), (3, (2, 2)), (5, (2, 3)), (7, (2, 4)), (9, (2, 5)), (11, (3, 5)), (17, (4, 5)), (20, (4, 5))];
+        for (level, slots) in table {
+            assert_eq!(pact_magic_slots(level), slots, "Warlock level {}", level);
+        }
+    }
+    
+    #[test]
+    fn pact_slots_count_only_warlock_levels() {
+        let mut fighter = Character { class: "Fighter".to_string(), level: 5, ..Default::default() };
+        assert_eq!(fighter.pact_slots(), None);
+        fighter.add_multiclass("Warlock", None, None);
+        fighter.level = 7;
+        fighter.multiclass_levels.insert("Warlock".to_string(), 2);
+        assert_eq!(fighter.pact_slots(), Some((2, 1)));
+        
+        let warlock = Character { class: "Warlock".to_string(), level: 5, ..Default::default() };
+        assert_eq!(warlock.pact_slots(), Some((2, 3)));
+    }
+    
+    #[test]
+    fn a_critical_doubles_the_dice_but_not_the_modifier() {
+        let damage = DiceExpr::parse("2d6+3").unwrap();
+        let critical = damage.critical();
+        assert_eq!(critical, DiceExpr::parse("4d6+3").unwrap());
+        let (dice, total) = critical.roll(&mut ScriptedRoller::new([1, 2, 3, 4]));
+        assert_eq!(dice, [1, 2, 3, 4]);
+        assert_eq!(total, 1 + 2 + 3 + 4 + 3);
+    }
+    
+    #[test]
+    fn relevelling_does_not_owe_an_ability_score_improvement_twice() {
+        let mut character = Character { class: "Fighter".to_string(), level: 3, ..Default::default() };
+        gain_level(&mut character, "Fighter", false, &mut ScriptedRoller::new([]), 0);
+        assert_eq!(character.pending_asi_levels(), [4]);
+        // What choose_ability_improvements records once the level 4 improvement is applied
+        character.asi_taken.insert(4);
+        
+        character.level = 3;
+        gain_level(&mut character, "Fighter", false, &mut ScriptedRoller::new([]), 0);
+        assert!(character.pending_asi_levels().is_empty());
+        
+        character.level = 7;
+        gain_level(&mut character, "Fighter", false, &mut ScriptedRoller::new([]), 0);
+        assert_eq!(character.pending_asi_levels(), [8]);
+    }
+    
+    #[test]
+    fn expertise_doubles_the_proficiency_bonus_and_plain_proficiency_does_not() {
+        let mut rogue = Character { class: "Rogue".to_string(), level: 5, dexterity: 16, ..Default::default() };
+        rogue.skills.insert("Stealth".to_string(), Proficiency::Expertise);
+        rogue.skills.insert("Acrobatics".to_string(), Proficiency::Proficient);
+        // +3 DEX, and a +3 proficiency bonus at level 5
+        assert_eq!(rogue.skill_modifier("Stealth"), 3 + 2 * 3);
+        assert_eq!(rogue.skill_modifier("Acrobatics"), 3 + 3);
+        assert_eq!(rogue.skill_modifier("Sleight of Hand"), 3);
+    }
+    
+    #[test]
+    fn ability_scores_drop_the_lowest_of_four_d6() {
+        let (dice, total) = roll_ability_score(&mut ScriptedRoller::new([3, 6, 1, 5]));
+        assert_eq!(dice, [3, 6, 1, 5]);
+        assert_eq!(total, 3 + 6 + 5);
+        // Only one of two equal low dice is dropped
+        assert_eq!(roll_ability_score(&mut ScriptedRoller::new([2, 2, 4, 4])).1, 2 + 4 + 4);
+    }
+    
+    #[test]
+    fn only_attacks_aimed_at_a_companion_need_confirming() {
+        let bram = Companion {
+            name: "Bram".to_string(), kind: "Dwarf guide".to_string(), hp: 10, max_hp: 10, ac: 12, notes: String::new(), dexterity: 10,
+        };
+        let character = Character { companions: vec![bram], ..Default::default() };
+        let risky = |action: &str| risky_action_reason(action, &character, &[]).is_some();
+        assert!(risky("I hit Bram"));
+        assert!(risky("shoot at bram!"));
+        assert!(!risky("We hit the road with Bram"));
+        assert!(!risky("strike a deal with Bram"));
+        assert!(!risky("attack the goblin next to Bram"));
+        assert!(!risky("I hit the goblin"));
+        // Whole words only: "Bramble" is not Bram
+        assert!(!risky("hit Bramble with a stick"));
+    }
+    
+    #[test]
+    fn homebrew_starting_equipment_is_validated() {
+        let class = |equipment: serde_json::Value| -> HomebrewClass {
+            serde_json::from_value(serde_json::json!({
+                "name": "Witch Hunter", "hit_die": 10, "saving_throws": ["Wisdom", "Constitution"],
+                "skill_choices": ["Insight", "Religion"], "skill_count": 1, "starting_equipment": equipment,
+            })).unwrap()
+        };
+        assert!(class(serde_json::json!(["Silvered longsword", "Chain shirt", "Holy symbol"])).validate().is_ok());
+        assert!(class(serde_json::json!([])).validate().is_err());
+        assert!(class(serde_json::json!(["Holy symbol", " "])).validate().is_err());
+        assert!(class(serde_json::json!(["Chain shirt", "Leather armor"])).validate().is_err());
+    }
+    
+    #[test]
+    fn carried_weight_counts_quantities_and_sets_encumbrance() {
+        assert_eq!(item_weight("Dagger (2)"), 2.0);
+        assert_eq!(item_weight("Rations (5 days)"), 10.0);
+        assert_eq!(item_weight("Greatclub"), 10.0);
+        assert_eq!(item_weight("Mysterious idol"), 0.0);
+        
+        let mut character = Character { strength: 10, ..Default::default() };
+        character.inventory = vec![Item::new("Chain mail"), Item::new("Longsword")];
+        assert_eq!(character.carried_weight(), 58.0);
+        assert!(character.encumbrance().unwrap().starts_with("encumbered"));
+        // Putting the mail in the stash takes it off the character's load
+        let mail = character.inventory.remove(0);
+        assert_eq!(item_weight(&mail.name), 55.0);
+        assert_eq!(character.carried_weight(), 3.0);
+        assert_eq!(character.encumbrance(), None);
+    }
+    
+    #[test]
+    fn every_class_starts_with_a_kit_and_gold() {
+        for class in BUILT_IN_CLASSES {
+            let (items, _, purse) = equipment_for(class);
+            assert!(!items.is_empty(), "{} has no starting kit", class);
+            assert!(purse.gp > 0, "{} starts without gold", class);
+        }
+    }
+    
+    #[test]
+    fn each_class_starts_in_its_own_armor() {
+        // Class, armor worn, and AC with 14 in DEX, CON and WIS
+        let expected = [
+            ("Artificer", Some("Studded leather"), 12 + 2),
+            ("Barbarian", None, 10 + 2 + 2),
+            ("Bard", Some("Leather armor"), 11 + 2),
+            ("Cleric", Some("Scale mail"), 14 + 2 + 2),
+            ("Druid", Some("Leather armor"), 11 + 2 + 2),
+            ("Fighter", Some("Chain mail"), 16 + 2),
+            ("Monk", None, 10 + 2 + 2),
+            ("Paladin", Some("Chain mail"), 16 + 2),
+            ("Ranger", Some("Scale mail"), 14 + 2),
+            ("Rogue", Some("Leather armor"), 11 + 2),
+            ("Sorcerer", None, 10 + 2),
+            ("Warlock", Some("Leather armor"), 11 + 2),
+            ("Wizard", None, 10 + 2),
+        ];
+        assert_eq!(expected.len(), BUILT_IN_CLASSES.len());
+        for (class, armor_name, armor_class) in expected {
+            let (mut inventory, armor, _) = equipment_for(class);
+            assert_eq!(armor.map(|a| a.name), armor_name, "{} starts in the wrong armor", class);
+            assert!(inventory.iter().all(|item| find_armor(&item.name).is_none()), "{} carries armor as gear", class);
+            inventory.extend(armor.map(|a| Item::new(a.name)));
+            let character = Character {
+                class: class.to_string(), dexterity: 14, constitution: 14, wisdom: 14, inventory, ..Default::default()
+            };
+            assert_eq!(character.compute_armor_class(), armor_class, "{} starts with the wrong AC", class);
+        }
+        
+        let (items, _, purse) = equipment_for("Wizard");
+        let names: Vec<&str> = items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["Spellbook", "Quarterstaff", "Component pouch", "Scholar's pack"]);
+        assert_eq!(purse, Purse { gp: 25 });
+    }
+    
+    #[test]
+    fn advantage_keeps_the_higher_d20() {
+        let (dice, kept) = roll_d20(&mut ScriptedRoller::new([4, 17]), RollMode::Advantage);
+        assert_eq!((dice, kept), (vec![4, 17], 17));
+    }
+    
+    #[test]
+    fn disadvantage_keeps_the_lower_d20() {
+        let (dice, kept) = roll_d20(&mut ScriptedRoller::new([4, 17]), RollMode::Disadvantage);
+        assert_eq!((dice, kept), (vec![4, 17], 4));
+    }
+    
+    #[test]
+    fn a_normal_roll_throws_one_d20() {
+        assert_eq!(roll_d20(&mut ScriptedRoller::new([11]), RollMode::Normal), (vec![11], 11));
+    }
+    
+    #[test]
+    fn a_fighter_multiclassing_into_wizard_gains_no_wizard_proficiencies() {
+        let mut fighter = Character { class: "Fighter".to_string(), level: 3, ..Default::default() };
+        let tools = fighter.tool_proficiencies.clone();
+        fighter.add_multiclass("Wizard", None, None);
+        gain_level(&mut fighter, "Wizard", false, &mut ScriptedRoller::new([]), 0);
+        
+        assert_eq!(fighter.class_label(), "Fighter / Wizard");
+        assert_eq!((fighter.class_level("Fighter"), fighter.class_level("Wizard")), (3, 1));
+        assert!(fighter.is_proficient_in_save(Ability::Strength));
+        assert!(!fighter.is_proficient_in_save(Ability::Intelligence));
+        assert!(!fighter.is_proficient_in_save(Ability::Wisdom));
+        assert_eq!(fighter.tool_proficiencies, tools);
+    }
+    
+    #[test]
+    fn spell_limits_follow_the_wizard_level_not_the_total_level() {
+        let mut wizard = Character { class: "Wizard".to_string(), level: 6, intelligence: 16, ..Default::default() };
+        wizard.add_multiclass("Fighter", None, None);
+        wizard.multiclass_levels.insert("Fighter".to_string(), 5);
+        assert_eq!(wizard.class_level("Wizard"), 1);
+        assert_eq!(wizard.prepared_spell_limit(), 3 + 1);
+        // A first-level wizard can't learn a 2nd-level spell, whatever their Fighter levels
+        let spell = |name: &str, level: u32| Spell { name: name.to_string(), level };
+        assert!(wizard.learn_spell(spell("Misty Step", 2)).is_err());
+        for i in 0..6 {
+            wizard.learn_spell(spell(&format!("Spell {}", i), 1)).unwrap();
+        }
+        assert!(wizard.learn_spell(spell("One too many", 1)).is_err());
+    }
+    
+    #[test]
+    fn a_wizard_multiclassing_into_fighter_gains_martial_weapons_but_not_fighter_saves() {
+        let mut wizard = Character { class: "Wizard".to_string(), level: 1, ..Default::default() };
+        let longsword = find_weapon("Longsword").unwrap();
+        assert!(!wizard.is_proficient_with(longsword));
+        wizard.add_multiclass("Fighter", None, None);
+        gain_level(&mut wizard, "Fighter", false, &mut ScriptedRoller::new([]), 0);
+        
+        assert!(wizard.is_proficient_with(longsword));
+        assert_eq!((wizard.class_level("Wizard"), wizard.class_level("Fighter")), (1, 1));
+        assert!(!wizard.is_proficient_in_save(Ability::Strength));
+        assert!(!wizard.is_proficient_in_save(Ability::Constitution));
+    }
+    
+    #[test]
+    fn advantage_and_disadvantage_cancel_out() {
+        assert_eq!(RollMode::Advantage.combine(RollMode::Disadvantage), RollMode::Normal);
+        assert_eq!(RollMode::Normal.combine(RollMode::Disadvantage), RollMode::Disadvantage);
+        assert_eq!(RollMode::Advantage.combine(RollMode::Advantage), RollMode::Advantage);
+    }
+}